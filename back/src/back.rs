@@ -1,7 +1,75 @@
 //! Utilities for implementing different kinds of backends.
 
+use std::time::Duration;
+use anyhow::anyhow;
+use parking_lot::RwLock;
+use ironic_core::bus::Bus;
+
 /// Common interface implemented by different backends.
 pub trait Backend {
     /// The main loop for this particular backend.
     fn run(&mut self) -> anyhow::Result<()>;
 }
+
+/// How long backends should wait on the bus lock before giving up.
+pub const BUS_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run some closure with the bus locked for reading.
+///
+/// With a timeout, this returns an error instead of blocking forever when
+/// another thread is stuck holding the bus. The bus lock is never poisoned:
+/// if a thread panics while holding it, the lock is just released.
+pub fn with_bus_read<T>(bus: &RwLock<Bus>, timeout: Option<Duration>,
+    f: impl FnOnce(&Bus) -> T) -> anyhow::Result<T>
+{
+    let guard = match timeout {
+        Some(timeout) => bus.try_read_for(timeout)
+            .ok_or_else(|| anyhow!("Timed out after {timeout:?} waiting to read the bus"))?,
+        None => bus.read(),
+    };
+    Ok(f(&guard))
+}
+
+/// Run some closure with the bus locked for writing. See [with_bus_read].
+pub fn with_bus_write<T>(bus: &RwLock<Bus>, timeout: Option<Duration>,
+    f: impl FnOnce(&mut Bus) -> T) -> anyhow::Result<T>
+{
+    let mut guard = match timeout {
+        Some(timeout) => bus.try_write_for(timeout)
+            .ok_or_else(|| anyhow!("Timed out after {timeout:?} waiting to write the bus"))?,
+        None => bus.write(),
+    };
+    Ok(f(&mut guard))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    #[test]
+    fn bus_lock_survives_panic() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let panic_bus = bus.clone();
+        let res = std::thread::spawn(move || {
+            let mut bus = panic_bus.write();
+            bus.cycle = 1234;
+            panic!("Panic while holding the bus");
+        }).join();
+        assert!(res.is_err());
+
+        let timeout = Some(Duration::from_millis(100));
+        assert_eq!(with_bus_read(&bus, timeout, |bus| bus.cycle).unwrap(), 1234);
+        with_bus_write(&bus, timeout, |bus| bus.cycle = 0).unwrap();
+        assert_eq!(with_bus_read(&bus, None, |bus| bus.cycle).unwrap(), 0);
+    }
+
+    #[test]
+    fn bus_lock_times_out() {
+        let bus = RwLock::new(Bus::with_boot0(None).unwrap());
+        let _guard = bus.write();
+        let timeout = Some(Duration::from_millis(10));
+        assert!(with_bus_read(&bus, timeout, |_| ()).is_err());
+        assert!(with_bus_write(&bus, timeout, |_| ()).is_err());
+    }
+}
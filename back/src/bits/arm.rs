@@ -1,6 +1,6 @@
 //! Wrapper types for representing ARM instructions as bitfields.
 
-use super::{xDisplay, DisassemblyContext};
+use super::{reg_name, xDisplay, DisassemblyContext};
 
 /// ['Stc', 'LdcImm']
 #[repr(transparent)]
@@ -44,12 +44,12 @@ impl xDisplay for MovRegBits {
         f.push_str(&format!(" r{}, r{}", self.rd(), self.rm()));
         if self.imm5() != 0 { // pretty sure this is right
             f.push_str(match ShiftType::from(self.stype()) {
-                ShiftType::Lsl => "lsl ",
-                ShiftType::Lsr => "lsr ",
-                ShiftType::Asr => "asr ",
-                ShiftType::Ror => "ror ",
+                ShiftType::Lsl => ", lsl ",
+                ShiftType::Lsr => ", lsr ",
+                ShiftType::Asr => ", asr ",
+                ShiftType::Ror => ", ror ",
             });
-            f.push_str(&format!("0x{:x}", self.imm5()));
+            f.push_str(&format!("#{}", self.imm5()));
         }
         Ok(())
     }
@@ -86,7 +86,7 @@ impl BxBits {
 }
 impl xDisplay for BxBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
-        f.push_str(&format!("r{}", self.rm()));
+        f.push_str(reg_name(self.rm()));
         Ok(())
     }
 }
@@ -104,7 +104,7 @@ impl ClzBits {
 }
 impl xDisplay for ClzBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
-        f.push_str(&format!("r{} r{}", self.rd(), self.rm()));
+        f.push_str(&format!("r{}, r{}", self.rd(), self.rm()));
         Ok(())
     }
 }
@@ -129,6 +129,22 @@ impl xDisplay for BkptBits {
     }
 }
 
+/// ['Svc']
+#[repr(transparent)]
+pub struct SvcBits(pub u32);
+impl SvcBits {
+    #[inline(always)]
+    pub fn cond(&self) -> u32 { (self.0 & 0xf0000000) >> 28 }
+    #[inline(always)]
+    pub fn imm24(&self) -> u32 { self.0 & 0x00ffffff }
+}
+impl xDisplay for SvcBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        f.push_str(&format!("0x{:x}", self.imm24()));
+        Ok(())
+    }
+}
+
 /// ['MsrReg']
 #[repr(transparent)]
 pub struct MsrRegBits(pub u32);
@@ -431,7 +447,21 @@ impl DpTestRsrBits {
     #[inline(always)]
     pub fn rm(&self) -> u32 { self.0 & 0x0000000f }
 }
-impl xDisplay for DpTestRsrBits {} // unused instruction
+impl xDisplay for DpTestRsrBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        use ironic_core::cpu::alu::ShiftType;
+        f.push_str(&format!("r{}, r{}, ", self.rn(), self.rm()));
+        let shift = ShiftType::from(self.stype());
+        f.push_str(match shift {
+            ShiftType::Lsl => "lsl ",
+            ShiftType::Lsr => "lsr ",
+            ShiftType::Asr => "asr ",
+            ShiftType::Ror => "ror ",
+        });
+        f.push_str(&format!("r{}", self.rs()));
+        Ok(())
+    }
+}
 
 /// ['Smlabb']
 #[repr(transparent)]
@@ -542,6 +572,75 @@ impl xDisplay for LsSignedRegBits {
     }
 }
 
+/// ['Swp', 'Swpb']
+#[repr(transparent)]
+pub struct SwpBits(pub u32);
+impl SwpBits {
+    #[inline(always)]
+    pub fn cond(&self) -> u32 { (self.0 & 0xf0000000) >> 28 }
+    #[inline(always)]
+    pub fn b(&self) -> bool { (self.0 & 0x00400000) != 0 }
+    #[inline(always)]
+    pub fn rn(&self) -> u32 { (self.0 & 0x000f0000) >> 16 }
+    #[inline(always)]
+    pub fn rt(&self) -> u32 { (self.0 & 0x0000f000) >> 12 }
+    #[inline(always)]
+    pub fn rm(&self) -> u32 { self.0 & 0x0000000f }
+}
+impl xDisplay for SwpBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        f.push_str(&format!("r{}, r{}, [r{}]", self.rt(), self.rm(), self.rn()));
+        Ok(())
+    }
+}
+
+/// ['Ldrex']
+#[repr(transparent)]
+pub struct LdrexBits(pub u32);
+impl LdrexBits {
+    #[inline(always)]
+    pub fn cond(&self) -> u32 { (self.0 & 0xf0000000) >> 28 }
+    #[inline(always)]
+    pub fn rn(&self) -> u32 { (self.0 & 0x000f0000) >> 16 }
+    #[inline(always)]
+    pub fn rt(&self) -> u32 { (self.0 & 0x0000f000) >> 12 }
+}
+impl xDisplay for LdrexBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        f.push_str(&format!("r{}, [r{}]", self.rt(), self.rn()));
+        Ok(())
+    }
+}
+
+/// ['Strex']
+#[repr(transparent)]
+pub struct StrexBits(pub u32);
+impl StrexBits {
+    #[inline(always)]
+    pub fn cond(&self) -> u32 { (self.0 & 0xf0000000) >> 28 }
+    #[inline(always)]
+    pub fn rn(&self) -> u32 { (self.0 & 0x000f0000) >> 16 }
+    #[inline(always)]
+    pub fn rd(&self) -> u32 { (self.0 & 0x0000f000) >> 12 }
+    #[inline(always)]
+    pub fn rt(&self) -> u32 { self.0 & 0x0000000f }
+}
+impl xDisplay for StrexBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        f.push_str(&format!("r{}, r{}, [r{}]", self.rd(), self.rt(), self.rn()));
+        Ok(())
+    }
+}
+
+/// ['Clrex']
+#[repr(transparent)]
+pub struct ClrexBits(pub u32);
+impl xDisplay for ClrexBits {
+    fn fmt(&self, _: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
 /// ['AndRegShiftReg', 'AdcRegShiftReg', 'OrrRegShiftReg', 'EorRegShiftReg', 'RscRegShiftReg', 'SbcRegShiftReg', 'AddRegShiftReg', 'BicRegShiftReg', 'RsbRegShiftReg', 'SubRegShiftReg']
 #[repr(transparent)]
 pub struct DpRsrBits(pub u32);
@@ -564,7 +663,7 @@ impl DpRsrBits {
 impl xDisplay for DpRsrBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
         use ironic_core::cpu::alu::ShiftType;
-        f.push_str(&format!("r{}, r{}, ", self.rd(), self.rn()));
+        f.push_str(&format!("r{}, r{}, r{}, ", self.rd(), self.rn(), self.rm()));
         let shift = ShiftType::from(self.stype());
         f.push_str(match shift {
             ShiftType::Lsl => "lsl ",
@@ -630,13 +729,17 @@ impl DpTestRegBits {
 impl xDisplay for DpTestRegBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
         use ironic_core::cpu::alu::ShiftType;
-        f.push_str(&format!("r{}, r{}, ", self.rn(), self.rm()));
+        f.push_str(&format!("r{}, r{}", self.rn(), self.rm()));
+        // A plain register operand is encoded as `lsl #0`
         let shift = ShiftType::from(self.stype());
+        if matches!(shift, ShiftType::Lsl) && self.imm5() == 0 {
+            return Ok(());
+        }
         f.push_str(match shift {
-            ShiftType::Lsl => "lsl ",
-            ShiftType::Lsr => "lsr ",
-            ShiftType::Asr => "asr ",
-            ShiftType::Ror => "ror ",
+            ShiftType::Lsl => ", lsl ",
+            ShiftType::Lsr => ", lsr ",
+            ShiftType::Asr => ", asr ",
+            ShiftType::Ror => ", ror ",
         });
         f.push_str(&format!("#{}", self.imm5()));
         Ok(())
@@ -720,7 +823,24 @@ impl LsTransAltBits {
     pub fn stype(&self) -> u32 { (self.0 & 0x00000060) >> 5 }
     #[inline(always)]
     pub fn rm(&self) -> u32 { self.0 & 0x0000000f }
-} impl xDisplay for LsTransAltBits {} // unused instruction
+}
+impl xDisplay for LsTransAltBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        let sign = if self.u() { "" } else { "-" };
+        f.push_str(&format!("r{}, [r{}], {sign}r{}", self.rt(), self.rn(), self.rm()));
+        if self.imm5() != 0 || self.stype() != 0 {
+            use ironic_core::cpu::alu::ShiftType;
+            let shift = match ShiftType::from(self.stype()) {
+                ShiftType::Lsl => "lsl",
+                ShiftType::Lsr => "lsr",
+                ShiftType::Asr => "asr",
+                ShiftType::Ror => "ror",
+            };
+            f.push_str(&format!(", {shift} #0x{:x}", self.imm5()));
+        }
+        Ok(())
+    }
+}
 
 /// ['SbcReg', 'OrrReg', 'BicReg', 'AddReg', 'RscReg', 'EorReg', 'AdcReg', 'SubReg', 'AndReg', 'RsbReg']
 #[repr(transparent)]
@@ -795,19 +915,17 @@ impl LsTransBits {
     pub fn rt(&self) -> u32 { (self.0 & 0x0000f000) >> 12 }
     #[inline(always)]
     pub fn imm12(&self) -> u32 { self.0 & 0x00000fff }
-} impl xDisplay for LsTransBits {} // unused instruction
+}
+impl xDisplay for LsTransBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        let sign = if self.u() { "" } else { "-" };
+        f.push_str(&format!("r{}, [r{}], #{sign}0x{:x}", self.rt(), self.rn(), self.imm12()));
+        Ok(())
+    }
+}
 
 /// Formats a register list for instructions like ldm and stm
-fn format_register_list(list: u32) -> String {
-    fn format_register(index: u32) -> &'static str {
-        match index {
-            0..=12 => &["r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12"][index as usize],
-            13 => "sp",
-            14 => "lr",
-            15 => "pc",
-            _ => unreachable!(),
-        }
-    }
+pub(crate) fn format_register_list(list: u32) -> String {
     let mut reglist = String::new();
     let mut start = None;
     let mut end = None;
@@ -820,11 +938,11 @@ fn format_register_list(list: u32) -> String {
             end = Some(i);
         } else if let (Some(s), Some(e)) = (start, end) {
             if s == e {
-                reglist += &format_register(s);
+                reglist += reg_name(s);
             } else if s+1 == e {
-                reglist += &format!("{}, {}", &format_register(s), &format_register(e));
+                reglist += &format!("{}, {}", reg_name(s), reg_name(e));
             } else {
-                reglist += &format!("{}-{}", format_register(s), format_register(e));
+                reglist += &format!("{}-{}", reg_name(s), reg_name(e));
             }
             reglist += ", ";
             start = None;
@@ -833,18 +951,18 @@ fn format_register_list(list: u32) -> String {
     }
     if let (Some(s), Some(e)) = (start, end) {
         if s == e {
-            reglist += &format_register(s);
+            reglist += reg_name(s);
         } else if s+1 == e {
-            reglist += &format!("{}, {}", &format_register(s), &format_register(e));
+            reglist += &format!("{}, {}", reg_name(s), reg_name(e));
         } else {
-            reglist += &format!("{}-{}", format_register(s), format_register(e));
+            reglist += &format!("{}-{}", reg_name(s), reg_name(e));
         }
         reglist += ", ";
     }
     // never collapse sp, lr, pc
     for i in 13..=15 {
-        if (list & (1 << 1)) != 0 {
-            reglist += format_register(i);
+        if (list & (1 << i)) != 0 {
+            reglist += reg_name(i);
             reglist += ", ";
         }
     }
@@ -1016,6 +1134,15 @@ impl LsImmBits {
     pub fn rt(&self) -> u32 { (self.0 & 0x0000f000) >> 12 }
     #[inline(always)]
     pub fn imm12(&self) -> u32 { self.0 & 0x00000fff }
+
+    /// Address loaded from by a PC-relative load at `pc`, if this is one.
+    pub fn literal_address(&self, pc: u32) -> Option<u32> {
+        if self.rn() != 15 || !self.p() || self.w() {
+            return None;
+        }
+        let base = pc.wrapping_add(8);
+        Some(if self.u() { base.wrapping_add(self.imm12()) } else { base.wrapping_sub(self.imm12()) })
+    }
 }
 impl xDisplay for LsImmBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
@@ -1073,17 +1200,24 @@ impl BranchBits {
     pub fn h(&self) -> bool { (self.0 & 0x01000000) != 0 }
     #[inline(always)]
     pub fn imm24(&self) -> u32 { self.0 & 0x00ffffff }
+
+    /// Branch target for the instruction at `pc` (the H bit only applies
+    /// to BLX).
+    pub fn target(&self, pc: u32, blx: bool) -> u32 {
+        let mut offset = (crate::interp::arm::branch::sign_extend(self.imm24(), 24) as u32) << 2;
+        if blx { offset |= (self.h() as u32) << 1 }
+        // The offset is relative to the PC in the execute stage
+        pc.wrapping_add(8).wrapping_add(offset)
+    }
 }
 impl xDisplay for BranchBits {
     fn fmt(&self, f: &mut String, ctx: DisassemblyContext) -> anyhow::Result<()> {
-        // FIXME: BlxImm needs to handle H bit
         use anyhow::bail;
         let (blx, base) = match ctx {
             DisassemblyContext::BlxDiscriminantAndPC(blx_base) => blx_base,
             _ => bail!("PC context required")
         };
-        let mut addr = base as i64 + (crate::interp::arm::branch::sign_extend(self.imm24(), 24, 30) << 2) as i64;
-        if blx { addr += ((self.h() as u32) as i64) << 1}
+        let addr = self.target(base, blx);
         f.push_str(&format!("0x{addr:x}"));
         Ok(())
     }
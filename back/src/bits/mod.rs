@@ -3,6 +3,15 @@
 pub mod arm;
 pub mod thumb;
 
+/// Name of a register in disassembly, with r13-r15 shown as sp, lr and pc.
+pub(crate) fn reg_name(r: u32) -> &'static str {
+    const NAMES: [&str; 16] = [
+        "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7",
+        "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc",
+    ];
+    NAMES[r as usize & 0xf]
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum DisassemblyContext {
     /// PC for offset calculations
@@ -36,6 +45,7 @@ pub mod disassembly {
     use ironic_core::cpu::reg::Cond;
     use crate::decode::thumb::*;
     use crate::decode::arm::*;
+    use ironic_core::dbg::symbols::SymbolMap;
 
     pub fn disassmble_thumb(op: u16, address: u32) -> anyhow::Result<String> {
         let instruction = ThumbInst::decode(op);
@@ -59,6 +69,19 @@ pub mod disassembly {
         bits.fmt(&mut res, ctx)?;
         Ok(res)
     }
+    /// Disassemble a Thumb `bl`/`blx` from its two halfwords, with the
+    /// prefix at `address`.
+    pub fn disassmble_thumb32(prefix: u16, suffix: u16, address: u32) -> anyhow::Result<String> {
+        if ThumbInst::decode(prefix) != ThumbInst::BlPrefix {
+            bail!("{prefix:04x} isn't a bl/blx prefix");
+        }
+        let instruction = ThumbInst::decode(suffix);
+        if !matches!(instruction, ThumbInst::BlImmSuffix | ThumbInst::BlxImmSuffix) {
+            bail!("{suffix:04x} isn't a bl/blx suffix");
+        }
+        let target = super::thumb::BlBits(prefix).target(&super::thumb::BlBits(suffix), address);
+        Ok(format!("{instruction:#}0x{target:x}"))
+    }
     pub fn disassmble_arm(op: u32, address: u32) -> anyhow::Result<String> {
         let instrcution = ArmInst::decode(op);
         if instrcution == ArmInst::Undefined {
@@ -89,8 +112,281 @@ pub mod disassembly {
             Cond::AL |
             Cond::UNC => "",
         };
+        if instrcution == ArmInst::Clrex {
+            // No operands, and no condition
+            return Ok("clrex".to_owned());
+        }
         let mut res = format!("{instrcution:#}{condition} ");
         bits.fmt(&mut res, ctx)?;
         Ok(res)
     }
-}
\ No newline at end of file
+    /// Target of a Thumb branch with an immediate offset at `address`.
+    pub fn branch_target_thumb(op: u16, address: u32) -> Option<u32> {
+        match ThumbInst::decode(op) {
+            ThumbInst::B => Some(super::thumb::BranchBits(op).target(address)),
+            ThumbInst::BAlt => Some(super::thumb::BranchAltBits(op).target(address)),
+            _ => None,
+        }
+    }
+    /// Target of an ARM branch with an immediate offset at `address`.
+    pub fn branch_target_arm(op: u32, address: u32) -> Option<u32> {
+        let bits = super::arm::BranchBits(op);
+        match ArmInst::decode(op) {
+            ArmInst::B | ArmInst::BlImm => Some(bits.target(address, false)),
+            ArmInst::BlxImm => Some(bits.target(address, true)),
+            _ => None,
+        }
+    }
+
+    /// Address of the literal loaded by a Thumb `ldr rN, [pc, #imm]` at `address`.
+    pub fn literal_address_thumb(op: u16, address: u32) -> Option<u32> {
+        match ThumbInst::decode(op) {
+            ThumbInst::LdrLit => Some(super::thumb::LoadStoreAltBits(op).literal_address(address)),
+            _ => None,
+        }
+    }
+    /// Address of the literal loaded by an ARM `ldr rN, [pc, #imm]` at `address`.
+    pub fn literal_address_arm(op: u32, address: u32) -> Option<u32> {
+        match ArmInst::decode(op) {
+            ArmInst::LdrImm => super::arm::LsImmBits(op).literal_address(address),
+            _ => None,
+        }
+    }
+
+    /// Append the symbol for a branch target or literal (e.g. `bl 0xffff1234 <ThreadCreate>`).
+    fn annotate(mut res: String, target: Option<u32>, symbols: Option<&SymbolMap>) -> String {
+        if let (Some(target), Some(symbols)) = (target, symbols)
+        && let Some(name) = symbols.describe(target) {
+            res += &format!(" <{name}>");
+        }
+        res
+    }
+    /// Like [disassmble_thumb], but with branch targets and literal loads
+    /// annotated with symbols when available.
+    pub fn disassmble_thumb_with_symbols(op: u16, address: u32, symbols: Option<&SymbolMap>) -> anyhow::Result<String> {
+        let res = disassmble_thumb(op, address)?;
+        let target = branch_target_thumb(op, address).or_else(|| literal_address_thumb(op, address));
+        Ok(annotate(res, target, symbols))
+    }
+    /// Like [disassmble_arm], but with branch targets and literal loads
+    /// annotated with symbols when available.
+    pub fn disassmble_arm_with_symbols(op: u32, address: u32, symbols: Option<&SymbolMap>) -> anyhow::Result<String> {
+        let res = disassmble_arm(op, address)?;
+        let target = branch_target_arm(op, address).or_else(|| literal_address_arm(op, address));
+        Ok(annotate(res, target, symbols))
+    }
+
+    /// Disassemble a flat, big-endian blob of ARM or Thumb code loaded at
+    /// `base`, one `address: opcode  mnemonic` line per instruction. Thumb
+    /// `bl`/`blx` pairs are shown as one instruction, and anything which
+    /// doesn't decode is shown as data.
+    pub fn disassemble_listing(data: &[u8], base: u32, thumb: bool, symbols: Option<&SymbolMap>) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut off = 0;
+        let width = if thumb { 2 } else { 4 };
+        while off + width <= data.len() {
+            let address = base.wrapping_add(off as u32);
+            if !thumb {
+                let op = u32::from_be_bytes(data[off..off + 4].try_into().unwrap());
+                let text = disassmble_arm_with_symbols(op, address, symbols)
+                    .unwrap_or_else(|_| format!(".word 0x{op:08x}"));
+                lines.push(format!("{address:08x}:  {op:08x}   {text}"));
+                off += 4;
+                continue;
+            }
+            let op = u16::from_be_bytes([data[off], data[off + 1]]);
+            if off + 4 <= data.len() {
+                let suffix = u16::from_be_bytes([data[off + 2], data[off + 3]]);
+                if let Ok(text) = disassmble_thumb32(op, suffix, address) {
+                    let target = super::thumb::BlBits(op).target(&super::thumb::BlBits(suffix), address);
+                    let text = annotate(text, Some(target), symbols);
+                    lines.push(format!("{address:08x}:  {op:04x} {suffix:04x}  {text}"));
+                    off += 4;
+                    continue;
+                }
+            }
+            let text = disassmble_thumb_with_symbols(op, address, symbols)
+                .unwrap_or_else(|_| format!(".hword 0x{op:04x}"));
+            lines.push(format!("{address:08x}:  {op:04x}        {text}"));
+            off += 2;
+        }
+        for (idx, byte) in data[off..].iter().enumerate() {
+            let address = base.wrapping_add((off + idx) as u32);
+            lines.push(format!("{address:08x}:  {byte:02x}          .byte 0x{byte:02x}"));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassembly::{disassemble_listing, disassmble_arm, disassmble_thumb, disassmble_thumb32};
+
+    #[test]
+    fn high_registers_are_named() {
+        assert_eq!(disassmble_thumb(0x4770, 0).unwrap(), "bx lr");
+        assert_eq!(disassmble_thumb(0x4718, 0).unwrap(), "bx r3");
+        assert_eq!(disassmble_thumb(0x47c0, 0).unwrap(), "blx r8");
+        assert_eq!(disassmble_thumb(0x9801, 0).unwrap(), "ldr r0, [sp, #0x4]");
+        assert_eq!(super::arm::format_register_list(0xe0f3), "r0, r1, r4-r7, sp, lr, pc");
+    }
+
+    #[test]
+    fn thumb_shift_moves_show_operands() {
+        assert_eq!(disassmble_thumb(0x4088, 0).unwrap(), "lsls r0, r1");
+        assert_eq!(disassmble_thumb(0x41d3, 0).unwrap(), "rors r3, r2");
+        assert_eq!(disassmble_thumb(0x0108, 0).unwrap(), "lsls r0, r1, #4");
+        assert_eq!(disassmble_thumb(0x0fd3, 0).unwrap(), "lsrs r3, r2, #31");
+        assert_eq!(disassmble_thumb(0x1008, 0).unwrap(), "asrs r0, r1, #32");
+    }
+
+    #[test]
+    fn thumb_bl_pairs_show_targets() {
+        // bl 0x1234 (forwards) and bl 0xff0 (backwards) from 0x1000
+        assert_eq!(disassmble_thumb32(0xf000, 0xf918, 0x1000).unwrap(), "bl 0x1234");
+        assert_eq!(disassmble_thumb32(0xf7ff, 0xfff6, 0x1000).unwrap(), "bl 0xff0");
+        // blx targets are word-aligned
+        assert_eq!(disassmble_thumb32(0xf000, 0xe918, 0x1002).unwrap(), "blx 0x1234");
+        assert!(disassmble_thumb32(0xf918, 0xf000, 0x1000).is_err());
+    }
+
+    #[test]
+    fn arm_shifted_register_operands() {
+        assert_eq!(disassmble_arm(0xe151_0312, 0).unwrap(), "cmp r1, r2, lsl r3");
+        assert_eq!(disassmble_arm(0x1131_0572, 0).unwrap(), "teqne r1, r2, ror r5");
+        assert_eq!(disassmble_arm(0xe151_0002, 0).unwrap(), "cmp r1, r2");
+        assert_eq!(disassmble_arm(0xe151_0142, 0).unwrap(), "cmp r1, r2, asr #2");
+        assert_eq!(disassmble_arm(0xe081_0312, 0).unwrap(), "add r0, r1, r2, lsl r3");
+        assert_eq!(disassmble_arm(0xe1a0_0101, 0).unwrap(), "mov  r0, r1, lsl #2");
+        // mov with an immediate shift takes the same ", <shift> #imm" form
+        assert_eq!(disassmble_arm(0xe1a0_0fa1, 0).unwrap(), "mov  r0, r1, lsr #31");
+        assert_eq!(disassmble_arm(0xe1a0_0001, 0).unwrap(), "mov  r0, r1");
+    }
+
+    #[test]
+    fn register_lists_include_r0_and_pc() {
+        assert_eq!(disassmble_arm(0xe8b0_8003, 0).unwrap(), "ldm ia, r0!, { r0, r1, pc }");
+        assert_eq!(disassmble_arm(0xe92d_4ff0, 0).unwrap(), "stm db, r13!, { r4-r11, lr }");
+        assert_eq!(disassmble_thumb(0xb501, 0).unwrap(), "push { r0, lr }");
+        assert_eq!(disassmble_thumb(0xbdf1, 0).unwrap(), "pop { r0, r4-r7, pc }");
+        assert_eq!(disassmble_thumb(0xc803, 0).unwrap(), "ldmia r0!, { r0, r1 }");
+    }
+
+    #[test]
+    fn listing_of_a_flat_binary() {
+        let arm: Vec<u8> = [0xe3a0_0004u32, 0xef00_00ab, 0xe12f_ff1e, 0xe600_0010]
+            .iter().flat_map(|op| op.to_be_bytes()).collect();
+        let lines = disassemble_listing(&arm, 0x1_0000, false, None);
+        assert_eq!(lines, [
+            "00010000:  e3a00004   mov  r0, #0x4",
+            "00010004:  ef0000ab   svc 0xab",
+            "00010008:  e12fff1e   bx lr",
+            "0001000c:  e6000010   .word 0xe6000010",
+        ]);
+
+        // bx lr, then bl 0x1234 from 0x1002, then a trailing byte
+        let thumb = [0x47, 0x70, 0xf0, 0x00, 0xf9, 0x17, 0xaa];
+        let lines = disassemble_listing(&thumb, 0x1000, true, None);
+        assert_eq!(lines, [
+            "00001000:  4770        bx lr",
+            "00001002:  f000 f917  bl 0x1234",
+            "00001006:  aa          .byte 0xaa",
+        ]);
+    }
+
+    /// `llvm-objdump -d --triple=armv5te-none-eabi` output (with opcodes
+    /// shown big-endian), as `address: opcode  text`.
+    const OBJDUMP_ARM: &str = "
+0: e3a00004  mov r0, #4
+4: ef0000ab  svc #171
+8: e12fff1e  bx lr
+10: e5912004  ldr r2, [r1, #4]
+14: e5812008  str r2, [r1, #8]
+18: e3510000  cmp r1, #0
+1c: 1afffffb  bne 0x10 <$a+0x10>
+20: eb000010  bl 0x68 <$a+0x68>
+28: e12fff13  bx r3
+2c: e2411001  sub r1, r1, #1
+30: e1510002  cmp r1, r2
+3c: e16f1f12  clz r1, r2
+44: e3c00003  bic r0, r0, #3
+48: e3800001  orr r0, r0, #1
+";
+    /// The same, with `--triple=thumbv5te-none-eabi`.
+    const OBJDUMP_THUMB: &str = "
+0: 4770  bx lr
+2: 2900  cmp r1, #0
+4: 4718  bx r3
+6: 9801  ldr r0, [sp, #4]
+8: 0108  lsls r0, r1, #4
+a: 4088  lsls r0, r1
+c: 47c0  blx r8
+e: f000f917  bl 0x240 <$t+0x240>
+";
+
+    /// Make disassembly comparable between tools: whitespace is collapsed,
+    /// symbol annotations dropped and immediates written in decimal, so that
+    /// `#0x10`, `#16` and `0x10` all read `16`.
+    fn normalize(text: &str) -> String {
+        let text = text.split(" <").next().unwrap();
+        let mut out = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                if !out.ends_with(' ') { out.push(' '); }
+                continue;
+            }
+            let at_token_start = !out.ends_with(|p: char| p.is_ascii_alphanumeric());
+            if at_token_start && (c == '#' || c.is_ascii_digit()) {
+                let mut num = String::new();
+                if c != '#' { num.push(c); }
+                while let Some(&d) = chars.peek() && (d.is_ascii_alphanumeric() || d == '-') {
+                    num.push(d);
+                    chars.next();
+                }
+                let (neg, digits) = match num.strip_prefix('-') {
+                    Some(rest) => (true, rest),
+                    None => (false, num.as_str()),
+                };
+                let val = match digits.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16).unwrap(),
+                    None => digits.parse::<i64>().unwrap(),
+                };
+                out.push_str(&(if neg { -val } else { val }).to_string());
+                continue;
+            }
+            out.push(c);
+        }
+        out.trim().to_owned()
+    }
+
+    /// Split a line of [OBJDUMP_ARM] or [OBJDUMP_THUMB].
+    fn golden_line(line: &str) -> (u32, u32, &str) {
+        let (addr, rest) = line.split_once(": ").unwrap();
+        let (op, text) = rest.split_once("  ").unwrap();
+        (u32::from_str_radix(addr, 16).unwrap(), u32::from_str_radix(op, 16).unwrap(), text)
+    }
+
+    #[test]
+    fn arm_matches_objdump() {
+        for line in OBJDUMP_ARM.trim().lines() {
+            let (addr, op, expected) = golden_line(line);
+            let ours = disassmble_arm(op, addr).unwrap();
+            assert_eq!(normalize(&ours), normalize(expected), "{op:08x}: {ours}");
+        }
+    }
+
+    #[test]
+    fn thumb_matches_objdump() {
+        for line in OBJDUMP_THUMB.trim().lines() {
+            let (addr, op, expected) = golden_line(line);
+            // bl/blx pairs are written as one 32-bit opcode
+            let ours = if op > 0xffff {
+                disassmble_thumb32((op >> 16) as u16, op as u16, addr).unwrap()
+            } else {
+                disassmble_thumb(op as u16, addr).unwrap()
+            };
+            assert_eq!(normalize(&ours), normalize(expected), "{op:04x}: {ours}");
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::{xDisplay, DisassemblyContext};
+use super::{reg_name, xDisplay, DisassemblyContext};
 use anyhow::bail;
 
 /// ["Bl", "Blx"]
@@ -9,8 +9,21 @@ impl BlBits {
     pub fn imm11(&self) -> u16 { self.0 & 0x07ff }
     #[inline(always)]
     pub fn h(&self) -> u16 { (self.0 >> 11) & 0x3 }
+
+    /// Branch target for a prefix (this) and suffix pair at `pc`.
+    ///
+    /// In Thumb-2 terms, the J1/J2 bits in the suffix are always set on
+    /// ARMv5, so the prefix's imm11 is just sign-extended above the suffix's.
+    /// `blx` targets are word-aligned.
+    pub fn target(&self, suffix: &BlBits, pc: u32) -> u32 {
+        let offset = crate::interp::thumb::branch::sign_extend((self.imm11() as u32) << 12, 23);
+        let res = pc.wrapping_add(4)
+            .wrapping_add(offset as u32)
+            .wrapping_add((suffix.imm11() as u32) << 1);
+        if suffix.h() == 0x1 { res & 0xffff_fffc } else { res }
+    }
 }
-impl xDisplay for BlBits {} // 2 parter
+impl xDisplay for BlBits {} // 2 parter, see disassmble_thumb32
 
 
 /// ['Neg']
@@ -113,12 +126,16 @@ impl xDisplay for AddSubSpImmAltBits {
 #[repr(transparent)]
 pub struct BxBits(pub u16);
 impl BxBits {
+    /// The full 4-bit Rm field (H2:Rm), so r8-r15 are addressable.
     #[inline(always)]
     pub fn rm(&self) -> u16 { (self.0 & 0x0078) >> 3 }
+    /// Bits [2:0] should always be zero.
+    #[inline(always)]
+    pub fn sbz(&self) -> u16 { self.0 & 0x0007 }
 }
 impl xDisplay for BxBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
-        f.push_str(&format!("r{}", self.rm()));
+        f.push_str(reg_name(self.rm() as u32));
         Ok(())
     }
 }
@@ -242,7 +259,19 @@ impl MovRsrBits {
     #[inline(always)]
     pub fn rdm(&self) -> u16 { self.0 & 0x0007 }
 }
-impl xDisplay for MovRsrBits {} //FIXME
+impl xDisplay for MovRsrBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        let mnemonic = match self.op() {
+            0b0010 => "lsls",
+            0b0011 => "lsrs",
+            0b0100 => "asrs",
+            0b0111 => "rors",
+            _ => bail!("Invalid shift op {:04b} for MovRegShiftReg", self.op()),
+        };
+        f.push_str(&format!("{mnemonic} r{}, r{}", self.rdm(), self.rs()));
+        Ok(())
+    }
+}
 
 /// ['Pop']
 #[repr(transparent)]
@@ -255,14 +284,8 @@ impl PopBits {
 }
 impl xDisplay for PopBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
-        let mut reglist = String::with_capacity(30);
-        for i in 0..=7 {
-            if (self.register_list() >> i) & 0x1 == 1 {
-                reglist += format!(" r{i}").as_str();
-            }
-        }
-        if self.p() { reglist += " pc" }
-        f.push_str(&format!("{{{reglist} }}"));
+        let list = self.register_list() as u32 | (self.p() as u32) << 15;
+        f.push_str(&format!("{{ {} }}", super::arm::format_register_list(list)));
 
         Ok(())
     }
@@ -298,20 +321,14 @@ impl PushBits {
 }
 impl xDisplay for PushBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
-        let mut reglist = String::with_capacity(30);
-        for i in 0..=7 {
-            if (self.register_list() >> i) & 0x1 == 1 {
-                reglist += format!(" r{i}").as_str();
-            }
-        }
-        if self.m() {reglist += " lr"}
-        f.push_str(&format!("{{{reglist} }}"));
+        let list = self.register_list() as u32 | (self.m() as u32) << 14;
+        f.push_str(&format!("{{ {} }}", super::arm::format_register_list(list)));
 
         Ok(())
     }
 }
 
-/// ['MovImm', 'AddSpImm']
+/// ['MovImm', 'AddSpImm', 'AddPcImm']
 #[repr(transparent)]
 pub struct MovImmBits(pub u16);
 impl MovImmBits {
@@ -375,13 +392,8 @@ impl LoadStoreMultiBits {
 }
 impl xDisplay for LoadStoreMultiBits {
     fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
-        let mut reglist = String::with_capacity(30);
-        for i in 0..=7 {
-            if (self.register_list() >> i) & 0x1 == 1 {
-                reglist += format!(" r{i}").as_str();
-            }
-        }
-        f.push_str(&format!("{}!, {{{reglist}}}", self.rn()));
+        let reglist = super::arm::format_register_list(self.register_list() as u32);
+        f.push_str(&format!("r{}!, {{ {reglist} }}", self.rn()));
 
         Ok(())
     }
@@ -412,16 +424,17 @@ impl LoadStoreAltBits {
     pub fn rt(&self) -> u16 { (self.0 & 0x0700) >> 8 }
     #[inline(always)]
     pub fn imm8(&self) -> u16 { self.0 & 0x00ff }
+
+    /// Address loaded from by an `LdrLit` at `pc`.
+    pub fn literal_address(&self, pc: u32) -> u32 {
+        (pc.wrapping_add(4) & !3).wrapping_add(self.imm8() as u32 * 4)
+    }
 }
 impl xDisplay for LoadStoreAltBits {
     fn fmt(&self, f: &mut String, ctx: DisassemblyContext) -> anyhow::Result<()> {
         let reg = match ctx {
-            DisassemblyContext::BaseRegister(r) => {
-                if r == 13 { "sp" }
-                else if r == 14 { "lr" }
-                else if r == 15 { "pc" }
-                else { bail!("Inappropriate base register") }
-            },
+            DisassemblyContext::BaseRegister(r @ 13..=15) => reg_name(r),
+            DisassemblyContext::BaseRegister(_) => bail!("Inappropriate base register"),
             _ => bail!("base register context required")
         };
         f.push_str(&format!("r{}, [{reg}, #0x{:x}]", self.rt(), self.imm8()*4));
@@ -439,17 +452,22 @@ pub struct BranchAltBits(pub u16);
 impl BranchAltBits {
     #[inline(always)]
     pub fn imm11(&self) -> u16 { self.0 & 0x07ff }
+
+    /// Branch target for the instruction at `pc`.
+    pub fn target(&self, pc: u32) -> u32 {
+        let offset = 
+            crate::interp::thumb::branch::sign_extend(self.imm11() as u32, 11) << 1;
+        pc.wrapping_add(4).wrapping_add(offset as u32)
+    }
 }
 impl xDisplay for BranchAltBits {
     fn fmt(&self, f: &mut String, ctx: DisassemblyContext) -> anyhow::Result<()> {
         let pc = match ctx {
-            DisassemblyContext::PC(pc) => pc as i64,
+            DisassemblyContext::PC(pc) => pc,
             _ => bail!("PC context required")
         };
-        let offset = 
-            crate::interp::thumb::branch::sign_extend(self.imm11() as u32, 11) << 1;
-        let target = pc.wrapping_add(offset as i64);
-        f.push_str(&format!("0x{target:x}"));
+        let target = self.target(pc);
+        f.push_str(&format!(" 0x{target:x}"));
         Ok(())
     }
     fn required_context(&self) -> DisassemblyContext {
@@ -465,16 +483,21 @@ impl BranchBits {
     pub fn cond(&self) -> u16 { (self.0 & 0x0f00) >> 8 }
     #[inline(always)]
     pub fn imm8(&self) -> u16 { self.0 & 0x00ff }
+
+    /// Branch target for the instruction at `pc`.
+    pub fn target(&self, pc: u32) -> u32 {
+        let offset = 
+            crate::interp::thumb::branch::sign_extend(self.imm8() as u32, 8) << 1;
+        pc.wrapping_add(4).wrapping_add(offset as u32)
+    }
 }
 impl xDisplay for BranchBits {
     fn fmt(&self, f: &mut String, ctx: DisassemblyContext) -> anyhow::Result<()> {
         let pc = match ctx {
-            DisassemblyContext::PC(pc) => pc as i64,
+            DisassemblyContext::PC(pc) => pc,
             _ => bail!("PC context required")
         };
-        let offset = 
-            crate::interp::thumb::branch::sign_extend(self.imm8() as u32, 11) << 1;
-        let target = pc.wrapping_add(offset as i64);
+        let target = self.target(pc);
         let cond = ironic_core::cpu::reg::Cond::try_from(self.cond() as u32)?;
         f.push_str(&format!("{cond:?} 0x{target:x}"));
         Ok(())
@@ -497,4 +520,17 @@ impl MovRegAltBits {
     #[inline(always)]
     pub fn rd(&self) -> u16 { self.0 & 0x0007 }
 }
-impl xDisplay for MovRegAltBits {} //FIXME
+impl xDisplay for MovRegAltBits {
+    fn fmt(&self, f: &mut String, _: DisassemblyContext) -> anyhow::Result<()> {
+        let mnemonic = match self.op() {
+            0b00 => "lsls",
+            0b01 => "lsrs",
+            0b10 => "asrs",
+            _ => bail!("Invalid shift op {:02b} for MovRegAlt", self.op()),
+        };
+        // lsr/asr #0 encode a shift by 32
+        let imm = if self.imm5() == 0 && self.op() != 0 { 32 } else { self.imm5() };
+        f.push_str(&format!("{mnemonic} r{}, r{}, #{imm}", self.rd(), self.rm()));
+        Ok(())
+    }
+}
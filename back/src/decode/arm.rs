@@ -18,6 +18,7 @@ pub enum ArmInst {
     StrImm, StrhImm, StrdImm, StrbImm, StrReg, StrbReg, StrhReg, StrdReg, 
     LdrImm, LdrhImm, LdrdImm, LdrbImm, LdrsbImm, LdrshImm, 
     LdrReg, LdrbReg, LdrhReg, LdrdReg, LdrsbReg, LdrshReg, 
+    Swp, Swpb, Ldrex, Strex, Clrex,
 
     Qdadd, Qsub, Qadd, Qdsub, Smull, Umlal, Smlal, Umull, Mul, Mla,
     Smulwb, Smlawb, Smlalbb, Smlabb, Smulbb,
@@ -128,9 +129,9 @@ impl std::fmt::Display for ArmInst {
             ArmInst::Ldrt           => write!(f, "ldrt"),
             ArmInst::Strt           => write!(f, "strt"),
             ArmInst::MovImmAlt      => write!(f, "mov"),
-            ArmInst::LdrbtAlt       => write!(f, "ldrb"),
-            ArmInst::StrbtAlt       => write!(f, "strb"),
-            ArmInst::LdrtAlt        => write!(f, "sdrt"),
+            ArmInst::LdrbtAlt       => write!(f, "ldrbt"),
+            ArmInst::StrbtAlt       => write!(f, "strbt"),
+            ArmInst::LdrtAlt        => write!(f, "ldrt"),
             ArmInst::StrtAlt        => write!(f, "strt"),
             ArmInst::Stm            => write!(f, "stm"),
             ArmInst::Stmda          => write!(f, "stm"),
@@ -162,6 +163,11 @@ impl std::fmt::Display for ArmInst {
             ArmInst::Svc            => write!(f, "svc"),
             ArmInst::Bkpt           => write!(f, "bkpt"),
             ArmInst::BlxImm         => write!(f, "blx"),
+            ArmInst::Swp            => write!(f, "swp"),
+            ArmInst::Swpb           => write!(f, "swpb"),
+            ArmInst::Ldrex          => write!(f, "ldrex"),
+            ArmInst::Strex          => write!(f, "strex"),
+            ArmInst::Clrex          => write!(f, "clrex"),
             ArmInst::Undefined      => write!(f, "undefined"),
         }
     }
@@ -175,6 +181,9 @@ impl ArmInst {
             if opcd & 0x0e00_0000 == 0x0a000000 {
                 return BlxImm;
             }
+            if opcd == 0xf57f_f01f {
+                return Clrex;
+            }
             return Undefined;
         }
         match opcd & 0x0ff000f0 {
@@ -187,6 +196,10 @@ impl ArmInst {
             0x01200020 => return Bxj,
             0x01200070 => return Bkpt,
             0x01200030 => return BlxReg,
+            0x01000090 => return Swp,
+            0x01400090 => return Swpb,
+            0x01900090 => return Ldrex,
+            0x01800090 => return Strex,
             _ => {},
         }
         match opcd & 0x0fe000f0 {
@@ -430,7 +443,7 @@ impl ArmInst {
             ArmInst::LdrdImm        => Box::new(LsSignedImmBits(bits)) as Box<dyn xDisplay>,
             ArmInst::LdrbImm        => Box::new(LsImmBits(bits)) as Box<dyn xDisplay>,
             ArmInst::LdrsbImm       => Box::new(LsSignedImmBits(bits)) as Box<dyn xDisplay>,
-            ArmInst::LdrshImm       => Box::new(LsSignedRegBits(bits)) as Box<dyn xDisplay>,
+            ArmInst::LdrshImm       => Box::new(LsSignedImmBits(bits)) as Box<dyn xDisplay>,
             ArmInst::LdrReg         => Box::new(LsRegBits(bits)) as Box<dyn xDisplay>,
             ArmInst::LdrbReg        => Box::new(LsRegBits(bits)) as Box<dyn xDisplay>,
             ArmInst::LdrhReg        => Box::new(LsSignedRegBits(bits)) as Box<dyn xDisplay>,
@@ -488,11 +501,93 @@ impl ArmInst {
             ArmInst::Bx             => Box::new(BxBits(bits)) as Box<dyn xDisplay>,
             ArmInst::BlxReg         => Box::new(BxBits(bits)) as Box<dyn xDisplay>,
             ArmInst::Bxj            => Box::new(BxBits(bits)) as Box<dyn xDisplay>,
-            ArmInst::Svc            => Box::new(BranchBits(bits)) as Box<dyn xDisplay>,
+            ArmInst::Svc            => Box::new(SvcBits(bits)) as Box<dyn xDisplay>,
             ArmInst::Bkpt           => Box::new(BkptBits(bits)) as Box<dyn xDisplay>,
             ArmInst::BlxImm         => Box::new(BranchBits(bits)) as Box<dyn xDisplay>,
+            ArmInst::Swp            => Box::new(SwpBits(bits)) as Box<dyn xDisplay>,
+            ArmInst::Swpb           => Box::new(SwpBits(bits)) as Box<dyn xDisplay>,
+            ArmInst::Ldrex          => Box::new(LdrexBits(bits)) as Box<dyn xDisplay>,
+            ArmInst::Strex          => Box::new(StrexBits(bits)) as Box<dyn xDisplay>,
+            ArmInst::Clrex          => Box::new(ClrexBits(bits)) as Box<dyn xDisplay>,
             ArmInst::Undefined      => todo!(),
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::ArmInst;
+    use std::collections::BTreeSet;
+
+    /// Every variant which should be produced by the decoder.
+    /// `PldReg` and `PldImm` are left out, since decoding for them is
+    /// currently disabled.
+    const ALL: &[ArmInst] = {
+        use ArmInst::*;
+        &[
+            AndRegShiftReg, AdcRegShiftReg, MovRegShiftReg, OrrRegShiftReg,
+            EorRegShiftReg, RscRegShiftReg, MvnRegShiftReg, SbcRegShiftReg,
+            AddRegShiftReg, BicRegShiftReg, RsbRegShiftReg, SubRegShiftReg,
+            TeqRegShiftReg, CmnRegShiftReg, TstRegShiftReg, CmpRegShiftReg,
+            SbcReg, OrrReg, BicReg, AddReg, RscReg, EorReg, MvnReg, AdcReg,
+            SubReg, MovReg, AndReg, RsbReg, CmpReg, TstReg, CmnReg, TeqReg,
+            MovImm, AddImm, AdcImm, RsbImm, OrrImm, BicImm, SubImm, MvnImm,
+            AndImm, RscImm, EorImm, SbcImm, CmnImm, CmpImm, TstImm, TeqImm,
+            StrImm, StrhImm, StrdImm, StrbImm, StrReg, StrbReg, StrhReg, StrdReg,
+            LdrImm, LdrhImm, LdrdImm, LdrbImm, LdrsbImm, LdrshImm,
+            LdrReg, LdrbReg, LdrhReg, LdrdReg, LdrsbReg, LdrshReg,
+            Swp, Swpb, Ldrex, Strex, Clrex,
+            Qdadd, Qsub, Qadd, Qdsub, Smull, Umlal, Smlal, Umull, Mul, Mla,
+            Smulwb, Smlawb, Smlalbb, Smlabb, Smulbb,
+            Ldrbt, Strbt, Ldrt, Strt,
+            MovImmAlt, LdrbtAlt, StrbtAlt, LdrtAlt, StrtAlt,
+            Stm, Stmda, Ldmda, Ldmib, Ldmdb, Ldm, Stmdb, Stmib,
+            LdmRegUser, StmRegUser,
+            MsrImm, MsrReg, Mrs, Mcrr, Mrrc, Mrc, Mcr, Stc,
+            LdcImm, Clz,
+            B, BlImm, Bx, BlxReg, Bxj,
+            Svc, Bkpt,
+            BlxImm,
+        ]
+    };
+
+    /// Produce a spread of opcodes covering every combination of the bits
+    /// the decoder looks at (the condition, bits 27:20 and bits 7:4).
+    fn sample_opcodes() -> impl Iterator<Item = u32> {
+        const FILL: &[u32] = &[
+            0x0000_0000, 0x000f_ff00, 0x000f_0000, 0x0000_f000,
+            0x0000_0f00, 0x0000_000f, 0x000f_f00f, 0x000f_ff0f,
+        ];
+        [0xeu32, 0xf].into_iter().flat_map(|cond| {
+            (0..0x100u32).flat_map(move |hi| {
+                (0..0x10u32).flat_map(move |lo| {
+                    FILL.iter().map(move |fill| (cond << 28) | (hi << 20) | (lo << 4) | fill)
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn every_variant_is_decoded_and_displayable() {
+        let mut seen = BTreeSet::new();
+        for opcd in sample_opcodes() {
+            let inst = ArmInst::decode(opcd);
+            if inst == ArmInst::Undefined {
+                continue;
+            }
+            seen.insert(format!("{inst:?}"));
+            // Disassembly may fail for unimplemented formatters, but it
+            // must never panic.
+            let res = std::panic::catch_unwind(|| {
+                let _ = crate::bits::disassembly::disassmble_arm(opcd, 0);
+            });
+            assert!(res.is_ok(), "Disassembling {inst:?} ({opcd:08x}) panicked");
+        }
+        let missing: Vec<_> = ALL.iter()
+            .map(|inst| format!("{inst:?}"))
+            .filter(|name| !seen.contains(name))
+            .collect();
+        assert!(missing.is_empty(), "Variants never produced by the decoder: {missing:?}");
+    }
+}
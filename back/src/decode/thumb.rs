@@ -10,7 +10,7 @@ pub enum ThumbInst {
     AndReg, MovReg, SubReg, AddReg, CmpRegAlt, AddRegAlt, MovRegAlt,
     MovRegShiftReg,
 
-    Neg, AddImm, MovImm, SubImm, CmpImm, AddSpImm, SubSpImm,
+    Neg, AddImm, MovImm, SubImm, CmpImm, AddSpImm, AddPcImm, SubSpImm,
     AddSpImmAlt, AddImmAlt, SubImmAlt, 
 
     StrbReg, LdrhReg, LdrbReg, StrReg, StrhReg, LdrReg, LdrsbReg, LdrshReg,
@@ -49,14 +49,15 @@ impl std::fmt::Display for ThumbInst {
             ThumbInst::AddReg         => write!(f, "add "),
             ThumbInst::CmpRegAlt      => write!(f, "cmp "),
             ThumbInst::AddRegAlt      => write!(f, "add "),
-            ThumbInst::MovRegAlt      => write!(f, "mov "),
-            ThumbInst::MovRegShiftReg => write!(f, "mov "),
+            ThumbInst::MovRegAlt      => write!(f, ""),
+            ThumbInst::MovRegShiftReg => write!(f, ""),
             ThumbInst::Neg            => write!(f, "neg "),
             ThumbInst::AddImm         => write!(f, "add "),
             ThumbInst::MovImm         => write!(f, "mov "),
             ThumbInst::SubImm         => write!(f, "sub "),
             ThumbInst::CmpImm         => write!(f, "cmp "),
             ThumbInst::AddSpImm       => write!(f, "add sp"),
+            ThumbInst::AddPcImm       => write!(f, "adr "),
             ThumbInst::SubSpImm       => write!(f, "sub sp"),
             ThumbInst::AddSpImmAlt    => write!(f, "add sp"),
             ThumbInst::AddImmAlt      => write!(f, "add "),
@@ -160,6 +161,7 @@ impl ThumbInst {
             0x2000 => return MovImm,
             0x3000 => return AddImmAlt,
             0xa800 => return AddSpImm,
+            0xa000 => return AddPcImm,
             0x8000 => return StrhImm,
             0xc000 => return Stm,
             0x3800 => return SubImmAlt,
@@ -210,6 +212,7 @@ impl ThumbInst {
             ThumbInst::SubImm         => Box::new(AddSubImmBits(bits)) as Box<dyn xDisplay>,
             ThumbInst::CmpImm         => Box::new(CmpImmBits(bits)) as Box<dyn xDisplay>,
             ThumbInst::AddSpImm       => Box::new(MovImmBits(bits)) as Box<dyn xDisplay>,
+            ThumbInst::AddPcImm       => Box::new(MovImmBits(bits)) as Box<dyn xDisplay>,
             ThumbInst::SubSpImm       => Box::new(AddSubSpImmAltBits(bits)) as Box<dyn xDisplay>,
             ThumbInst::AddSpImmAlt    => Box::new(AddSubSpImmAltBits(bits)) as Box<dyn xDisplay>,
             ThumbInst::AddImmAlt      => Box::new(AddSubImmAltBits(bits)) as Box<dyn xDisplay>,
@@ -0,0 +1,647 @@
+//! A GDB remote serial protocol stub.
+//!
+//! The stub runs on its own thread and talks to GDB over TCP. Requests are
+//! forwarded to the emulator thread over a channel, which services them
+//! while the CPU is stopped (see [InterpBackend::run_debugger]).
+//!
+//! Registers use GDB's default ARM layout (r0-r15, f0-f7, fps, cpsr) in the
+//! target's byte order, so connect with `set endian big` and
+//! `target remote :<port>`.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+
+use ironic_core::bus::prim::MemDevice;
+use ironic_core::cpu::CpuRes;
+use ironic_core::cpu::psr::Psr;
+
+use crate::back::{with_bus_read, with_bus_write, BUS_LOCK_TIMEOUT};
+use crate::interp::InterpBackend;
+
+/// Number of CPU cycles to run between checking for an interrupt from GDB.
+const CONTINUE_SLICE: usize = 100_000;
+
+/// GDB's register number for the CPSR.
+const GDB_CPSR_REGNUM: usize = 25;
+
+/// Largest packet we tell GDB to send. Memory reads are limited so that the
+/// reply (two hex characters per byte) fits too.
+const MAX_PACKET_SIZE: usize = 0x1000;
+
+/// A request from the stub to the emulator thread.
+#[derive(Debug, PartialEq)]
+pub enum DebugRequest {
+    /// Read r0-r15 and the CPSR.
+    ReadRegs,
+    /// Write r0-r15 and the CPSR.
+    WriteRegs([u32; 17]),
+    ReadMem { addr: u32, len: usize },
+    WriteMem { addr: u32, data: Vec<u8> },
+    SetBreakpoint(u32),
+    ClearBreakpoint(u32),
+    /// List the tasks queued on the bus (`monitor tasks`).
+    PendingTasks,
+    Step,
+    Continue,
+    /// Stop a running CPU.
+    Interrupt,
+    /// Let the emulator run freely without the debugger.
+    Detach,
+    Kill,
+}
+
+/// Why the CPU stopped.
+#[derive(Debug, PartialEq)]
+pub enum StopReason {
+    /// Stopped on a breakpoint, or after a single step.
+    Trap,
+    /// Stopped by [DebugRequest::Interrupt].
+    Interrupt,
+    /// The guest exited with some status code.
+    Exited(i32),
+    /// Emulation halted and can't continue.
+    Halted,
+}
+
+/// A response from the emulator thread.
+#[derive(Debug, PartialEq)]
+pub enum DebugResponse {
+    Regs([u32; 17]),
+    Mem(Vec<u8>),
+    /// Target bus cycle and description of each pending bus task.
+    Tasks(Vec<(u64, String)>),
+    Ok,
+    Error,
+    Stopped(StopReason),
+}
+
+/// The emulator's side of the connection to a stub.
+pub struct DebugPort {
+    rx: Receiver<DebugRequest>,
+    tx: Sender<DebugResponse>,
+}
+
+/// The stub's side of the connection to the emulator.
+pub struct DebugLink {
+    tx: Sender<DebugRequest>,
+    rx: Receiver<DebugResponse>,
+}
+impl DebugLink {
+    /// Send a request and wait for the response.
+    pub fn request(&self, req: DebugRequest) -> anyhow::Result<DebugResponse> {
+        self.tx.send(req).map_err(|_| anyhow!("Emulator thread is gone"))?;
+        self.rx.recv().map_err(|_| anyhow!("Emulator thread is gone"))
+    }
+}
+
+/// Create a connected pair of [DebugPort] and [DebugLink].
+pub fn debug_channel() -> (DebugPort, DebugLink) {
+    let (req_tx, req_rx) = channel();
+    let (resp_tx, resp_rx) = channel();
+    (DebugPort { rx: req_rx, tx: resp_tx }, DebugLink { tx: req_tx, rx: resp_rx })
+}
+
+/// Debugger support on the emulator side.
+impl InterpBackend {
+    /// Service requests from the debugger until it detaches or kills the
+    /// emulator. The CPU only runs when the debugger asks it to.
+    pub fn run_debugger(&mut self) -> anyhow::Result<()> {
+        let Some(port) = self.debug_port.take() else {
+            bail!("No debugger is attached");
+        };
+        self.debugger_attached = true;
+        // Set when stopped on a BKPT instruction, which is stepped over when
+        // resuming (otherwise we'd never get past it).
+        let mut on_bkpt_inst = false;
+        while let Ok(req) = port.rx.recv() {
+            let resp = match req {
+                DebugRequest::ReadRegs => DebugResponse::Regs(self.debug_read_regs()),
+                DebugRequest::WriteRegs(regs) => {
+                    self.debug_write_regs(&regs);
+                    DebugResponse::Ok
+                },
+                DebugRequest::ReadMem { addr, len } => match self.debug_read_mem(addr, len) {
+                    Ok(data) => DebugResponse::Mem(data),
+                    Err(_) => DebugResponse::Error,
+                },
+                DebugRequest::WriteMem { addr, data } => match self.debug_write_mem(addr, &data) {
+                    Ok(_) => DebugResponse::Ok,
+                    Err(_) => DebugResponse::Error,
+                },
+                DebugRequest::SetBreakpoint(addr) => {
+                    self.breakpoints.insert(addr);
+                    DebugResponse::Ok
+                },
+                DebugRequest::ClearBreakpoint(addr) => {
+                    self.breakpoints.remove(&addr);
+                    DebugResponse::Ok
+                },
+                DebugRequest::PendingTasks => DebugResponse::Tasks(self.cpu.with_bus(|bus| bus.pending_tasks())),
+                DebugRequest::Step | DebugRequest::Continue => {
+                    if on_bkpt_inst {
+                        self.resume_from_breakpoint();
+                    } else {
+                        self.skip_breakpoint = Some(self.cpu.read_fetch_pc());
+                    }
+                    let step = matches!(req, DebugRequest::Step);
+                    let (reason, bkpt) = self.debug_resume(&port, step);
+                    on_bkpt_inst = bkpt;
+                    let done = matches!(reason, StopReason::Exited(_) | StopReason::Halted);
+                    let _ = port.tx.send(DebugResponse::Stopped(reason));
+                    if done {
+                        return Ok(());
+                    }
+                    continue;
+                },
+                // Already stopped
+                DebugRequest::Interrupt => continue,
+                DebugRequest::Detach => {
+                    let _ = port.tx.send(DebugResponse::Ok);
+                    break;
+                },
+                DebugRequest::Kill => {
+                    let _ = port.tx.send(DebugResponse::Ok);
+                    info!(target: "GDB", "Killed by the debugger");
+                    return Ok(());
+                },
+            };
+            let _ = port.tx.send(resp);
+        }
+        info!(target: "GDB", "Debugger detached, resuming execution");
+        self.debugger_attached = false;
+        self.breakpoints.clear();
+        if on_bkpt_inst {
+            self.resume_from_breakpoint();
+        }
+        while let CpuRes::StepOk = self.run_for(usize::MAX)? {}
+        Ok(())
+    }
+
+    /// Run until the CPU stops. Also returns true if the CPU stopped on a
+    /// BKPT instruction (rather than a breakpoint set by the debugger).
+    fn debug_resume(&mut self, port: &DebugPort, step: bool) -> (StopReason, bool) {
+        loop {
+            let res = match self.run_for(if step { 1 } else { CONTINUE_SLICE }) {
+                Ok(res) => res,
+                Err(reason) => CpuRes::HaltEmulation(reason),
+            };
+            match res {
+                CpuRes::StepOk if step => return (StopReason::Trap, false),
+                CpuRes::StepOk => {},
+                CpuRes::Breakpoint(_) => {
+                    let pc = self.cpu.read_fetch_pc();
+                    return (StopReason::Trap, !self.breakpoints.contains(&pc));
+                },
+                CpuRes::Watchpoint(_) => return (StopReason::Trap, false),
+                CpuRes::Semihosting => return (StopReason::Exited(self.exit_code.unwrap_or(0)), false),
+                CpuRes::HaltEmulation(reason) => {
+                    warn!(target: "GDB", "Emulation halted: {reason:#}");
+                    return (StopReason::Halted, false);
+                },
+                CpuRes::StepException(e) => {
+                    warn!(target: "GDB", "Emulation stopped on exception {e:?}");
+                    return (StopReason::Halted, false);
+                },
+            }
+            match port.rx.try_recv() {
+                Ok(DebugRequest::Interrupt) => return (StopReason::Interrupt, false),
+                Ok(req) => warn!(target: "GDB", "Ignoring {req:?} while running"),
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => return (StopReason::Interrupt, false),
+            }
+        }
+    }
+
+    fn debug_read_regs(&self) -> [u32; 17] {
+        let mut regs = [0; 17];
+        regs[..15].copy_from_slice(&self.cpu.reg.r);
+        regs[15] = self.cpu.read_fetch_pc();
+        regs[16] = self.cpu.reg.cpsr.0;
+        regs
+    }
+
+    fn debug_write_regs(&mut self, regs: &[u32; 17]) {
+        // Switch modes first, so that the banked registers are written
+        self.cpu.reg.write_cpsr(Psr(regs[16]));
+        self.cpu.reg.r.copy_from_slice(&regs[..15]);
+        self.cpu.write_exec_pc(regs[15]);
+    }
+
+    /// Read guest memory at some virtual address. The mask ROM can be read
+    /// too, but memory-mapped I/O can't (reads may have side effects).
+    fn debug_read_mem(&self, addr: u32, len: usize) -> anyhow::Result<Vec<u8>> {
+        use ironic_core::cpu::mmu::prim::{TLBReq, Access};
+        let mut data = vec![0; len];
+        for (idx, byte) in data.iter_mut().enumerate() {
+            let paddr = self.cpu.translate(TLBReq::new(addr.wrapping_add(idx as u32), Access::Debug))?;
+            with_bus_read(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| {
+                match bus.resolve_mem(paddr) {
+                    Some((MemDevice::MaskRom, off)) => *byte = bus.mrom.read::<u8>(off)?,
+                    Some(_) => bus.dma_read(paddr, std::slice::from_mut(byte))?,
+                    None => bail!("Can't read {paddr:08x} from the debugger"),
+                }
+                anyhow::Ok(())
+            })??;
+        }
+        Ok(data)
+    }
+
+    /// Write guest memory at some virtual address.
+    fn debug_write_mem(&mut self, addr: u32, data: &[u8]) -> anyhow::Result<()> {
+        use ironic_core::cpu::mmu::prim::{TLBReq, Access};
+        for (idx, byte) in data.iter().enumerate() {
+            let paddr = self.cpu.translate(TLBReq::new(addr.wrapping_add(idx as u32), Access::Debug))?;
+            with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.dma_write(paddr, std::slice::from_ref(byte)))??;
+        }
+        Ok(())
+    }
+}
+
+/// What to do after handling a packet.
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Send some reply.
+    Reply(String),
+    /// Resume the CPU, and reply once it stops.
+    Resume(DebugRequest),
+    /// Send some reply and close the connection.
+    Close(String),
+}
+
+/// GDB's side of the protocol.
+pub struct GdbStub {
+    link: DebugLink,
+}
+impl GdbStub {
+    pub fn new(link: DebugLink) -> Self {
+        Self { link }
+    }
+
+    /// Wait for GDB to connect on some port, then serve it until it detaches.
+    pub fn serve(&mut self, port: u16) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        info!(target: "GDB", "Waiting for GDB to connect on port {port}");
+        let (mut stream, peer) = listener.accept()?;
+        info!(target: "GDB", "GDB connected from {peer}");
+        stream.set_nodelay(true)?;
+        loop {
+            let Some(packet) = read_packet(&mut stream)? else {
+                info!(target: "GDB", "GDB disconnected");
+                let _ = self.link.request(DebugRequest::Detach);
+                return Ok(());
+            };
+            match self.handle_packet(&packet)? {
+                Action::Reply(reply) => write_packet(&mut stream, &reply)?,
+                Action::Resume(req) => {
+                    let reply = self.resume(&mut stream, req)?;
+                    let exited = reply.starts_with('W') || reply.starts_with('X');
+                    write_packet(&mut stream, &reply)?;
+                    if exited {
+                        return Ok(());
+                    }
+                },
+                Action::Close(reply) => {
+                    write_packet(&mut stream, &reply)?;
+                    return Ok(());
+                },
+            }
+        }
+    }
+
+    /// Resume the CPU, forwarding interrupts from GDB until it stops.
+    fn resume(&mut self, stream: &mut TcpStream, req: DebugRequest) -> anyhow::Result<String> {
+        self.link.tx.send(req).map_err(|_| anyhow!("Emulator thread is gone"))?;
+        stream.set_read_timeout(Some(Duration::from_millis(50)))?;
+        let reason = loop {
+            match self.link.rx.try_recv() {
+                Ok(DebugResponse::Stopped(reason)) => break reason,
+                Ok(resp) => bail!("Unexpected response {resp:?} while running"),
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => bail!("Emulator thread is gone"),
+            }
+            let mut buf = [0u8; 1];
+            match stream.read(&mut buf) {
+                Ok(1) if buf[0] == 0x03 => {
+                    let _ = self.link.tx.send(DebugRequest::Interrupt);
+                },
+                Ok(0) => bail!("GDB disconnected while running"),
+                _ => {},
+            }
+        };
+        stream.set_read_timeout(None)?;
+        Ok(stop_reply(&reason))
+    }
+
+    /// Handle a single packet from GDB.
+    fn handle_packet(&mut self, packet: &str) -> anyhow::Result<Action> {
+        use Action::*;
+        let (cmd, args) = packet.split_at_checked(packet.len().min(1)).unwrap_or(("", packet));
+        Ok(match cmd {
+            "?" => Reply("S05".to_owned()),
+            "q" if args.starts_with("Supported") => Reply(format!("PacketSize={MAX_PACKET_SIZE:x}")),
+            "q" if args.starts_with("Attached") => Reply("1".to_owned()),
+            "q" if args.starts_with("Rcmd,") => {
+                let Some(cmd) = decode_hex(&args[5..]).and_then(|x| String::from_utf8(x).ok()) else {
+                    return Ok(Reply("E01".to_owned()));
+                };
+                Reply(encode_hex(self.monitor_command(cmd.trim())?.as_bytes()))
+            },
+            "H" => Reply("OK".to_owned()),
+            "g" => match self.link.request(DebugRequest::ReadRegs)? {
+                DebugResponse::Regs(regs) => Reply(encode_regs(&regs)),
+                _ => Reply("E01".to_owned()),
+            },
+            "G" => match decode_regs(args) {
+                Some(regs) => Reply(self.ok_or_error(DebugRequest::WriteRegs(regs))?),
+                None => Reply("E01".to_owned()),
+            },
+            "p" => {
+                let Ok(regnum) = usize::from_str_radix(args, 16) else {
+                    return Ok(Reply("E01".to_owned()));
+                };
+                match self.link.request(DebugRequest::ReadRegs)? {
+                    DebugResponse::Regs(regs) => Reply(encode_reg(&regs, regnum)),
+                    _ => Reply("E01".to_owned()),
+                }
+            },
+            "m" => {
+                let Some((addr, len)) = parse_addr_len(args).filter(|&(_, len)| len <= MAX_PACKET_SIZE / 2) else {
+                    return Ok(Reply("E01".to_owned()));
+                };
+                match self.link.request(DebugRequest::ReadMem { addr, len })? {
+                    DebugResponse::Mem(data) => Reply(encode_hex(&data)),
+                    _ => Reply("E01".to_owned()),
+                }
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(addr_len, hex)| {
+                    let (addr, len) = parse_addr_len(addr_len)?;
+                    let data = decode_hex(hex)?;
+                    (data.len() == len).then_some((addr, data))
+                });
+                match parsed {
+                    Some((addr, data)) => Reply(self.ok_or_error(DebugRequest::WriteMem { addr, data })?),
+                    None => Reply("E01".to_owned()),
+                }
+            },
+            // Software and hardware breakpoints are handled the same way
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next();
+                let addr = parts.next().and_then(|x| u32::from_str_radix(x, 16).ok());
+                match (kind, addr) {
+                    (Some("0") | Some("1"), Some(addr)) => {
+                        let req = if cmd == "Z" {
+                            DebugRequest::SetBreakpoint(addr)
+                        } else {
+                            DebugRequest::ClearBreakpoint(addr)
+                        };
+                        Reply(self.ok_or_error(req)?)
+                    },
+                    _ => Reply(String::new()),
+                }
+            },
+            "s" => Resume(DebugRequest::Step),
+            "c" => Resume(DebugRequest::Continue),
+            "D" => {
+                self.link.request(DebugRequest::Detach)?;
+                Close("OK".to_owned())
+            },
+            "k" => {
+                self.link.request(DebugRequest::Kill)?;
+                Close("OK".to_owned())
+            },
+            // An empty reply means the packet isn't supported
+            _ => Reply(String::new()),
+        })
+    }
+
+    /// Run a `monitor` command, returning the output for GDB to print.
+    fn monitor_command(&self, cmd: &str) -> anyhow::Result<String> {
+        Ok(match cmd {
+            "tasks" => match self.link.request(DebugRequest::PendingTasks)? {
+                DebugResponse::Tasks(tasks) if tasks.is_empty() => "No pending tasks\n".to_owned(),
+                DebugResponse::Tasks(tasks) => tasks.iter()
+                    .map(|(cycle, desc)| format!("{cycle:>12} {desc}\n"))
+                    .collect(),
+                resp => format!("Unexpected response {resp:?}\n"),
+            },
+            _ => format!("Unknown monitor command '{cmd}' (supported: tasks)\n"),
+        })
+    }
+
+    fn ok_or_error(&self, req: DebugRequest) -> anyhow::Result<String> {
+        Ok(match self.link.request(req)? {
+            DebugResponse::Ok => "OK".to_owned(),
+            _ => "E01".to_owned(),
+        })
+    }
+}
+
+/// Format the stop reply packet for some reason.
+fn stop_reply(reason: &StopReason) -> String {
+    match reason {
+        StopReason::Trap => "S05".to_owned(),
+        StopReason::Interrupt => "S02".to_owned(),
+        StopReason::Exited(code) => format!("W{:02x}", *code as u8),
+        StopReason::Halted => "X06".to_owned(),
+    }
+}
+
+/// Encode r0-r15 and the CPSR for a `g` packet (with the unused FPA
+/// registers in between).
+fn encode_regs(regs: &[u32; 17]) -> String {
+    let mut res = String::new();
+    for reg in &regs[..16] {
+        res.push_str(&format!("{reg:08x}"));
+    }
+    // f0-f7 (12 bytes each) and fps
+    res.push_str(&"0".repeat(8 * 24 + 8));
+    res.push_str(&format!("{:08x}", regs[16]));
+    res
+}
+
+/// Decode the registers in a `G` packet.
+fn decode_regs(hex: &str) -> Option<[u32; 17]> {
+    let word = |idx: usize| -> Option<u32> {
+        u32::from_str_radix(hex.get(idx * 8..idx * 8 + 8)?, 16).ok()
+    };
+    let mut regs = [0; 17];
+    for (idx, reg) in regs[..16].iter_mut().enumerate() {
+        *reg = word(idx)?;
+    }
+    // Skip over f0-f7 and fps
+    regs[16] = word(16 + 8 * 3 + 1)?;
+    Some(regs)
+}
+
+/// Encode a single register for a `p` packet.
+fn encode_reg(regs: &[u32; 17], regnum: usize) -> String {
+    match regnum {
+        0..=15 => format!("{:08x}", regs[regnum]),
+        16..=23 => "0".repeat(24),
+        24 => "0".repeat(8),
+        GDB_CPSR_REGNUM => format!("{:08x}", regs[16]),
+        _ => "E01".to_owned(),
+    }
+}
+
+fn parse_addr_len(s: &str) -> Option<(u32, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((u32::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.is_ascii() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|idx| u8::from_str_radix(&s[idx..idx + 2], 16).ok()).collect()
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b))
+}
+
+/// Read the next packet from GDB, acknowledging it. Returns [None] when the
+/// connection is closed.
+fn read_packet(stream: &mut TcpStream) -> anyhow::Result<Option<String>> {
+    let mut byte = [0u8; 1];
+    loop {
+        // Skip acks (and interrupts, since we're already stopped)
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut cs = [0u8; 2];
+        stream.read_exact(&mut cs)?;
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let expected = std::str::from_utf8(&cs).ok().and_then(|x| u8::from_str_radix(x, 16).ok());
+        if expected == Some(checksum(&data)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+        warn!(target: "GDB", "Bad checksum on packet {data:?}");
+        stream.write_all(b"-")?;
+    }
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> anyhow::Result<()> {
+    stream.write_all(format!("${data}#{:02x}", checksum(data)).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use super::*;
+
+    #[test]
+    fn register_packets_round_trip() {
+        let mut regs = [0; 17];
+        for (idx, reg) in regs.iter_mut().enumerate() {
+            *reg = 0x1000_0000 + idx as u32;
+        }
+        let hex = encode_regs(&regs);
+        assert_eq!(hex.len(), (16 * 4 + 8 * 12 + 4 + 4) * 2);
+        assert_eq!(decode_regs(&hex), Some(regs));
+        assert_eq!(encode_reg(&regs, 15), "1000000f");
+        assert_eq!(encode_reg(&regs, GDB_CPSR_REGNUM), "10000010");
+        assert_eq!(checksum("OK"), 0x9a);
+    }
+
+    #[test]
+    fn malformed_packets_are_rejected() {
+        let (_port, link) = debug_channel();
+        let mut stub = GdbStub::new(link);
+        // Too long for a reply, so never sent on to the emulator
+        assert_eq!(stub.handle_packet("m0,ffffffffffff").unwrap(), Action::Reply("E01".to_owned()));
+        assert_eq!(stub.handle_packet("m0,801").unwrap(), Action::Reply("E01".to_owned()));
+        assert_eq!(stub.handle_packet("M0,2:a\u{e9}0").unwrap(), Action::Reply("E01".to_owned()));
+        assert_eq!(stub.handle_packet("\u{e9}").unwrap(), Action::Reply(String::new()));
+        assert_eq!(decode_hex("a\u{e9}0"), None);
+        assert_eq!(decode_hex("beef"), Some(vec![0xbe, 0xef]));
+    }
+
+    #[test]
+    fn breakpoint_and_step_from_stub() {
+        const PROGRAM: [u32; 4] = [
+            0xe3a0_0001, // mov r0, #1
+            0xe3a0_1002, // mov r1, #2
+            0xe3a0_2003, // mov r2, #3
+            0xe120_0070, // bkpt #0
+        ];
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        for (idx, word) in PROGRAM.iter().enumerate() {
+            bus.write().write32(0x1000 + idx as u32 * 4, *word).unwrap();
+        }
+        let (port, link) = debug_channel();
+        let mut back = InterpBackend::new(bus, None, false);
+        back.cpu.write_exec_pc(0x1000);
+        back.debug_port = Some(port);
+        let emu = std::thread::spawn(move || {
+            back.run_debugger().unwrap();
+            back.cpu.reg.r
+        });
+
+        let mut stub = GdbStub::new(link);
+        let resume = |stub: &mut GdbStub, packet: &str| {
+            let Action::Resume(req) = stub.handle_packet(packet).unwrap() else { panic!() };
+            match stub.link.request(req).unwrap() {
+                DebugResponse::Stopped(reason) => stop_reply(&reason),
+                resp => panic!("{resp:?}"),
+            }
+        };
+        let pc = |stub: &mut GdbStub| match stub.handle_packet("pf").unwrap() {
+            Action::Reply(x) => x,
+            action => panic!("{action:?}"),
+        };
+
+        assert_eq!(stub.handle_packet("Z0,1008,4").unwrap(), Action::Reply("OK".to_owned()));
+        assert_eq!(resume(&mut stub, "c"), "S05");
+        assert_eq!(pc(&mut stub), "00001008");
+        // Stepping moves past the breakpoint we're stopped on
+        assert_eq!(resume(&mut stub, "s"), "S05");
+        assert_eq!(pc(&mut stub), "0000100c");
+        // Stops on the BKPT instruction, then skips it
+        assert_eq!(resume(&mut stub, "c"), "S05");
+        assert_eq!(pc(&mut stub), "0000100c");
+        assert_eq!(stub.handle_packet("m1000,4").unwrap(), Action::Reply("e3a00001".to_owned()));
+        assert_eq!(stub.handle_packet("M2000,2:beef").unwrap(), Action::Reply("OK".to_owned()));
+        assert_eq!(stub.handle_packet("m2000,2").unwrap(), Action::Reply("beef".to_owned()));
+        let monitor = |stub: &mut GdbStub, cmd: &str| match stub.handle_packet(&format!("qRcmd,{}", encode_hex(cmd.as_bytes()))).unwrap() {
+            Action::Reply(x) => String::from_utf8(decode_hex(&x).unwrap()).unwrap(),
+            action => panic!("{action:?}"),
+        };
+        assert_eq!(monitor(&mut stub, "tasks"), "No pending tasks\n");
+        assert!(monitor(&mut stub, "bogus").starts_with("Unknown monitor command"));
+        assert_eq!(stub.handle_packet("k").unwrap(), Action::Close("OK".to_owned()));
+
+        let regs = emu.join().unwrap();
+        assert_eq!(&regs[..3], &[1, 2, 3]);
+    }
+}
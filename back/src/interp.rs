@@ -6,12 +6,16 @@ pub mod dispatch;
 pub mod lut;
 
 use anyhow::anyhow;
+use bincode::{Decode, Encode};
 use gimli::{BigEndian, read::*};
-use log::{error, info};
+use log::{error, info, warn};
 use parking_lot::RwLock;
 
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 extern crate elf;
@@ -19,30 +23,62 @@ extern crate elf;
 use crate::back::*;
 use crate::interp::lut::*;
 use crate::interp::dispatch::DispatchRes;
+use crate::symbols::SymbolMap;
 
 use crate::decode::arm::*;
 use crate::decode::thumb::*;
 
 use ironic_core::bus::*;
-use ironic_core::cpu::{Cpu, CpuRes};
-use ironic_core::cpu::reg::Reg;
+use ironic_core::bus::watch::WatchHit;
+use ironic_core::cpu::{Cpu, CpuRes, CpuState};
+use ironic_core::cpu::reg::{Reg, RegisterFile};
 use ironic_core::cpu::excep::ExceptionType;
 
+/// Semihosting operation numbers (passed in r0).
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+/// Semihosting SYS_EXIT reason code for a normal application exit.
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// Where boot0 leaves boot1: the start of SRAM, which is run from its
+/// mirror at [BOOT1_ENTRY].
+const BOOT1_ADDR: u32 = ironic_core::dev::SRAM_BASE_A;
+const BOOT1_ENTRY: u32 = ironic_core::dev::SRAM_BASE_C;
+const BOOT1_MAX_SIZE: usize = ironic_core::dev::SRM0_SIZE as usize;
+/// Where boot1 leaves boot2 in MEM2. The image starts with a header, whose
+/// first word is its length; the ELF loader stub follows it.
+const BOOT2_ADDR: u32 = 0x1010_0000;
+
 static PPC_EARLY_ON: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
 
+/// A known boot1 revision, identified by the boot1 hash fused into OTP.
+pub struct Boot1Version {
+    /// The first five words of OTP (the boot1 SHA-1 hash).
+    pub otp_hash: [u32; 5],
+    /// The revision letter (or a description, for unprogrammed OTP).
+    pub name: &'static str,
+}
+
 /// A list of known boot1 hashes in OTP
 /// https://wiibrew.org/wiki/Boot1
-static BOOT1_VERSIONS: &[([u32;5], &str)] = &[
-    ([0x0, 0x0, 0x0, 0x0, 0x0], "? - OTP NOT FACTORY PROGRAMMED!"),
-    ([0xb30c32b9, 0x62c7cd08, 0xabe33d01, 0x5b9b8b1d, 0xb1097544], "a"),
-    ([0xef3ef781, 0x09608d56, 0xdf5679a6, 0xf92e13f7, 0x8bbddfdf], "b"),
-    ([0xd220c8a4, 0x86c631d0, 0xdf5adb31, 0x96ecbc66, 0x8780cc8d], "c"),
-    ([0xf793068a, 0x09e80986, 0xe2a023c0, 0xc23f0614, 0x0ed16974], "d"),
+pub static BOOT1_VERSIONS: &[Boot1Version] = &[
+    Boot1Version { otp_hash: [0x0, 0x0, 0x0, 0x0, 0x0], name: "? - OTP NOT FACTORY PROGRAMMED!" },
+    Boot1Version { otp_hash: [0xb30c32b9, 0x62c7cd08, 0xabe33d01, 0x5b9b8b1d, 0xb1097544], name: "a" },
+    Boot1Version { otp_hash: [0xef3ef781, 0x09608d56, 0xdf5679a6, 0xf92e13f7, 0x8bbddfdf], name: "b" },
+    Boot1Version { otp_hash: [0xd220c8a4, 0x86c631d0, 0xdf5adb31, 0x96ecbc66, 0x8780cc8d], name: "c" },
+    Boot1Version { otp_hash: [0xf793068a, 0x09e80986, 0xe2a023c0, 0xc23f0614, 0x0ed16974], name: "d" },
 ];
 
+/// Identify a boot1 revision from the boot1 hash stored in OTP.
+pub fn identify_boot1(otp_hash: [u32; 5]) -> Option<&'static str> {
+    BOOT1_VERSIONS.iter().find(|v| v.otp_hash == otp_hash).map(|v| v.name)
+}
+
 
 /// Current stage in the platform's boot process.
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
 pub enum BootStatus { 
     /// Execution in the mask ROM.
     Boot0, 
@@ -59,6 +95,72 @@ pub enum BootStatus {
     UserKernelStub, 
     UserKernel, 
 }
+impl std::str::FromStr for BootStatus {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "boot0" => Ok(BootStatus::Boot0),
+            "boot1" => Ok(BootStatus::Boot1),
+            "boot2-stub" => Ok(BootStatus::Boot2Stub),
+            "boot2" => Ok(BootStatus::Boot2),
+            "ios-kernel" => Ok(BootStatus::IOSKernel),
+            "user-kernel-stub" => Ok(BootStatus::UserKernelStub),
+            "user-kernel" => Ok(BootStatus::UserKernel),
+            _ => Err(anyhow!("Unknown boot stage \"{s}\", expected one of boot0, boot1, \
+                boot2-stub, boot2, ios-kernel, user-kernel-stub, user-kernel")),
+        }
+    }
+}
+
+/// Rules used to validate (and start) a custom kernel ELF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelProfile {
+    /// An IOS-style kernel, which must be an EXEC type ELF with the entry
+    /// point at the reset vector.
+    Ios,
+    /// A boot2-style image. EXEC or DYN type ELFs are allowed, and the entry
+    /// point may be anywhere inside a loadable segment. DYN type ELFs are
+    /// loaded at their link address (no relocations are applied).
+    Boot2,
+    /// Any 32-bit ARM ELF, starting at its entry point.
+    Raw,
+}
+impl std::str::FromStr for KernelProfile {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "ios" => Ok(KernelProfile::Ios),
+            "boot2" => Ok(KernelProfile::Boot2),
+            "raw" => Ok(KernelProfile::Raw),
+            _ => Err(anyhow!("Unknown kernel profile \"{s}\", expected one of ios, boot2, raw")),
+        }
+    }
+}
+
+/// A closure run after the instruction at some PC retires. The bus is held
+/// while hooks run, so they can get at it with [Cpu::with_bus].
+pub type PcHook = Box<dyn FnMut(&mut Cpu) + Send>;
+
+/// A closure run on each taken branch, with the source and destination PC.
+/// The destination of every taken branch is the start of a basic block, so
+/// coverage tools can use this to reconstruct which code was executed.
+pub type BranchHook = Box<dyn FnMut(u32, u32) + Send>;
+
+/// A closure which receives each line of semihosting output (without the
+/// trailing newline).
+pub type SvcSink = Box<dyn FnMut(&str) + Send>;
+
+/// Create an [SvcSink] which writes each line of semihosting output to a
+/// file, without any log formatting.
+pub fn svc_file_sink(path: impl AsRef<std::path::Path>) -> anyhow::Result<SvcSink> {
+    use std::io::Write;
+    let mut file = std::fs::File::create(path)?;
+    Ok(Box::new(move |line| {
+        if let Err(e) = writeln!(file, "{line}").and_then(|_| file.flush()) {
+            error!(target: "SVC", "Failed to write semihosting output: {e}");
+        }
+    }))
+}
 
 /// Backend for interpreting-style emulation. 
 ///
@@ -72,7 +174,6 @@ pub enum BootStatus {
 /// maybe at some point it will become more efficient to let dispatched
 /// instructions return some hint to the backend (requesting that a bus cycle
 /// should be completed before the next instruction).
-
 pub struct InterpBackend {
     /// Reference to a bus (attached to memories and devices).
     pub bus: Arc<RwLock<Bus>>,
@@ -87,10 +188,69 @@ pub struct InterpBackend {
 
     /// Buffer for semi-hosting debug writes.
     pub svc_buf: String,
+    /// Where semihosting output goes. When unset, it's logged.
+    pub svc_sink: Option<SvcSink>,
+    /// Exit code requested by the guest with a semihosting exit call.
+    pub exit_code: Option<i32>,
     /// Current stage in the platform boot process.
     pub boot_status: BootStatus,
+    /// Sent each new [BootStatus] on a transition. See
+    /// [InterpBackend::with_boot_status_sender].
+    boot_status_tx: Option<std::sync::mpsc::Sender<BootStatus>>,
+    /// Stop emulation when this boot stage is entered.
+    pub stop_at_stage: Option<BootStatus>,
     pub custom_kernel: Option<String>,
-    debugger_attached: bool,
+    /// Start from this boot1 image instead of running boot0.
+    pub boot1: Option<String>,
+    /// Start from this boot2 image instead of running boot0 and boot1.
+    pub boot2: Option<String>,
+    /// Which set of checks to use when loading the custom kernel.
+    pub kernel_profile: KernelProfile,
+    /// When set, breakpoint instructions stop execution and return to the
+    /// caller. Otherwise, they halt emulation.
+    pub debugger_attached: bool,
+    /// Instrumentation hooks, keyed on PC.
+    pc_hooks: HashMap<u32, Vec<PcHook>>,
+    /// Optional recorder for taken branches.
+    branch_hook: Option<BranchHook>,
+    /// Skip over simple countdown delay loops instead of interpreting them.
+    pub fast_forward_loops: bool,
+    /// Addresses where execution stops (e.g. set by a debugger).
+    pub breakpoints: HashSet<u32>,
+    /// Ignore the breakpoint at this address for the next step, so that
+    /// execution can resume after stopping on it.
+    pub(crate) skip_breakpoint: Option<u32>,
+    /// Connection to a debugger stub. See [crate::gdb].
+    pub debug_port: Option<crate::gdb::DebugPort>,
+    /// Set from another thread (e.g. a Ctrl-C handler) to stop emulation
+    /// before the next instruction.
+    pub stop_requested: Arc<AtomicBool>,
+    /// Stop emulation once this many CPU cycles have elapsed.
+    pub max_cycles: Option<usize>,
+    /// Set when nothing but the emulator thread uses the bus (without
+    /// PPC HLE or a debugger). The bus is then kept locked while running,
+    /// instead of being locked for every access.
+    pub single_threaded: bool,
+    /// Optional exporter for an execution trace. See [crate::trace].
+    pub trace: Option<crate::trace::TraceExporter>,
+    /// Symbols used to annotate disassembled branch targets.
+    pub symbols: Option<SymbolMap>,
+    /// Restore this save state before running. See [InterpBackend::load_state].
+    pub load_state_from: Option<PathBuf>,
+    /// Write a save state here when emulation stops.
+    pub save_state_on_exit: Option<PathBuf>,
+    /// Halt instead of taking the undefined instruction exception when an
+    /// opcode fails to decode (IOS syscalls are still let through).
+    pub halt_on_undef: bool,
+    /// Periodically log the emulation speed. See [crate::perf].
+    pub perf: Option<crate::perf::PerfReporter>,
+    /// Set while the bus has any watchpoints.
+    watching: bool,
+    /// Watchpoint hit by the instruction executed in the last step.
+    watch_hit: Option<WatchHit>,
+    /// CPU cycles left before the current run has to stop, which a
+    /// fast-forwarded delay loop mustn't go past.
+    cycles_left: usize,
 }
 impl InterpBackend {
     pub fn new(bus: Arc<RwLock<Bus>>, custom_kernel: Option<String>, ppc_early_on: bool) -> Self {
@@ -99,24 +259,142 @@ impl InterpBackend {
         }
         InterpBackend {
             svc_buf: String::new(),
+            svc_sink: None,
+            exit_code: None,
             cpu: Cpu::new(bus.clone()),
             boot_status: BootStatus::Boot0,
+            boot_status_tx: None,
+            stop_at_stage: None,
             cpu_cycle: 0,
             bus_cycle: 0,
             bus,
             custom_kernel,
+            boot1: None,
+            boot2: None,
+            kernel_profile: KernelProfile::Ios,
             debugger_attached: false,
+            pc_hooks: HashMap::new(),
+            branch_hook: None,
+            fast_forward_loops: false,
+            breakpoints: HashSet::new(),
+            skip_breakpoint: None,
+            debug_port: None,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            max_cycles: None,
+            single_threaded: false,
+            trace: None,
+            symbols: None,
+            load_state_from: None,
+            save_state_on_exit: None,
+            halt_on_undef: false,
+            perf: None,
+            watching: false,
+            watch_hit: None,
+            cycles_left: 0,
+        }
+    }
+
+    /// Attach a closure which runs each time the instruction at `pc` retires.
+    /// The closure may inspect or modify the CPU state (e.g. to force a
+    /// return value in r0).
+    pub fn add_pc_hook(&mut self, pc: u32, hook: PcHook) {
+        self.pc_hooks.entry(pc).or_default().push(hook);
+    }
+
+    /// Send each new [BootStatus] to `tx` when the boot stage changes, e.g.
+    /// for a test harness to wait for the kernel to start.
+    pub fn with_boot_status_sender(mut self, tx: std::sync::mpsc::Sender<BootStatus>) -> Self {
+        self.boot_status_tx = Some(tx);
+        self
+    }
+
+    /// Attach a closure which runs each time a branch is taken, replacing
+    /// any previously attached branch hook.
+    pub fn set_branch_hook(&mut self, hook: BranchHook) {
+        self.branch_hook = Some(hook);
+    }
+
+    /// Run any hooks attached to the given PC.
+    fn run_pc_hooks(&mut self, pc: u32) {
+        if let Some(hooks) = self.pc_hooks.get_mut(&pc) {
+            // Hold the bus while the hooks run, so that their accesses
+            // through the CPU don't each take the lock again
+            let held = self.cpu.holds_bus();
+            self.cpu.hold_bus();
+            for hook in hooks.iter_mut() {
+                hook(&mut self.cpu);
+            }
+            if !held {
+                self.cpu.release_bus();
+            }
         }
     }
 }
 
+/// Backend state kept in a save state, along with the bus.
+#[derive(Encode, Decode)]
+struct BackendState {
+    cpu: CpuState,
+    cpu_cycle: usize,
+    bus_cycle: usize,
+    boot_status: BootStatus,
+    svc_buf: String,
+}
+
+/// Save states.
+impl InterpBackend {
+    /// Write the state of the CPU and bus to a file. See
+    /// [ironic_core::bus::state] for what isn't included.
+    pub fn save_state(&self, path: &Path) -> anyhow::Result<()> {
+        let state = BackendState {
+            cpu: self.cpu.save_state(),
+            cpu_cycle: self.cpu_cycle,
+            bus_cycle: self.bus_cycle,
+            boot_status: self.boot_status,
+            svc_buf: self.svc_buf.clone(),
+        };
+        self.cpu.with_bus(|bus| bus.save_state(path, &state))
+    }
+
+    /// Restore the state of the CPU and bus from a file written by
+    /// [InterpBackend::save_state].
+    pub fn load_state(&mut self, path: &Path) -> anyhow::Result<()> {
+        let state: BackendState = self.cpu.with_bus_mut(|bus| bus.load_state(path))?;
+        self.cpu.load_state(state.cpu);
+        self.cpu_cycle = state.cpu_cycle;
+        self.bus_cycle = state.bus_cycle;
+        self.boot_status = state.boot_status;
+        self.svc_buf = state.svc_buf;
+        self.skip_breakpoint = None;
+        info!(target: "Other", "Resuming at pc={:08x}", self.cpu.read_fetch_pc());
+        Ok(())
+    }
+}
+
 impl InterpBackend {
     /// Check if we need to update the current boot stage.
     pub fn update_boot_status(&mut self) {
+        let prev = self.boot_status;
+        self.detect_boot_status();
+        if self.boot_status != prev {
+            self.notify_boot_status();
+        }
+    }
+
+    /// Tell anyone listening about the current boot stage.
+    fn notify_boot_status(&mut self) {
+        // The receiver may have gone away, and that's fine
+        if let Some(tx) = self.boot_status_tx.as_ref() && tx.send(self.boot_status).is_err() {
+            self.boot_status_tx = None;
+        }
+    }
+
+    fn detect_boot_status(&mut self) {
         match self.boot_status {
             BootStatus::Boot0 => {
                 if self.cpu.read_fetch_pc() == 0xfff0_0000 {
-                    if let Some(bus) = self.bus.try_read_for(Duration::new(1,0)) { // Try to detect boot1 version
+                    // Try to detect boot1 version
+                    let identify = |bus: &Bus| {
                         let boot1_otp_hash =
                         [
                             bus.hlwd.otp.read(0),
@@ -125,17 +403,17 @@ impl InterpBackend {
                             bus.hlwd.otp.read(3),
                             bus.hlwd.otp.read(4),
                         ];
-                        let mut version = "? (unknown)";
-                        for known_versions in BOOT1_VERSIONS {
-                            if boot1_otp_hash == known_versions.0 {
-                                version = known_versions.1;
-                                break;
-                            }
-                        }
-                        info!(target: "Other", "Entered boot1. Version: boot1{version}");
-                    }
-                    else { // Couldn't get bus -> no problem skip it.
-                        info!(target: "Other", "Entered boot1");
+                        identify_boot1(boot1_otp_hash).unwrap_or("? (unknown)")
+                    };
+                    let detected = if self.single_threaded {
+                        Ok(self.cpu.with_bus(identify))
+                    } else {
+                        with_bus_read(&self.bus, Some(Duration::new(1,0)), identify)
+                    };
+                    match detected {
+                        Ok(version) => info!(target: "Other", "Entered boot1. Version: boot1{version}"),
+                        // Couldn't get bus -> no problem skip it.
+                        Err(_) => info!(target: "Other", "Entered boot1"),
                     }
                     self.boot_status = BootStatus::Boot1;
                 }
@@ -174,38 +452,75 @@ impl InterpBackend {
         }
     }
 
-    /// Write semihosting debug strings to stdout.
-    pub fn svc_read(&mut self) -> anyhow::Result<()> {
-        use ironic_core::cpu::mmu::prim::{TLBReq, Access};
+    /// Handle a semihosting call (SVC 0xAB).
+    ///
+    /// The operation number is in r0, and r1 holds the argument. Returns
+    /// `Some(code)` when the guest has asked to exit with some status code.
+    pub fn svc_read(&mut self) -> anyhow::Result<Option<i32>> {
+        match self.cpu.reg.r[0] {
+            SYS_WRITEC => {
+                let mut c = [0u8; 1];
+                let paddr = self.svc_paddr(self.cpu.reg.r[1])?;
+                self.cpu.with_bus(|bus| bus.dma_read(paddr, &mut c))?;
+                self.svc_write(&String::from_utf8_lossy(&c));
+            },
+            SYS_WRITE0 => {
+                // Pull the buffer out of guest memory
+                // Official code only sends 15 chars + null byte at a time
+                // Probably a limitation of their early semihosting hardware
+                let mut line_buf = [0u8; 16];
+                let paddr = self.svc_paddr(self.cpu.reg.r[1])?;
+                self.cpu.with_bus(|bus| bus.dma_read(paddr, &mut line_buf))?;
+                let s = std::str::from_utf8(&line_buf)?
+                    .trim_matches(char::from(0));
+                self.svc_write(s);
+            },
+            SYS_EXIT => {
+                // r1 is a reason code. Anything other than a normal
+                // application exit is reported as a failure.
+                let reason = self.cpu.reg.r[1];
+                let code = if reason == ADP_STOPPED_APPLICATION_EXIT { 0 } else { 1 };
+                info!(target: "SVC", "Guest called SYS_EXIT (reason={reason:08x})");
+                return Ok(Some(code));
+            },
+            SYS_EXIT_EXTENDED => {
+                // r1 points to a block with the reason code and exit status
+                let mut block = [0u8; 8];
+                let paddr = self.svc_paddr(self.cpu.reg.r[1])?;
+                self.cpu.with_bus(|bus| bus.dma_read(paddr, &mut block))?;
+                let reason = u32::from_be_bytes(block[0..4].try_into()?);
+                let status = u32::from_be_bytes(block[4..8].try_into()?);
+                info!(target: "SVC", "Guest called SYS_EXIT_EXTENDED (reason={reason:08x}, status={status})");
+                let code = if reason == ADP_STOPPED_APPLICATION_EXIT { status as i32 } else { 1 };
+                return Ok(Some(code));
+            },
+            op => {
+                warn!(target: "SVC", "Unimplemented semihosting operation {op:#x}");
+            },
+        }
+        Ok(None)
+    }
 
-        // On the SVC calls, r1 should contain a pointer to some buffer.
+    /// Resolve a pointer passed to a semihosting call.
+    fn svc_paddr(&self, vaddr: u32) -> anyhow::Result<u32> {
+        use ironic_core::cpu::mmu::prim::{TLBReq, Access};
         // They might be virtual addresses, so we need to do an out-of-band
         // request to MMU code in order to resolve the actual location.
-        let paddr = match self.cpu.translate(
-            TLBReq::new(self.cpu.reg.r[1], Access::Debug)
-        ) {
-            Ok(val) => val,
-            Err(reason) => return Err(reason),
-        };
-
-        // Pull the buffer out of guest memory
-        // Official code only sends 15 chars + null byte at a time
-        // Probably a limitation of their early semihosting hardware
-        // We buffer that internally until we see a newline, that's our cue to print
-        let mut line_buf = [0u8; 16];
-        self.bus.read().dma_read(paddr, &mut line_buf)?;
+        self.cpu.translate(TLBReq::new(vaddr, Access::Debug))
+    }
 
-        let s = std::str::from_utf8(&line_buf)?
-            .trim_matches(char::from(0));
+    /// Buffer semihosting output, printing it once we see a newline.
+    fn svc_write(&mut self, s: &str) {
         self.svc_buf += s;
-
         if let Some(idx) = self.svc_buf.find('\n') {
             let string: String = self.svc_buf.chars()
                 .take(idx).collect();
-            info!(target: "SVC", "{string}");
+            match self.svc_sink.as_mut() {
+                Some(sink) => sink(&string),
+                None => info!(target: "SVC", "{string}"),
+            }
             self.svc_buf.clear();
         }
-        Ok(())
     }
 
     /// Log IOS syscalls to stdout.
@@ -263,13 +578,448 @@ impl InterpBackend {
                 )?;
                 info!(target: "Other", "DBG hotpatching module entrypoint {paddr:08x}");
                 info!(target: "Other", "{:?}", self.cpu.reg);
-                self.bus.write().dma_write(paddr,
-                    &Self::THREAD_CANCEL_PATCH)?;
+                self.cpu.with_bus_mut(|bus| bus.dma_write(paddr, &Self::THREAD_CANCEL_PATCH))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the custom kernel ELF (if one was given). This is done by
+    /// [Backend::run], but embedders driving emulation with
+    /// [InterpBackend::step_for] need to call it first.
+    pub fn load_custom_kernel(&mut self) -> anyhow::Result<()> {
+        if self.custom_kernel.is_some() {
+            // Read the user supplied kernel file
+            let filename = self.custom_kernel.as_ref().unwrap();
+            let mut kernel_bytes = fs::read(filename).map_err(|ioerr| anyhow!("Error opening kernel file: {filename}. Got error: {ioerr}"))?;
+            let kernel_elf = elf::File::open_stream(&mut std::io::Cursor::new(&mut kernel_bytes))?;
+            info!(target: "Custom Kernel", "{}", kernel_header_summary(&kernel_elf));
+            check_loadable_kernel(&kernel_elf, kernel_bytes.len())
+                .map_err(|err| anyhow!("Refusing to load kernel {filename}: {err}"))?;
+            match validate_custom_kernel(&kernel_elf, self.kernel_profile) {
+                std::result::Result::Ok(_) => {/* We have a valid ELF (probably) */},
+                std::result::Result::Err(p) => {
+                    error!(target: "Custom Kernel", "!!!!!!!!!!");
+                    error!(target: "Custom Kernel", "Custom Kernel ELF header validation failed. Things may not work as expected.");
+                    error!(target: "Custom Kernel", "Failed validations:");
+                    for problem in p {
+                        error!(target: "Custom Kernel", "{}", problem);
+                    }
+                    error!(target: "Custom Kernel", "!!!!!!!!!");
+                    std::thread::sleep(std::time::Duration::from_secs(2));
+                    // We try to continue, chances are we crash and burn shortly after this
+                    // but on the chance this mangled ELF executes for a while via dumb luck
+                    // we sleep for a few seconds to let the user see the error.
+                }
+            }
+            match load_custom_kernel_debuginfo(&kernel_elf) {
+                Ok(debuginfo) => with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.install_debuginfo(debuginfo))?,
+                Err(err) => {error!(target: "Custom Kernel", "Failed to load debuginfo for kernel: {err}")},
+            }
+
+            match load_custom_kernel_debug_frame(&kernel_elf) {
+                Ok(debug_frames) => with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.install_debug_frames(debug_frames))?,
+                Err(err) => {error!(target: "Custom Kernel", "Failed to load debug frames for kernel: {err}")},
+            }
+
+            match crate::symbols::load_elf_symbols(&kernel_elf) {
+                Ok(elf_symbols) if !elf_symbols.is_empty() => {
+                    info!(target: "Custom Kernel", "Loaded {} symbols from the kernel", elf_symbols.len());
+                    // Symbols from a user-supplied map take priority
+                    let symbols = self.symbols.get_or_insert_with(SymbolMap::new);
+                    symbols.merge(&elf_symbols);
+                    with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.install_symbols(symbols.clone()))?;
+                },
+                Ok(_) => {},
+                Err(err) => {error!(target: "Custom Kernel", "Failed to load symbols for kernel: {err}")},
+            }
+
+            let headers = kernel_elf.phdrs;
+            with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| {
+                // We are relying on the mirror being available
+                // Or else we would be writing to mask ROM.
+                bus.rom_disabled = true;
+                bus.mirror_enabled = true;
+                // A basic ELF loader
+                for header in headers.iter() {
+                    if header.progtype == elf::types::PT_LOAD && header.filesz > 0 {
+                        let start = header.offset as usize;
+                        let end = start + header.filesz as usize;
+                        info!(target: "Custom Kernel", "Loading offset: {:#10x}  phys addr: {:#10x} filesz: {:#10x}", header.offset, header.paddr, header.filesz);
+                        bus.dma_write(header.paddr as u32, &kernel_bytes[start..end])?;
+                    }
+                }
+                if PPC_EARLY_ON.load(std::sync::atomic::Ordering::Acquire) {
+                    bus.hlwd.ppc_on = true;
+                }
+                anyhow::Ok(())
+            })??;
+            self.boot_status = BootStatus::UserKernel;
+            if self.kernel_profile != KernelProfile::Ios {
+                // Other profiles don't necessarily start at the reset vector
+                let entry = kernel_elf.ehdr.entry as u32;
+                info!(target: "Custom Kernel", "Starting execution at entry point {entry:#010x}");
+                self.cpu.reg.cpsr.set_thumb(entry & 1 != 0);
+                self.cpu.write_exec_pc(entry & !1);
             }
+            self.notify_boot_status();
         }
         Ok(())
     }
 
+    /// Load a real boot1 or boot2 image where the previous stage would have
+    /// left it, and start from it. The two are mutually exclusive, since
+    /// boot1 loads its own boot2 from the NAND.
+    pub fn load_boot_images(&mut self) -> anyhow::Result<()> {
+        if self.boot1.is_none() && self.boot2.is_none() {
+            return Ok(());
+        }
+        if self.custom_kernel.is_some() {
+            anyhow::bail!("boot1/boot2 images can't be used with a custom kernel");
+        }
+        if self.boot1.is_some() && self.boot2.is_some() {
+            anyhow::bail!("Only one of the boot1 and boot2 images can be loaded");
+        }
+        let read = |filename: &str| {
+            fs::read(filename).map_err(|ioerr| anyhow!("Error opening {filename}: {ioerr}"))
+        };
+        let (pc, stage) = with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| {
+            if let Some(filename) = self.boot2.as_ref() {
+                let image = read(filename)?;
+                let max_size = bus.mem2.size().saturating_sub((BOOT2_ADDR - ironic_core::dev::MEM2_BASE) as usize);
+                if image.len() > max_size {
+                    anyhow::bail!("boot2 image {filename} is {:#x} bytes, but only {max_size:#x} fit in MEM2", image.len());
+                }
+                let hdr_len = image.get(..4).map_or(0, |w| u32::from_be_bytes(w.try_into().unwrap()));
+                if hdr_len < 4 || hdr_len as usize >= image.len() {
+                    anyhow::bail!("boot2 image {filename} has a bad header length {hdr_len:#x}");
+                }
+                info!(target: "Other", "Loading boot2 {filename} ({:#x} bytes) at {BOOT2_ADDR:08x}", image.len());
+                bus.dma_write(BOOT2_ADDR, &image)?;
+                // boot1 leaves the mask ROM unmapped and SRAM mirrored
+                bus.rom_disabled = true;
+                bus.mirror_enabled = true;
+                return Ok((BOOT2_ADDR + hdr_len, BootStatus::Boot2Stub));
+            }
+            let filename = self.boot1.as_ref().unwrap();
+            let image = read(filename)?;
+            if image.len() > BOOT1_MAX_SIZE {
+                anyhow::bail!("boot1 image {filename} is {:#x} bytes, but only {BOOT1_MAX_SIZE:#x} fit in SRAM", image.len());
+            }
+            info!(target: "Other", "Loading boot1 {filename} ({:#x} bytes) at {BOOT1_ADDR:08x}", image.len());
+            // boot0 runs boot1 with the mask ROM still mapped, and without
+            // the SRAM mirror
+            bus.rom_disabled = false;
+            bus.mirror_enabled = false;
+            bus.dma_write(BOOT1_ADDR, &image)?;
+            anyhow::Ok((BOOT1_ENTRY, BootStatus::Boot1))
+        })??;
+
+        info!(target: "Other", "Starting {stage:?} at {pc:08x}");
+        self.cpu.reg.cpsr.set_thumb(false);
+        self.cpu.write_exec_pc(pc);
+        self.boot_status = stage;
+        self.notify_boot_status();
+        Ok(())
+    }
+
+    /// Run the emulator for up to `max_cycles` CPU cycles. Returns
+    /// [CpuRes::StepOk] if the budget was used up, or the reason that
+    /// emulation stopped otherwise. Calling this repeatedly is equivalent
+    /// to [Backend::run], so embedders can do their own work in between.
+    pub fn step_for(&mut self, max_cycles: usize) -> CpuRes {
+        match self.run_for(max_cycles) {
+            Ok(res) => res,
+            Err(reason) => CpuRes::HaltEmulation(reason),
+        }
+    }
+
+    pub(crate) fn run_for(&mut self, max_cycles: usize) -> anyhow::Result<CpuRes> {
+        if !self.single_threaded {
+            return self.run_steps(max_cycles);
+        }
+        self.cpu.hold_bus();
+        let res = self.run_steps(max_cycles);
+        self.cpu.release_bus();
+        res
+    }
+
+    /// Disassemble the instruction at `pc` for error messages.
+    fn disassemble_at(&self, pc: u32, thumb: bool) -> String {
+        let res = if thumb {
+            self.cpu.read16(pc).and_then(|opcd|
+                crate::bits::disassembly::disassmble_thumb_with_symbols(opcd, pc, self.symbols.as_ref()))
+        } else {
+            self.cpu.read32(pc).and_then(|opcd|
+                crate::bits::disassembly::disassmble_arm_with_symbols(opcd, pc, self.symbols.as_ref()))
+        };
+        res.unwrap_or("Unknown".to_owned())
+    }
+
+    /// Describe an exception taken by the last step. The exception has
+    /// already been taken, so this needs the registers from before the step
+    /// to say where it came from.
+    pub(crate) fn exception_report(&self, e: ExceptionType, pre_step: &RegisterFile) -> String {
+        let thumb = pre_step.cpsr.thumb();
+        let pc = if thumb { pre_step.pc.wrapping_sub(4) } else { pre_step.pc.wrapping_sub(8) };
+        format!("Unimplemented exception type {e:?} at pc={pc:08x}: {}\n{pre_step:?}",
+            self.disassemble_at(pc, thumb))
+    }
+
+    fn run_steps(&mut self, max_cycles: usize) -> anyhow::Result<CpuRes> {
+        let stop_cycle = self.cpu_cycle.saturating_add(max_cycles);
+        while self.cpu_cycle < stop_cycle {
+            if self.stop_requested.load(Ordering::Relaxed) {
+                info!(target: "Other", "Stopping emulation by request");
+                return Ok(CpuRes::HaltEmulation(anyhow!("Stopped by request")));
+            }
+            if let Some(max_cycles) = self.max_cycles && self.cpu_cycle >= max_cycles {
+                info!(target: "Other", "Stopping emulation after reaching the cycle limit ({max_cycles})");
+                return Ok(CpuRes::HaltEmulation(anyhow!("Reached the cycle limit")));
+            }
+            let skip_breakpoint = self.skip_breakpoint.take();
+            if !self.breakpoints.is_empty() {
+                let pc = self.cpu.read_fetch_pc();
+                if skip_breakpoint != Some(pc) && self.breakpoints.contains(&pc) {
+                    info!(target: "Other", "Stopped on breakpoint at {pc:#010x}");
+                    return Ok(CpuRes::Breakpoint(0));
+                }
+            }
+
+            // Take ownership of the bus to deal with any pending tasks
+            let (cpu_cycle, pc, regs, cpsr) = (self.cpu_cycle, self.cpu.read_fetch_pc(), self.cpu.reg.r, self.cpu.reg.cpsr.0);
+            let irq_input;
+            (irq_input, self.watching) = self.cpu.with_bus_mut(|bus| {
+                // Anything hit outside of the loop (e.g. by the debugger
+                // reading memory) isn't interesting
+                bus.take_watch_hit();
+                bus.step(cpu_cycle)?;
+                bus.update_debug_location(Some(pc), Some(regs[14]), Some(regs[13]));
+                if bus.debuginfo.track_regs {
+                    bus.update_debug_regs(regs, cpsr);
+                }
+                anyhow::Ok((bus.hlwd.irq.arm_irq_output, bus.has_watchpoints()))
+            })?;
+            self.cpu.irq_input = irq_input;
+            self.bus_cycle += 1;
+            // DMA from the devices stepped above counts, but nothing else
+            // outside of the instruction itself does.
+            let mut watch_hit = if self.watching {
+                self.cpu.with_bus(|bus| bus.take_watch_hit())
+            } else {
+                None
+            };
+
+            // Before each CPU step, check if we need to patch any close code
+            // I'm ok swallowing the possible Err result here because the only way this can error is
+            // failing to translate the address the PC is at. This is obviously very rare, and in
+            // the case it does happen we will know very soon anyway.
+            self.hotpatch_check().unwrap_or_default();
+
+            // Kept for reporting exceptions, which change the registers
+            let pre_step = self.cpu.reg;
+            let prev_status = self.boot_status;
+            self.cycles_left = stop_cycle.min(self.max_cycles.unwrap_or(usize::MAX)) - self.cpu_cycle;
+            let res = self.cpu_step();
+            if let Some(hit) = self.watch_hit.take() {
+                watch_hit.get_or_insert(hit);
+            }
+            if let Some(trace) = self.trace.as_mut()
+            && let Err(e) = trace.finish(&self.cpu, self.symbols.as_ref()) {
+                error!(target: "Other", "Failed to write execution trace, disabling it: {e}");
+                self.trace = None;
+            }
+            match res {
+                CpuRes::StepOk => {},
+                CpuRes::HaltEmulation(reason) => {
+                    error!(target: "Other", "CPU returned fatal error: {reason:#}");
+                    error!(target: "Other", "{:?}", self.cpu.reg);
+                    let pc = self.cpu.read_fetch_pc();
+                    error!(target: "Other", "Possibly faulting instruction: {}",
+                        self.disassemble_at(pc, self.cpu.reg.cpsr.thumb()));
+                    return Ok(CpuRes::HaltEmulation(reason));
+                },
+                CpuRes::StepException(e) => {
+                    match e {
+                        ExceptionType::Undef(_) => {},
+                        ExceptionType::Irq => {},
+                        ExceptionType::Swi => {},
+                        _ => {
+                            error!(target: "Other", "{}", self.exception_report(e, &pre_step));
+                            return Ok(CpuRes::StepException(e));
+                        }
+                    }
+                },
+                CpuRes::Breakpoint(imm) => {
+                    info!(target: "Other", "Stopped on BKPT #{imm:#x} at {:#010x}", self.cpu.read_fetch_pc());
+                    return Ok(CpuRes::Breakpoint(imm));
+                },
+                // Not returned by a single step, but reported like any
+                // other hit (below) if it ever is.
+                CpuRes::Watchpoint(hit) => {
+                    watch_hit.get_or_insert(hit);
+                },
+                CpuRes::Semihosting => {
+                    match self.svc_read() {
+                        Ok(Some(code)) => {
+                            info!(target: "Other", "Guest exited with code {code}");
+                            self.exit_code = Some(code);
+                            return Ok(CpuRes::Semihosting);
+                        },
+                        Ok(None) => {},
+                        Err(reason) => info!(target: "Other", "FIXME: svc_read got error {reason}"),
+                    }
+                }
+            }
+            self.cpu_cycle += 1;
+            self.check_cycle_sync();
+            if let Some(perf) = self.perf.as_mut() {
+                perf.poll(self.cpu_cycle, self.bus_cycle);
+            }
+
+            if self.boot_status != prev_status && self.stop_at_stage == Some(self.boot_status) {
+                info!(target: "Other", "Stopping emulation on entering {:?}", self.boot_status);
+                return Ok(CpuRes::HaltEmulation(anyhow!("Reached boot stage {:?}", self.boot_status)));
+            }
+
+            if let Some(hit) = watch_hit {
+                let disasm = hit.pc.map(|pc| self.disassemble_at(pc, pre_step.cpsr.thumb())).unwrap_or_default();
+                info!(target: "Other", "Watchpoint hit: {hit}: {disasm}");
+                if !self.debugger_attached {
+                    return Ok(CpuRes::HaltEmulation(anyhow!("Watchpoint hit: {hit}")));
+                }
+                return Ok(CpuRes::Watchpoint(hit));
+            }
+        }
+        Ok(CpuRes::StepOk)
+    }
+
+    /// The bus is stepped at most once per CPU cycle (the CPU can run ahead
+    /// of it, e.g. when fast-forwarding loops), so the bus should never be
+    /// ahead of the CPU. Only checked in debug builds.
+    fn check_cycle_sync(&self) {
+        debug_assert!(self.bus_cycle <= self.cpu_cycle,
+            "Bus is ahead of the CPU: {} bus cycles after {} CPU cycles", self.bus_cycle, self.cpu_cycle);
+    }
+
+    /// Describe the number of CPU and bus cycles elapsed, and their ratio.
+    pub fn cycle_report(&self) -> String {
+        let ratio = self.cpu_cycle as f64 / self.bus_cycle.max(1) as f64;
+        format!("CPU cycles: {}, bus cycles: {}, ratio {ratio:.2}:1", self.cpu_cycle, self.bus_cycle)
+    }
+
+    /// Number of cycles to run between releasing the bus in single-threaded
+    /// mode.
+    const SINGLE_THREADED_BATCH: usize = 0x10_0000;
+
+    /// Maximum number of delay loop iterations skipped in one step while
+    /// IRQs are enabled, so that pending interrupts are still taken promptly.
+    const FAST_FORWARD_CHUNK: u32 = 4096;
+
+    /// Detect a countdown delay loop at the current PC, like:
+    ///
+    /// ```text
+    /// loop: subs rN, rN, #1
+    ///       bne loop
+    /// ```
+    ///
+    /// If one is found, skip over the iterations by computing the final
+    /// register, flags, and cycle count directly. Returns true if the loop
+    /// was fast-forwarded.
+    fn fast_forward_delay_loop(&mut self) -> bool {
+        // Breakpoints, hooks and traces have to see every instruction
+        if !self.breakpoints.is_empty() || !self.pc_hooks.is_empty()
+            || self.branch_hook.is_some() || self.trace.is_some() {
+            return false;
+        }
+        // Each iteration takes two cycles, and has to fit in the budget
+        let max_iterations = u32::try_from(self.cycles_left / 2).unwrap_or(u32::MAX);
+        if max_iterations == 0 {
+            return false;
+        }
+        let pc = self.cpu.read_fetch_pc();
+        let (rd, loop_len) = if self.cpu.reg.cpsr.thumb() {
+            let (Ok(subs), Ok(bne)) = (self.cpu.read16(pc), self.cpu.read16(pc.wrapping_add(2))) else {
+                return false;
+            };
+            // subs rN, #1; bne -4
+            if subs & 0xf8ff != 0x3801 || bne != 0xd1fd {
+                return false;
+            }
+            (((subs >> 8) & 0x7) as usize, 4)
+        } else {
+            let (Ok(subs), Ok(bne)) = (self.cpu.read32(pc), self.cpu.read32(pc.wrapping_add(4))) else {
+                return false;
+            };
+            // subs rN, rN, #1 (with rN != pc); bne -8
+            let rn = (subs >> 16) & 0xf;
+            let rd = (subs >> 12) & 0xf;
+            if subs & 0xfff0_0fff != 0xe250_0001 || rn != rd || rd == 15 || bne != 0x1aff_fffd {
+                return false;
+            }
+            (rd as usize, 8)
+        };
+        let count = self.cpu.reg.r[rd];
+        // Starting from zero would wrap around, just interpret it normally
+        if count == 0 {
+            return false;
+        }
+        let iterations = if self.cpu.reg.cpsr.irq_disable() {
+            count
+        } else {
+            count.min(Self::FAST_FORWARD_CHUNK)
+        }.min(max_iterations);
+        // Flags are the result of the last subtraction
+        let operand = count - iterations + 1;
+        let res = operand - 1;
+        self.cpu.reg.r[rd] = res;
+        self.cpu.reg.cpsr.set_n(res & 0x8000_0000 != 0);
+        self.cpu.reg.cpsr.set_z(res == 0);
+        self.cpu.reg.cpsr.set_c(true);
+        self.cpu.reg.cpsr.set_v(operand == 0x8000_0000);
+        if res == 0 {
+            self.cpu.write_exec_pc(pc.wrapping_add(loop_len));
+        }
+        // Two instructions per iteration. The caller accounts for one cycle.
+        self.cpu_cycle += (iterations as usize * 2) - 1;
+        true
+    }
+
+    /// Step over the breakpoint instruction that the CPU stopped on, so that
+    /// execution can be resumed after [CpuRes::Breakpoint].
+    pub fn resume_from_breakpoint(&mut self) {
+        self.cpu.increment_pc();
+    }
+
+    /// Fetch and decode the Thumb instruction at the current PC.
+    fn fetch_thumb(&mut self) -> anyhow::Result<(u16, ThumbFn)> {
+        let pc = self.cpu.read_fetch_pc();
+        let opcd = self.cpu.read16(pc)?;
+        Ok((opcd, INTERP_LUT.thumb.lookup(opcd)))
+    }
+
+    /// Fetch and decode the ARM instruction at the current PC.
+    fn fetch_arm(&mut self) -> anyhow::Result<(u32, ArmFn)> {
+        let pc = self.cpu.read_fetch_pc();
+        let opcd = self.cpu.read32(pc)?;
+        Ok((opcd, INTERP_LUT.arm.lookup(opcd)))
+    }
+
+    /// Start a trace record for the instruction `opcd` just fetched from `pc`.
+    fn trace_begin(&mut self, pc: u32, opcd: u32) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.begin(self.cpu_cycle, &self.cpu, pc, opcd);
+        }
+    }
+
+    /// Drop any watchpoint hit made before executing an instruction (e.g.
+    /// by fetching it), so that only the guest's own accesses are reported.
+    fn ignore_watch_hits(&mut self) {
+        if self.watching {
+            self.cpu.with_bus(|bus| bus.take_watch_hit());
+        }
+    }
+
     /// Do a single step of the CPU.
     pub fn cpu_step(&mut self) -> CpuRes {
         assert!((self.cpu.read_fetch_pc() & 1) == 0);
@@ -282,30 +1032,39 @@ impl InterpBackend {
             };
         }
 
+        let exec_pc = self.cpu.read_fetch_pc();
+
+        if self.fast_forward_loops && self.fast_forward_delay_loop() {
+            self.update_boot_status();
+            return CpuRes::StepOk;
+        }
+
         // Fetch/decode/execute an ARM or Thumb instruction depending on
         // the state of the Thumb flag in the CPSR.
         let disp_res = if self.cpu.reg.cpsr.thumb() {
             self.dbg_print().unwrap_or_default(); // Ok to fail - just a debug print
-            let opcd = match self.cpu.read16(self.cpu.read_fetch_pc()) {
+            let (opcd, func) = match self.fetch_thumb() {
                 Ok(val) => val,
                 Err(reason) => {
                     return CpuRes::HaltEmulation(reason);
                 }
             };
-            let func = INTERP_LUT.thumb.lookup(opcd);
+            self.trace_begin(exec_pc, opcd.into());
+            self.ignore_watch_hits();
             func.0(&mut self.cpu, opcd)
         } else {
             self.dbg_print().unwrap_or_default(); // Ok to fail - just a debug print
-            let opcd = match self.cpu.read32(self.cpu.read_fetch_pc()) {
+            let (opcd, func) = match self.fetch_arm() {
                 Ok(val) => val,
                 Err(reason) => {
                     return CpuRes::HaltEmulation(reason);
                 }
             };
+            self.trace_begin(exec_pc, opcd);
+            self.ignore_watch_hits();
             match self.cpu.reg.cond_pass(opcd) {
                 Ok(cond_did_pass) => {
                     if cond_did_pass {
-                        let func = INTERP_LUT.arm.lookup(opcd);
                         func.0(&mut self.cpu, opcd)
                     } else {
                         DispatchRes::CondFailed
@@ -317,14 +1076,26 @@ impl InterpBackend {
             }
         };
 
+        if self.watching {
+            self.watch_hit = self.cpu.with_bus(|bus| bus.take_watch_hit());
+        }
+
         // Depending on the instruction, adjust the program counter
         let cpu_res = match disp_res {
             DispatchRes::Breakpoint => {
-                self.debugger_attached = true;
-                self.cpu.increment_pc();
-                CpuRes::StepOk
+                let imm = self.cpu.scratch;
+                if !self.debugger_attached {
+                    return CpuRes::HaltEmulation(anyhow!("hit BKPT #{imm:#x} at {exec_pc:#010x}"));
+                }
+                // Leave the PC on the breakpoint until the debugger resumes
+                return CpuRes::Breakpoint(imm);
             }
-            DispatchRes::RetireBranch => { CpuRes::StepOk },
+            DispatchRes::RetireBranch => {
+                if let Some(hook) = self.branch_hook.as_mut() {
+                    hook(exec_pc, self.cpu.read_fetch_pc());
+                }
+                CpuRes::StepOk
+            },
             DispatchRes::RetireOk | 
             DispatchRes::CondFailed => {
                 self.cpu.increment_pc(); 
@@ -354,6 +1125,10 @@ impl InterpBackend {
                     }
                     // fall through all other Swis to the exception handler
                 }
+                if self.halt_on_undef && let ExceptionType::Undef(op) = e
+                && (self.cpu.reg.cpsr.thumb() || !dispatch::is_ios_syscall(op)) {
+                    return CpuRes::HaltEmulation(anyhow!("Undefined instruction {op:08x} at {exec_pc:08x}"));
+                }
                 if let Err(reason) = self.cpu.generate_exception(e){
                     return CpuRes::HaltEmulation(reason);
                 };
@@ -365,6 +1140,10 @@ impl InterpBackend {
             },
         };
 
+        if !self.pc_hooks.is_empty() && matches!(cpu_res, CpuRes::StepOk) {
+            self.run_pc_hooks(exec_pc);
+        }
+
         self.update_boot_status();
         cpu_res
     }
@@ -372,116 +1151,24 @@ impl InterpBackend {
 
 impl Backend for InterpBackend {
     fn run(&mut self) -> anyhow::Result<()> {
-        if self.custom_kernel.is_some() {
-            // Read the user supplied kernel file
-            let filename = self.custom_kernel.as_ref().unwrap();
-            let mut kernel_bytes = fs::read(filename).map_err(|ioerr| anyhow!("Error opening kernel file: {filename}. Got error: {ioerr}"))?;
-            let kernel_elf = elf::File::open_stream(&mut std::io::Cursor::new(&mut kernel_bytes))?;
-            match validate_custom_kernel(&kernel_elf.ehdr) {
-                std::result::Result::Ok(_) => {/* We have a valid ELF (probably) */},
-                std::result::Result::Err(p) => {
-                    error!(target: "Custom Kernel", "!!!!!!!!!!");
-                    error!(target: "Custom Kernel", "Custom Kernel ELF header validation failed. Things may not work as expected.");
-                    error!(target: "Custom Kernel", "Failed validations:");
-                    for problem in p {
-                        error!(target: "Custom Kernel", "{}", problem);
-                    }
-                    error!(target: "Custom Kernel", "!!!!!!!!!");
-                    std::thread::sleep(std::time::Duration::from_secs(2));
-                    // We try to continue, chances are we crash and burn shortly after this
-                    // but on the chance this mangled ELF executes for a while via dumb luck
-                    // we sleep for a few seconds to let the user see the error.
-                }
-            }
-            match load_custom_kernel_debuginfo(&kernel_elf) {
-                Ok(debuginfo) => {self.bus.write().install_debuginfo(debuginfo)},
-                Err(err) => {error!(target: "Custom Kernel", "Failed to load debuginfo for kernel: {err}")},
-            }
-
-            match load_custom_kernel_debug_frame(&kernel_elf) {
-                Ok(debug_frames) => {self.bus.write().install_debug_frames(debug_frames)},
-                Err(err) => {error!(target: "Custom Kernel", "Failed to load debug frames for kernel: {err}")},
-            }
-
-            let headers = kernel_elf.phdrs;
-            let mut bus = self.bus.write();
-            // We are relying on the mirror being available
-            // Or else we would be writing to mask ROM.
-            bus.rom_disabled = true;
-            bus.mirror_enabled = true;
-            // A basic ELF loader
-            for header in headers.iter() {
-                if header.progtype == elf::types::PT_LOAD && header.filesz > 0 {
-                    let start = header.offset as usize;
-                    let end = start + header.filesz as usize;
-                    info!(target: "Custom Kernel", "Loading offset: {:#10x}  phys addr: {:#10x} filesz: {:#10x}", header.offset, header.paddr, header.filesz);
-                    bus.dma_write(header.paddr as u32, &kernel_bytes[start..end])?;
-                }
-            }
-            self.boot_status = BootStatus::UserKernel;
-            if PPC_EARLY_ON.load(std::sync::atomic::Ordering::Acquire) {
-                bus.hlwd.ppc_on = true;
-            }
+        self.load_custom_kernel()?;
+        self.load_boot_images()?;
+        if let Some(path) = self.load_state_from.take() {
+            self.load_state(&path)?;
         }
-        loop {
-            // Take ownership of the bus to deal with any pending tasks
-            {
-                let mut bus = self.bus.write();
-                bus.step(self.cpu_cycle)?;
-                self.bus_cycle += 1;
-                bus.update_debug_location(Some(self.cpu.read_fetch_pc()), Some(self.cpu.reg.r[14]), Some(self.cpu.reg.r[13]));
-                self.cpu.irq_input = bus.hlwd.irq.arm_irq_output;
-            }
-
-            // Before each CPU step, check if we need to patch any close code
-            // I'm ok swallowing the possible Err result here because the only way this can error is
-            // failing to translate the address the PC is at. This is obviously very rare, and in
-            // the case it does happen we will know very soon anyway.
-            self.hotpatch_check().unwrap_or_default();
-
-            let res = self.cpu_step();
-            match res {
-                CpuRes::StepOk => {},
-                CpuRes::HaltEmulation(reason) => {
-                    error!(target: "Other", "CPU returned fatal error: {reason:#}");
-                    error!(target: "Other", "{:?}", self.cpu.reg);
-                    let pc = self.cpu.read_fetch_pc();
-                    if self.cpu.reg.cpsr.thumb() {
-                        if let Ok(opcd) = self.cpu.read16(pc){
-                            error!(target: "Other",
-                                "Possibly faulting instruction: {}",
-                                crate::bits::disassembly::disassmble_thumb(opcd, pc).unwrap_or("Unknown".to_owned())
-                            );
-                        }
-                    }
-                    else if let Ok(opcd) = self.cpu.read32(pc){
-                        error!(target: "Other",
-                            "Possibly faulting instrcution: {}",
-                            crate::bits::disassembly::disassmble_arm(opcd, pc).unwrap_or("Unknown".to_owned())
-                        );
-                    }
-                    break;
-                },
-                CpuRes::StepException(e) => {
-                    match e {
-                        ExceptionType::Undef(_) => {},
-                        ExceptionType::Irq => {},
-                        ExceptionType::Swi => {},
-                        _ => {
-                            info!(target: "Other", "Unimplemented exception type {e:?}");
-                            break;
-                        }
-                    }
-                },
-                CpuRes::Semihosting => {
-                    self.svc_read().unwrap_or_else(|reason|{
-                        info!(target: "Other", "FIXME: svc_read got error {reason}");
-                    });
-                }
-            }
-            self.cpu_cycle += 1;
+        if self.debug_port.is_some() {
+            self.run_debugger()?;
+        } else {
+            // Let go of the bus every so often in single-threaded mode, so
+            // that e.g. the Ctrl-C handler can still get at it
+            let budget = if self.single_threaded { Self::SINGLE_THREADED_BATCH } else { usize::MAX };
+            while let CpuRes::StepOk = self.run_for(budget)? {}
         }
         info!(target: "Other", "CPU stopped at pc={:08x}", self.cpu.read_fetch_pc());
+        info!(target: "Other", "{}", self.cycle_report());
+        if let Some(path) = self.save_state_on_exit.as_ref() {
+            self.save_state(path)?;
+        }
         Ok(())
     }
 }
@@ -494,16 +1181,81 @@ macro_rules! elf_header_expect_equal {
     };
 }
 
-fn validate_custom_kernel(header: &elf::types::FileHeader) -> std::result::Result<(), Vec<String>> {
+/// Describe the ELF header of a custom kernel, for the log.
+fn kernel_header_summary(kernel_elf: &elf::File) -> String {
+    format!("{} (entry {:#x})", kernel_elf.ehdr, kernel_elf.ehdr.entry)
+}
+
+/// Check that a custom kernel can be read and passes validation for some
+/// profile, returning a description of each problem.
+pub fn check_custom_kernel(filename: &str, profile: KernelProfile) -> Vec<String> {
+    let mut kernel_bytes = match fs::read(filename) {
+        Ok(bytes) => bytes,
+        Err(ioerr) => return vec![format!("Error opening kernel file: {filename}. Got error: {ioerr}")],
+    };
+    match elf::File::open_stream(&mut std::io::Cursor::new(&mut kernel_bytes)) {
+        Ok(kernel_elf) => match check_loadable_kernel(&kernel_elf, kernel_bytes.len()) {
+            Ok(()) => validate_custom_kernel(&kernel_elf, profile).err().unwrap_or_default(),
+            Err(err) => vec![err.to_string()],
+        },
+        Err(err) => vec![format!("Failed to parse kernel ELF {filename}: {err}")],
+    }
+}
+
+/// Hard checks that the ELF loader can load a kernel at all. Unlike
+/// [validate_custom_kernel], failing these is an error rather than a warning:
+/// the loader would otherwise panic or scribble a corrupted image into memory.
+fn check_loadable_kernel(kernel_elf: &elf::File, file_len: usize) -> anyhow::Result<()> {
+    use elf::types::*;
+    let header = &kernel_elf.ehdr;
+    if header.class != ELFCLASS32 {
+        anyhow::bail!("ELF class is {}, but Starlet only runs 32-bit ARM code (build with a 32-bit ARM toolchain)", header.class);
+    }
+    if header.data != ELFDATA2MSB {
+        anyhow::bail!("ELF data is {}, but Starlet is big endian (build with -mbig-endian)", header.data);
+    }
+    for phdr in kernel_elf.phdrs.iter().filter(|phdr| phdr.progtype == PT_LOAD && phdr.filesz > 0) {
+        let file_end = phdr.offset.checked_add(phdr.filesz);
+        if file_end.is_none_or(|end| end > file_len as u64) {
+            anyhow::bail!("Segment at offset {:#x} (filesz {:#x}) runs past the end of the file ({:#x} bytes)",
+                phdr.offset, phdr.filesz, file_len);
+        }
+        if phdr.paddr.checked_add(phdr.filesz).is_none_or(|end| end > 1 << 32) {
+            anyhow::bail!("Segment at phys addr {:#x} (filesz {:#x}) doesn't fit in the 32-bit address space",
+                phdr.paddr, phdr.filesz);
+        }
+    }
+    Ok(())
+}
+
+fn validate_custom_kernel(kernel_elf: &elf::File, profile: KernelProfile) -> std::result::Result<(), Vec<String>> {
     use elf::types::*;
+    let header = &kernel_elf.ehdr;
     let mut problems: Vec<String> = Vec::with_capacity(0);
-    elf_header_expect_equal!(problems, header.class, ELFCLASS32, "ELF Class is not 32-bit");
-    elf_header_expect_equal!(problems, header.data, ELFDATA2MSB, "ELF Data is not big endian");
+    elf_header_expect_equal!(problems, header.machine, EM_ARM, "ELF Type is not 32-bit ARM");
+    if profile == KernelProfile::Raw {
+        return if problems.is_empty() { Ok(()) } else { Err(problems) };
+    }
     elf_header_expect_equal!(problems, header.version, EV_CURRENT, "ELF Version is not known to us");
     elf_header_expect_equal!(problems, header.osabi, ELFOSABI_SYSV, "ELF ABI is not known to us");
-    elf_header_expect_equal!(problems, header.elftype, ET_EXEC, "Our ELF loader only implements EXEC type ELF");
-    elf_header_expect_equal!(problems, header.machine, EM_ARM, "ELF Type is not 32-bit ARM");
-    elf_header_expect_equal!(problems, header.entry, 0xffff_0000u64, "Entry point of ELF does not match CPU reset vector");
+    match profile {
+        KernelProfile::Ios => {
+            elf_header_expect_equal!(problems, header.elftype, ET_EXEC, "Our ELF loader only implements EXEC type ELF");
+            elf_header_expect_equal!(problems, header.entry, 0xffff_0000u64, "Entry point of ELF does not match CPU reset vector");
+        },
+        KernelProfile::Boot2 => {
+            if header.elftype != ET_EXEC && header.elftype != ET_DYN {
+                problems.push(format!("boot2 profile expects an EXEC or DYN type ELF. Got: {}", header.elftype));
+            }
+            let entry_is_loaded = kernel_elf.phdrs.iter().any(|phdr| {
+                phdr.progtype == PT_LOAD && (phdr.vaddr..phdr.vaddr + phdr.memsz).contains(&header.entry)
+            });
+            if !entry_is_loaded {
+                problems.push(format!("Entry point {:#x} is not inside any loadable segment", header.entry));
+            }
+        },
+        KernelProfile::Raw => unreachable!(),
+    }
     if problems.is_empty() {
         std::result::Result::Ok(())
     }
@@ -535,4 +1287,524 @@ fn load_custom_kernel_debug_frame(kernel_elf:&elf::File) -> anyhow::Result<gimli
         },
         None => anyhow::bail!("No debug frame section found"),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testrom::{backend_with_code, build_elf, build_test_rom, temp_file, TEST_ROM_BASE};
+
+    #[test]
+    fn identify_boot1_revisions() {
+        for (hash, name) in [
+            ([0xb30c32b9, 0x62c7cd08, 0xabe33d01, 0x5b9b8b1d, 0xb1097544], "a"),
+            ([0xef3ef781, 0x09608d56, 0xdf5679a6, 0xf92e13f7, 0x8bbddfdf], "b"),
+            ([0xd220c8a4, 0x86c631d0, 0xdf5adb31, 0x96ecbc66, 0x8780cc8d], "c"),
+            ([0xf793068a, 0x09e80986, 0xe2a023c0, 0xc23f0614, 0x0ed16974], "d"),
+        ] {
+            assert_eq!(identify_boot1(hash), Some(name));
+        }
+        assert_eq!(identify_boot1([0; 5]), Some("? - OTP NOT FACTORY PROGRAMMED!"));
+        assert_eq!(identify_boot1([0xb30c32b9, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn sys_exit_reports_the_guest_status() {
+        const ADP_STOPPED_RUN_TIME_ERROR: u32 = 0x20023;
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = InterpBackend::new(bus.clone(), None, false);
+
+        back.cpu.reg.r[0] = SYS_EXIT;
+        back.cpu.reg.r[1] = ADP_STOPPED_APPLICATION_EXIT;
+        assert_eq!(back.svc_read().unwrap(), Some(0));
+        back.cpu.reg.r[1] = ADP_STOPPED_RUN_TIME_ERROR;
+        assert_eq!(back.svc_read().unwrap(), Some(1));
+        back.cpu.reg.r[1] = 0xffff_ffff;
+        assert_eq!(back.svc_read().unwrap(), Some(1));
+
+        // The extended call passes the reason and status in a block
+        let mut block = [0u8; 8];
+        block[0..4].copy_from_slice(&ADP_STOPPED_APPLICATION_EXIT.to_be_bytes());
+        block[4..8].copy_from_slice(&7u32.to_be_bytes());
+        bus.write().dma_write(0x1000, &block).unwrap();
+        back.cpu.reg.r[0] = SYS_EXIT_EXTENDED;
+        back.cpu.reg.r[1] = 0x1000;
+        assert_eq!(back.svc_read().unwrap(), Some(7));
+        block[0..4].copy_from_slice(&ADP_STOPPED_RUN_TIME_ERROR.to_be_bytes());
+        bus.write().dma_write(0x1000, &block).unwrap();
+        assert_eq!(back.svc_read().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn kernel_header_is_readable() {
+        let mut elf = build_test_rom();
+        let kernel_elf = elf::File::open_stream(&mut std::io::Cursor::new(&mut elf)).unwrap();
+        assert_eq!(kernel_header_summary(&kernel_elf),
+            "File Header for 32-bit 2's complement, big endian Elf Executable file \
+            for UNIX System V ARM 32-bit architecture (AARCH32) (entry 0x10000)");
+    }
+
+    #[test]
+    fn max_cycles_stops_emulation() {
+        let mut back = backend_with_code(&[]);
+        back.max_cycles = Some(2);
+        assert!(matches!(back.step_for(100), CpuRes::HaltEmulation(_)));
+        assert_eq!(back.cpu_cycle, 2);
+        assert_eq!(back.cpu.read_fetch_pc(), TEST_ROM_BASE + 8);
+        assert!(back.svc_buf.is_empty());
+    }
+
+    #[test]
+    fn single_threaded_mode_matches_locked_mode() {
+        let mut results = Vec::new();
+        for single_threaded in [false, true] {
+            let mut back = backend_with_code(&[]);
+            back.single_threaded = single_threaded;
+            assert!(matches!(back.step_for(3), CpuRes::StepOk));
+            // The bus is only held while running
+            assert!(back.bus.try_write().is_some());
+            assert!(matches!(back.step_for(100), CpuRes::Semihosting));
+            results.push((back.cpu.reg.r, back.cpu.read_fetch_pc(), back.cpu_cycle, back.exit_code));
+        }
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[test]
+    fn resuming_from_a_save_state_matches_running_through() {
+        let state_path = std::env::temp_dir().join(format!("ironic-state-{}.bin", std::process::id()));
+
+        let mut back = backend_with_code(&[]);
+        assert!(matches!(back.step_for(100), CpuRes::Semihosting));
+        let expected = (back.cpu.reg.r, back.cpu.read_fetch_pc(), back.cpu_cycle, back.exit_code);
+
+        // Stop after the first semihosting call, with "hello" still buffered
+        let mut back = backend_with_code(&[]);
+        back.max_cycles = Some(3);
+        back.save_state_on_exit = Some(state_path.clone());
+        back.run().unwrap();
+        assert_eq!(back.svc_buf, "hello");
+
+        // Without the kernel, everything has to come from the save state
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = InterpBackend::new(bus, None, false);
+        back.load_state_from = Some(state_path.clone());
+        back.run().unwrap();
+        std::fs::remove_file(&state_path).unwrap();
+        assert_eq!((back.cpu.reg.r, back.cpu.read_fetch_pc(), back.cpu_cycle, back.exit_code), expected);
+        assert!(back.svc_buf.is_empty());
+    }
+
+    #[test]
+    fn stop_request_halts_at_the_next_instruction() {
+        let state_path = std::env::temp_dir().join(format!("ironic-stop-state-{}.bin", std::process::id()));
+
+        let mut back = backend_with_code(&[]);
+        back.save_state_on_exit = Some(state_path.clone());
+        // Pretend Ctrl-C is pressed while the instruction at 0x0c runs
+        let stop = back.stop_requested.clone();
+        back.add_pc_hook(TEST_ROM_BASE + 0x0c, Box::new(move |_| stop.store(true, Ordering::Relaxed)));
+        back.run().unwrap();
+
+        // The guest never got to exit, but the state is still saved
+        assert_eq!(back.cpu.read_fetch_pc(), TEST_ROM_BASE + 0x10);
+        assert_eq!(back.exit_code, None);
+        assert!(state_path.exists());
+        std::fs::remove_file(&state_path).unwrap();
+    }
+
+    #[test]
+    fn registers_are_recorded_only_when_tracked() {
+        let mut back = backend_with_code(&[]);
+        assert!(matches!(back.step_for(1), CpuRes::StepOk));
+        assert_eq!(back.bus.read().debuginfo.last_regs, None);
+
+        // mov r0, #4 has run by the time the next step is recorded
+        back.bus.write().debuginfo.track_regs = true;
+        assert!(matches!(back.step_for(1), CpuRes::StepOk));
+        let (regs, _) = back.bus.read().debuginfo.last_regs.unwrap();
+        assert_eq!(regs[0], 4);
+    }
+
+    #[test]
+    fn undefined_instruction_takes_the_undef_vector() {
+        use ironic_core::cpu::reg::CpuMode;
+        const UNDEF_OP: u32 = 0xe060_0090;
+
+        let mut results = Vec::new();
+        for halt_on_undef in [false, true] {
+            let mut back = backend_with_code(&[UNDEF_OP]);
+            back.halt_on_undef = halt_on_undef;
+            results.push((back.step_for(1), back.cpu.read_fetch_pc(), back.cpu.reg.cpsr.mode(), back.cpu.reg[14u32]));
+        }
+
+        // The exception is handled by the guest, so emulation carries on
+        let (res, pc, mode, lr) = &results[0];
+        assert!(matches!(res, CpuRes::StepOk));
+        assert_eq!((*pc, *mode, *lr), (0xffff_0004, CpuMode::Und, TEST_ROM_BASE + 4));
+
+        // With --halt-on-undef, the CPU is left on the faulting instruction
+        let (res, pc, mode, _) = &results[1];
+        assert!(matches!(res, CpuRes::HaltEmulation(_)));
+        assert_eq!(*pc, TEST_ROM_BASE);
+        assert_ne!(*mode, CpuMode::Und);
+    }
+
+    #[test]
+    fn data_abort_report_shows_the_faulting_state() {
+        use ironic_core::cpu::reg::CpuMode;
+        const LDREX_OP: u32 = 0xe191_0f9f; // ldrex r0, [r1]
+
+        let mut back = backend_with_code(&[LDREX_OP]);
+        back.cpu.exclusives = true;
+        back.cpu.reg[1u32] = 0x1002;
+        let pre_step = back.cpu.reg;
+
+        // Data aborts aren't handled, so emulation stops once it's taken
+        assert!(matches!(back.step_for(1), CpuRes::StepException(ExceptionType::Dabt)));
+        assert_eq!(back.cpu.reg.cpsr.mode(), CpuMode::Abt);
+        let report = back.exception_report(ExceptionType::Dabt, &pre_step);
+        assert!(report.starts_with(&format!("Unimplemented exception type Dabt at pc={TEST_ROM_BASE:08x}: ldrex")),
+            "{report}");
+        assert!(report.ends_with(&format!("{pre_step:?}")), "{report}");
+        assert!(!report.contains("Abt"), "{report}");
+    }
+
+    #[test]
+    fn p15_registers_are_wired_up() {
+        use ironic_core::cpu::coproc::SystemControl;
+        let mut back = backend_with_code(&[
+            0xee10_0f10, // mrc p15, 0, r0, c0, c0, 0   (MIDR)
+            0xee07_0f3e, // mcr p15, 0, r0, c7, c14, 1  (clean and invalidate DCache line)
+            0xee01_1f10, // mcr p15, 0, r1, c1, c0, 0   (control register)
+            0xee11_2f10, // mrc p15, 0, r2, c1, c0, 0
+        ]);
+
+        // Turn on the caches, but leave the MMU off
+        back.cpu.reg.r[1] = 0x0000_1004;
+        assert!(matches!(back.step_for(4), CpuRes::StepOk));
+        assert_eq!(back.cpu.read_fetch_pc(), TEST_ROM_BASE + 0x10);
+        assert_eq!(back.cpu.reg.r[0], SystemControl::MIDR);
+        assert_eq!(back.cpu.reg.r[2], 0x0000_1004);
+        assert!(back.cpu.p15.c1_ctrl.icache_enabled() && back.cpu.p15.c1_ctrl.dcache_enabled());
+        assert!(!back.cpu.p15.c1_ctrl.mmu_enabled());
+    }
+
+    #[test]
+    fn watchpoint_stops_after_the_access() {
+        use ironic_core::bus::watch::WatchKind;
+        // The literal loaded by the `ldr r1, [pc, #0]` at 0x1c
+        const LITERAL: u32 = TEST_ROM_BASE + 0x24;
+
+        let mut results = Vec::new();
+        for debugger_attached in [false, true] {
+            let mut back = backend_with_code(&[]);
+            back.debugger_attached = debugger_attached;
+            back.bus.write().add_watchpoint(LITERAL..=LITERAL + 3, WatchKind::Read);
+            results.push((back.step_for(100), back.cpu.read_fetch_pc()));
+        }
+
+        let (res, pc) = &results[0];
+        assert!(matches!(res, CpuRes::HaltEmulation(_)));
+        assert_eq!(*pc, TEST_ROM_BASE + 0x20);
+
+        // A debugger gets to look around and carry on
+        let (res, pc) = &results[1];
+        let CpuRes::Watchpoint(hit) = res else { panic!("Expected a watchpoint hit") };
+        assert_eq!((hit.addr, hit.write, hit.pc), (LITERAL, false, Some(TEST_ROM_BASE + 0x1c)));
+        assert_eq!(*pc, TEST_ROM_BASE + 0x20);
+    }
+
+    #[test]
+    fn instruction_fetches_dont_hit_watchpoints() {
+        use ironic_core::bus::watch::WatchKind;
+        // The `ldr r1, [pc, #0]` at 0x1c, which is fetched but never loaded
+        const LDR: u32 = TEST_ROM_BASE + 0x1c;
+
+        let mut back = backend_with_code(&[]);
+        back.debugger_attached = true;
+        back.bus.write().add_watchpoint(LDR..=LDR + 3, WatchKind::Read);
+        let res = back.step_for(100);
+        assert!(!matches!(res, CpuRes::Watchpoint(_)), "An instruction fetch was reported as a watchpoint hit");
+        assert!(back.cpu.read_fetch_pc() > LDR);
+    }
+
+    #[test]
+    fn boot_stage_transitions_are_sent_and_can_stop_emulation() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut back = backend_with_code(&[]).with_boot_status_sender(tx);
+        assert_eq!(back.boot_status, BootStatus::UserKernel);
+
+        // Pretend that IOS is about to jump to the foreign kernel stub,
+        // from the (zeroed, so harmless) word before the test image
+        back.boot_status = BootStatus::IOSKernel;
+        back.stop_at_stage = Some(BootStatus::UserKernelStub);
+        back.cpu.write_exec_pc(TEST_ROM_BASE - 4);
+        assert!(matches!(back.step_for(100), CpuRes::HaltEmulation(_)));
+        assert_eq!(back.cpu.read_fetch_pc(), TEST_ROM_BASE);
+        assert_eq!(rx.try_recv(), Ok(BootStatus::UserKernelStub));
+        assert!(rx.try_recv().is_err());
+        assert_eq!("user-kernel-stub".parse::<BootStatus>().unwrap(), BootStatus::UserKernelStub);
+    }
+
+    #[test]
+    fn little_endian_kernel_is_refused() {
+        let path = temp_file("le-rom.elf", &build_elf(false));
+
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = InterpBackend::new(bus.clone(), Some(path.to_string_lossy().into_owned()), false);
+        back.kernel_profile = KernelProfile::Raw;
+        let err = back.load_custom_kernel().unwrap_err();
+        let problems = check_custom_kernel(&path.to_string_lossy(), KernelProfile::Raw);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(err.to_string().contains("big endian"), "{err}");
+        assert_eq!(problems.len(), 1);
+        // Nothing was written to memory
+        assert_eq!(bus.read().read32(TEST_ROM_BASE).unwrap(), 0);
+    }
+
+    #[test]
+    fn truncated_kernel_is_refused() {
+        let mut elf = build_test_rom();
+        elf.truncate(elf.len() - 8);
+        let path = temp_file("short-rom.elf", &elf);
+
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = InterpBackend::new(bus, Some(path.to_string_lossy().into_owned()), false);
+        back.kernel_profile = KernelProfile::Raw;
+        let err = back.load_custom_kernel().unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("past the end of the file"), "{err}");
+    }
+
+    /// Write a raw image of big-endian ARM code to a temporary file.
+    fn write_image(name: &str, words: &[u32]) -> PathBuf {
+        let image: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        temp_file(&format!("{name}.bin"), &image)
+    }
+
+    #[test]
+    fn boot_images_start_at_their_entry_points() {
+        let code = [0xe3a0_0042, 0xeaff_fffe]; // mov r0, #0x42; b .
+        let boot1 = write_image("boot1", &code);
+        // boot2 starts with a header, whose first word is its length
+        let boot2 = write_image("boot2", &[0x8, 0, code[0], code[1]]);
+
+        for (boot1, boot2, pc, stage) in [
+            (Some(&boot1), None, 0xfff0_0000, BootStatus::Boot1),
+            (None, Some(&boot2), 0x1010_0008, BootStatus::Boot2Stub),
+        ] {
+            let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+            let mut back = InterpBackend::new(bus, None, false);
+            back.boot1 = boot1.map(|p| p.to_string_lossy().into_owned());
+            back.boot2 = boot2.map(|p| p.to_string_lossy().into_owned());
+            back.load_boot_images().unwrap();
+            assert_eq!(back.boot_status, stage);
+            assert_eq!(back.cpu.read_fetch_pc(), pc);
+            assert!(matches!(back.step_for(4), CpuRes::StepOk));
+            assert_eq!(back.cpu.reg.r[0], 0x42);
+            assert_eq!(back.cpu.read_fetch_pc(), pc + 4);
+        }
+
+        // Loading boot2 and then starting boot1 anyway is refused
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = InterpBackend::new(bus, None, false);
+        back.boot1 = Some(boot1.to_string_lossy().into_owned());
+        back.boot2 = Some(boot2.to_string_lossy().into_owned());
+        assert!(back.load_boot_images().is_err());
+        assert_eq!(back.boot_status, BootStatus::Boot0);
+        std::fs::remove_file(&boot1).unwrap();
+        std::fs::remove_file(&boot2).unwrap();
+    }
+
+    #[test]
+    fn oversized_boot1_is_refused() {
+        let boot1 = write_image("big-boot1", &vec![0; 0x4001]);
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = InterpBackend::new(bus, None, false);
+        back.boot1 = Some(boot1.to_string_lossy().into_owned());
+        let err = back.load_boot_images().unwrap_err();
+        std::fs::remove_file(&boot1).unwrap();
+        assert!(err.to_string().contains("fit in SRAM"), "{err}");
+        assert_eq!(back.boot_status, BootStatus::Boot0);
+    }
+
+    #[test]
+    fn svc_sink_captures_only_guest_output() {
+        let out_path = std::env::temp_dir().join(format!("ironic-sink-out-{}.txt", std::process::id()));
+
+        let mut back = backend_with_code(&[]);
+        back.svc_sink = Some(svc_file_sink(&out_path).unwrap());
+        assert!(matches!(back.step_for(100), CpuRes::Semihosting));
+        drop(back);
+
+        let output = std::fs::read_to_string(&out_path).unwrap();
+        std::fs::remove_file(&out_path).unwrap();
+        assert_eq!(output, "hello\n");
+    }
+
+    #[test]
+    fn pc_hooks_run_only_at_their_pc() {
+        use std::sync::atomic::AtomicUsize;
+
+        let mut back = backend_with_code(&[]);
+        // Turn the SYS_WRITE0 into a SYS_WRITEC, so only the 'h' is printed
+        back.add_pc_hook(TEST_ROM_BASE, Box::new(|cpu| {
+            assert_eq!(cpu.reg[0u32], 4);
+            cpu.reg[0u32] = 3;
+        }));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let exit_hits = hits.clone();
+        back.add_pc_hook(TEST_ROM_BASE + 0x18, Box::new(move |cpu| {
+            assert_eq!(cpu.reg[0u32], 0x18);
+            exit_hits.fetch_add(1, Ordering::Relaxed);
+        }));
+
+        assert!(matches!(back.step_for(3), CpuRes::StepOk));
+        assert_eq!(back.svc_buf, "h");
+        assert_eq!(hits.load(Ordering::Relaxed), 0);
+        assert!(matches!(back.step_for(100), CpuRes::Semihosting));
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+        assert_eq!(back.exit_code, Some(0));
+    }
+
+    #[test]
+    fn branch_hook_finds_basic_blocks() {
+        use std::collections::BTreeSet;
+        let mut back = backend_with_code(&[
+            0xe3a0_0003, // 00: mov r0, #3
+            0xe250_0001, // 04: subs r0, r0, #1
+            0x1aff_fffd, // 08: bne 0x04
+            0xea00_0000, // 0c: b 0x14
+            0xe3a0_1001, // 10: mov r1, #1
+            0xe3a0_2002, // 14: mov r2, #2
+        ]);
+
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let recorder = events.clone();
+        back.set_branch_hook(Box::new(move |src, dst| recorder.lock().push((src, dst))));
+        assert!(matches!(back.step_for(9), CpuRes::StepOk));
+        assert_eq!(back.cpu.read_fetch_pc(), TEST_ROM_BASE + 0x18);
+        assert_eq!((back.cpu.reg[1u32], back.cpu.reg[2u32]), (0, 2));
+
+        // The loop is taken twice before falling through to the jump
+        let events = events.lock().clone();
+        assert_eq!(events, vec![
+            (TEST_ROM_BASE + 0x08, TEST_ROM_BASE + 0x04),
+            (TEST_ROM_BASE + 0x08, TEST_ROM_BASE + 0x04),
+            (TEST_ROM_BASE + 0x0c, TEST_ROM_BASE + 0x14),
+        ]);
+
+        // The loop target splits the entry block
+        let starts: BTreeSet<u32> = std::iter::once(TEST_ROM_BASE)
+            .chain(events.iter().map(|&(_, dst)| dst))
+            .collect();
+        assert_eq!(starts.into_iter().collect::<Vec<_>>(),
+            vec![TEST_ROM_BASE, TEST_ROM_BASE + 0x04, TEST_ROM_BASE + 0x14]);
+    }
+
+    #[test]
+    fn fast_forwarded_delay_loop_matches_interpreting_it() {
+        const DELAY: [u32; 7] = [
+            0xe3a0_0a03, // 00: mov r0, #0x3000
+            0xe3a0_1a02, // 04: mov r1, #0x2000
+            0xe250_0001, // 08: subs r0, r0, #1
+            0x1aff_fffd, // 0c: bne 0x08
+            0xe3a0_205a, // 10: mov r2, #0x5a
+            0xe581_2000, // 14: str r2, [r1]
+            0xe1a0_0000, // 18: nop
+        ];
+
+        // Up to (but not including) the nop
+        const CYCLES: usize = 2 + 2 * 0x3000 + 2;
+
+        let mut results = Vec::new();
+        for fast_forward_loops in [false, true] {
+            let mut back = backend_with_code(&DELAY);
+            back.fast_forward_loops = fast_forward_loops;
+            assert!(matches!(back.step_for(CYCLES), CpuRes::StepOk));
+            assert_eq!(back.cpu.read_fetch_pc(), TEST_ROM_BASE + 0x18);
+            let stored = back.bus.read().read32(0x2000).unwrap();
+            results.push((back.cpu.reg.r, back.cpu.reg.cpsr.0, back.cpu_cycle, stored, back.bus_cycle));
+        }
+
+        let (regs, cpsr, cycles, stored, bus_cycles) = results[0];
+        assert_eq!((regs[0], regs[2], stored), (0, 0x5a, 0x5a));
+        assert_eq!(cycles, CYCLES);
+        assert_eq!(bus_cycles, cycles);
+        let (ff_regs, ff_cpsr, ff_cycles, ff_stored, ff_bus_cycles) = results[1];
+        assert_eq!((ff_regs, ff_cpsr, ff_cycles, ff_stored), (regs, cpsr, cycles, stored));
+        assert!(ff_bus_cycles < 16, "{ff_bus_cycles} steps");
+    }
+
+    #[test]
+    fn fast_forwarding_stops_for_budgets_and_breakpoints() {
+        const DELAY: [u32; 3] = [
+            0xe3a0_0a03, // 00: mov r0, #0x3000
+            0xe250_0001, // 04: subs r0, r0, #1
+            0x1aff_fffd, // 08: bne 0x04
+        ];
+
+        // Each budget ends in the middle of the loop
+        let mut back = backend_with_code(&DELAY);
+        back.fast_forward_loops = true;
+        back.max_cycles = Some(1001);
+        assert!(matches!(back.step_for(100), CpuRes::StepOk));
+        assert_eq!(back.cpu_cycle, 100);
+        assert!(matches!(back.step_for(usize::MAX), CpuRes::HaltEmulation(_)));
+        assert_eq!(back.cpu_cycle, 1001);
+        assert_eq!(back.cpu.reg.r[0], 0x3000 - 500);
+
+        // Breakpoints inside the loop are still hit
+        let mut back = backend_with_code(&DELAY);
+        back.fast_forward_loops = true;
+        back.debugger_attached = true;
+        back.breakpoints.insert(TEST_ROM_BASE + 8);
+        assert!(matches!(back.step_for(usize::MAX), CpuRes::Breakpoint(_)));
+        assert_eq!(back.cpu.reg.r[0], 0x3000 - 1);
+    }
+
+    #[test]
+    fn stepping_in_chunks_matches_one_long_step() {
+        let mut results = Vec::new();
+        for chunk in [2, 1000] {
+            let mut back = backend_with_code(&[]);
+            let mut chunks = 1;
+            let res = loop {
+                let before = back.cpu_cycle;
+                match back.step_for(chunk) {
+                    // Each chunk runs for exactly its budget
+                    CpuRes::StepOk => assert_eq!(back.cpu_cycle, before + chunk),
+                    res => break res,
+                }
+                chunks += 1;
+            };
+            assert!(matches!(res, CpuRes::Semihosting));
+            results.push((back.cpu.reg.r, back.cpu_cycle, back.exit_code, chunks));
+        }
+
+        let (regs, cycles, exit_code, chunks) = results[0];
+        assert_eq!(exit_code, Some(0));
+        assert!(chunks > 1);
+        assert_eq!(results[1], (regs, cycles, exit_code, 1));
+    }
+
+    #[test]
+    fn bkpt_stops_for_the_debugger() {
+        const BKPT_OP: u32 = 0xe121_2374; // bkpt #0x1234
+
+        let mut results = Vec::new();
+        for debugger_attached in [true, false] {
+            let mut back = backend_with_code(&[0xe3a0_0004, BKPT_OP]);
+            back.debugger_attached = debugger_attached;
+            results.push((back.step_for(100), back.cpu.read_fetch_pc()));
+        }
+
+        // The CPU is left on the breakpoint until the debugger resumes
+        assert!(matches!(results[0], (CpuRes::Breakpoint(0x1234), pc) if pc == TEST_ROM_BASE + 4));
+        match &results[1].0 {
+            CpuRes::HaltEmulation(reason) => assert_eq!(reason.to_string(), "hit BKPT #0x1234 at 0x00010004"),
+            _ => panic!("BKPT without a debugger should halt emulation"),
+        }
+    }
+}
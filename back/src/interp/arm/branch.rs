@@ -5,26 +5,28 @@ use ironic_core::cpu::reg::Reg;
 use crate::bits::arm::*;
 use crate::interp::DispatchRes;
 
-pub fn sign_extend(x: u32, source_bits: i32, dest_bits: i32) -> i32 {
-    if ((x as i32 >> (source_bits - 1)) & 1) != 0 {
-        x as i32 | ((1 << dest_bits)-(1 << source_bits))
-    } else { 
-        x as i32 
-    }
+/// Sign-extend the low `bits` of `x`.
+pub fn sign_extend(x: u32, bits: i32) -> i32 {
+    ((x << (32 - bits)) as i32) >> (32 - bits)
+}
+
+/// Byte offset encoded in the 24-bit immediate of a branch. Branch targets
+/// are computed with wrapping arithmetic, so they may wrap around the top
+/// or bottom of the address space.
+fn branch_offset(op: &BranchBits) -> u32 {
+    (sign_extend(op.imm24(), 24) as u32) << 2
 }
 
 pub fn bl_imm(cpu: &mut Cpu, op: BranchBits) -> DispatchRes {
-    let offset = sign_extend(op.imm24(), 24, 30) << 2;
     let new_lr = cpu.read_fetch_pc().wrapping_add(4);
-    let dest_pc = (cpu.read_exec_pc() as i32).wrapping_add(offset) as u32;
+    let dest_pc = cpu.read_exec_pc().wrapping_add(branch_offset(&op));
 
     cpu.reg[Reg::Lr] = new_lr;
     cpu.write_exec_pc(dest_pc);
     DispatchRes::RetireBranch
 }
 pub fn b(cpu: &mut Cpu, op: BranchBits) -> DispatchRes {
-    let offset = sign_extend(op.imm24(), 24, 30) << 2;
-    let target = (cpu.read_exec_pc() as i32).wrapping_add(offset) as u32;
+    let target = cpu.read_exec_pc().wrapping_add(branch_offset(&op));
     cpu.write_exec_pc(target);
     DispatchRes::RetireBranch
 }
@@ -36,10 +38,9 @@ pub fn bx(cpu: &mut Cpu, op: BxBits) -> DispatchRes {
 }
 
 pub fn blx_immm(cpu: &mut Cpu, op: BranchBits) -> DispatchRes {
-    let mut offset = (sign_extend(op.imm24(), 24, 30) as u32) << 2;
-    offset += (op.h() as u32) << 1;
+    let offset = branch_offset(&op) | (op.h() as u32) << 1;
     let new_lr = cpu.read_fetch_pc().wrapping_add(4);
-    let dest_pc = (cpu.read_exec_pc()).wrapping_add(4 + offset);
+    let dest_pc = cpu.read_exec_pc().wrapping_add(offset).wrapping_add(4);
 
     cpu.reg.cpsr.set_thumb(true);
     cpu.reg.pc = dest_pc;
@@ -47,3 +48,63 @@ pub fn blx_immm(cpu: &mut Cpu, op: BranchBits) -> DispatchRes {
     DispatchRes::RetireBranch
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use super::*;
+
+    /// Create an ARM CPU about to execute an instruction at `pc`.
+    fn cpu_at(pc: u32) -> Cpu {
+        let mut cpu = Cpu::new(Arc::new(RwLock::new(Bus::with_boot0(None).unwrap())));
+        cpu.write_exec_pc(pc);
+        cpu
+    }
+
+    #[test]
+    fn backward_branch_wraps_to_top_of_memory() {
+        // b #-0x10 (from 0x0, the target is 0x0 + 8 - 0x10)
+        let mut cpu = cpu_at(0x0000_0000);
+        assert!(matches!(b(&mut cpu, BranchBits(0xeaff_fffc)), DispatchRes::RetireBranch));
+        assert_eq!(cpu.read_fetch_pc(), 0xffff_fff8);
+
+        // bl #-0x10
+        let mut cpu = cpu_at(0x0000_0004);
+        assert!(matches!(bl_imm(&mut cpu, BranchBits(0xebff_fffc)), DispatchRes::RetireBranch));
+        assert_eq!(cpu.read_fetch_pc(), 0xffff_fffc);
+        assert_eq!(cpu.reg[Reg::Lr], 0x0000_0008);
+    }
+
+    #[test]
+    fn forward_branch_wraps_to_bottom_of_memory() {
+        // b #+0x10 (from 0xffff_fff0, the target is 0xffff_fff0 + 8 + 0x10)
+        let mut cpu = cpu_at(0xffff_fff0);
+        assert!(matches!(b(&mut cpu, BranchBits(0xea00_0004)), DispatchRes::RetireBranch));
+        assert_eq!(cpu.read_fetch_pc(), 0x0000_0008);
+
+        // blx #+0xa into Thumb state
+        let mut cpu = cpu_at(0xffff_fffc);
+        assert!(matches!(blx_immm(&mut cpu, BranchBits(0xfb00_0000)), DispatchRes::RetireBranch));
+        assert!(cpu.reg.cpsr.thumb());
+        assert_eq!(cpu.read_fetch_pc(), 0x0000_0006);
+        assert_eq!(cpu.reg[Reg::Lr], 0x0000_0000);
+    }
+
+    #[test]
+    fn blx_with_negative_offset() {
+        // blx #-4 (the target is the instruction after this one, in Thumb)
+        let mut cpu = cpu_at(0x0000_1000);
+        assert!(matches!(blx_immm(&mut cpu, BranchBits(0xfaff_ffff)), DispatchRes::RetireBranch));
+        assert!(cpu.reg.cpsr.thumb());
+        assert_eq!(cpu.read_fetch_pc(), 0x0000_1004);
+    }
+
+    #[test]
+    fn disassembled_targets_wrap() {
+        use crate::bits::disassembly::disassmble_arm;
+        assert_eq!(disassmble_arm(0xeaff_fffc, 0x0000_0000).unwrap(), "b 0xfffffff8");
+        assert_eq!(disassmble_arm(0xea00_0004, 0xffff_fff0).unwrap(), "b 0x8");
+    }
+}
@@ -1,17 +1,23 @@
 //! Implementation of co-processor instructions.
 
 use ironic_core::cpu::Cpu;
+use ironic_core::cpu::excep::ExceptionType;
 use crate::bits::arm::*;
 use crate::interp::DispatchRes;
 
 pub fn mcr(cpu: &mut Cpu, op: MoveCoprocBits) -> DispatchRes {
-    assert_eq!(op.coproc(), 15);
+    // p15 is the only coprocessor
+    if op.coproc() != 15 {
+        return DispatchRes::Exception(ExceptionType::Undef(op.0));
+    }
     cpu.p15.write(cpu.reg[op.rt()], op.crn(), op.crm(), op.opc2());
     DispatchRes::RetireOk
 }
 
 pub fn mrc(cpu: &mut Cpu, op: MoveCoprocBits) -> DispatchRes {
-    assert_eq!(op.coproc(), 15);
+    if op.coproc() != 15 {
+        return DispatchRes::Exception(ExceptionType::Undef(op.0));
+    }
     if op.rt() != 15 {
         let val = cpu.p15.read(op.crn(), op.crm(), op.opc2());
         cpu.reg[op.rt()] = val;
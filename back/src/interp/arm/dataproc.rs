@@ -511,9 +511,11 @@ pub fn tst_reg(cpu: &mut Cpu, op: DpTestRegBits) -> DispatchRes {
 }
 
 pub fn clz(cpu: &mut Cpu, op: ClzBits) -> DispatchRes {
-    assert_ne!(op.rm(), 15);
-    assert_ne!(op.rd(), 15);
+    if op.rm() == 15 || op.rd() == 15 {
+        return DispatchRes::FatalErr(anyhow!("clz with r15 is unpredictable pc={:08x}", cpu.read_fetch_pc()));
+    }
 
+    // CLZ never affects the condition flags; an input of zero yields 32.
     let rm = cpu.reg[op.rm()];
     let res = rm.leading_zeros();
     cpu.reg[op.rd()] = res;
@@ -580,4 +582,24 @@ pub fn teq_reg(cpu: &mut Cpu, op: DpTestRegBits) -> DispatchRes {
     cpu.reg.cpsr.set_z(res == 0);
     cpu.reg.cpsr.set_c(carry);
     DispatchRes::RetireOk
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use super::*;
+
+    #[test]
+    fn clz_counts_leading_zeros() {
+        let mut cpu = Cpu::new(Arc::new(RwLock::new(Bus::with_boot0(None).unwrap())));
+        let flags = cpu.reg.cpsr.0;
+        // clz r0, r1
+        for (rm, expected) in [(0, 32), (1, 31), (0x8000_0000, 0), (0xffff_ffff, 0), (0x0001_0000, 15)] {
+            cpu.reg[1u32] = rm;
+            assert!(matches!(clz(&mut cpu, ClzBits(0xe16f_0f11)), DispatchRes::RetireOk));
+            assert_eq!(cpu.reg[0u32], expected, "clz of {rm:#010x}");
+        }
+        assert_eq!(cpu.reg.cpsr.0, flags);
+    }
+}
@@ -1,23 +1,40 @@
 //! Load/store instructions.
 
 
-use anyhow::bail;
+use anyhow::anyhow;
 use ironic_core::cpu::Cpu;
 use ironic_core::cpu::reg::CpuMode;
+use ironic_core::cpu::excep::ExceptionType;
 use ironic_core::cpu::alu::*;
 use crate::bits::arm::*;
 use crate::interp::DispatchRes;
 
-pub fn do_amode(rn: u32, imm: u32, u: bool, p: bool, w: bool) -> anyhow::Result<(u32, u32)> {
+/// Compute the (address, writeback value) pair for a load/store.
+///
+/// - `p` set: offset/pre-indexed, the offset is applied before the access
+///   (and written back if `w` is set).
+/// - `p` clear: post-indexed, the access uses `rn` and the offset is always
+///   written back. Here `w` selects an unprivileged access (`ldrt`),
+///   which callers must handle with [unprivileged].
+pub fn do_amode(rn: u32, imm: u32, u: bool, p: bool, w: bool) -> (u32, u32) {
     let res = if u { rn.wrapping_add(imm) } else { rn.wrapping_sub(imm) };
     match (p, w) {
-        (false, false)  => Ok((rn, res)),
-        (true, false)   => Ok((res, rn)),
-        (true, true)    => Ok((res, res)),
-        (false, true)   => bail!("Unsupported addressing mode?"),
+        (false, _)      => (rn, res),
+        (true, false)   => (res, rn),
+        (true, true)    => (res, res),
     }
 }
 
+/// Perform some access as if the CPU was in User mode, for the `ldrt`
+/// family of instructions. Only the permission checks are affected.
+pub fn unprivileged<T>(cpu: &mut Cpu, f: impl FnOnce(&mut Cpu) -> T) -> T {
+    let mode = cpu.reg.cpsr.mode();
+    cpu.reg.cpsr.set_mode(CpuMode::Usr);
+    let res = f(cpu);
+    cpu.reg.cpsr.set_mode(mode);
+    res
+}
+
 pub fn do_amode_lit(pc: u32, imm: u32, p: bool, u: bool) -> u32 {
     match (p, u) {
         (true, true) => pc.wrapping_add(imm),
@@ -33,11 +50,8 @@ pub fn ldrb_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
         let addr = do_amode_lit(cpu.read_exec_pc(), op.imm12(), op.p(), op.u());
         cpu.read8(addr)
     } else {
-        let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()],
-            op.imm12(), op.u(), op.p(), op.w()){
-                Ok(val) => val,
-                Err(reason) => { return DispatchRes::FatalErr(reason); }
-            };
+        let (addr, wb_addr) = do_amode(cpu.reg[op.rn()],
+            op.imm12(), op.u(), op.p(), op.w());
         cpu.reg[op.rn()] = wb_addr;
         cpu.read8(addr)
     };
@@ -54,10 +68,7 @@ pub fn ldrb_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
 pub fn ldrh_imm(cpu: &mut Cpu, op: LsSignedImmBits) -> DispatchRes {
     assert_ne!(op.rt(), 15);
     let offset = (op.imm4h() << 4) | op.imm4l();
-    let (addr,wb_addr) = match do_amode(cpu.reg[op.rn()], offset, op.u(), op.p(), op.w()) {
-        Ok(val) => val,
-        Err(reason) => { return DispatchRes::FatalErr(reason); }
-    };
+    let (addr,wb_addr) = do_amode(cpu.reg[op.rn()], offset, op.u(), op.p(), op.w());
     let res = match cpu.read16(addr) {
         Ok(val) => val,
         Err(reason) => {
@@ -69,7 +80,63 @@ pub fn ldrh_imm(cpu: &mut Cpu, op: LsSignedImmBits) -> DispatchRes {
     DispatchRes::RetireOk
 }
 
+pub fn ldrsb_imm(cpu: &mut Cpu, op: LsSignedImmBits) -> DispatchRes {
+    assert_ne!(op.rt(), 15);
+    let offset = (op.imm4h() << 4) | op.imm4l();
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()], offset, op.u(), op.p(), op.w());
+    let res = match cpu.read8(addr) {
+        Ok(val) => val,
+        Err(reason) => {
+            return DispatchRes::FatalErr(reason);
+        }
+    };
+    cpu.reg[op.rn()] = wb_addr;
+    cpu.reg[op.rt()] = res as i8 as i32 as u32;
+    DispatchRes::RetireOk
+}
+
+pub fn ldrsb_reg(cpu: &mut Cpu, op: LsSignedRegBits) -> DispatchRes {
+    assert_ne!(op.rt(), 15);
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()], cpu.reg[op.rm()], op.u(), op.p(), op.w());
+    let res = match cpu.read8(addr) {
+        Ok(val) => val,
+        Err(reason) => {
+            return DispatchRes::FatalErr(reason);
+        }
+    };
+    cpu.reg[op.rn()] = wb_addr;
+    cpu.reg[op.rt()] = res as i8 as i32 as u32;
+    DispatchRes::RetireOk
+}
 
+pub fn ldrsh_imm(cpu: &mut Cpu, op: LsSignedImmBits) -> DispatchRes {
+    assert_ne!(op.rt(), 15);
+    let offset = (op.imm4h() << 4) | op.imm4l();
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()], offset, op.u(), op.p(), op.w());
+    let res = match cpu.read16(addr) {
+        Ok(val) => val,
+        Err(reason) => {
+            return DispatchRes::FatalErr(reason);
+        }
+    };
+    cpu.reg[op.rn()] = wb_addr;
+    cpu.reg[op.rt()] = res as i16 as i32 as u32;
+    DispatchRes::RetireOk
+}
+
+pub fn ldrsh_reg(cpu: &mut Cpu, op: LsSignedRegBits) -> DispatchRes {
+    assert_ne!(op.rt(), 15);
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()], cpu.reg[op.rm()], op.u(), op.p(), op.w());
+    let res = match cpu.read16(addr) {
+        Ok(val) => val,
+        Err(reason) => {
+            return DispatchRes::FatalErr(reason);
+        }
+    };
+    cpu.reg[op.rn()] = wb_addr;
+    cpu.reg[op.rt()] = res as i16 as i32 as u32;
+    DispatchRes::RetireOk
+}
 
 pub fn ldr_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
     let res = if op.rn() == 15 {
@@ -77,11 +144,8 @@ pub fn ldr_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
         let addr = do_amode_lit(cpu.read_exec_pc(), op.imm12(), op.p(), op.u());
         cpu.read32(addr)
     } else {
-        let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()],
-            op.imm12(), op.u(), op.p(), op.w()){
-                Ok(val) => val,
-                Err(reason) => { return DispatchRes::FatalErr(reason); }
-            };
+        let (addr, wb_addr) = do_amode(cpu.reg[op.rn()],
+            op.imm12(), op.u(), op.p(), op.w());
         cpu.reg[op.rn()] = wb_addr;
         cpu.read32(addr)
     };
@@ -102,11 +166,8 @@ pub fn ldr_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
 }
 
 pub fn str_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
-    let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()],
-    op.imm12(), op.u(), op.p(), op.w()){
-        Ok(val) => val,
-        Err(reason) => { return DispatchRes::FatalErr(reason); }
-    };
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()],
+        op.imm12(), op.u(), op.p(), op.w());
     cpu.reg[op.rn()] = wb_addr;
     match cpu.write32(addr, cpu.reg[op.rt()]) {
         Ok(_) => DispatchRes::RetireOk,
@@ -114,11 +175,8 @@ pub fn str_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
     }
 }
 pub fn strb_imm(cpu: &mut Cpu, op: LsImmBits) -> DispatchRes {
-    let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()],
-    op.imm12(), op.u(), op.p(), op.w()){
-        Ok(val) => val,
-        Err(reason) => { return DispatchRes::FatalErr(reason); }
-    };
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()],
+        op.imm12(), op.u(), op.p(), op.w());
     cpu.reg[op.rn()] = wb_addr;
     match cpu.write8(addr, cpu.reg[op.rt()]) {
         Ok(_) => DispatchRes::RetireOk,
@@ -134,11 +192,8 @@ pub fn ldr_reg(cpu: &mut Cpu, op: LsRegBits) -> DispatchRes {
         stype: op.stype(), imm5: op.imm5(), c_in: cpu.reg.cpsr.c()
     });
 
-    let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()],
-        offset, op.u(), op.p(), op.w()) {
-            Ok(val) => val,
-            Err(reason) => { return DispatchRes::FatalErr(reason); }
-    };
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()],
+        offset, op.u(), op.p(), op.w());
     let val = match cpu.read32(addr) {
         Ok(val) => val,
         Err(reason) => {
@@ -161,11 +216,8 @@ pub fn str_reg(cpu: &mut Cpu, op: LsRegBits) -> DispatchRes {
         stype: op.stype(), imm5: op.imm5(), c_in: cpu.reg.cpsr.c()
     });
 
-    let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()],
-        offset, op.u(), op.p(), op.w()){
-            Ok(val) => val,
-            Err(reason) => { return DispatchRes::FatalErr(reason); }
-        };
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()],
+        offset, op.u(), op.p(), op.w());
 
     let val = cpu.reg[op.rt()];
     match cpu.write32(addr, val) {
@@ -180,6 +232,87 @@ pub fn str_reg(cpu: &mut Cpu, op: LsRegBits) -> DispatchRes {
 
 
 
+/// Common part of the `ldrt` family: a post-indexed load done with User
+/// mode permissions.
+fn load_trans(cpu: &mut Cpu, rn: u32, rt: u32, offset: u32, u: bool, byte: bool) -> DispatchRes {
+    if rn == 15 || rt == 15 || rn == rt {
+        return DispatchRes::FatalErr(anyhow!("Unpredictable ldrt operands rn={rn} rt={rt}"));
+    }
+    let (addr, wb_addr) = do_amode(cpu.reg[rn], offset, u, false, true);
+    let res = unprivileged(cpu, |cpu| if byte {
+        cpu.read8(addr).map(|x| x as u32)
+    } else {
+        cpu.read32(addr)
+    });
+    match res {
+        Ok(val) => {
+            cpu.reg[rn] = wb_addr;
+            cpu.reg[rt] = val;
+            DispatchRes::RetireOk
+        },
+        Err(reason) => DispatchRes::FatalErr(reason),
+    }
+}
+
+/// Common part of the `strt` family: a post-indexed store done with User
+/// mode permissions.
+fn store_trans(cpu: &mut Cpu, rn: u32, rt: u32, offset: u32, u: bool, byte: bool) -> DispatchRes {
+    if rn == 15 || rt == 15 || rn == rt {
+        return DispatchRes::FatalErr(anyhow!("Unpredictable strt operands rn={rn} rt={rt}"));
+    }
+    let (addr, wb_addr) = do_amode(cpu.reg[rn], offset, u, false, true);
+    let val = cpu.reg[rt];
+    let res = unprivileged(cpu, |cpu| if byte {
+        cpu.write8(addr, val)
+    } else {
+        cpu.write32(addr, val)
+    });
+    match res {
+        Ok(_) => {
+            cpu.reg[rn] = wb_addr;
+            DispatchRes::RetireOk
+        },
+        Err(reason) => DispatchRes::FatalErr(reason),
+    }
+}
+
+/// Compute the shifted register offset for the `ldrt` family.
+fn trans_reg_offset(cpu: &Cpu, op: &LsTransAltBits) -> u32 {
+    let (offset, _) = barrel_shift(ShiftArgs::Reg { rm: cpu.reg[op.rm()],
+        stype: op.stype(), imm5: op.imm5(), c_in: cpu.reg.cpsr.c()
+    });
+    offset
+}
+
+pub fn ldrt(cpu: &mut Cpu, op: LsTransBits) -> DispatchRes {
+    load_trans(cpu, op.rn(), op.rt(), op.imm12(), op.u(), false)
+}
+pub fn ldrbt(cpu: &mut Cpu, op: LsTransBits) -> DispatchRes {
+    load_trans(cpu, op.rn(), op.rt(), op.imm12(), op.u(), true)
+}
+pub fn strt(cpu: &mut Cpu, op: LsTransBits) -> DispatchRes {
+    store_trans(cpu, op.rn(), op.rt(), op.imm12(), op.u(), false)
+}
+pub fn strbt(cpu: &mut Cpu, op: LsTransBits) -> DispatchRes {
+    store_trans(cpu, op.rn(), op.rt(), op.imm12(), op.u(), true)
+}
+pub fn ldrt_reg(cpu: &mut Cpu, op: LsTransAltBits) -> DispatchRes {
+    let offset = trans_reg_offset(cpu, &op);
+    load_trans(cpu, op.rn(), op.rt(), offset, op.u(), false)
+}
+pub fn ldrbt_reg(cpu: &mut Cpu, op: LsTransAltBits) -> DispatchRes {
+    let offset = trans_reg_offset(cpu, &op);
+    load_trans(cpu, op.rn(), op.rt(), offset, op.u(), true)
+}
+pub fn strt_reg(cpu: &mut Cpu, op: LsTransAltBits) -> DispatchRes {
+    let offset = trans_reg_offset(cpu, &op);
+    store_trans(cpu, op.rn(), op.rt(), offset, op.u(), false)
+}
+pub fn strbt_reg(cpu: &mut Cpu, op: LsTransAltBits) -> DispatchRes {
+    let offset = trans_reg_offset(cpu, &op);
+    store_trans(cpu, op.rn(), op.rt(), offset, op.u(), true)
+}
+
 pub fn stm_user(cpu: &mut Cpu, op: StmRegUserBits) -> DispatchRes {
     assert_ne!(op.rn(), 15);
     let reglist = op.register_list();
@@ -427,13 +560,112 @@ pub fn stm(cpu: &mut Cpu, op: LsMultiBits) -> DispatchRes {
     DispatchRes::RetireOk
 }
 
+/// Swap a word or byte between a register and memory.
+///
+/// The old value is read from memory before the new value is written.
+/// Since the interpreter is single-threaded, this is trivially atomic.
+pub fn swp(cpu: &mut Cpu, op: SwpBits) -> DispatchRes {
+    if op.rn() == 15 || op.rt() == 15 || op.rm() == 15 {
+        return DispatchRes::FatalErr(anyhow!("swp with r15 is unpredictable pc={:08x}", cpu.read_fetch_pc()));
+    }
+    if op.rn() == op.rt() || op.rn() == op.rm() {
+        return DispatchRes::FatalErr(anyhow!("swp with rn={} overlapping rt/rm is unpredictable", op.rn()));
+    }
+    let addr = cpu.reg[op.rn()];
+    let new_val = cpu.reg[op.rm()];
+    let old_val = if op.b() {
+        match cpu.read8(addr) {
+            Ok(val) => val as u32,
+            Err(reason) => return DispatchRes::FatalErr(reason),
+        }
+    } else {
+        // Unaligned word reads are rotated, like LDR
+        match cpu.read32(addr & !3) {
+            Ok(val) => val.rotate_right((addr & 3) * 8),
+            Err(reason) => return DispatchRes::FatalErr(reason),
+        }
+    };
+    let res = if op.b() {
+        cpu.write8(addr, new_val)
+    } else {
+        cpu.write32(addr & !3, new_val)
+    };
+    if let Err(reason) = res {
+        return DispatchRes::FatalErr(reason);
+    }
+    cpu.reg[op.rt()] = old_val;
+    DispatchRes::RetireOk
+}
+
+/// Take a data abort for an unaligned access, recording the alignment
+/// fault in p15 like the MMU would.
+fn alignment_fault(cpu: &mut Cpu, addr: u32) -> DispatchRes {
+    cpu.p15.c5_dfsr = 0b0001;
+    cpu.p15.c6_dfar = addr;
+    DispatchRes::Exception(ExceptionType::Dabt)
+}
+
+/// Load a word and tag its address in the exclusive monitor.
+/// Only with [Cpu::exclusives] set, see there.
+pub fn ldrex(cpu: &mut Cpu, op: LdrexBits) -> DispatchRes {
+    if !cpu.exclusives {
+        return DispatchRes::Exception(ExceptionType::Undef(op.0));
+    }
+    if op.rn() == 15 || op.rt() == 15 {
+        return DispatchRes::FatalErr(anyhow!("ldrex with r15 is unpredictable pc={:08x}", cpu.read_fetch_pc()));
+    }
+    let addr = cpu.reg[op.rn()];
+    if addr & 3 != 0 {
+        return alignment_fault(cpu, addr);
+    }
+    match cpu.read32(addr) {
+        Ok(val) => {
+            cpu.reg[op.rt()] = val;
+            cpu.exclusive_addr = Some(addr);
+            DispatchRes::RetireOk
+        },
+        Err(reason) => DispatchRes::FatalErr(reason),
+    }
+}
+
+/// Store a word if the address is still tagged by the exclusive monitor,
+/// writing 0 to `rd` on success and 1 on failure. The monitor is cleared
+/// either way. Only with [Cpu::exclusives] set.
+pub fn strex(cpu: &mut Cpu, op: StrexBits) -> DispatchRes {
+    if !cpu.exclusives {
+        return DispatchRes::Exception(ExceptionType::Undef(op.0));
+    }
+    if op.rn() == 15 || op.rd() == 15 || op.rt() == 15 {
+        return DispatchRes::FatalErr(anyhow!("strex with r15 is unpredictable pc={:08x}", cpu.read_fetch_pc()));
+    }
+    if op.rd() == op.rn() || op.rd() == op.rt() {
+        return DispatchRes::FatalErr(anyhow!("strex with rd={} overlapping rn/rt is unpredictable", op.rd()));
+    }
+    let addr = cpu.reg[op.rn()];
+    if addr & 3 != 0 {
+        return alignment_fault(cpu, addr);
+    }
+    let tagged = cpu.exclusive_addr.take() == Some(addr);
+    if tagged && let Err(reason) = cpu.write32(addr, cpu.reg[op.rt()]) {
+        return DispatchRes::FatalErr(reason);
+    }
+    cpu.reg[op.rd()] = if tagged { 0 } else { 1 };
+    DispatchRes::RetireOk
+}
+
+/// Clear the exclusive monitor. Only with [Cpu::exclusives] set.
+pub fn clrex(cpu: &mut Cpu, op: ClrexBits) -> DispatchRes {
+    if !cpu.exclusives {
+        return DispatchRes::Exception(ExceptionType::Undef(op.0));
+    }
+    cpu.exclusive_addr = None;
+    DispatchRes::RetireOk
+}
+
 pub fn strh_imm(cpu: &mut Cpu, op: LsSignedImmBits) -> DispatchRes {
     let offset = (op.imm4h() << 4) | op.imm4l();
-    let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()],
-        offset, op.u(), op.p(), op.w()) {
-            Ok(val) => val,
-            Err(reason) => { return DispatchRes::FatalErr(reason); }
-        };
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()],
+        offset, op.u(), op.p(), op.w());
     cpu.reg[op.rn()] = wb_addr;
     match cpu.write16(addr, cpu.reg[op.rt()]) {
         Ok(_) => DispatchRes::RetireOk,
@@ -442,13 +674,177 @@ pub fn strh_imm(cpu: &mut Cpu, op: LsSignedImmBits) -> DispatchRes {
 }
 
 pub fn strh_reg(cpu: &mut Cpu, op: LsSignedRegBits) -> DispatchRes {
-    let (addr, wb_addr) = match do_amode(cpu.reg[op.rn()], cpu.reg[op.rm()], op.u(), op.p(), op.w()) {
-        Ok(val) => val,
-        Err(reason) => { return DispatchRes::FatalErr(reason); }
-    };
+    let (addr, wb_addr) = do_amode(cpu.reg[op.rn()], cpu.reg[op.rm()], op.u(), op.p(), op.w());
     cpu.reg[op.rn()] = wb_addr;
     match cpu.write16(addr, cpu.reg[op.rt()]) {
         Ok(_) => DispatchRes::RetireOk,
         Err(reason) => DispatchRes::FatalErr(reason)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use super::*;
+
+    #[test]
+    fn amode_offset() {
+        // ldr r0, [r1, #4] / ldr r0, [r1, #-4]
+        assert_eq!(do_amode(0x1000, 4, true, true, false), (0x1004, 0x1000));
+        assert_eq!(do_amode(0x1000, 4, false, true, false), (0x0ffc, 0x1000));
+    }
+
+    #[test]
+    fn amode_pre_indexed() {
+        // ldr r0, [r1, #4]! / ldr r0, [r1, #-4]!
+        assert_eq!(do_amode(0x1000, 4, true, true, true), (0x1004, 0x1004));
+        assert_eq!(do_amode(0x1000, 4, false, true, true), (0x0ffc, 0x0ffc));
+    }
+
+    #[test]
+    fn amode_post_indexed() {
+        // ldr r0, [r1], #4 / ldr r0, [r1], #-4
+        assert_eq!(do_amode(0x1000, 4, true, false, false), (0x1000, 0x1004));
+        assert_eq!(do_amode(0x1000, 4, false, false, false), (0x1000, 0x0ffc));
+    }
+
+    #[test]
+    fn amode_post_indexed_unprivileged() {
+        // ldrt r0, [r1], #4 / ldrt r0, [r1], #-4
+        assert_eq!(do_amode(0x1000, 4, true, false, true), (0x1000, 0x1004));
+        assert_eq!(do_amode(0x1000, 4, false, false, true), (0x1000, 0x0ffc));
+    }
+
+    #[test]
+    fn amode_wraps() {
+        assert_eq!(do_amode(0xffff_fffc, 8, true, true, false), (0x0000_0004, 0xffff_fffc));
+        assert_eq!(do_amode(0x0000_0004, 8, false, false, false), (0x0000_0004, 0xffff_fffc));
+    }
+
+    #[test]
+    fn ldr_literal_sees_pc_plus_8() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        bus.write().dma_write(0x1000, &[
+            0xe5, 0x9f, 0x00, 0x00, // ldr r0, [pc, #0]
+            0x11, 0x11, 0x11, 0x11,
+            0x22, 0x22, 0x22, 0x22,
+        ]).unwrap();
+        let mut cpu = Cpu::new(bus);
+        cpu.write_exec_pc(0x1000);
+        assert_eq!(cpu.read_exec_pc(), 0x1008);
+        assert!(matches!(ldr_imm(&mut cpu, LsImmBits(0xe59f_0000)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0x2222_2222);
+    }
+
+    #[test]
+    fn signed_loads_sign_extend() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        bus.write().dma_write(0x1000, &[0x80, 0x7f, 0x80, 0x00]).unwrap();
+        let mut cpu = Cpu::new(bus);
+        cpu.reg[1u32] = 0x1000;
+        cpu.reg[2u32] = 2;
+
+        // ldrsb r0, [r1] / ldrsb r0, [r1, #1]
+        assert!(matches!(ldrsb_imm(&mut cpu, LsSignedImmBits(0xe1d1_00d0)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0xffff_ff80);
+        assert!(matches!(ldrsb_imm(&mut cpu, LsSignedImmBits(0xe1d1_00d1)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0x0000_007f);
+
+        // ldrsh r0, [r1, r2] / ldrsh r0, [r1]
+        assert!(matches!(ldrsh_reg(&mut cpu, LsSignedRegBits(0xe191_00f2)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0xffff_8000);
+        assert!(matches!(ldrsh_imm(&mut cpu, LsSignedImmBits(0xe1d1_00f0)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0xffff_807f);
+
+        // ldrsb r0, [r1, r2]
+        assert!(matches!(ldrsb_reg(&mut cpu, LsSignedRegBits(0xe191_00d2)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0xffff_ff80);
+        assert_eq!(cpu.reg[1u32], 0x1000);
+    }
+
+    #[test]
+    fn swp_exchanges_register_and_memory() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        bus.write().dma_write(0x1000, &[0x11, 0x22, 0x33, 0x44]).unwrap();
+        let mut cpu = Cpu::new(bus);
+        cpu.reg[1u32] = 0xdead_beef;
+        cpu.reg[2u32] = 0x1000;
+
+        // swp r0, r1, [r2]
+        assert!(matches!(swp(&mut cpu, SwpBits(0xe102_0091)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0x1122_3344);
+        assert_eq!(cpu.reg[1u32], 0xdead_beef);
+        assert_eq!(cpu.read32(0x1000).unwrap(), 0xdead_beef);
+
+        // swpb r0, r1, [r2] only swaps the low byte of r1
+        cpu.reg[1u32] = 0x5555_5577;
+        assert!(matches!(swp(&mut cpu, SwpBits(0xe142_0091)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0xde);
+        assert_eq!(cpu.read32(0x1000).unwrap(), 0x77ad_beef);
+
+        // swp r0, r0, [r2] stores the old r0 before it's overwritten
+        cpu.reg[0u32] = 0x0102_0304;
+        assert!(matches!(swp(&mut cpu, SwpBits(0xe102_0090)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0x77ad_beef);
+        assert_eq!(cpu.read32(0x1000).unwrap(), 0x0102_0304);
+
+        // swp r2, r1, [r2] is unpredictable
+        assert!(matches!(swp(&mut cpu, SwpBits(0xe102_2091)), DispatchRes::FatalErr(_)));
+    }
+
+    #[test]
+    fn strex_only_succeeds_after_ldrex() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        bus.write().dma_write(0x1000, &[0x00, 0x00, 0x00, 0x01]).unwrap();
+        let mut cpu = Cpu::new(bus);
+        cpu.exclusives = true;
+        cpu.reg[1u32] = 0x1000;
+        cpu.reg[2u32] = 0x1234_5678;
+
+        // ldrex r0, [r1] / strex r3, r2, [r1]
+        assert!(matches!(ldrex(&mut cpu, LdrexBits(0xe191_0f9f)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 1);
+        assert!(matches!(strex(&mut cpu, StrexBits(0xe181_3f92)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[3u32], 0);
+        assert_eq!(cpu.read32(0x1000).unwrap(), 0x1234_5678);
+
+        // Without another ldrex, the monitor is clear and the store fails
+        cpu.reg[2u32] = 0xdead_beef;
+        assert!(matches!(strex(&mut cpu, StrexBits(0xe181_3f92)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[3u32], 1);
+        assert_eq!(cpu.read32(0x1000).unwrap(), 0x1234_5678);
+
+        // clrex clears it, too
+        assert!(matches!(ldrex(&mut cpu, LdrexBits(0xe191_0f9f)), DispatchRes::RetireOk));
+        assert!(matches!(clrex(&mut cpu, ClrexBits(0xf57f_f01f)), DispatchRes::RetireOk));
+        assert!(matches!(strex(&mut cpu, StrexBits(0xe181_3f92)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[3u32], 1);
+    }
+
+    #[test]
+    fn exclusives_are_undefined_unless_enabled() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut cpu = Cpu::new(bus);
+        cpu.reg[1u32] = 0x1000;
+        assert!(matches!(ldrex(&mut cpu, LdrexBits(0xe191_0f9f)),
+            DispatchRes::Exception(ExceptionType::Undef(0xe191_0f9f))));
+        assert!(matches!(clrex(&mut cpu, ClrexBits(0xf57f_f01f)),
+            DispatchRes::Exception(ExceptionType::Undef(_))));
+        assert_eq!(cpu.exclusive_addr, None);
+    }
+
+    #[test]
+    fn unaligned_exclusives_take_a_data_abort() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut cpu = Cpu::new(bus);
+        cpu.exclusives = true;
+        cpu.reg[1u32] = 0x1002;
+        assert!(matches!(ldrex(&mut cpu, LdrexBits(0xe191_0f9f)),
+            DispatchRes::Exception(ExceptionType::Dabt)));
+        assert_eq!((cpu.p15.c5_dfsr, cpu.p15.c6_dfar), (0b0001, 0x1002));
+        assert!(matches!(strex(&mut cpu, StrexBits(0xe181_3f92)),
+            DispatchRes::Exception(ExceptionType::Dabt)));
+    }
+}
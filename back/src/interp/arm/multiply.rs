@@ -3,19 +3,35 @@ use ironic_core::cpu::Cpu;
 use crate::bits::arm::*;
 use crate::interp::DispatchRes;
 
-pub fn umull(cpu: &mut Cpu, op: SignedMlBits) -> DispatchRes {
-    let rm_val = cpu.reg[op.rm()] as usize;
-    let rn_val = cpu.reg[op.rn()] as usize;
-    let res = rm_val * rn_val;
-
-    let res_hi = ((res & 0xffff_ffff_0000_0000) >> 32) as u32;
-    let res_lo =  (res & 0x0000_0000_ffff_ffff) as u32;
+/// Write a 64-bit multiply result to RdHi:RdLo, updating N/Z if requested.
+/// C and V are left unchanged.
+fn write_long_result(cpu: &mut Cpu, op: &SignedMlBits, res: u64) {
+    let res_hi = (res >> 32) as u32;
+    let res_lo = res as u32;
     cpu.reg[op.rdhi()] = res_hi;
     cpu.reg[op.rdlo()] = res_lo;
     if op.s() {
         cpu.reg.cpsr.set_n((res_hi & 0x8000_0000) != 0);
-        cpu.reg.cpsr.set_z((res_hi == 0) && (res_lo == 0));
+        cpu.reg.cpsr.set_z(res == 0);
     }
+}
+
+/// Read the existing value of RdHi:RdLo for the accumulating variants.
+fn read_long_acc(cpu: &Cpu, op: &SignedMlBits) -> u64 {
+    ((cpu.reg[op.rdhi()] as u64) << 32) | cpu.reg[op.rdlo()] as u64
+}
+
+pub fn umull(cpu: &mut Cpu, op: SignedMlBits) -> DispatchRes {
+    let rm_val = cpu.reg[op.rm()] as u64;
+    let rn_val = cpu.reg[op.rn()] as u64;
+    write_long_result(cpu, &op, rm_val * rn_val);
+    DispatchRes::RetireOk
+}
+
+pub fn smull(cpu: &mut Cpu, op: SignedMlBits) -> DispatchRes {
+    let rm_val = cpu.reg[op.rm()] as i32 as i64;
+    let rn_val = cpu.reg[op.rn()] as i32 as i64;
+    write_long_result(cpu, &op, (rm_val * rn_val) as u64);
     DispatchRes::RetireOk
 }
 
@@ -35,18 +51,83 @@ pub fn mul(cpu: &mut Cpu, op: MulBits) -> DispatchRes {
 pub fn umlal(cpu: &mut Cpu, op: SignedMlBits) -> DispatchRes {
     let rm_val = cpu.reg[op.rm()] as u64;
     let rn_val = cpu.reg[op.rn()] as u64;
-    let existing: u64 = ((cpu.reg[op.rdhi()] as u64) << 32) | cpu.reg[op.rdlo()] as u64;
-
-    let res = (rm_val*rn_val)+existing;
-    let res_hi = ((res & 0xffff_ffff_0000_0000) >> 32) as u32;
-    let res_lo =  (res & 0x0000_0000_ffff_ffff) as u32;
+    let res = (rm_val * rn_val).wrapping_add(read_long_acc(cpu, &op));
+    write_long_result(cpu, &op, res);
+    DispatchRes::RetireOk
+}
 
-    cpu.reg[op.rdhi()] = res_hi;
-    cpu.reg[op.rdlo()] = res_lo;
-    if op.s() {
-        cpu.reg.cpsr.set_n((res_hi & 0x8000_0000) != 0);
-        cpu.reg.cpsr.set_z((res_hi == 0) && (res_lo == 0));
-    }
+pub fn smlal(cpu: &mut Cpu, op: SignedMlBits) -> DispatchRes {
+    let rm_val = cpu.reg[op.rm()] as i32 as i64;
+    let rn_val = cpu.reg[op.rn()] as i32 as i64;
+    let res = ((rm_val * rn_val) as u64).wrapping_add(read_long_acc(cpu, &op));
+    write_long_result(cpu, &op, res);
     DispatchRes::RetireOk
 }
 
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use super::*;
+
+    fn cpu() -> Cpu {
+        Cpu::new(Arc::new(RwLock::new(Bus::with_boot0(None).unwrap())))
+    }
+
+    // Encode a long multiply: RdHi=r1, RdLo=r0, Rs=r3, Rm=r2
+    fn long_mul(base: u32, s: bool) -> SignedMlBits {
+        SignedMlBits(0xe000_0090 | base | ((s as u32) << 20) | (1 << 16) | (3 << 8) | 2)
+    }
+
+    #[test]
+    fn umull_full_width() {
+        // umull r0, r1, r2, r3
+        let mut cpu = cpu();
+        cpu.reg.r[2] = 0xffff_ffff;
+        cpu.reg.r[3] = 0xffff_ffff;
+        umull(&mut cpu, long_mul(0x0080_0000, false));
+        assert_eq!(cpu.reg.r[1], 0xffff_fffe);
+        assert_eq!(cpu.reg.r[0], 0x0000_0001);
+    }
+
+    #[test]
+    fn smull_signed_product() {
+        // smulls r0, r1, r2, r3 with -2 * 3
+        let mut cpu = cpu();
+        cpu.reg.r[2] = (-2i32) as u32;
+        cpu.reg.r[3] = 3;
+        smull(&mut cpu, long_mul(0x00c0_0000, true));
+        assert_eq!(cpu.reg.r[1], 0xffff_ffff);
+        assert_eq!(cpu.reg.r[0], 0xffff_fffa);
+        assert!(cpu.reg.cpsr.n());
+        assert!(!cpu.reg.cpsr.z());
+    }
+
+    #[test]
+    fn smlal_accumulates() {
+        // smlal r0, r1, r2, r3: 0x0000_0001_0000_0000 + (-1 * 1)
+        let mut cpu = cpu();
+        cpu.reg.r[1] = 0x0000_0001;
+        cpu.reg.r[0] = 0x0000_0000;
+        cpu.reg.r[2] = 0xffff_ffff;
+        cpu.reg.r[3] = 1;
+        smlal(&mut cpu, long_mul(0x00e0_0000, false));
+        assert_eq!(cpu.reg.r[1], 0x0000_0000);
+        assert_eq!(cpu.reg.r[0], 0xffff_ffff);
+    }
+
+    #[test]
+    fn umlal_sets_zero_flag() {
+        // umlals r0, r1, r2, r3: 0xffff_ffff_ffff_ffff + 1 wraps to zero
+        let mut cpu = cpu();
+        cpu.reg.r[1] = 0xffff_ffff;
+        cpu.reg.r[0] = 0xffff_ffff;
+        cpu.reg.r[2] = 1;
+        cpu.reg.r[3] = 1;
+        umlal(&mut cpu, long_mul(0x00a0_0000, true));
+        assert_eq!((cpu.reg.r[1], cpu.reg.r[0]), (0, 0));
+        assert!(cpu.reg.cpsr.z());
+        assert!(!cpu.reg.cpsr.n());
+    }
+}
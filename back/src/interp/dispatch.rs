@@ -7,6 +7,7 @@ use crate::interp::{arm, thumb};
 use crate::decode::arm::ArmInst;
 use crate::decode::thumb::ThumbInst;
 use anyhow::anyhow;
+use log::error;
 /// The result of dispatching an instruction.
 #[derive(Debug)]
 pub enum DispatchRes {
@@ -23,24 +24,45 @@ pub enum DispatchRes {
     RetireOk,
     /// This instruction resulted in an exception.
     Exception(ExceptionType),
-    /// A breakpoint instruction has been executed, the emulator pauses and waites for the debugger.
+    /// A breakpoint instruction has been executed, the emulator pauses and waits for the debugger.
+    /// The program counter is left pointing at the breakpoint.
     Breakpoint
 }
 
 
+/// Returns true if this ARM opcode is one of the undefined instructions IOS
+/// uses for syscalls.
+pub fn is_ios_syscall(op: u32) -> bool {
+    (op & 0xe600_0000) == 0xe600_0000
+}
+
 /// Handler for unimplemented ARM instructions.
+///
+/// Opcodes which don't decode to anything take the undefined instruction
+/// exception, while instructions we recognize but don't implement yet are
+/// fatal.
 pub fn arm_unimpl_instr(cpu: &mut Cpu, op: u32) -> DispatchRes {
-    if (op & 0xe600_0000) != 0xe600_0000 {
-        return DispatchRes::FatalErr(anyhow!("pc={:08x} Couldn't dispatch instruction {op:08x} ({:?})",
-        cpu.read_fetch_pc(), ArmInst::decode(op)));
+    if is_ios_syscall(op) {
+        return DispatchRes::Exception(ExceptionType::Undef(op));
     }
-    DispatchRes::Exception(ExceptionType::Undef(op))
+    let inst = ArmInst::decode(op);
+    if inst == ArmInst::Undefined {
+        error!(target: "Other", "pc={:08x} Undefined instruction {op:08x}", cpu.read_fetch_pc());
+        return DispatchRes::Exception(ExceptionType::Undef(op));
+    }
+    DispatchRes::FatalErr(anyhow!("pc={:08x} Couldn't dispatch instruction {op:08x} ({inst:?})",
+        cpu.read_fetch_pc()))
 }
 
 /// Handler for unimplemented Thumb instructions.
 pub fn thumb_unimpl_instr(cpu: &mut Cpu, op: u16) -> DispatchRes {
-    DispatchRes::FatalErr(anyhow!("pc={:08x} Couldn't dispatch Thumb instruction {op:04x} ({:?})",
-        cpu.read_fetch_pc(), ThumbInst::decode(op)))
+    let inst = ThumbInst::decode(op);
+    if inst == ThumbInst::Undefined {
+        error!(target: "Other", "pc={:08x} Undefined Thumb instruction {op:04x}", cpu.read_fetch_pc());
+        return DispatchRes::Exception(ExceptionType::Undef(op as u32));
+    }
+    DispatchRes::FatalErr(anyhow!("pc={:08x} Couldn't dispatch Thumb instruction {op:04x} ({inst:?})",
+        cpu.read_fetch_pc()))
 }
 
 // We use these macros to coerce the borrow checker into taking pointers to
@@ -67,11 +89,17 @@ impl ArmFn {
             Mrs         => ArmFn(afn!(arm::status::mrs)),
             Umull       => ArmFn(afn!(arm::multiply::umull)),
             Umlal       => ArmFn(afn!(arm::multiply::umlal)),
+            Smull       => ArmFn(afn!(arm::multiply::smull)),
+            Smlal       => ArmFn(afn!(arm::multiply::smlal)),
             Mul         => ArmFn(afn!(arm::multiply::mul)),
 
             LdrImm      => ArmFn(afn!(arm::loadstore::ldr_imm)),
             LdrbImm     => ArmFn(afn!(arm::loadstore::ldrb_imm)),
             LdrhImm     => ArmFn(afn!(arm::loadstore::ldrh_imm)),
+            LdrsbImm    => ArmFn(afn!(arm::loadstore::ldrsb_imm)),
+            LdrsbReg    => ArmFn(afn!(arm::loadstore::ldrsb_reg)),
+            LdrshImm    => ArmFn(afn!(arm::loadstore::ldrsh_imm)),
+            LdrshReg    => ArmFn(afn!(arm::loadstore::ldrsh_reg)),
             SubImm      => ArmFn(afn!(arm::dataproc::sub_imm)),
             SubReg      => ArmFn(afn!(arm::dataproc::sub_reg)),
 
@@ -84,6 +112,14 @@ impl ArmFn {
 
             StrImm      => ArmFn(afn!(arm::loadstore::str_imm)),
             StrbImm     => ArmFn(afn!(arm::loadstore::strb_imm)),
+            Ldrt        => ArmFn(afn!(arm::loadstore::ldrt)),
+            Ldrbt       => ArmFn(afn!(arm::loadstore::ldrbt)),
+            Strt        => ArmFn(afn!(arm::loadstore::strt)),
+            Strbt       => ArmFn(afn!(arm::loadstore::strbt)),
+            LdrtAlt     => ArmFn(afn!(arm::loadstore::ldrt_reg)),
+            LdrbtAlt    => ArmFn(afn!(arm::loadstore::ldrbt_reg)),
+            StrtAlt     => ArmFn(afn!(arm::loadstore::strt_reg)),
+            StrbtAlt    => ArmFn(afn!(arm::loadstore::strbt_reg)),
             Stmdb       => ArmFn(afn!(arm::loadstore::stmdb)),
             Stm         => ArmFn(afn!(arm::loadstore::stm)),
             StmRegUser  => ArmFn(afn!(arm::loadstore::stm_user)),
@@ -125,6 +161,11 @@ impl ArmFn {
             BicReg      => ArmFn(afn!(arm::dataproc::bic_reg)),
             BicRegShiftReg => ArmFn(afn!(arm::dataproc::bic_rsr)),
             Clz         => ArmFn(afn!(arm::dataproc::clz)),
+            Swp         => ArmFn(afn!(arm::loadstore::swp)),
+            Swpb        => ArmFn(afn!(arm::loadstore::swp)),
+            Ldrex       => ArmFn(afn!(arm::loadstore::ldrex)),
+            Strex       => ArmFn(afn!(arm::loadstore::strex)),
+            Clrex       => ArmFn(afn!(arm::loadstore::clrex)),
 
             OrrRegShiftReg => ArmFn(afn!(arm::dataproc::orr_rsr)),
             AndRegShiftReg => ArmFn(afn!(arm::dataproc::and_rsr)),
@@ -182,6 +223,7 @@ impl ThumbFn {
             SubImmAlt   => ThumbFn(tfn!(thumb::dataproc::sub_imm_alt)),
             AddSpImmAlt => ThumbFn(tfn!(thumb::dataproc::add_sp_imm_alt)),
             AddSpImm    => ThumbFn(tfn!(thumb::dataproc::add_sp_imm)),
+            AddPcImm    => ThumbFn(tfn!(thumb::dataproc::add_pc_imm)),
             SubSpImm    => ThumbFn(tfn!(thumb::dataproc::sub_sp_imm)),
             AndReg      => ThumbFn(tfn!(thumb::dataproc::and_reg)),
             OrrReg      => ThumbFn(tfn!(thumb::dataproc::orr_reg)),
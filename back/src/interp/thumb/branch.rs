@@ -38,7 +38,23 @@ pub fn blx_imm_suffix(cpu: &mut Cpu, op: BlBits) -> DispatchRes {
     cpu.write_exec_pc(dest_pc);
     DispatchRes::RetireBranch
 }
+
+/// Branch to some address, switching to ARM or Thumb state based on bit 0.
+/// When switching to ARM state, the target is forced to be word-aligned.
+fn interworking_branch(cpu: &mut Cpu, dest_pc: u32) {
+    let thumb = dest_pc & 1 != 0;
+    cpu.reg.cpsr.set_thumb(thumb);
+    if thumb {
+        cpu.write_exec_pc(dest_pc & 0xffff_fffe);
+    } else {
+        cpu.write_exec_pc(dest_pc & 0xffff_fffc);
+    }
+}
+
 pub fn bx(cpu: &mut Cpu, op: BxBits) -> DispatchRes {
+    if op.sbz() != 0 {
+        error!(target: "Other", "bx with non-zero SBZ bits {:04x} at pc={:08x}", op.0, cpu.read_fetch_pc());
+    }
     let dest_pc = if op.rm() == 15 {
         // temporary shity cIOS hack
         // they literally just flick between ARM and thumb using a bx pc
@@ -54,18 +70,19 @@ pub fn bx(cpu: &mut Cpu, op: BxBits) -> DispatchRes {
     } else {
         cpu.reg[op.rm()]
     };
-    cpu.reg.cpsr.set_thumb(dest_pc & 1 != 0);
-    cpu.write_exec_pc(dest_pc & 0xffff_fffe);
+    interworking_branch(cpu, dest_pc);
     DispatchRes::RetireBranch
 }
 
 pub fn blx_reg(cpu: &mut Cpu, op: BxBits) -> DispatchRes {
-    assert_ne!(op.rm(), 15);
-    let new_lr = cpu.read_fetch_pc().wrapping_add(2) | 1;
+    if op.rm() == 15 {
+        return DispatchRes::FatalErr(anyhow!("blx pc is unpredictable pc={:08x}", cpu.read_fetch_pc()));
+    }
+    // Read the target before LR is clobbered, since `blx lr` is legal
     let dest_pc = cpu.reg[op.rm()];
-    cpu.reg.cpsr.set_thumb(dest_pc & 1 != 0);
+    let new_lr = cpu.read_fetch_pc().wrapping_add(2) | 1;
     cpu.reg[Reg::Lr] = new_lr;
-    cpu.write_exec_pc(dest_pc & 0xffff_fffe);
+    interworking_branch(cpu, dest_pc);
     DispatchRes::RetireBranch
 }
 
@@ -99,3 +116,56 @@ pub fn b(cpu: &mut Cpu, op: BranchBits) -> DispatchRes {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use crate::bits::disassembly::disassmble_thumb;
+    use super::*;
+
+    #[test]
+    fn branches_wrap_around_the_address_space() {
+        let mut cpu = Cpu::new(Arc::new(RwLock::new(Bus::with_boot0(None).unwrap())));
+        cpu.reg.cpsr.set_thumb(true);
+
+        // b #-0x8 from 0x0 (the target is 0x0 + 4 - 0x8)
+        cpu.write_exec_pc(0x0000_0000);
+        assert!(matches!(b_unconditional(&mut cpu, BranchAltBits(0xe7fc)), DispatchRes::RetireBranch));
+        assert_eq!(cpu.read_fetch_pc(), 0xffff_fffc);
+
+        // b #+0x4 from 0xffff_fffc
+        assert!(matches!(b_unconditional(&mut cpu, BranchAltBits(0xe002)), DispatchRes::RetireBranch));
+        assert_eq!(cpu.read_fetch_pc(), 0x0000_0004);
+
+        assert_eq!(disassmble_thumb(0xe7fc, 0x0000_0000).unwrap(), "b 0xfffffffc");
+        // beq #-0xc (the immediate is only 8 bits wide)
+        assert!(disassmble_thumb(0xd0fa, 0x0000_1000).unwrap().ends_with(" 0xff8"));
+    }
+
+    #[test]
+    fn bx_lr_returns_to_arm_or_thumb_callers() {
+        let mut cpu = Cpu::new(Arc::new(RwLock::new(Bus::with_boot0(None).unwrap())));
+
+        // blx r8 from Thumb leaves LR pointing at the next halfword, with bit 0 set
+        cpu.reg.cpsr.set_thumb(true);
+        cpu.write_exec_pc(0x0000_1000);
+        cpu.reg[8u32] = 0x0000_2001;
+        assert!(matches!(blx_reg(&mut cpu, BxBits(0x47c0)), DispatchRes::RetireBranch));
+        assert_eq!(cpu.reg[Reg::Lr], 0x0000_1003);
+        assert!(cpu.reg.cpsr.thumb());
+        assert_eq!(cpu.read_fetch_pc(), 0x0000_2000);
+
+        // bx lr back to a Thumb caller
+        assert!(matches!(bx(&mut cpu, BxBits(0x4770)), DispatchRes::RetireBranch));
+        assert!(cpu.reg.cpsr.thumb());
+        assert_eq!(cpu.read_fetch_pc(), 0x0000_1002);
+
+        // bx lr back to an ARM caller
+        cpu.reg[Reg::Lr] = 0x0000_3004;
+        assert!(matches!(bx(&mut cpu, BxBits(0x4770)), DispatchRes::RetireBranch));
+        assert!(!cpu.reg.cpsr.thumb());
+        assert_eq!(cpu.read_fetch_pc(), 0x0000_3004);
+    }
+}
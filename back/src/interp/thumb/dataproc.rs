@@ -82,8 +82,13 @@ pub fn add_reg_alt(cpu: &mut Cpu, op: AddRegAltBits) -> DispatchRes {
     let rd = if op.dn() { op.rdn() | 0x8 } else { op.rdn() };
     let rn = rd;
     let (alu_out, _n, _z, _c, _v) = add_generic(cpu.reg[rn], cpu.reg[op.rm()]);
-    cpu.reg[rd] = alu_out;
-    DispatchRes::RetireOk
+    if rd == 15 {
+        cpu.write_exec_pc(alu_out & 0xffff_fffe);
+        DispatchRes::RetireBranch
+    } else {
+        cpu.reg[rd] = alu_out;
+        DispatchRes::RetireOk
+    }
 }
 
 pub fn tst_reg(cpu: &mut Cpu, op: CmpRegBits) -> DispatchRes {
@@ -284,6 +289,13 @@ pub fn add_sp_imm(cpu: &mut Cpu, op: MovImmBits) -> DispatchRes {
     DispatchRes::RetireOk
 }
 
+/// `adr rd, #imm` (an alias for `add rd, pc, #imm`). The PC is word-aligned first.
+pub fn add_pc_imm(cpu: &mut Cpu, op: MovImmBits) -> DispatchRes {
+    let imm = (op.imm8() as u32) << 2;
+    cpu.reg[op.rd()] = (cpu.read_exec_pc() & 0xffff_fffc).wrapping_add(imm);
+    DispatchRes::RetireOk
+}
+
 pub fn add_sp_imm_alt(cpu: &mut Cpu, op: AddSubSpImmAltBits) -> DispatchRes {
     let imm7 = (op.imm7() as u32) << 2;
     let res = cpu.reg[Reg::Sp].wrapping_add(imm7);
@@ -293,3 +305,104 @@ pub fn add_sp_imm_alt(cpu: &mut Cpu, op: AddSubSpImmAltBits) -> DispatchRes {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use crate::bits::disassembly::disassmble_thumb;
+    use super::*;
+
+    /// Run `op` with r0 = `val`, r1 = `amount`, and the carry flag set.
+    /// Returns (r0, carry).
+    fn shift(op: u16, val: u32, amount: u32) -> (u32, bool) {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut cpu = Cpu::new(bus);
+        cpu.reg.r[0] = val;
+        cpu.reg.r[1] = amount;
+        cpu.reg.cpsr.set_c(true);
+        assert!(matches!(mov_rsr(&mut cpu, MovRsrBits(op)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg.cpsr.z(), cpu.reg.r[0] == 0);
+        assert_eq!(cpu.reg.cpsr.n(), cpu.reg.r[0] & 0x8000_0000 != 0);
+        (cpu.reg.r[0], cpu.reg.cpsr.c())
+    }
+
+    const LSLS: u16 = 0x4088; // lsls r0, r1
+    const LSRS: u16 = 0x40c8; // lsrs r0, r1
+    const ASRS: u16 = 0x4108; // asrs r0, r1
+    const RORS: u16 = 0x41c8; // rors r0, r1
+
+    #[test]
+    fn lsrs_by_register() {
+        assert_eq!(shift(LSRS, 0x8000_0003, 1), (0x4000_0001, true));
+        assert_eq!(shift(LSRS, 0x8000_0002, 1), (0x4000_0001, false));
+        // Zero leaves the value and carry alone, only the low byte counts
+        assert_eq!(shift(LSRS, 0x1234, 0), (0x1234, true));
+        assert_eq!(shift(LSRS, 0x1234, 0x100), (0x1234, true));
+        // 32 shifts out everything, carry is bit 31
+        assert_eq!(shift(LSRS, 0x8000_0000, 32), (0, true));
+        assert_eq!(shift(LSRS, 0x7fff_ffff, 32), (0, false));
+        assert_eq!(shift(LSRS, 0xffff_ffff, 33), (0, false));
+    }
+
+    #[test]
+    fn lsls_by_register() {
+        assert_eq!(shift(LSLS, 0x8000_0001, 1), (0x0000_0002, true));
+        assert_eq!(shift(LSLS, 0x0000_0001, 31), (0x8000_0000, false));
+        assert_eq!(shift(LSLS, 0x0000_0001, 32), (0, true));
+        assert_eq!(shift(LSLS, 0xffff_ffff, 33), (0, false));
+    }
+
+    #[test]
+    fn asrs_by_register() {
+        assert_eq!(shift(ASRS, 0x8000_0001, 1), (0xc000_0000, true));
+        assert_eq!(shift(ASRS, 0x8000_0000, 40), (0xffff_ffff, true));
+        assert_eq!(shift(ASRS, 0x7fff_ffff, 40), (0, false));
+    }
+
+    #[test]
+    fn rors_by_register() {
+        assert_eq!(shift(RORS, 0x0000_0001, 1), (0x8000_0000, true));
+        assert_eq!(shift(RORS, 0x0000_0002, 1), (0x0000_0001, false));
+        // Multiples of 32 leave the value alone, carry is bit 31
+        assert_eq!(shift(RORS, 0x8000_0000, 32), (0x8000_0000, true));
+        assert_eq!(shift(RORS, 0x1234, 64), (0x1234, false));
+    }
+
+    #[test]
+    fn disassemble_shift_by_register() {
+        assert_eq!(disassmble_thumb(LSLS, 0).unwrap(), "lsls r0, r1");
+        assert_eq!(disassmble_thumb(LSRS, 0).unwrap(), "lsrs r0, r1");
+        assert_eq!(disassmble_thumb(ASRS, 0).unwrap(), "asrs r0, r1");
+        assert_eq!(disassmble_thumb(RORS, 0).unwrap(), "rors r0, r1");
+    }
+
+    #[test]
+    fn neg_flags() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut cpu = Cpu::new(bus);
+        const NEG_R0_R1: u16 = 0x4248; // neg r0, r1
+
+        cpu.reg.r[1] = 1;
+        neg(&mut cpu, NegBits(NEG_R0_R1));
+        assert_eq!(cpu.reg.r[0], 0xffff_ffff);
+        assert!(cpu.reg.cpsr.n() && !cpu.reg.cpsr.z());
+        assert!(!cpu.reg.cpsr.c() && !cpu.reg.cpsr.v());
+
+        cpu.reg.r[1] = 0x8000_0000;
+        neg(&mut cpu, NegBits(NEG_R0_R1));
+        assert_eq!(cpu.reg.r[0], 0x8000_0000);
+        assert!(cpu.reg.cpsr.n() && cpu.reg.cpsr.v());
+        assert!(!cpu.reg.cpsr.c());
+
+        // Negating zero doesn't borrow
+        cpu.reg.r[1] = 0;
+        neg(&mut cpu, NegBits(NEG_R0_R1));
+        assert_eq!(cpu.reg.r[0], 0);
+        assert!(cpu.reg.cpsr.z() && cpu.reg.cpsr.c());
+        assert!(!cpu.reg.cpsr.n() && !cpu.reg.cpsr.v());
+
+        assert_eq!(disassmble_thumb(NEG_R0_R1, 0).unwrap(), "neg r0, r1");
+    }
+}
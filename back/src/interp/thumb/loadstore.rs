@@ -337,4 +337,48 @@ pub fn pop(cpu: &mut Cpu, op: PopBits) -> DispatchRes {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use crate::interp::thumb::dataproc::add_pc_imm;
+    use super::*;
+
+    /// Create a Thumb CPU about to execute an instruction at `pc`.
+    fn thumb_cpu_at(pc: u32) -> Cpu {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        bus.write().dma_write(0x1000, &[
+            0x11, 0x11, 0x11, 0x11,
+            0x22, 0x22, 0x22, 0x22,
+            0x33, 0x33, 0x33, 0x33,
+        ]).unwrap();
+        let mut cpu = Cpu::new(bus);
+        cpu.reg.cpsr.set_thumb(true);
+        cpu.write_exec_pc(pc);
+        cpu
+    }
+
+    #[test]
+    fn ldr_literal_sees_aligned_pc_plus_4() {
+        // ldr r0, [pc, #0] at 0x1000 reads from 0x1004
+        let mut cpu = thumb_cpu_at(0x1000);
+        assert_eq!(cpu.read_exec_pc(), 0x1004);
+        assert!(matches!(ldr_lit(&mut cpu, LoadStoreAltBits(0x4800)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0x2222_2222);
+
+        // At 0x1002, the PC (0x1006) is word-aligned down to 0x1004
+        let mut cpu = thumb_cpu_at(0x1002);
+        assert_eq!(cpu.read_exec_pc(), 0x1006);
+        assert!(matches!(ldr_lit(&mut cpu, LoadStoreAltBits(0x4800)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[0u32], 0x2222_2222);
+    }
 
+    #[test]
+    fn adr_sees_aligned_pc_plus_4() {
+        // adr r1, #4 (add r1, pc, #4)
+        let mut cpu = thumb_cpu_at(0x1002);
+        assert!(matches!(add_pc_imm(&mut cpu, MovImmBits(0xa101)), DispatchRes::RetireOk));
+        assert_eq!(cpu.reg[1u32], 0x1008);
+    }
+}
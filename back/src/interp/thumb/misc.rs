@@ -15,7 +15,7 @@ pub fn svc(_cpu: &mut Cpu, _op: MiscBits) -> DispatchRes {
 /// fd = cpu debug print on
 /// fc = cpu debug print off
 /// fb = dump RAM and continue
-/// All other values, store in scratch reg and wait for debugger
+/// All other values, store in scratch reg and stop for the debugger
 pub fn bkpt(cpu: &mut Cpu, op: MiscBits) -> DispatchRes {
     let cmd = op.imm8() as u8;
     info!(target:"Other", "Breakpoint instruction: {cmd:#x}");
@@ -32,8 +32,7 @@ pub fn bkpt(cpu: &mut Cpu, op: MiscBits) -> DispatchRes {
             return DispatchRes::RetireOk;
         },
         0xfb => {
-            let bus = cpu.bus.read();
-            match bus.dump_memory("bkpt.bin") {
+            match cpu.with_bus(|bus| bus.dump_memory("bkpt.bin")) {
                 Ok(path) => {
                     debug!(target: "Other", "Dumped RAM to {}/*.bkpt.bin", path.to_string_lossy());
                     return DispatchRes::RetireOk;
@@ -3,8 +3,13 @@
 pub mod back;
 pub mod bits;
 pub mod decode;
+pub mod gdb;
 
 pub mod interp;
 
 pub mod ipc;
+pub mod perf;
 pub mod ppc;
+pub mod symbols;
+pub mod testrom;
+pub mod trace;
@@ -0,0 +1,76 @@
+//! Periodic reports of emulation speed.
+//!
+//! [PerfReporter] is polled by the interpreter loop with the current cycle
+//! counts, and logs the number of instructions and bus cycles run per
+//! second (of host time) since its last report. The clock is only read
+//! every [CHECK_CYCLES] cycles, so polling it is cheap.
+
+use std::time::{Duration, Instant};
+
+use log::info;
+
+/// Number of CPU cycles between reading the clock.
+const CHECK_CYCLES: usize = 0x10000;
+
+/// Logs instructions and bus cycles per second at a steady cadence.
+pub struct PerfReporter {
+    /// Time between reports.
+    interval: Duration,
+    /// Read the clock again once the CPU reaches this cycle.
+    next_check: usize,
+    /// When the last report was made, and the cycle counts at that time.
+    last: Option<(Instant, usize, usize)>,
+}
+impl PerfReporter {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, next_check: 0, last: None }
+    }
+
+    /// Log a report if the interval has passed since the last one.
+    pub fn poll(&mut self, cpu_cycle: usize, bus_cycle: usize) {
+        if cpu_cycle < self.next_check {
+            return;
+        }
+        self.next_check = cpu_cycle.saturating_add(CHECK_CYCLES);
+        if let Some(report) = self.sample(Instant::now(), cpu_cycle, bus_cycle) {
+            info!(target: "Other", "{report}");
+        }
+    }
+
+    /// Describe the speed since the last report, if it was at least an
+    /// interval ago. The first sample only starts the count.
+    fn sample(&mut self, now: Instant, cpu_cycle: usize, bus_cycle: usize) -> Option<String> {
+        let Some((then, last_cpu, last_bus)) = self.last else {
+            self.last = Some((now, cpu_cycle, bus_cycle));
+            return None;
+        };
+        let elapsed = now.duration_since(then);
+        if elapsed < self.interval {
+            return None;
+        }
+        self.last = Some((now, cpu_cycle, bus_cycle));
+        let secs = elapsed.as_secs_f64();
+        let ips = cpu_cycle.saturating_sub(last_cpu) as f64 / secs;
+        let bus = bus_cycle.saturating_sub(last_bus) as f64 / secs;
+        Some(format!("IPS: {:.2}M, bus cycles/s: {:.2}M", ips / 1e6, bus / 1e6))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_rates_once_per_interval() {
+        let mut perf = PerfReporter::new(Duration::from_secs(1));
+        let start = Instant::now();
+        assert_eq!(perf.sample(start, 0, 0), None);
+        // Not a whole interval yet
+        assert_eq!(perf.sample(start + Duration::from_millis(500), 1_000_000, 500_000), None);
+        assert_eq!(perf.sample(start + Duration::from_secs(2), 8_000_000, 4_000_000).as_deref(),
+            Some("IPS: 4.00M, bus cycles/s: 2.00M"));
+        // Rates are measured from the last report
+        assert_eq!(perf.sample(start + Duration::from_secs(3), 9_000_000, 5_000_000).as_deref(),
+            Some("IPS: 1.00M, bus cycles/s: 1.00M"));
+    }
+}
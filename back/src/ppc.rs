@@ -1,20 +1,23 @@
 //! Backend for handling PowerPC HLE.
 //!
-//! NOTE: The socket is blocking right now, but I guess ultimately we don't
-//! want that. 
+//! Nothing here blocks for long: the socket is polled, and waiting on
+//! ARM-world is done with [PpcIrqSignal], so the thread stops promptly when
+//! [PpcBackend::stop_requested] is set (e.g. after the emulator thread exits).
 
 use ironic_core::bus::*;
 use ironic_core::dev::hlwd::irq::*;
+use ironic_core::dev::hlwd::ipc::PpcIrqSignal;
 use crate::back::*;
 
-use log::{info, error};
+use log::{debug, info, warn, error};
 use parking_lot::RwLock;
 use std::env::temp_dir;
 use std::path::PathBuf;
 use std::thread;
 use std::sync::Arc;
-use std::net::Shutdown;
-use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::{ErrorKind, Read, Write};
 
 
 #[cfg(target_family = "unix")]
@@ -23,27 +26,54 @@ use std::time::Duration;
 #[cfg(target_family = "windows")]
 use uds_windows::{UnixStream, UnixListener};
 
+// Command numbers used by the PPC-side client. These are part of the socket
+// protocol, so don't renumber them.
+pub const CMD_HOST_READ: u32 = 1;
+pub const CMD_HOST_WRITE: u32 = 2;
+pub const CMD_MESSAGE: u32 = 3;
+pub const CMD_ACK: u32 = 4;
+pub const CMD_MESSAGE_NO_RETURN: u32 = 5;
+pub const CMD_READ_BULK: u32 = 6;
+pub const CMD_WRITE_BULK: u32 = 7;
+pub const CMD_VERSION: u32 = 8;
+pub const CMD_SHUTDOWN: u32 = 255;
+
+/// Version of the socket protocol, sent in reply to [CMD_VERSION]. Servers
+/// which predate the command (version 1) hang up on it instead.
+///
+/// - 2: Added [CMD_READ_BULK], [CMD_WRITE_BULK] and [CMD_VERSION]
+/// - 3: Failed requests get [ERR_REPLY] instead of `OK` (or for reads,
+///   instead of the data, and the connection is closed)
+pub const PROTOCOL_VERSION: u32 = 3;
+
 /// A type of command sent over the socket.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum Command { 
-    HostWrite, 
-    HostRead, 
-    Message, 
-    Ack, 
-    MessageNoReturn,
-    Shutdown,
-    Unimpl,
+    HostRead = CMD_HOST_READ,
+    HostWrite = CMD_HOST_WRITE,
+    Message = CMD_MESSAGE,
+    Ack = CMD_ACK,
+    MessageNoReturn = CMD_MESSAGE_NO_RETURN,
+    ReadBulk = CMD_READ_BULK,
+    WriteBulk = CMD_WRITE_BULK,
+    Version = CMD_VERSION,
+    Shutdown = CMD_SHUTDOWN,
+    /// Any unrecognized command number.
+    Unimpl = 0,
 }
 impl Command {
     fn from_u32(x: u32) -> Self {
         match x {
-            1 => Self::HostRead,
-            2 => Self::HostWrite,
-            3 => Self::Message,
-            4 => Self::Ack,
-            5 => Self::MessageNoReturn,
-            255 => Self::Shutdown,
+            CMD_HOST_READ => Self::HostRead,
+            CMD_HOST_WRITE => Self::HostWrite,
+            CMD_MESSAGE => Self::Message,
+            CMD_ACK => Self::Ack,
+            CMD_MESSAGE_NO_RETURN => Self::MessageNoReturn,
+            CMD_READ_BULK => Self::ReadBulk,
+            CMD_WRITE_BULK => Self::WriteBulk,
+            CMD_VERSION => Self::Version,
+            CMD_SHUTDOWN => Self::Shutdown,
             _ => Self::Unimpl,
         }
     }
@@ -65,11 +95,111 @@ impl SocketReq {
         let len = u32::from_le_bytes(s[0x8..0xc].try_into().unwrap());
         SocketReq { cmd, addr, len }
     }
+
+    /// Number of bytes following the header: the data, for writes.
+    pub fn payload_len(&self) -> usize {
+        match self.cmd {
+            Command::HostWrite | Command::WriteBulk => self.len as usize,
+            _ => 0,
+        }
+    }
 }
 
 pub const IPC_SOCK: &str = "ironic-ppc.sock";
 pub const BUF_LEN: usize = 0x10000;
 
+/// Sent instead of the usual reply to a request which can't be handled.
+pub const ERR_REPLY: &[u8] = b"ER";
+
+/// Why ARM-world raised the PPC IPC IRQ.
+enum IpcEvent {
+    /// ARM acknowledged our last message.
+    Ack,
+    /// ARM sent us a message.
+    Message(u32),
+}
+
+/// How clients connect to the PPC HLE server. Parsed from `unix` (a socket
+/// at [IPC_SOCK] in the temporary directory) or `tcp:<addr:port>`.
+///
+/// There's no authentication: any client which can connect can read and
+/// write physical memory. Only bind TCP to a loopback address, unless
+/// everything on the network is trusted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PpcTransport {
+    #[default]
+    Unix,
+    Tcp(String),
+}
+impl std::str::FromStr for PpcTransport {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.split_once(':') {
+            None if s == "unix" => Ok(PpcTransport::Unix),
+            Some(("tcp", addr)) if !addr.is_empty() => Ok(PpcTransport::Tcp(addr.to_owned())),
+            _ => anyhow::bail!("Expected unix or tcp:<addr:port>, got \"{s}\""),
+        }
+    }
+}
+
+/// A connection to a client, over any transport.
+pub trait PpcStream: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()>;
+    fn shutdown(&self, how: Shutdown) -> std::io::Result<()>;
+}
+impl PpcStream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+    fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+}
+impl PpcStream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+    fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+/// A socket accepting clients, over any transport.
+pub trait PpcListener {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()>;
+    fn accept_client(&self) -> std::io::Result<Box<dyn PpcStream>>;
+}
+impl PpcListener for UnixListener {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        UnixListener::set_nonblocking(self, nonblocking)
+    }
+    fn accept_client(&self) -> std::io::Result<Box<dyn PpcStream>> {
+        Ok(Box::new(self.accept()?.0))
+    }
+}
+impl PpcListener for TcpListener {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        TcpListener::set_nonblocking(self, nonblocking)
+    }
+    fn accept_client(&self) -> std::io::Result<Box<dyn PpcStream>> {
+        let (stream, _) = self.accept()?;
+        // Requests are small and latency-sensitive
+        stream.set_nodelay(true)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// How long to wait on the socket or on ARM-world before checking whether
+/// we've been asked to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
 pub struct PpcBackend {
     /// Reference to the system bus.
     pub bus: Arc<RwLock<Bus>>,
@@ -77,25 +207,66 @@ pub struct PpcBackend {
     pub ibuf: [u8; BUF_LEN],
     /// Output buffer for the socket.
     pub obuf: [u8; BUF_LEN],
+    /// Number of bytes received in the input buffer.
+    ibuf_len: usize,
     /// Counter to prevent infinite retry on the socket
-    socket_errors: u8
+    socket_errors: u8,
+    /// Raised when ARM-world raises the PPC IRQ line.
+    irq_signal: Arc<PpcIrqSignal>,
+    /// Set to make the thread return from [Backend::run].
+    pub stop_requested: Arc<AtomicBool>,
+    /// How clients connect to us.
+    pub transport: PpcTransport,
 }
 impl PpcBackend {
     pub fn new(bus: Arc<RwLock<Bus>>) -> Self {
+        let irq_signal = bus.read().ppc_irq_signal.clone();
         PpcBackend {
             bus,
             ibuf: [0; BUF_LEN],
             obuf: [0; BUF_LEN],
+            ibuf_len: 0,
             socket_errors: 0,
+            irq_signal,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            transport: PpcTransport::default(),
         }
     }
 
-    fn recv(&mut self, client: &mut UnixStream) -> Option<usize> {
-        let res = client.read(&mut self.ibuf);
-        match res {
-            Ok(len) => if len == 0 { None } else { Some(len) },
-            Err(_) => None
+    fn stopping(&self) -> bool {
+        self.stop_requested.load(Ordering::Relaxed)
+    }
+
+    /// Read from the client until the input buffer holds at least `len`
+    /// bytes. Anything after that (the start of the next request) is
+    /// kept for later. Returns false if the client went away, or if we were
+    /// asked to stop.
+    fn recv_until(&mut self, client: &mut dyn PpcStream, len: usize) -> bool {
+        while self.ibuf_len < len {
+            if self.stopping() {
+                return false;
+            }
+            match client.read(&mut self.ibuf[self.ibuf_len..]) {
+                Ok(0) => {
+                    info!(target: "PPC", "Dropping client: disconnected");
+                    return false;
+                },
+                Ok(n) => self.ibuf_len += n,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
+                Err(e) => {
+                    info!(target: "PPC", "Dropping client: {e}");
+                    return false;
+                },
+            }
         }
+        true
+    }
+
+    /// Drop a handled request from the input buffer.
+    fn consume(&mut self, req: &SocketReq) {
+        let used = 0xc + req.payload_len();
+        self.ibuf.copy_within(used..self.ibuf_len, 0);
+        self.ibuf_len -= used;
     }
 }
 
@@ -111,181 +282,283 @@ impl PpcBackend {
         dir
     }
 
-    /// Handle clients connected to the socket.
-    pub fn server_loop(&mut self, sock: UnixListener) -> anyhow::Result<()> {
-            let res = sock.accept();
-            let mut client = match res {
-                Ok((stream, _)) => stream,
+    /// Accept clients on the socket, one at a time, until we're asked to
+    /// stop.
+    pub fn server_loop(&mut self, sock: impl PpcListener) -> anyhow::Result<()> {
+        sock.set_nonblocking(true)?;
+        while !self.stopping() {
+            let mut client = match sock.accept_client() {
+                Ok(stream) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                },
                 Err(e) => {
                     if self.socket_errors > 10 {
                         info!(target:"PPC", "accept() error {e:?}");
                         return Err(anyhow::anyhow!(e));
                     }
-                    else {
-                        self.socket_errors += 1;
-                        std::thread::sleep(Duration::from_millis(50));
-                        return Ok(());
-                    }
+                    self.socket_errors += 1;
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
                 }
             };
             self.socket_errors = 0;
 
-            loop {
-                info!(target:"PPC", "waiting for command");
-
-                let res = self.wait_for_request(&mut client);
-                if let Some(req) = res {
-                    match req.cmd {
-                        Command::Ack => self.handle_ack(req)?,
-                        Command::HostRead => self.handle_read(&mut client, req)?,
-                        Command::HostWrite => self.handle_write(&mut client, req)?,
-                        Command::Message => {
-                            self.handle_message(&mut client, req)?;
-                            let armmsg = self.wait_for_resp();
-                            let _ = client.write(&u32::to_le_bytes(armmsg))?; // maybe FIXME: is it ok to ignore the # of bytes written here?
-                        },
-                        Command::MessageNoReturn => {
-                            self.handle_message(&mut client, req)?;
-                        },
-                        Command::Shutdown => {
-                            let _ = client.write(b"kk")?;
+            // Reads time out, so that we notice when we're asked to stop
+            client.set_nonblocking(false)?;
+            client.set_read_timeout(Some(POLL_INTERVAL))?;
+            if let Err(reason) = self.serve_client(client.as_mut()) {
+                info!(target: "PPC", "Dropping client: {reason}");
+            }
+            let _ = client.shutdown(Shutdown::Both);
+        }
+        Ok(())
+    }
+
+    /// Reply `OK` to a request which succeeded, or [ERR_REPLY] if it failed.
+    fn reply(client: &mut dyn PpcStream, res: anyhow::Result<()>) -> std::io::Result<()> {
+        match res {
+            Ok(()) => client.write_all(b"OK"),
+            Err(reason) => {
+                error!(target: "PPC", "Request failed: {reason:#}");
+                client.write_all(ERR_REPLY)
+            },
+        }
+    }
+
+    /// Handle commands from a client until it disconnects or shuts down the
+    /// connection. Requests which fail are answered with [ERR_REPLY], and
+    /// only problems talking to the client or reaching the bus are returned.
+    fn serve_client(&mut self, client: &mut dyn PpcStream) -> anyhow::Result<()> {
+        self.ibuf_len = 0;
+        loop {
+            info!(target:"PPC", "waiting for command");
+
+            let Some(req) = self.wait_for_request(client) else {
+                break;
+            };
+            match req.cmd {
+                Command::Ack => {
+                    // Acks have no reply
+                    if let Err(reason) = self.handle_ack(&req) {
+                        error!(target: "PPC", "Request failed: {reason:#}");
+                    }
+                },
+                Command::HostRead | Command::ReadBulk => {
+                    let res = match req.cmd {
+                        Command::ReadBulk => self.handle_read_bulk(&req),
+                        _ => self.handle_read(&req),
+                    };
+                    if let Err(reason) = res {
+                        // There's no status in the reply to a read, so hang
+                        // up rather than let the client take this for data
+                        error!(target: "PPC", "Dropping client: request failed: {reason:#}");
+                        client.write_all(ERR_REPLY)?;
+                        break;
+                    }
+                    client.write_all(&self.obuf[0..req.len as usize])?;
+                },
+                Command::HostWrite | Command::WriteBulk => {
+                    if !self.recv_payload(client, &req) {
+                        break;
+                    }
+                    let res = match req.cmd {
+                        Command::WriteBulk => self.handle_write_bulk(&req),
+                        _ => self.handle_write(&req),
+                    };
+                    Self::reply(client, res)?;
+                },
+                Command::Message => {
+                    let res = self.handle_message(&req);
+                    let sent = res.is_ok();
+                    Self::reply(client, res)?;
+                    if sent {
+                        let Some(armmsg) = self.wait_for_resp()? else {
                             break;
-                        }
-                        Command::Unimpl => break,
+                        };
+                        client.write_all(&u32::to_le_bytes(armmsg))?;
                     }
+                },
+                Command::MessageNoReturn => {
+                    let res = self.handle_message(&req);
+                    Self::reply(client, res)?;
+                },
+                Command::Version => client.write_all(&PROTOCOL_VERSION.to_le_bytes())?,
+                Command::Shutdown => {
+                    client.write_all(b"kk")?;
+                    break;
                 }
+                Command::Unimpl => break,
             }
-            client.shutdown(Shutdown::Both)?;
+            self.consume(&req);
+        }
         Ok(())
     }
 
-    /// Block until we get a response from ARM-world.
-    fn wait_for_resp(&mut self) -> u32 {
-        info!(target: "PPC", "waiting for response ...");
+    /// Wait until the PPC IRQ line is raised. Returns false if we were asked
+    /// to stop first.
+    fn wait_for_irq(&self) -> anyhow::Result<bool> {
         loop {
-            if self.bus.read().hlwd.irq.ppc_irq_output {
-                info!(target: "PPC", "got irq");
-                let mut bus = self.bus.write();
-
-                if bus.hlwd.ipc.state.ppc_ack {
-                    info!(target: "PPC", "got extra ACK");
-                    bus.hlwd.ipc.state.ppc_ack = false;
-                    bus.hlwd.irq.ppc_irq_status.unset(HollywoodIrq::PpcIpc);
-                    bus.hlwd.irq.update_irq_lines();
-                    continue
-                }
-
-                if bus.hlwd.ipc.state.ppc_req {
-                    let armmsg = bus.hlwd.ipc.arm_msg;
-                    info!(target: "PPC", "Got message from ARM {armmsg:08x}");
-                    bus.hlwd.ipc.state.ppc_req = false;
-                    bus.hlwd.ipc.state.arm_ack = true;
-                    bus.hlwd.irq.ppc_irq_status.unset(HollywoodIrq::PpcIpc);
-                    bus.hlwd.irq.update_irq_lines();
-                    return armmsg;
-                }
+            // Read the count first, so that an IRQ raised after checking the
+            // line still wakes us up
+            let seen = self.irq_signal.count();
+            if with_bus_read(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.hlwd.irq.ppc_irq_output)? {
+                return Ok(true);
+            }
+            if self.stopping() {
+                return Ok(false);
+            }
+            self.irq_signal.wait(seen, POLL_INTERVAL);
+        }
+    }
 
-                drop(bus); // Release RwLock
-                error!(target: "PPC", "Invalid IRQ state");
-                unreachable!("Invalid IRQ state. You forgot to update your IRQ lines somewhere!");
+    /// Acknowledge whatever ARM-world raised the PPC IPC IRQ for.
+    fn take_ipc_event(&self) -> anyhow::Result<IpcEvent> {
+        let event = with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| {
+            let event = if bus.hlwd.ipc.state.ppc_ack {
+                bus.hlwd.ipc.state.ppc_ack = false;
+                IpcEvent::Ack
+            } else if bus.hlwd.ipc.state.ppc_req {
+                bus.hlwd.ipc.state.ppc_req = false;
+                bus.hlwd.ipc.state.arm_ack = true;
+                IpcEvent::Message(bus.hlwd.ipc.arm_msg)
             } else {
-                thread::sleep(std::time::Duration::from_millis(10));
+                return None;
+            };
+            bus.hlwd.irq.ppc_irq_status.unset(HollywoodIrq::PpcIpc);
+            bus.hlwd.irq.update_irq_lines();
+            Some(event)
+        })?;
+        let Some(event) = event else {
+            error!(target: "PPC", "Invalid IRQ state");
+            unreachable!("Invalid IRQ state. You forgot to update your IRQ lines somewhere!");
+        };
+        Ok(event)
+    }
+
+    /// Block until we get a response from ARM-world. Returns None if we were
+    /// asked to stop first.
+    fn wait_for_resp(&mut self) -> anyhow::Result<Option<u32>> {
+        info!(target: "PPC", "waiting for response ...");
+        loop {
+            if !self.wait_for_irq()? {
+                return Ok(None);
+            }
+            info!(target: "PPC", "got irq");
+            match self.take_ipc_event()? {
+                IpcEvent::Ack => info!(target: "PPC", "got extra ACK"),
+                IpcEvent::Message(armmsg) => {
+                    info!(target: "PPC", "Got message from ARM {armmsg:08x}");
+                    return Ok(Some(armmsg));
+                },
             }
         }
     }
 
-    /// Block until we get an ACK from ARM-world.
-    fn wait_for_ack(&mut self) {
+    /// Block until we get an ACK from ARM-world. Returns false if we were
+    /// asked to stop first.
+    fn wait_for_ack(&mut self) -> anyhow::Result<bool> {
         info!(target: "PPC", "waiting for ACK ...");
         loop {
-            if self.bus.read().hlwd.irq.ppc_irq_output {
-                info!(target: "PPC", "got irq");
-                let mut bus = self.bus.write();
-
-                if bus.hlwd.ipc.state.ppc_ack {
-                    bus.hlwd.ipc.state.ppc_ack = false;
+            if !self.wait_for_irq()? {
+                return Ok(false);
+            }
+            info!(target: "PPC", "got irq");
+            match self.take_ipc_event()? {
+                IpcEvent::Ack => {
                     info!(target: "PPC", "got ACK");
-                    bus.hlwd.irq.ppc_irq_status.unset(HollywoodIrq::PpcIpc);
-                    bus.hlwd.irq.update_irq_lines();
-                    break;
-                }
-                if bus.hlwd.ipc.state.ppc_req {
-                    let armmsg = bus.hlwd.ipc.arm_msg;
+                    return Ok(true);
+                },
+                IpcEvent::Message(armmsg) => {
                     info!(target: "PPC", "Got extra message from ARM {armmsg:08x}");
-                    bus.hlwd.ipc.state.ppc_req = false;
-                    bus.hlwd.ipc.state.arm_ack = true;
-                    bus.hlwd.irq.ppc_irq_status.unset(HollywoodIrq::PpcIpc);
-                    bus.hlwd.irq.update_irq_lines();
-                    continue;
-                }
-
-                drop(bus); // Release RwLock
-                error!(target: "PPC", "Invalid IRQ state");
-                unreachable!("Invalid IRQ state. You forgot to update your IRQ lines somewhere!")
-            } else {
-                thread::sleep(std::time::Duration::from_millis(10));
+                },
             }
         }
     }
 
-    /// Block until we receive some command message from a client.
-    fn wait_for_request(&mut self, client: &mut UnixStream) -> Option<SocketReq> {
-        let mut long_block = 0u8;
-        loop {
-            let try_recv = self.recv(client); // maybe FIXME: allow discarding recv length here?
-            // As we wait longer, increase the time we sleep
-            if try_recv.is_none() {
-                long_block = long_block.saturating_add(1);
-                std::thread::sleep(std::time::Duration::from_millis(5 * long_block as u64));
-            }
-            else {
-                break;
-            }
+    /// Wait until we receive some command message from a client. Returns
+    /// None if the client went away, if it sent a request we can't handle,
+    /// or if we were asked to stop.
+    fn wait_for_request(&mut self, client: &mut dyn PpcStream) -> Option<SocketReq> {
+        // The header may arrive in pieces
+        if !self.recv_until(client, 0xc) {
+            return None;
         }
         let req = SocketReq::from_buf(
             &self.ibuf[0..0xc].try_into().unwrap()
         );
         if req.len as usize > BUF_LEN - 0xc {
-            error!(target: "PPC", "Socket message exceeds BUF_LEN {BUF_LEN:x}");
-            panic!("Socket message exceeds BUF_LEN {BUF_LEN:x}");
+            error!(target: "PPC", "Dropping client: request for {:x} bytes exceeds BUF_LEN {BUF_LEN:x}", req.len);
+            let _ = client.write_all(ERR_REPLY);
+            return None;
         }
         Some(req)
     }
 
+    /// Wait for the rest of a request's payload, if it was split over
+    /// several reads. Returns false if the client went away, or if we were
+    /// asked to stop.
+    fn recv_payload(&mut self, client: &mut dyn PpcStream, req: &SocketReq) -> bool {
+        self.recv_until(client, 0xc + req.payload_len())
+    }
+
+    /// Bulk transfers move whole words, as many as fit in the buffers.
+    fn check_bulk_len(req: &SocketReq) -> anyhow::Result<()> {
+        if req.len == 0 || !req.len.is_multiple_of(4) {
+            anyhow::bail!("Bulk transfer of {:x} bytes at {:08x} isn't a whole number of words", req.len, req.addr);
+        }
+        Ok(())
+    }
+
     /// Read from physical memory.
-    pub fn handle_read(&mut self, client: &mut UnixStream, req: SocketReq) -> anyhow::Result<()> {
+    /// The data is left at the start of the output buffer.
+    pub fn handle_read(&mut self, req: &SocketReq) -> anyhow::Result<()> {
         info!(target: "PPC", "read {:x} bytes at {:08x}", req.len, req.addr);
-        self.bus.read().dma_read(req.addr,
-            &mut self.obuf[0..req.len as usize])?;
-        let _ = client.write(&self.obuf[0..req.len as usize])?; // maybe FIXME: is it ok to ignore the # of bytes written here?
-        Ok(())
+        let buf = &mut self.obuf[0..req.len as usize];
+        with_bus_read(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.ppc_read(req.addr, buf))?
     }
 
     /// Write to physical memory.
-    pub fn handle_write(&mut self, client: &mut UnixStream, req: SocketReq) -> anyhow::Result<()> {
+    pub fn handle_write(&mut self, req: &SocketReq) -> anyhow::Result<()> {
         info!(target: "PPC", "write {:x} bytes at {:08x}", req.len, req.addr);
         let data = &self.ibuf[0xc..(0xc + req.len as usize)];
-        self.bus.write().dma_write(req.addr, data)?;
-        let _ = client.write("OK".as_bytes())?; // maybe FIXME: is it ok to ignore the # of bytes written here?
-        Ok(())
+        with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.ppc_write(req.addr, data))?
+    }
+
+    /// Read many words from physical memory at once. The data is sent as-is
+    /// (big-endian), like [PpcBackend::handle_read].
+    pub fn handle_read_bulk(&mut self, req: &SocketReq) -> anyhow::Result<()> {
+        Self::check_bulk_len(req)?;
+        debug!(target: "PPC", "bulk read {:x} bytes at {:08x}", req.len, req.addr);
+        let buf = &mut self.obuf[0..req.len as usize];
+        with_bus_read(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.ppc_read(req.addr, buf))?
+    }
+
+    /// Write many words to physical memory at once. See
+    /// [PpcBackend::handle_read_bulk].
+    pub fn handle_write_bulk(&mut self, req: &SocketReq) -> anyhow::Result<()> {
+        Self::check_bulk_len(req)?;
+        debug!(target: "PPC", "bulk write {:x} bytes at {:08x}", req.len, req.addr);
+        let data = &self.ibuf[0xc..(0xc + req.len as usize)];
+        with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.ppc_write(req.addr, data))?
     }
 
     /// Tell ARM-world that an IPC request is ready at the location indicated
     /// by the pointer in PPC_MSG.
-    pub fn handle_message(&mut self, client: &mut UnixStream, req: SocketReq) -> anyhow::Result<()> {
-        let mut bus = self.bus.write();
-        bus.hlwd.ipc.ppc_msg = req.addr;
-        bus.hlwd.ipc.state.arm_req = true;
-        bus.hlwd.ipc.state.arm_ack = true;
-        let _ = client.write("OK".as_bytes())?; // maybe FIXME: is it ok to ignore the # of bytes written here?
-        Ok(())
+    pub fn handle_message(&mut self, req: &SocketReq) -> anyhow::Result<()> {
+        with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| {
+            bus.hlwd.ipc.ppc_msg = req.addr;
+            bus.hlwd.ipc.state.arm_req = true;
+            bus.hlwd.ipc.state.arm_ack = true;
+        })
     }
 
-    pub fn handle_ack(&mut self, _req: SocketReq) -> anyhow::Result<()> {
-        let mut bus = self.bus.write();
-        let ppc_ctrl = bus.hlwd.ipc.read_handler(4)? & 0x3c;
-        bus.hlwd.ipc.write_handler(4, ppc_ctrl | 0x8)?;
-        Ok(())
+    pub fn handle_ack(&mut self, _req: &SocketReq) -> anyhow::Result<()> {
+        with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| {
+            let ppc_ctrl = bus.hlwd.ipc.read_handler(4)? & 0x3c;
+            bus.hlwd.ipc.write_handler(4, ppc_ctrl | 0x8)
+        })?
     }
 
 }
@@ -294,44 +567,281 @@ impl PpcBackend {
 impl Backend for PpcBackend {
     fn run(&mut self) -> anyhow::Result<()> {
         info!(target: "PPC", "PPC backend thread started");
-        self.bus.write().hlwd.ipc.state.ppc_ctrl_write(0x36);
+        with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.hlwd.ipc.state.ppc_ctrl_write(0x36))?;
 
         loop {
-            if self.bus.read().hlwd.ppc_on {
+            if with_bus_read(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.hlwd.ppc_on)? {
                 info!(target: "PPC", "Broadway came online");
                 break;
             }
+            if self.stopping() {
+                return Ok(());
+            }
             thread::sleep(std::time::Duration::from_millis(500));
         }
 
         // Block until we get an IRQ with an ACK/MSG
-        self.wait_for_ack();
+        if !self.wait_for_ack()? {
+            return Ok(());
+        }
 
         // Send an extra ACK
-        self.bus.write().hlwd.ipc.state.arm_ack = true;
+        with_bus_write(&self.bus, Some(BUS_LOCK_TIMEOUT), |bus| bus.hlwd.ipc.state.arm_ack = true)?;
         thread::sleep(std::time::Duration::from_millis(100));
 
-        loop {
-            // Try binding to the socket
-            let res = std::fs::remove_file(PpcBackend::resolve_socket_path());
-            match res {
-                Ok(_) => {},
-                Err(_e) => {},
-            }
-            let res = UnixListener::bind(PpcBackend::resolve_socket_path());
-            let sock = match res {
-                Ok(sock) => Some(sock),
-                Err(e) => {
-                    error!(target: "PPC", "Couldn't bind to {},\n{e:?}", PpcBackend::resolve_socket_path().to_string_lossy());
-                    None
+        match self.transport.clone() {
+            PpcTransport::Unix => {
+                let path = PpcBackend::resolve_socket_path();
+                while !self.stopping() {
+                    // Try binding to the socket
+                    let _ = std::fs::remove_file(&path);
+                    match UnixListener::bind(&path) {
+                        // If we successfully bind, run the server until it exits
+                        Ok(sock) => self.server_loop(sock)?,
+                        Err(e) => {
+                            error!(target: "PPC", "Couldn't bind to {},\n{e:?}", path.to_string_lossy());
+                            thread::sleep(std::time::Duration::from_millis(500));
+                        }
+                    }
                 }
-            };
-
-            // If we successfully bind, run the server until it exits
-            if sock.is_some() {
-                self.server_loop(sock.unwrap())?;
-            }
+                let _ = std::fs::remove_file(&path);
+            },
+            PpcTransport::Tcp(addr) => {
+                while !self.stopping() {
+                    match TcpListener::bind(&addr) {
+                        Ok(sock) => {
+                            info!(target: "PPC", "Listening on tcp:{addr}");
+                            if sock.local_addr().is_ok_and(|local| !local.ip().is_loopback()) {
+                                warn!(target: "PPC", "tcp:{addr} isn't a loopback address: anything which can reach it can read and write emulated memory");
+                            }
+                            self.server_loop(sock)?
+                        },
+                        Err(e) => {
+                            error!(target: "PPC", "Couldn't bind to {addr},\n{e:?}");
+                            thread::sleep(std::time::Duration::from_millis(500));
+                        }
+                    }
+                }
+            },
         }
+        info!(target: "PPC", "PPC backend thread stopped");
+        Ok(())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_numbers_round_trip() {
+        use Command::*;
+        for cmd in [HostRead, HostWrite, Message, Ack, MessageNoReturn, ReadBulk, WriteBulk, Version, Shutdown, Unimpl] {
+            assert_eq!(Command::from_u32(cmd as u32), cmd);
+        }
+        assert_eq!(Command::from_u32(1), HostRead);
+        assert_eq!(Command::from_u32(2), HostWrite);
+        assert_eq!(Command::from_u32(9), Unimpl);
+    }
+
+    #[test]
+    fn wait_for_resp_wakes_on_irq_and_stops_on_request() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        {
+            let mut bus = bus.write();
+            bus.hlwd.irq.ppc_irq_enable.set(HollywoodIrq::PpcIpc);
+            bus.hlwd.ipc.state.ppc_ctrl_write(0x30);
+            bus.hlwd.ipc.arm_msg = 0x1234_5678;
+        }
+        let mut back = PpcBackend::new(bus.clone());
+
+        // ARM-world sends a message a little later
+        let arm_bus = bus.clone();
+        let arm = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let mut bus = arm_bus.write();
+            bus.hlwd.ipc.state.arm_ctrl_write(0x1);
+            bus.step(0).unwrap();
+        });
+        assert_eq!(back.wait_for_resp().unwrap(), Some(0x1234_5678));
+        arm.join().unwrap();
+        assert!(!bus.read().hlwd.irq.ppc_irq_output);
+
+        back.stop_requested.store(true, Ordering::Relaxed);
+        assert_eq!(back.wait_for_resp().unwrap(), None);
+    }
+
+    #[test]
+    fn parse_transport() {
+        assert_eq!("unix".parse::<PpcTransport>().unwrap(), PpcTransport::Unix);
+        assert_eq!("tcp:127.0.0.1:5000".parse::<PpcTransport>().unwrap(),
+            PpcTransport::Tcp("127.0.0.1:5000".to_owned()));
+        assert!("tcp:".parse::<PpcTransport>().is_err());
+        assert!("udp:127.0.0.1:5000".parse::<PpcTransport>().is_err());
+    }
+
+    #[test]
+    fn host_read_over_tcp() {
+        let sock = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        bus.write().write32(0x1000_0100, 0xcafe_f00d).unwrap();
+        let mut back = PpcBackend::new(bus);
+        let stop = back.stop_requested.clone();
+        let server = thread::spawn(move || back.server_loop(sock));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut req = Vec::new();
+        for x in [CMD_HOST_READ, 0x1000_0100, 4] {
+            req.extend_from_slice(&x.to_le_bytes());
+        }
+        client.write_all(&req).unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, 0xcafe_f00du32.to_be_bytes());
+
+        client.write_all(&CMD_SHUTDOWN.to_le_bytes().repeat(3)).unwrap();
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"kk");
+
+        stop.store(true, Ordering::Relaxed);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn bulk_transfers_over_tcp() {
+        fn header(cmd: u32, addr: u32, len: u32) -> Vec<u8> {
+            [cmd, addr, len].iter().flat_map(|x| x.to_le_bytes()).collect()
+        }
+        let sock = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = PpcBackend::new(bus.clone());
+        let stop = back.stop_requested.clone();
+        let server = thread::spawn(move || back.server_loop(sock));
+        let mut client = TcpStream::connect(addr).unwrap();
+
+        client.write_all(&header(CMD_VERSION, 0, 0)).unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), PROTOCOL_VERSION);
+
+        // A payload which arrives in pieces
+        let data: Vec<u8> = (0..0x800u32).flat_map(|x| x.to_be_bytes()).collect();
+        client.write_all(&header(CMD_WRITE_BULK, 0x1000_0000, data.len() as u32)).unwrap();
+        client.write_all(&data[..0x100]).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        client.write_all(&data[0x100..]).unwrap();
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"OK");
+        assert_eq!(bus.read().read32(0x1000_0000 + 4 * 0x7ff).unwrap(), 0x7ff);
+
+        client.write_all(&header(CMD_READ_BULK, 0x1000_0000, data.len() as u32)).unwrap();
+        let mut buf = vec![0; data.len()];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        // A bad request is refused, but the client can carry on
+        client.write_all(&header(CMD_WRITE_BULK, 0x1000_0000, 3)).unwrap();
+        client.write_all(&[1, 2, 3]).unwrap();
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, ERR_REPLY);
+        client.write_all(&header(CMD_VERSION, 0, 0)).unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), PROTOCOL_VERSION);
+
+        // A bad read has no status to report, so the client is dropped, but
+        // the server keeps going
+        client.write_all(&header(CMD_READ_BULK, 0x1000_0000, 3)).unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, ERR_REPLY);
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(&header(CMD_READ_BULK, 0x1000_0000, 4)).unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[..4]);
+
+        stop.store(true, Ordering::Relaxed);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn split_and_pipelined_requests_over_tcp() {
+        fn header(cmd: u32, addr: u32, len: u32) -> Vec<u8> {
+            [cmd, addr, len].iter().flat_map(|x| x.to_le_bytes()).collect()
+        }
+        let sock = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = PpcBackend::new(bus.clone());
+        let stop = back.stop_requested.clone();
+        let server = thread::spawn(move || back.server_loop(sock));
+        let mut client = TcpStream::connect(addr).unwrap();
+
+        // A header which arrives in pieces
+        let req = header(CMD_HOST_WRITE, 0x1000_0000, 4);
+        client.write_all(&req[..5]).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        client.write_all(&req[5..]).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        client.write_all(&0x1122_3344u32.to_be_bytes()).unwrap();
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"OK");
+
+        // Two requests sent at once are both answered
+        let mut reqs = header(CMD_HOST_WRITE, 0x1000_0004, 4);
+        reqs.extend_from_slice(&0x5566_7788u32.to_be_bytes());
+        reqs.extend(header(CMD_HOST_READ, 0x1000_0000, 8));
+        client.write_all(&reqs).unwrap();
+        let mut buf = [0; 10];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"OK\x11\x22\x33\x44\x55\x66\x77\x88");
+
+        // An oversized request is refused, and the client dropped
+        client.write_all(&header(CMD_HOST_READ, 0x1000_0000, BUF_LEN as u32)).unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, ERR_REPLY);
+
+        // The server is still there for the next client
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(&header(CMD_VERSION, 0, 0)).unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), PROTOCOL_VERSION);
+
+        stop.store(true, Ordering::Relaxed);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn server_loop_stops_on_request() {
+        let path = temp_dir().join(format!("ironic-ppc-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sock = UnixListener::bind(&path).unwrap();
+
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut back = PpcBackend::new(bus);
+        let stop = back.stop_requested.clone();
+        let server = thread::spawn(move || back.server_loop(sock));
+
+        // A client that goes away doesn't keep the server busy
+        drop(UnixStream::connect(&path).unwrap());
+        let mut client = UnixStream::connect(&path).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        stop.store(true, Ordering::Relaxed);
+        server.join().unwrap().unwrap();
+        // The server hung up on the connected client
+        let mut buf = [0; 1];
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
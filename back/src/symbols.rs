@@ -0,0 +1,106 @@
+//! Symbol maps for annotating disassembly.
+//!
+//! See [SymbolMap] for the text format. Symbols are also read from the
+//! symbol table of a custom kernel ELF.
+
+pub use ironic_core::dbg::symbols::SymbolMap;
+
+/// Build a symbol map from the symbols in an ELF symbol table.
+///
+/// Only functions, data objects and untyped labels are kept (without ARM
+/// mapping symbols like `$a`/`$t`/`$d`), and the Thumb bit is cleared from
+/// function addresses.
+pub fn from_elf_symbols(symbols: &[elf::types::Symbol]) -> SymbolMap {
+    use elf::types::{STT_FUNC, STT_NOTYPE, STT_OBJECT};
+    let mut map = SymbolMap::new();
+    for sym in symbols {
+        if sym.shndx == 0 || sym.name.is_empty() || sym.name.starts_with('$') {
+            continue;
+        }
+        let size = (sym.size != 0).then_some(sym.size as u32);
+        match sym.symtype {
+            STT_FUNC => map.insert_function(sym.value as u32 & !1, &sym.name, size),
+            STT_OBJECT | STT_NOTYPE => map.insert(sym.value as u32, &sym.name, size),
+            _ => continue,
+        }
+    }
+    map
+}
+
+/// Read the symbols from an ELF's `SHT_SYMTAB` section(s).
+pub fn load_elf_symbols(file: &elf::File) -> anyhow::Result<SymbolMap> {
+    let mut map = SymbolMap::new();
+    for section in file.sections.iter().filter(|s| s.shdr.shtype == elf::types::SHT_SYMTAB) {
+        map.merge(&from_elf_symbols(&file.get_symbols(section)?));
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::disassembly::{disassmble_arm_with_symbols, disassmble_thumb_with_symbols};
+
+    const MAP: &str = "
+        # Exported symbols
+        0xffff1234  ThreadCreate    0x40
+        ffff1274,   ThreadJoin
+        ; Thumb code
+        00001000 thumb_func
+    ";
+
+    #[test]
+    fn branch_targets_are_annotated() {
+        let map = SymbolMap::parse(MAP).unwrap();
+        // bl from 0xffff_0000 to just inside ThreadCreate, then to its start
+        assert_eq!(disassmble_arm_with_symbols(0xeb00_048d, 0xffff_0000, Some(&map)).unwrap(),
+            "bl 0xffff123c <ThreadCreate+0x8>");
+        assert_eq!(disassmble_arm_with_symbols(0xeb00_048b, 0xffff_0000, Some(&map)).unwrap(),
+            "bl 0xffff1234 <ThreadCreate>");
+        // Unknown targets are left alone
+        assert_eq!(disassmble_arm_with_symbols(0xeb00_0000, 0xffff_0000, Some(&map)).unwrap(),
+            "bl 0xffff0008");
+        assert_eq!(disassmble_arm_with_symbols(0xeb00_048b, 0xffff_0000, None).unwrap(),
+            "bl 0xffff1234");
+        // b #-0x8 from 0x1004
+        assert_eq!(disassmble_thumb_with_symbols(0xe7fc, 0x0000_1004, Some(&map)).unwrap(),
+            "b 0x1000 <thumb_func>");
+    }
+
+    #[test]
+    fn literal_loads_are_annotated() {
+        let map = SymbolMap::parse("0x1010 kernel_stack_top\n0x2000 table 0x100").unwrap();
+        // ldr r0, [pc, #0x8] at 0x1000
+        assert_eq!(disassmble_arm_with_symbols(0xe59f_0008, 0x1000, Some(&map)).unwrap(),
+            "ldr r0, [r15, #0x8] <kernel_stack_top>");
+        // ldr r1, [pc, #0x10] at 0x1ffe, which reads from 0x2010
+        assert_eq!(disassmble_thumb_with_symbols(0x4904, 0x1ffe, Some(&map)).unwrap(),
+            "ldr r1, [pc, #0x10] <table+0x10>");
+    }
+
+    #[test]
+    fn elf_symbols_skip_mapping_symbols() {
+        use elf::types::*;
+        let sym = |name: &str, value: u64, size: u64, symtype| Symbol {
+            name: name.to_owned(), value, size, shndx: 1, symtype,
+            bind: STB_GLOBAL, vis: STV_DEFAULT,
+        };
+        let map = from_elf_symbols(&[
+            sym("main", 0x1000, 0x20, STT_FUNC),
+            sym("thumb_func", 0x1021, 0x10, STT_FUNC),
+            sym("$a", 0x1000, 0, STT_NOTYPE),
+            sym("crt0.s", 0, 0, STT_FILE),
+            sym("", 0x3000, 0, STT_NOTYPE),
+            Symbol { shndx: 0, ..sym("undefined", 0, 0, STT_FUNC) },
+            sym("buffer", 0x2000, 0x40, STT_OBJECT),
+        ]);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.describe(0x1010).as_deref(), Some("main+0x10"));
+        // The Thumb bit isn't part of the address
+        assert_eq!(map.lookup(0x1020), Some("thumb_func"));
+        assert_eq!(map.describe(0x2004).as_deref(), Some("buffer+0x4"));
+        // Only functions are used for code addresses
+        assert_eq!(map.describe_code(0x1024).as_deref(), Some("thumb_func+0x4"));
+        assert_eq!(map.describe_code(0x2004), None);
+    }
+}
@@ -0,0 +1,129 @@
+//! A tiny built-in test image for bring-up without any real firmware.
+//!
+//! The image prints "hello" with semihosting calls and then exits with
+//! SYS_EXIT. Run it with `--custom-kernel <path> --kernel-profile raw`.
+
+/// Physical address the test image is loaded and started at (in MEM1).
+pub const TEST_ROM_BASE: u32 = 0x0001_0000;
+
+/// Hand-assembled ARM code for the test image.
+const TEST_ROM_CODE: [u32; 10] = [
+    0xe3a0_0004, // 00: mov r0, #4          (SYS_WRITE0)
+    0xe28f_101c, // 04: add r1, pc, #0x1c   (r1 = "hello")
+    0xef00_00ab, // 08: svc 0xab
+    0xe3a0_0003, // 0c: mov r0, #3          (SYS_WRITEC)
+    0xe28f_1020, // 10: add r1, pc, #0x20   (r1 = '\n')
+    0xef00_00ab, // 14: svc 0xab
+    0xe3a0_0018, // 18: mov r0, #0x18       (SYS_EXIT)
+    0xe59f_1000, // 1c: ldr r1, [pc, #0]    (ADP_Stopped_ApplicationExit)
+    0xef00_00ab, // 20: svc 0xab
+    0x0002_0026, // 24: .word 0x20026
+];
+
+/// Build the test image as a big-endian 32-bit ARM ELF.
+pub fn build_test_rom() -> Vec<u8> {
+    build_elf(true)
+}
+
+/// Build the test image with either byte order. Starlet can't run a
+/// little-endian image, but the loader needs one to refuse.
+pub(crate) fn build_elf(big_endian: bool) -> Vec<u8> {
+    const EHDR_SIZE: u16 = 52;
+    const PHDR_SIZE: u16 = 32;
+    let half = |x: u16| if big_endian { x.to_be_bytes() } else { x.to_le_bytes() };
+    let word = |x: u32| if big_endian { x.to_be_bytes() } else { x.to_le_bytes() };
+
+    let mut payload = Vec::new();
+    for op in TEST_ROM_CODE {
+        payload.extend_from_slice(&word(op));
+    }
+    // 28: "hello", padded out to the 16 bytes read by SYS_WRITE0
+    let mut hello = [0u8; 16];
+    hello[..5].copy_from_slice(b"hello");
+    payload.extend_from_slice(&hello);
+    // 38: '\n'
+    payload.extend_from_slice(&[b'\n', 0, 0, 0]);
+
+    let payload_off = (EHDR_SIZE + PHDR_SIZE) as u32;
+    let mut elf = Vec::new();
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, if big_endian { 2 } else { 1 }, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&half(2));                    // e_type: EXEC
+    elf.extend_from_slice(&half(40));                   // e_machine: ARM
+    elf.extend_from_slice(&word(1));                    // e_version
+    elf.extend_from_slice(&word(TEST_ROM_BASE));        // e_entry
+    elf.extend_from_slice(&word(EHDR_SIZE as u32));     // e_phoff
+    elf.extend_from_slice(&word(0));                    // e_shoff
+    elf.extend_from_slice(&word(0));                    // e_flags
+    elf.extend_from_slice(&half(EHDR_SIZE));            // e_ehsize
+    elf.extend_from_slice(&half(PHDR_SIZE));            // e_phentsize
+    elf.extend_from_slice(&half(1));                    // e_phnum
+    elf.extend_from_slice(&half(40));                   // e_shentsize
+    elf.extend_from_slice(&half(0));                    // e_shnum
+    elf.extend_from_slice(&half(0));                    // e_shstrndx
+
+    elf.extend_from_slice(&word(1));                    // p_type: LOAD
+    elf.extend_from_slice(&word(payload_off));          // p_offset
+    elf.extend_from_slice(&word(TEST_ROM_BASE));        // p_vaddr
+    elf.extend_from_slice(&word(TEST_ROM_BASE));        // p_paddr
+    elf.extend_from_slice(&word(payload.len() as u32)); // p_filesz
+    elf.extend_from_slice(&word(payload.len() as u32)); // p_memsz
+    elf.extend_from_slice(&word(5));                    // p_flags: R+X
+    elf.extend_from_slice(&word(4));                    // p_align
+
+    elf.extend_from_slice(&payload);
+    elf
+}
+
+/// Write `contents` to a file in the temporary directory, named so that
+/// test runs in parallel processes don't clash.
+#[cfg(test)]
+pub(crate) fn temp_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ironic-{}-{name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Build a backend with the test image loaded, and `code` written over the
+/// start of it. The image file is removed again, so the backend won't try
+/// to load it a second time when run.
+#[cfg(test)]
+pub(crate) fn backend_with_code(code: &[u32]) -> crate::interp::InterpBackend {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use parking_lot::RwLock;
+    use ironic_core::bus::Bus;
+    use crate::interp::{InterpBackend, KernelProfile};
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let path = temp_file(&format!("rom-{}.elf", NEXT_ID.fetch_add(1, Ordering::Relaxed)), &build_test_rom());
+    let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+    let mut back = InterpBackend::new(bus, Some(path.to_string_lossy().into_owned()), false);
+    back.kernel_profile = KernelProfile::Raw;
+    back.load_custom_kernel().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    back.custom_kernel = None;
+    for (idx, op) in code.iter().enumerate() {
+        back.bus.write().write32(TEST_ROM_BASE + 4 * idx as u32, *op).unwrap();
+    }
+    back
+}
+
+#[cfg(test)]
+mod tests {
+    use ironic_core::cpu::CpuRes;
+    use super::*;
+
+    #[test]
+    fn test_rom_prints_hello_and_exits() {
+        let mut back = backend_with_code(&[]);
+        assert_eq!(back.cpu.read_fetch_pc(), TEST_ROM_BASE);
+
+        // The string is buffered until the newline is written
+        assert!(matches!(back.step_for(3), CpuRes::StepOk));
+        assert_eq!(back.svc_buf, "hello");
+        assert!(matches!(back.step_for(100), CpuRes::Semihosting));
+        assert!(back.svc_buf.is_empty());
+        assert_eq!(back.exit_code, Some(0));
+    }
+}
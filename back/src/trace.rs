@@ -0,0 +1,215 @@
+//! Exporting an execution trace for offline analysis.
+//!
+//! Each retired step is written as one line, either as JSON (so a viewer
+//! can load the file incrementally and scrub through execution):
+//!
+//! ```text
+//! {"cycle":0,"pc":"0x00010000","thumb":false,"opcode":"0xe3a00004","disasm":"mov  r0, #0x4","changed":{"r0":"0x00000004"}}
+//! ```
+//!
+//! where `changed` only lists the registers (in the mode active after the
+//! step) whose value differs from before the step, including the CPSR; or
+//! as plain text with the whole register file after each step, which is
+//! easier to diff against traces from other emulators:
+//!
+//! ```text
+//! 00010000: e3a00004 mov  r0, #0x4          r0=00000004 r1=00000000 ... lr=00000000 cpsr=000000d3
+//! ```
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use ironic_core::cpu::Cpu;
+
+use crate::bits::disassembly::{disassmble_arm_with_symbols, disassmble_thumb_with_symbols};
+use crate::symbols::SymbolMap;
+
+const REG_NAMES: [&str; 15] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7",
+    "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+];
+
+/// The format of each line in an execution trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// A JSON object with the registers changed by the step.
+    Json,
+    /// Plain text with the whole register file after the step.
+    Text,
+}
+
+/// State captured before an instruction executes.
+struct PendingStep {
+    cycle: usize,
+    pc: u32,
+    thumb: bool,
+    opcd: u32,
+    regs: [u32; 15],
+    cpsr: u32,
+}
+
+/// Writes an execution trace, one line per step. The output is buffered,
+/// and flushed when the exporter is dropped (including while unwinding
+/// from a panic).
+pub struct TraceExporter {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    pending: Option<PendingStep>,
+}
+impl TraceExporter {
+    pub fn new(out: Box<dyn Write + Send>, format: TraceFormat) -> Self {
+        TraceExporter { out, format, pending: None }
+    }
+
+    /// Create an exporter which writes to a new file at `path`.
+    pub fn create(path: impl AsRef<Path>, format: TraceFormat) -> anyhow::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(Self::new(Box::new(BufWriter::new(file)), format))
+    }
+
+    /// Capture the state of the CPU before the instruction `opcd` at `pc`
+    /// executes.
+    pub(crate) fn begin(&mut self, cycle: usize, cpu: &Cpu, pc: u32, opcd: u32) {
+        let thumb = cpu.reg.cpsr.thumb();
+        self.pending = Some(PendingStep {
+            cycle, pc, thumb, opcd,
+            regs: cpu.reg.r,
+            cpsr: cpu.reg.cpsr.0,
+        });
+    }
+
+    /// Write a record for the step started by [TraceExporter::begin], if
+    /// there is one.
+    pub(crate) fn finish(&mut self, cpu: &Cpu, symbols: Option<&SymbolMap>) -> std::io::Result<()> {
+        let Some(step) = self.pending.take() else {
+            return Ok(());
+        };
+        let disasm = if step.thumb {
+            disassmble_thumb_with_symbols(step.opcd as u16, step.pc, symbols)
+        } else {
+            disassmble_arm_with_symbols(step.opcd, step.pc, symbols)
+        };
+        let disasm = disasm.unwrap_or("Unknown".to_owned());
+        let width = if step.thumb { 4 } else { 8 };
+        match self.format {
+            TraceFormat::Json => self.write_json(&step, cpu, width, disasm),
+            TraceFormat::Text => self.write_text(&step, cpu, width, disasm),
+        }
+    }
+
+    fn write_json(&mut self, step: &PendingStep, cpu: &Cpu, width: usize, disasm: String) -> std::io::Result<()> {
+        let opcode = format!("\"0x{:0width$x}\"", step.opcd);
+        let disasm = json_string(&disasm);
+
+        let mut changed = Vec::new();
+        for (idx, (old, new)) in step.regs.iter().zip(cpu.reg.r.iter()).enumerate() {
+            if old != new {
+                changed.push(format!("\"{}\":\"0x{new:08x}\"", REG_NAMES[idx]));
+            }
+        }
+        if step.cpsr != cpu.reg.cpsr.0 {
+            changed.push(format!("\"cpsr\":\"0x{:08x}\"", cpu.reg.cpsr.0));
+        }
+
+        writeln!(self.out,
+            "{{\"cycle\":{},\"pc\":\"0x{:08x}\",\"thumb\":{},\"opcode\":{opcode},\"disasm\":{disasm},\"changed\":{{{}}}}}",
+            step.cycle, step.pc, step.thumb, changed.join(","))
+    }
+
+    fn write_text(&mut self, step: &PendingStep, cpu: &Cpu, width: usize, disasm: String) -> std::io::Result<()> {
+        let opcode = format!("{:0width$x}", step.opcd);
+        write!(self.out, "{:08x}: {opcode:8} {disasm:32}", step.pc)?;
+        for (name, val) in REG_NAMES.iter().zip(cpu.reg.r.iter()) {
+            write!(self.out, " {name}={val:08x}")?;
+        }
+        writeln!(self.out, " cpsr={:08x}", cpu.reg.cpsr.0)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+impl Drop for TraceExporter {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// Quote a string for use as a JSON value.
+pub fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use ironic_core::cpu::CpuRes;
+    use crate::testrom::{backend_with_code, TEST_ROM_BASE};
+    use super::*;
+
+    #[test]
+    fn trace_records_each_step() {
+        let out_path = std::env::temp_dir().join(format!("ironic-trace-out-{}.jsonl", std::process::id()));
+
+        let mut back = backend_with_code(&[]);
+        back.trace = Some(TraceExporter::create(&out_path, TraceFormat::Json).unwrap());
+        assert!(matches!(back.step_for(2), CpuRes::StepOk));
+        drop(back);
+
+        let trace = std::fs::read_to_string(&out_path).unwrap();
+        std::fs::remove_file(&out_path).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|l| l.starts_with('{') && l.ends_with('}')));
+
+        // mov r0, #4
+        let expected = format!("{{\"cycle\":0,\"pc\":\"0x{:08x}\",\"thumb\":false,\"opcode\":\"0xe3a00004\",", TEST_ROM_BASE);
+        assert!(lines[0].starts_with(&expected), "{}", lines[0]);
+        assert!(lines[0].ends_with("\"changed\":{\"r0\":\"0x00000004\"}}"), "{}", lines[0]);
+        // add r1, pc, #0x1c
+        assert!(lines[1].contains(&format!("\"pc\":\"0x{:08x}\"", TEST_ROM_BASE + 4)), "{}", lines[1]);
+        assert!(lines[1].ends_with(&format!("\"changed\":{{\"r1\":\"0x{:08x}\"}}}}", TEST_ROM_BASE + 0x28)), "{}", lines[1]);
+    }
+
+    #[test]
+    fn text_trace_is_flushed_on_panic() {
+        let out_path = std::env::temp_dir().join(format!("ironic-text-trace-out-{}.txt", std::process::id()));
+
+        let res = std::panic::catch_unwind(|| {
+            let mut back = backend_with_code(&[]);
+            back.trace = Some(TraceExporter::create(&out_path, TraceFormat::Text).unwrap());
+            assert!(matches!(back.step_for(2), CpuRes::StepOk));
+            panic!("emulator crashed");
+        });
+        assert!(res.is_err());
+
+        let trace = std::fs::read_to_string(&out_path).unwrap();
+        std::fs::remove_file(&out_path).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("{TEST_ROM_BASE:08x}: e3a00004 mov  r0, #0x4 ")), "{}", lines[0]);
+        assert!(lines[0].contains(" r0=00000004 r1=00000000 "), "{}", lines[0]);
+        assert!(lines[1].starts_with(&format!("{:08x}: e28f101c add r1, r15, #0x1c ", TEST_ROM_BASE + 4)), "{}", lines[1]);
+        assert!(lines[1].contains(&format!(" r0=00000004 r1={:08x} ", TEST_ROM_BASE + 0x28)), "{}", lines[1]);
+        assert!(lines[1].ends_with(" cpsr=000000d3"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("ldr r0, [pc, #0x4] \"x\"\\\n"), "\"ldr r0, [pc, #0x4] \\\"x\\\"\\\\\\n\"");
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+    }
+}
@@ -3,11 +3,24 @@ pub mod decode;
 pub mod dispatch;
 pub mod mmio;
 pub mod task;
+pub mod timeline;
+pub mod codewatch;
+pub mod watch;
+pub mod state;
 use std::env::current_dir;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+use anyhow::bail;
+
+use crate::bus::prim::{AccessLatency, Intercept, InterceptReadFn, InterceptWriteFn};
 
 use crate::bus::task::*;
+use crate::bus::codewatch::CodeWatch;
+use crate::bus::watch::{Watchpoint, WatchHit};
 
 use crate::mem::*;
+use crate::dev::{MEM1_SIZE, MEM2_SIZE, MEM1_MAX_SIZE, MEM2_MAX_SIZE};
 use crate::dev::hlwd::*;
 use crate::dev::aes::*;
 use crate::dev::sha::*;
@@ -28,6 +41,13 @@ pub struct DebugInfo {
     pub last_pc: Option<u32>,
     pub last_lr: Option<u32>,
     pub last_sp: Option<u32>,
+    /// General-purpose registers r0-r14 and the CPSR, as of the last step.
+    /// Only recorded when `track_regs` is set.
+    pub last_regs: Option<([u32; 15], u32)>,
+    /// Whether to record `last_regs` on every step.
+    pub track_regs: bool,
+    /// Symbols for the running code, e.g. from a custom kernel's symbol table.
+    pub symbols: Option<crate::dbg::symbols::SymbolMap>,
 }
 
 /// Implementation of an emulated bus.
@@ -50,7 +70,9 @@ pub struct Bus {
     pub ohci0: OhcInterface,
     pub ohci1: OhcInterface,
     pub sd0: SDInterface,
-    pub sd1: WLANInterface,
+    pub sd1: SDInterface,
+    /// See [BusConfig::sd_block_latency].
+    pub sd_block_latency: usize,
 
     /// True when the ROM mapping is disabled.
     pub rom_disabled: bool,
@@ -61,38 +83,177 @@ pub struct Bus {
     pub tasks: Vec<Task>,
     pub cycle: usize,
     pub debuginfo: Box<DebugInfo>,
+
+    /// Regions with artificial access latency.
+    pub access_latency: Vec<AccessLatency>,
+    /// Latency accumulated since the last bus step.
+    pending_latency: AtomicUsize,
+    /// Ranges where accesses are handled by user-provided closures.
+    intercepts: Vec<Intercept>,
+    /// Memory pages cached as code by the backend.
+    pub code_watch: CodeWatch,
+    /// Ranges of physical addresses where accesses are reported.
+    watchpoints: Vec<Watchpoint>,
+    /// The first watchpoint hit which hasn't been taken yet.
+    watch_hit: std::sync::Mutex<Option<WatchHit>>,
+    /// Raised along with the PPC IPC interrupt, for the PPC HLE backend.
+    pub ppc_irq_signal: std::sync::Arc<crate::dev::hlwd::ipc::PpcIrqSignal>,
+}
+
+/// Parameters for constructing a [Bus].
+#[derive(Debug, Clone)]
+pub struct BusConfig {
+    /// File with the mask ROM, or None to leave it empty (e.g. for tests
+    /// which don't need to run boot0).
+    pub boot0: Option<String>,
+    /// NAND flash dump.
+    pub nand: String,
+    /// Directory where writes to the NAND are saved as patches against the
+    /// dump.
+    pub saved_writes: std::path::PathBuf,
+    /// Size of MEM1 in bytes.
+    pub mem1_size: u32,
+    /// Size of MEM2 in bytes.
+    pub mem2_size: u32,
+    /// Back MEM1 and MEM2 with anonymous mappings instead of the heap, so
+    /// that only pages which are actually used take up memory.
+    pub mmap_mem: bool,
+    /// Bus cycles for the SD host controllers to move a 512-byte block on a
+    /// 4-bit bus at the undivided SD clock. Transfers are paced by this,
+    /// scaled to the clock and block size the guest sets up.
+    pub sd_block_latency: usize,
+}
+impl Default for BusConfig {
+    fn default() -> Self {
+        BusConfig {
+            boot0: Some("./boot0.bin".to_owned()),
+            nand: "./nand.bin".to_owned(),
+            saved_writes: "./saved-writes".into(),
+            mem1_size: MEM1_SIZE,
+            mem2_size: MEM2_SIZE,
+            mmap_mem: false,
+            sd_block_latency: 1000,
+        }
+    }
+}
+impl BusConfig {
+    /// Check that MEM1 and MEM2 fit in the memory map. Sizes are multiples of
+    /// 64KiB, like every other region.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, size, max) in [
+            ("MEM1", self.mem1_size, MEM1_MAX_SIZE),
+            ("MEM2", self.mem2_size, MEM2_MAX_SIZE),
+        ] {
+            if size == 0 || size % 0x1_0000 != 0 {
+                bail!("{name} size {size:#x} isn't a non-zero multiple of 0x10000");
+            }
+            if size > max {
+                bail!("{name} size {size:#x} is larger than the maximum {max:#x}");
+            }
+        }
+        Ok(())
+    }
 }
+
 impl Bus {
-    pub fn new()-> anyhow::Result<Self> {
+    pub fn new(config: &BusConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        let ram = |size: u32| if config.mmap_mem {
+            BigEndianMemory::new_mapped(size as usize)
+        } else {
+            BigEndianMemory::new(size as usize, None, None)
+        };
         Ok(Bus {
-            mrom: BigEndianMemory::new(0x0000_2000, Some("./boot0.bin"), false)?,
-            sram0: BigEndianMemory::new(0x0001_0000, None, false)?,
-            sram1: BigEndianMemory::new(0x0001_0000, None, false)?,
-            mem1: BigEndianMemory::new(0x0180_0000, None, false)?,
-            mem2: BigEndianMemory::new(0x0400_0000, None, false)?,
+            mrom: BigEndianMemory::new(0x0000_2000, config.boot0.as_deref(), None)?,
+            sram0: BigEndianMemory::new(0x0001_0000, None, None)?,
+            sram1: BigEndianMemory::new(0x0001_0000, None, None)?,
+            mem1: ram(config.mem1_size)?,
+            mem2: ram(config.mem2_size)?,
 
             hlwd: Hollywood::new()?,
-            nand: NandInterface::new("./nand.bin")?,
+            nand: NandInterface::new(&config.nand, &config.saved_writes)?,
             aes: AesInterface::new(),
             sha: ShaInterface::new(),
             ehci: EhcInterface::new(),
             ohci0: OhcInterface { idx: 0, ..Default::default() },
             ohci1: OhcInterface { idx: 1, ..Default::default() },
             sd0: SDInterface::default(),
-            sd1: WLANInterface::default(),
+            sd1: SDInterface::empty(1),
+            sd_block_latency: config.sd_block_latency,
 
             rom_disabled: false,
             mirror_enabled: false,
             tasks: Vec::new(),
             cycle: 0,
             debuginfo: Box::default(),
+            access_latency: Vec::new(),
+            pending_latency: AtomicUsize::new(0),
+            intercepts: Vec::new(),
+            code_watch: CodeWatch::default(),
+            watchpoints: Vec::new(),
+            watch_hit: std::sync::Mutex::new(None),
+            ppc_irq_signal: Default::default(),
         })
     }
 
+    /// Create a bus with the mask ROM loaded from some file, or left empty
+    /// (e.g. for tests which don't need to run boot0).
+    pub fn with_boot0(boot0: Option<&str>) -> anyhow::Result<Self> {
+        Self::new(&BusConfig { boot0: boot0.map(str::to_owned), ..Default::default() })
+    }
+
+    /// Handle CPU reads and/or writes on some inclusive range of physical
+    /// addresses with the given closures, instead of the underlying memory
+    /// or device. Accesses without a closure go to the device as usual.
+    /// DMA accesses are never intercepted.
+    pub fn intercept(&mut self, range: std::ops::RangeInclusive<u32>,
+        read_fn: Option<InterceptReadFn>, write_fn: Option<InterceptWriteFn>)
+    {
+        self.intercepts.push(Intercept {
+            start: *range.start(),
+            end: *range.end(),
+            read_fn,
+            write_fn,
+        });
+    }
+
+    /// Find an intercepted read handler for some address.
+    pub(crate) fn intercepted_read(&self, addr: u32) -> Option<&InterceptReadFn> {
+        self.intercepts.iter()
+            .filter(|i| i.contains(addr))
+            .find_map(|i| i.read_fn.as_ref())
+    }
+
+    /// Find an intercepted write handler for some address.
+    pub(crate) fn intercepted_write(&mut self, addr: u32) -> Option<&mut InterceptWriteFn> {
+        self.intercepts.iter_mut()
+            .filter(|i| i.contains(addr))
+            .find_map(|i| i.write_fn.as_mut())
+    }
+
+    /// Charge any artificial latency configured for this address.
+    /// The accumulated latency is consumed on the next bus step.
+    pub fn charge_latency(&self, addr: u32) {
+        for region in self.access_latency.iter() {
+            if region.contains(addr) {
+                self.pending_latency.fetch_add(region.cycles, Relaxed);
+            }
+        }
+    }
+
+    /// Take the latency accumulated since the last bus step.
+    pub fn take_pending_latency(&self) -> usize {
+        self.pending_latency.swap(0, Relaxed)
+    }
+
     pub fn install_debuginfo(&mut self, debuginfo: Dwarf<EndianArcSlice<BigEndian>>) {
         self.debuginfo.debuginfo = Some(debuginfo);
     }
 
+    pub fn install_symbols(&mut self, symbols: crate::dbg::symbols::SymbolMap) {
+        self.debuginfo.symbols = Some(symbols);
+    }
+
     pub fn install_debug_frames(&mut self, debug_frames: DebugFrame<EndianArcSlice<BigEndian>>) {
         self.debuginfo.debug_frames = Some(debug_frames);
     }
@@ -101,10 +262,21 @@ impl Bus {
         if let Some(pc) = pc { self.debuginfo.last_pc = Some(pc); }
         if let Some(lr) = lr { self.debuginfo.last_lr = Some(lr); }
         if let Some(sp) = sp { self.debuginfo.last_sp = Some(sp); }
-    } 
+    }
+
+    pub fn update_debug_regs(&mut self, regs: [u32; 15], cpsr: u32) {
+        self.debuginfo.last_regs = Some((regs, cpsr));
+    }
 
-    pub fn dump_memory(&self, suffix: &'static str) -> anyhow::Result<std::path::PathBuf> {
-        let dir = current_dir()?;
+    pub fn dump_memory(&self, suffix: &str) -> anyhow::Result<std::path::PathBuf> {
+        self.dump_memory_to(&current_dir()?, suffix)
+    }
+
+    /// Dump each memory to `<dir>/<name>.<suffix>`, creating `dir` if it
+    /// doesn't exist.
+    pub fn dump_memory_to(&self, dir: &std::path::Path, suffix: &str) -> anyhow::Result<std::path::PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let dir = dir.to_path_buf();
 
         let mut sram0_dir = dir.clone();
         sram0_dir.push("sram0");
@@ -127,5 +299,15 @@ impl Bus {
         self.mem2.dump(&mem2_dir)?;
         Ok(dir)
     }
+
+    /// Write the register state of each device to its own file in `dir`.
+    pub fn dump_device_regs(&self, dir: &std::path::Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("hlwd.txt"), self.hlwd.dump_regs())?;
+        std::fs::write(dir.join("irq.txt"), format!("{:#?}\n", self.hlwd.irq))?;
+        std::fs::write(dir.join("sdhc0.bin"), self.sd0.dump_regs())?;
+        std::fs::write(dir.join("sdhc1.bin"), self.sd1.dump_regs())?;
+        Ok(())
+    }
 }
 
@@ -0,0 +1,117 @@
+//! Tracking writes to memory which has been cached as code.
+//!
+//! Backends which cache decoded instructions (keyed on the underlying memory
+//! device and offset, so that aliased mappings share entries) mark the pages
+//! they've cached with [CodeWatch::watch]. Any later CPU or DMA write to a
+//! watched page marks it dirty, and the backend is expected to drop its
+//! cached entries for the page after calling [CodeWatch::take_dirty].
+
+use std::collections::HashSet;
+
+use crate::bus::*;
+use crate::bus::prim::*;
+
+/// Size of a watched page (in bytes), as a shift.
+pub const CODE_PAGE_SHIFT: usize = 12;
+
+/// Set of memory pages which are currently cached as code.
+#[derive(Default)]
+pub struct CodeWatch {
+    watched: HashSet<(MemDevice, usize)>,
+    dirty: Vec<(MemDevice, usize)>,
+}
+impl CodeWatch {
+    /// Start watching the page containing some offset into a memory device.
+    pub fn watch(&mut self, dev: MemDevice, off: usize) {
+        self.watched.insert((dev, off >> CODE_PAGE_SHIFT));
+    }
+
+    /// Take the list of pages written since the last call. Dirty pages are
+    /// no longer watched until they're cached again.
+    pub fn take_dirty(&mut self) -> Vec<(MemDevice, usize)> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Note a write of `len` bytes at some offset into a memory device.
+    pub(crate) fn note_write(&mut self, dev: MemDevice, off: usize, len: usize) {
+        if self.watched.is_empty() || len == 0 {
+            return;
+        }
+        let first = off >> CODE_PAGE_SHIFT;
+        let last = (off + len - 1) >> CODE_PAGE_SHIFT;
+        for page in first..=last {
+            if self.watched.remove(&(dev, page)) {
+                self.dirty.push((dev, page));
+            }
+        }
+    }
+}
+
+impl Bus {
+    /// Mark any code cached from a range of physical addresses as stale.
+    /// Writes through the bus (CPU or DMA) do this already, so this is only
+    /// needed after changing memory behind the bus's back (e.g. through
+    /// [Bus::mem1] directly).
+    pub fn invalidate_code(&mut self, range: std::ops::Range<u32>) {
+        let page_size = 1u32 << CODE_PAGE_SHIFT;
+        let mut addr = range.start;
+        while addr < range.end {
+            let next = (addr & !(page_size - 1)).saturating_add(page_size).min(range.end);
+            if let Some((dev, off)) = self.resolve_mem(addr) {
+                self.code_watch.note_write(dev, off, (next - addr) as usize);
+            }
+            addr = next;
+        }
+    }
+
+    /// Resolve a physical address to some memory device and offset, if the
+    /// address is backed by plain memory (and not intercepted).
+    pub fn resolve_mem(&self, addr: u32) -> Option<(MemDevice, usize)> {
+        if !self.intercepts.is_empty() && self.intercepted_read(addr).is_some() {
+            return None;
+        }
+        match self.decode_phys_addr(addr)? {
+            DeviceHandle { dev: Device::Mem(dev), mask } => Some((dev, (addr & mask) as usize)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_dirty_watched_pages() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        let (dev, off) = bus.resolve_mem(0x0000_1000).unwrap();
+        bus.code_watch.watch(dev, off);
+
+        // Writes elsewhere don't matter
+        bus.write32(0x0000_2000, 0).unwrap();
+        assert!(bus.code_watch.take_dirty().is_empty());
+
+        bus.write32(0x0000_1ffc, 0).unwrap();
+        assert_eq!(bus.code_watch.take_dirty(), vec![(MemDevice::Mem1, 1)]);
+
+        // The page isn't watched again until it's cached again
+        bus.dma_write(0x0000_1000, &[0; 4]).unwrap();
+        assert!(bus.code_watch.take_dirty().is_empty());
+        bus.code_watch.watch(dev, off);
+        bus.dma_write(0x0000_0ffe, &[0; 4]).unwrap();
+        assert_eq!(bus.code_watch.take_dirty(), vec![(MemDevice::Mem1, 1)]);
+    }
+
+    #[test]
+    fn invalidate_code_covers_every_page_in_range() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        for addr in [0x0000_1000, 0x0000_2000, 0x0000_3000] {
+            let (dev, off) = bus.resolve_mem(addr).unwrap();
+            bus.code_watch.watch(dev, off);
+        }
+        bus.invalidate_code(0x0000_1ffc..0x0000_3000);
+        assert_eq!(bus.code_watch.take_dirty(), vec![(MemDevice::Mem1, 1), (MemDevice::Mem1, 2)]);
+        bus.invalidate_code(0x0000_3000..0x0000_3000);
+        assert!(bus.code_watch.take_dirty().is_empty());
+    }
+}
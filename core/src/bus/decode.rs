@@ -24,8 +24,10 @@ macro_rules! decl_io_handle {
 // These are declarations of all the constant DeviceHandle structures whose 
 // parameters (base address, size, etc.) will never change during runtime.
 
-decl_mem_handle!(MEM1_HANDLE, Mem1, 0x017f_ffff);
-decl_mem_handle!(MEM2_HANDLE, Mem2, 0x03ff_ffff);
+// MEM1 and MEM2 are never larger than MEM{1,2}_MAX_SIZE, so masking off the
+// top nybble gives the offset into either of them.
+decl_mem_handle!(MEM1_HANDLE, Mem1, 0x0fff_ffff);
+decl_mem_handle!(MEM2_HANDLE, Mem2, 0x0fff_ffff);
 
 decl_io_handle!(NAND_HANDLE, Nand,  0x0000_001f);
 decl_io_handle!(AES_HANDLE, Aes,    0x0000_001f);
@@ -41,8 +43,10 @@ decl_io_handle!(AHB_HANDLE, Ahb,    0x0000_3fff);
 decl_io_handle!(MI_HANDLE, Mi,      0x0000_01ff);
 decl_io_handle!(DDR_HANDLE, Ddr,    0x0000_01ff);
 decl_io_handle!(DI_HANDLE, Di,      0x0000_03ff);
+decl_io_handle!(DSP_HANDLE, Dsp,    0x0000_01ff);
 //decl_io_handle!(SI_HANDLE, Si,      0x0000_03ff);
 decl_io_handle!(EXI_HANDLE, Exi,    0x0000_03ff);
+decl_io_handle!(CYCLECTR_HANDLE, CycleCounter, 0x0000_0007);
 
 
 impl Bus {
@@ -69,10 +73,12 @@ impl Bus {
             0x0d00 | 0x0d80 |
             0x0d8b => self.resolve_hlwd(addr),
 
-            0x0000..=0x017f => Some(MEM1_HANDLE),
-            0x1000..=0x13ff => Some(MEM2_HANDLE),
+            0x0d8f => match addr {
+                CYCLECTR_BASE..=CYCLECTR_TAIL => Some(CYCLECTR_HANDLE),
+                _ => None,
+            },
 
-            _ => None,
+            _ => self.resolve_ram(addr),
         }
     }
 }
@@ -83,6 +89,7 @@ impl Bus {
     fn resolve_hlwd(&self, addr: u32) -> Option<DeviceHandle> {
         match addr {
             HLWD_BASE..=HLWD_TAIL   => Some(HLWD_HANDLE),
+            DSP_BASE..=DSP_TAIL     => Some(DSP_HANDLE),
             DI_BASE..=DI_TAIL       => Some(DI_HANDLE),
             EXI_REG_BASE..=EXI_REG_TAIL |
             EXI_BASE..=EXI_TAIL     => Some(EXI_HANDLE),
@@ -93,6 +100,19 @@ impl Bus {
         }
     }
 
+    /// Resolve a physical address in MEM1 or MEM2, which are as large as the
+    /// [BusConfig] for this bus asked for.
+    fn resolve_ram(&self, addr: u32) -> Option<DeviceHandle> {
+        let addr = addr as usize;
+        if (MEM1_BASE as usize..MEM1_BASE as usize + self.mem1.data.len()).contains(&addr) {
+            Some(MEM1_HANDLE)
+        } else if (MEM2_BASE as usize..MEM2_BASE as usize + self.mem2.data.len()).contains(&addr) {
+            Some(MEM2_HANDLE)
+        } else {
+            None
+        }
+    }
+
     /// Resolve a physical address associated with SRAM or the mask ROM.
     fn resolve_sram(&self, addr: u32) -> Option<DeviceHandle> {
         match (!self.rom_disabled, self.mirror_enabled) {
@@ -177,3 +197,58 @@ fn resolve_norom_nomir(addr: u32) -> Option<DeviceHandle> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubled_mem2_is_addressable() {
+        let bus_config = BusConfig { boot0: None, mem2_size: 2 * MEM2_SIZE, ..Default::default() };
+        let mut bus = Bus::new(&bus_config).unwrap();
+        let top = MEM2_BASE + 2 * MEM2_SIZE;
+
+        bus.write32(top - 4, 0xcafe_f00d).unwrap();
+        assert_eq!(bus.read32(top - 4).unwrap(), 0xcafe_f00d);
+        assert_eq!(bus.mem2.read::<u32>((2 * MEM2_SIZE - 4) as usize).unwrap(), 0xcafe_f00d);
+
+        // DMA across what used to be the end of MEM2
+        let buf = [0x5a; 0x200];
+        bus.dma_write(MEM2_TAIL - 0xff, &buf).unwrap();
+        let mut out = [0; 0x200];
+        bus.dma_read(MEM2_TAIL - 0xff, &mut out).unwrap();
+        assert_eq!(out, buf);
+
+        assert!(bus.decode_phys_addr(top).is_none());
+        assert!(bus.read32(top).is_err());
+        assert!(bus.dma_read(top - 0x100, &mut out).is_err());
+    }
+
+    #[test]
+    fn default_mem2_ends_at_its_size() {
+        let bus = Bus::with_boot0(None).unwrap();
+        assert!(bus.read32(MEM2_TAIL - 3).is_ok());
+        assert!(bus.read32(MEM2_TAIL + 1).is_err());
+        assert!(bus.read32(MEM1_TAIL - 3).is_ok());
+        assert!(bus.read32(MEM1_TAIL + 1).is_err());
+    }
+
+    #[test]
+    fn mmap_backed_memory_works() {
+        let bus_config = BusConfig { boot0: None, mmap_mem: true, ..Default::default() };
+        let mut bus = Bus::new(&bus_config).unwrap();
+        bus.write32(MEM1_BASE + 0x100, 0x1234_5678).unwrap();
+        assert_eq!(bus.read32(MEM1_BASE + 0x100).unwrap(), 0x1234_5678);
+    }
+
+    #[test]
+    fn oversized_memory_is_rejected() {
+        for bus_config in [
+            BusConfig { mem1_size: MEM1_MAX_SIZE + 0x1_0000, ..Default::default() },
+            BusConfig { mem2_size: 0x1234, ..Default::default() },
+            BusConfig { mem2_size: 0, ..Default::default() },
+        ] {
+            assert!(bus_config.validate().is_err());
+        }
+    }
+}
@@ -63,11 +63,21 @@ impl Bus {
 impl Bus {
     /// Dispatch a physical read access (to memory, or some I/O device).
     fn do_read(&self, addr: u32, width: BusWidth) -> anyhow::Result<BusPacket> {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, width.size(), false, false);
+        }
+        if !self.intercepts.is_empty() && let Some(read_fn) = self.intercepted_read(addr) {
+            return read_fn(addr, width);
+        }
         let handle = match self.decode_phys_addr(addr) {
             Some (h)=> {h},
             None => { bail!("Unresolved physical address {addr:08x}. current cycle count: {}", self.cycle); }
         };
 
+        if !self.access_latency.is_empty() {
+            self.charge_latency(addr);
+        }
+
         let off = (addr & handle.mask) as usize;
         let resp = match handle.dev {
             Device::Mem(dev) => self.do_mem_read(dev, off, width)?,
@@ -78,11 +88,21 @@ impl Bus {
 
     /// Dispatch a physical write access (to memory, or some I/O device).
     fn do_write(&mut self, addr: u32, msg: BusPacket) -> anyhow::Result<()> {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, msg.size(), true, false);
+        }
+        if !self.intercepts.is_empty() && let Some(write_fn) = self.intercepted_write(addr) {
+            return write_fn(addr, msg);
+        }
         let handle = match self.decode_phys_addr(addr) {
             Some(val) => val,
             None => { bail!("Unresolved physical address {addr:08x}"); },
         };
 
+        if !self.access_latency.is_empty() {
+            self.charge_latency(addr);
+        }
+
         let off = (addr & handle.mask) as usize;
         match handle.dev {
             Device::Mem(dev) => self.do_mem_write(dev, off, msg)?,
@@ -122,11 +142,12 @@ impl Bus {
             Mem1    => &mut self.mem1,
             Mem2    => &mut self.mem2,
         };
-        match msg {
-            Word(val) => target_ref.write::<u32>(off, val)?,
-            Half(val) => target_ref.write::<u16>(off, val)?,
-            Byte(val) => target_ref.write::<u8>(off, val)?,
+        let len = match msg {
+            Word(val) => { target_ref.write::<u32>(off, val)?; 4 },
+            Half(val) => { target_ref.write::<u16>(off, val)?; 2 },
+            Byte(val) => { target_ref.write::<u8>(off, val)?; 1 },
         };
+        self.code_watch.note_write(dev, off, len);
         Ok(())
     }
 }
@@ -135,6 +156,9 @@ impl Bus {
     /// Dispatch a DMA write to some memory device.
     fn do_dma_write(&mut self, addr: u32, buf: &[u8]) -> anyhow::Result<()> {
         use MemDevice::*;
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, buf.len(), true, true);
+        }
         let handle = match self.decode_phys_addr(addr){
             Some(val) => val,
             None => {
@@ -144,13 +168,16 @@ impl Bus {
 
         let off = (addr & handle.mask) as usize;
         match handle.dev {
-            Device::Mem(dev) => { match dev {
-                MaskRom => { bail!("Bus error: DMA write on mask ROM"); },
-                Sram0   => self.sram0.write_buf(off, buf)?,
-                Sram1   => self.sram1.write_buf(off, buf)?,
-                Mem1    => self.mem1.write_buf(off, buf)?,
-                Mem2    => self.mem2.write_buf(off, buf)?,
-            }},
+            Device::Mem(dev) => {
+                match dev {
+                    MaskRom => { bail!("Bus error: DMA write on mask ROM"); },
+                    Sram0   => self.sram0.write_buf(off, buf)?,
+                    Sram1   => self.sram1.write_buf(off, buf)?,
+                    Mem1    => self.mem1.write_buf(off, buf)?,
+                    Mem2    => self.mem2.write_buf(off, buf)?,
+                }
+                self.code_watch.note_write(dev, off, buf.len());
+            },
             _ => { bail!("Bus error: DMA write on memory-mapped I/O region"); },
         }
         Ok(())
@@ -159,6 +186,9 @@ impl Bus {
     /// Dispatch a DMA read to some memory device.
     fn do_dma_read(&self, addr: u32, buf: &mut [u8]) -> anyhow::Result<()> {
         use MemDevice::*;
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, buf.len(), false, true);
+        }
         let handle = match self.decode_phys_addr(addr) {
                 Some(val) => val,
                 None => { bail!("Unresolved physical address {addr:08x}"); }
@@ -180,3 +210,58 @@ impl Bus {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dev::SD0_BASE;
+
+    #[test]
+    fn access_latency_is_charged_on_the_next_step() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.access_latency.push("0x0d070000-0x0d0701ff=8".parse().unwrap());
+
+        // Accesses outside the region cost nothing extra
+        let start = bus.cycle;
+        bus.read32(0x0000_1000).unwrap();
+        bus.step(1).unwrap();
+        assert_eq!(bus.cycle, start + 1);
+
+        // Each access inside the region is charged
+        let start = bus.cycle;
+        bus.read32(SD0_BASE + 0x40).unwrap();
+        bus.read32(SD0_BASE + 0x48).unwrap();
+        bus.step(1).unwrap();
+        assert_eq!(bus.cycle, start + 1 + 16);
+        bus.step(1).unwrap();
+        assert_eq!(bus.cycle, start + 1 + 16 + 1);
+
+        assert!("0x0d070000=8".parse::<AccessLatency>().is_err());
+        assert!("0x0d0701ff-0x0d070000=8".parse::<AccessLatency>().is_err());
+    }
+
+    #[test]
+    fn intercepts_replace_part_of_a_device() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        let caps = bus.read32(SD0_BASE + 0x40).unwrap();
+        let max_current = bus.read32(SD0_BASE + 0x48).unwrap();
+        bus.intercept(SD0_BASE + 0x40..=SD0_BASE + 0x43,
+            Some(Box::new(|_, _| Ok(BusPacket::Word(0x1234_5678)))), None);
+        assert_ne!(caps, 0x1234_5678);
+        assert_eq!(bus.read32(SD0_BASE + 0x40).unwrap(), 0x1234_5678);
+        assert_eq!(bus.read32(SD0_BASE + 0x48).unwrap(), max_current);
+
+        // Intercepted writes never reach the memory underneath
+        let writes = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let log = writes.clone();
+        bus.intercept(0x0000_1000..=0x0000_1003, None, Some(Box::new(move |addr, msg| {
+            log.lock().push((addr, msg.size()));
+            Ok(())
+        })));
+        bus.write32(0x0000_1000, 0xdead_beef).unwrap();
+        bus.write32(0x0000_1004, 0xcafe_f00d).unwrap();
+        assert_eq!(*writes.lock(), vec![(0x0000_1000, 4)]);
+        assert_eq!(bus.read32(0x0000_1000).unwrap(), 0);
+        assert_eq!(bus.read32(0x0000_1004).unwrap(), 0xcafe_f00d);
+    }
+}
@@ -1,5 +1,6 @@
 
 use anyhow::bail;
+use log::{debug, warn};
 
 use crate::bus::*;
 use crate::bus::prim::*;
@@ -20,6 +21,11 @@ impl Bus {
     /// Dispatch a physical read access to some memory-mapped I/O device.
     pub fn do_mmio_read(&self, dev: IoDevice, off: usize, width: BusWidth) -> anyhow::Result<BusPacket> {
         use IoDevice::*;
+        // Devices are cleared when entering reset and ignore writes while
+        // held there, so reads will just return their power-on defaults
+        if let Some(bit) = Bus::ahb_reset_bit(dev) && self.hlwd.ahb_in_reset(bit) {
+            debug!(target: "HLWD", "Read on {dev:?} at {off:x} while held in reset");
+        }
         match (width, dev) {
             (BusWidth::W, Nand)  => self.nand.read(off),
             (BusWidth::W, Aes)   => self.aes.read(off),
@@ -34,8 +40,11 @@ impl Bus {
             (BusWidth::W, Ahb)   => self.hlwd.ahb.read(off),
             (BusWidth::W, Di)    => self.hlwd.di.read(off),
             (BusWidth::W, Exi)   => self.hlwd.exi.read(off),
+            (BusWidth::H, Dsp)   => self.hlwd.dsp.read(off),
             (BusWidth::H, Mi)    => self.hlwd.mi.read(off),
             (BusWidth::H, Ddr)   => self.hlwd.ddr.read(off),
+
+            (BusWidth::W, CycleCounter) => self.cycle_counter_read(off),
             _ => { bail!("Unsupported read {width:?} for {dev:?} at {off:x}"); },
         }
     }
@@ -44,6 +53,11 @@ impl Bus {
     pub fn do_mmio_write(&mut self, dev: IoDevice, off: usize, msg: BusPacket) -> anyhow::Result<()> {
         use IoDevice::*;
         use BusPacket::*;
+        // Writes to devices held in reset are dropped
+        if let Some(bit) = Bus::ahb_reset_bit(dev) && self.hlwd.ahb_in_reset(bit) {
+            warn!(target: "HLWD", "Dropped write {msg:?} on {dev:?} at {off:x} while held in reset");
+            return Ok(());
+        }
         let task = match (msg, dev) {
             (Word(val), Nand)  => self.nand.write(off, val),
             (Word(val), Aes)   => self.aes.write(off, val),
@@ -59,6 +73,7 @@ impl Bus {
             (Word(val), Ahb)   => self.hlwd.ahb.write(off, val),
             (Word(val), Exi)   => self.hlwd.exi.write(off, val),
             (Word(val), Di)    => self.hlwd.di.write(off, val),
+            (Half(val), Dsp)   => self.hlwd.dsp.write(off, val),
             (Half(val), Mi)    => self.hlwd.mi.write(off, val),
             (Half(val), Ddr)   => self.hlwd.ddr.write(off, val),
 
@@ -86,24 +101,44 @@ impl Bus {
         if !self.tasks.is_empty() {
             self.drain_tasks()?;
         }
-        self.cycle += 1;
+        self.cycle += 1 + self.take_pending_latency();
         Ok(())
     }
 
+    /// Returns true if the device targeted by some task is held in reset.
+    fn task_held_in_reset(&self, task: &BusTask) -> bool {
+        use crate::dev::hlwd::AhbResetBit;
+        match task {
+            BusTask::Nand(_) => self.hlwd.ahb_in_reset(AhbResetBit::Nand),
+            BusTask::Aes(_) => self.hlwd.ahb_in_reset(AhbResetBit::Aes),
+            BusTask::Sha(_) => self.hlwd.ahb_in_reset(AhbResetBit::Sha),
+            _ => false,
+        }
+    }
+
     /// Dispatch all of the pending tasks on the Bus.
     fn drain_tasks(&mut self) -> anyhow::Result<()> {
         let mut idx = 0;
         while idx != self.tasks.len() {
             if self.tasks[idx].target_cycle <= self.cycle {
                 let task = self.tasks.remove(idx);
+                if self.task_held_in_reset(&task.kind) {
+                    warn!(target: "HLWD", "Dropped {:?} while the device is held in reset", task.kind);
+                    continue;
+                }
                 match task.kind {
                     BusTask::Nand(x) => self.handle_task_nand(x)?,
                     BusTask::Aes(x) => self.handle_task_aes(x)?,
                     BusTask::Sha(x) => self.handle_task_sha(x)?,
                     BusTask::Mi{kind, data} => self.handle_task_mi(kind, data)?,
+                    BusTask::AramDma => self.handle_task_aram_dma()?,
+                    BusTask::DspMail => self.handle_task_dsp_mail()?,
                     BusTask::SetRomDisabled(x) => self.rom_disabled = x,
                     BusTask::SetMirrorEnabled(x) => self.mirror_enabled = x,
-                    BusTask::SDHC(task) => self.handle_task_sdhc(task),
+                    BusTask::SetUsbReset(x) => self.handle_task_usb_reset(x),
+                    BusTask::AhbReset(x) => self.handle_task_ahb_reset(x),
+                    BusTask::SDHC(slot, task) => self.handle_task_sdhc(slot, task)?,
+                    BusTask::Timeline(event) => self.handle_task_timeline(event)?,
                 }
             } else {
                 idx += 1;
@@ -49,6 +49,62 @@ impl_accesswidth!(u16);
 impl_accesswidth!(u8);
 
 
+/// Artificial latency (in bus cycles) charged to accesses on some range of
+/// physical addresses. Parsed from strings like `0x0d070000-0x0d0701ff=8`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccessLatency {
+    pub start: u32,
+    pub end: u32,
+    pub cycles: usize,
+}
+impl AccessLatency {
+    /// Returns true if the given physical address falls in this region.
+    pub fn contains(&self, addr: u32) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+impl std::str::FromStr for AccessLatency {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        fn parse_addr(x: &str) -> anyhow::Result<u32> {
+            let x = x.trim();
+            let x = x.strip_prefix("0x").or_else(|| x.strip_prefix("0X")).unwrap_or(x);
+            Ok(u32::from_str_radix(x, 16)?)
+        }
+        let (range, cycles) = s.split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected LO-HI=N, got \"{s}\""))?;
+        let (start, end) = range.split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("Expected LO-HI=N, got \"{s}\""))?;
+        let res = AccessLatency {
+            start: parse_addr(start)?,
+            end: parse_addr(end)?,
+            cycles: cycles.trim().parse()?,
+        };
+        anyhow::ensure!(res.start <= res.end, "Latency region start {:08x} is after end {:08x}", res.start, res.end);
+        Ok(res)
+    }
+}
+
+/// Closure handling a read on an intercepted range of physical addresses.
+pub type InterceptReadFn = Box<dyn Fn(u32, BusWidth) -> anyhow::Result<BusPacket> + Send + Sync>;
+/// Closure handling a write on an intercepted range of physical addresses.
+pub type InterceptWriteFn = Box<dyn FnMut(u32, BusPacket) -> anyhow::Result<()> + Send + Sync>;
+
+/// A range of physical addresses where accesses are handled by closures
+/// instead of the underlying memory or device.
+pub struct Intercept {
+    pub start: u32,
+    pub end: u32,
+    pub read_fn: Option<InterceptReadFn>,
+    pub write_fn: Option<InterceptWriteFn>,
+}
+impl Intercept {
+    /// Returns true if the given physical address falls in this range.
+    pub fn contains(&self, addr: u32) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
 /// Handle to a target for some physical memory access.
 #[derive(Debug, Clone, Copy)]
 pub struct DeviceHandle {
@@ -61,7 +117,7 @@ pub struct DeviceHandle {
 pub enum Device { Mem(MemDevice), Io(IoDevice) }
 
 /// Different kinds of memory devices that support physical memory accesses.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MemDevice { MaskRom, Sram0, Sram1, Mem1, Mem2 }
 
 /// Different kinds of I/O devices that support physical memory accesses.
@@ -80,18 +136,33 @@ pub enum IoDevice {
     Ahb, 
     Ddr,
     Di, 
+    Dsp,
     Si, 
     Exi, 
     Mi,
+
+    CycleCounter,
 }
 
 /// A message on the bus containing some value.
 #[derive(Debug, Clone, Copy)]
 pub enum BusPacket { Byte(u8), Half(u16), Word(u32) }
+impl BusPacket {
+    /// Size of this packet in bytes.
+    pub fn size(&self) -> usize {
+        match self { BusPacket::Byte(_) => 1, BusPacket::Half(_) => 2, BusPacket::Word(_) => 4 }
+    }
+}
 
 /// The width of an access on the bus.
 #[derive(Debug, Clone, Copy)]
 pub enum BusWidth { B, H, W }
+impl BusWidth {
+    /// Size of an access with this width in bytes.
+    pub fn size(self) -> usize {
+        match self { BusWidth::B => 1, BusWidth::H => 2, BusWidth::W => 4 }
+    }
+}
 
 /// An abstract request on the bus.
 #[derive(Debug)]
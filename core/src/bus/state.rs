@@ -0,0 +1,175 @@
+//! Save states.
+//!
+//! A save state is an LZ4-compressed stream of bincode-encoded values: a
+//! header, the CPU state (supplied by the backend), and then the memories
+//! and devices owned by the [Bus].
+//!
+//! The contents of the NAND flash and SD card images aren't included, so
+//! the same images need to be used when a state is restored (writes to the
+//! NAND are kept with the usual write tracking instead). Debugging state,
+//! access latency regions and intercepts aren't included either.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering::Relaxed;
+
+use anyhow::{bail, Context};
+use bincode::config;
+use bincode::de::DecoderImpl;
+use bincode::{Decode, Encode};
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+use log::info;
+
+use crate::bus::Bus;
+use crate::bus::codewatch::CodeWatch;
+use crate::mem::BigEndianMemory;
+
+const SAVE_STATE_MAGIC: [u8; 4] = *b"IRST";
+/// Bumped whenever the layout of a save state changes.
+const SAVE_STATE_VERSION: u32 = 7;
+
+fn put(out: &mut impl Write, val: impl Encode) -> anyhow::Result<()> {
+    bincode::encode_into_std_write(val, out, config::standard())?;
+    Ok(())
+}
+
+fn restore_mem(mem: &mut BigEndianMemory, saved: Vec<u8>, name: &str) -> anyhow::Result<()> {
+    if saved.len() != mem.data.len() {
+        bail!("Saved {name} is {:#x} bytes, expected {:#x}", saved.len(), mem.data.len());
+    }
+    mem.data.copy_from_slice(&saved);
+    Ok(())
+}
+
+impl Bus {
+    /// Write the state of the machine to a file. `cpu` is the state of the
+    /// CPU (along with anything else the backend needs to resume).
+    pub fn save_state(&self, path: impl AsRef<Path>, cpu: &impl Encode) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Couldn't create save state {}", path.display()))?;
+        let mut out = FrameEncoder::new(BufWriter::new(file));
+
+        put(&mut out, SAVE_STATE_MAGIC)?;
+        put(&mut out, SAVE_STATE_VERSION)?;
+        put(&mut out, cpu)?;
+        for mem in [&self.mrom, &self.sram0, &self.sram1, &self.mem1, &self.mem2] {
+            put(&mut out, mem)?;
+        }
+        put(&mut out, &self.hlwd)?;
+        put(&mut out, self.nand.reg)?;
+        put(&mut out, &self.aes)?;
+        put(&mut out, &self.sha)?;
+        put(&mut out, &self.ehci)?;
+        put(&mut out, &self.ohci0)?;
+        put(&mut out, &self.ohci1)?;
+        put(&mut out, &self.sd0)?;
+        put(&mut out, &self.sd1)?;
+        put(&mut out, self.rom_disabled)?;
+        put(&mut out, self.mirror_enabled)?;
+        put(&mut out, &self.tasks)?;
+        put(&mut out, self.cycle)?;
+        put(&mut out, self.pending_latency.load(Relaxed))?;
+        out.finish()?.flush()?;
+
+        info!(target: "Other", "Saved state to {} at bus cycle {}", path.display(), self.cycle);
+        Ok(())
+    }
+
+    /// Restore the state of the machine from a file written by
+    /// [Bus::save_state], returning the CPU state saved with it.
+    ///
+    /// If this fails, the bus may have been partially restored and shouldn't
+    /// be used.
+    pub fn load_state<T: Decode<()>>(&mut self, path: impl AsRef<Path>) -> anyhow::Result<T> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Couldn't open save state {}", path.display()))?;
+        let mut input = BufReader::new(FrameDecoder::new(BufReader::new(file)));
+        let mut dec = DecoderImpl::new(&mut input, config::standard(), ());
+
+        let magic: [u8; 4] = Decode::decode(&mut dec)?;
+        if magic != SAVE_STATE_MAGIC {
+            bail!("{} isn't a save state", path.display());
+        }
+        let version: u32 = Decode::decode(&mut dec)?;
+        if version != SAVE_STATE_VERSION {
+            bail!("Save state {} has version {version}, expected {SAVE_STATE_VERSION}", path.display());
+        }
+        let cpu = T::decode(&mut dec)?;
+        for (mem, name) in [
+            (&mut self.mrom, "mask ROM"),
+            (&mut self.sram0, "SRAM0"),
+            (&mut self.sram1, "SRAM1"),
+            (&mut self.mem1, "MEM1"),
+            (&mut self.mem2, "MEM2"),
+        ] {
+            restore_mem(mem, Decode::decode(&mut dec)?, name)?;
+        }
+        self.hlwd = Decode::decode(&mut dec)?;
+        self.nand.reg = Decode::decode(&mut dec)?;
+        self.aes = Decode::decode(&mut dec)?;
+        self.sha = Decode::decode(&mut dec)?;
+        self.ehci = Decode::decode(&mut dec)?;
+        self.ohci0 = Decode::decode(&mut dec)?;
+        self.ohci1 = Decode::decode(&mut dec)?;
+        self.sd0.decode_state(&mut dec)?;
+        self.sd1.decode_state(&mut dec)?;
+        self.rom_disabled = Decode::decode(&mut dec)?;
+        self.mirror_enabled = Decode::decode(&mut dec)?;
+        self.tasks = Decode::decode(&mut dec)?;
+        self.cycle = Decode::decode(&mut dec)?;
+        self.pending_latency.store(Decode::decode(&mut dec)?, Relaxed);
+
+        // Whatever was cached as code is gone now
+        self.code_watch = CodeWatch::default();
+        info!(target: "Other", "Loaded state from {} at bus cycle {}", path.display(), self.cycle);
+        Ok(cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::task::{BusTask, Task};
+    use crate::dev::hlwd::irq::HollywoodIrq;
+
+    #[test]
+    fn state_round_trip() {
+        let path = std::env::temp_dir().join(format!("ironic-state-{}.bin", std::process::id()));
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.write32(0x0000_1000, 0xdead_beef).unwrap();
+        bus.write32(0x1000_0000, 0xcafe_f00d).unwrap();
+        bus.hlwd.timer.alarm = 0x1234;
+        bus.hlwd.irq.arm_irq_enable.set(HollywoodIrq::Sdhc);
+        bus.rom_disabled = true;
+        bus.cycle = 42;
+        bus.tasks.push(Task { kind: BusTask::SetMirrorEnabled(true), target_cycle: 50 });
+        bus.save_state(&path, &(0x1234_5678u32, true)).unwrap();
+
+        let mut restored = Bus::with_boot0(None).unwrap();
+        let cpu: (u32, bool) = restored.load_state(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cpu, (0x1234_5678, true));
+        assert_eq!(restored.read32(0x0000_1000).unwrap(), 0xdead_beef);
+        assert_eq!(restored.read32(0x1000_0000).unwrap(), 0xcafe_f00d);
+        assert_eq!(restored.hlwd.dump_regs(), bus.hlwd.dump_regs());
+        assert!(restored.hlwd.irq.arm_irq_enable.is_set(HollywoodIrq::Sdhc));
+        assert!(restored.rom_disabled);
+        assert_eq!(restored.cycle, 42);
+        assert_eq!(restored.tasks.len(), 1);
+        assert!(matches!(restored.tasks[0], Task { kind: BusTask::SetMirrorEnabled(true), target_cycle: 50 }));
+    }
+
+    #[test]
+    fn load_state_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("ironic-not-state-{}.bin", std::process::id()));
+        let mut out = FrameEncoder::new(File::create(&path).unwrap());
+        out.write_all(b"not a save state").unwrap();
+        out.finish().unwrap();
+        let res = Bus::with_boot0(None).unwrap().load_state::<u32>(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(res.unwrap_err().to_string().contains("isn't a save state"));
+    }
+}
@@ -1,12 +1,13 @@
-use super::SDHCTask;
+use bincode::{Decode, Encode};
+use super::{Bus, SDHCTask};
 
 
 /// Some type of indirect access (from memory interface to the DDR interface).
-#[derive(Debug)]
+#[derive(Debug, Encode, Decode)]
 pub enum IndirAccess { Read, Write }
 
 /// Representing some device and piece of work to-be-completed by the bus.
-#[derive(Debug)]
+#[derive(Debug, Encode, Decode)]
 pub enum BusTask {
     /// A NAND interface command.
     Nand(u32),
@@ -19,17 +20,61 @@ pub enum BusTask {
     SetRomDisabled(bool),
     /// Change the state of the SRAM mappings
     SetMirrorEnabled(bool),
+    /// Put the AHB devices in this mask of `reset_ahb` bits into reset
+    AhbReset(u32),
+    /// Assert (true) or deassert (false) the USB controller force-reset
+    SetUsbReset(bool),
 
     /// A read/write access request on the DDR interface.
     Mi { kind: IndirAccess, data: u16 },
+    /// An ARAM DMA transfer on the DSP interface.
+    AramDma,
+    /// Mail sent to the DSP.
+    DspMail,
 
-    // SD Host Controller
-    SDHC(SDHCTask),
+    /// Work for the SD host controller in some slot.
+    SDHC(usize, SDHCTask),
+
+    /// An event from a scripted input timeline.
+    Timeline(super::timeline::TimelineEvent),
+}
+
+impl std::fmt::Display for BusTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use BusTask::*;
+        match self {
+            Nand(cmd) => write!(f, "NAND command {cmd:08x}"),
+            Aes(cmd) => write!(f, "AES command {cmd:08x}"),
+            Sha(cmd) => write!(f, "SHA command {cmd:08x}"),
+            SetRomDisabled(x) => write!(f, "Set boot ROM disabled={x}"),
+            SetMirrorEnabled(x) => write!(f, "Set SRAM mirror enabled={x}"),
+            AhbReset(mask) => write!(f, "AHB reset {mask:08x}"),
+            SetUsbReset(x) => write!(f, "Set USB reset={x}"),
+            Mi { kind, data } => write!(f, "MI {kind:?} {data:04x}"),
+            AramDma => write!(f, "ARAM DMA"),
+            DspMail => write!(f, "DSP mail"),
+            SDHC(slot, task) => write!(f, "SDHC{slot} {task:?}"),
+            Timeline(event) => write!(f, "Timeline {event:?}"),
+        }
+    }
 }
 
 /// An entry kept by the [Bus], representing some task to-be-completed.
+#[derive(Encode, Decode)]
 pub struct Task {
     pub kind: BusTask,
     pub target_cycle: usize,
 }
 
+
+impl Bus {
+    /// Describe the tasks waiting to be completed, along with the bus cycle
+    /// each one is due on, in the order they'll run.
+    pub fn pending_tasks(&self) -> Vec<(u64, String)> {
+        let mut res: Vec<_> = self.tasks.iter()
+            .map(|t| (t.target_cycle as u64, t.kind.to_string()))
+            .collect();
+        res.sort_by_key(|(cycle, _)| *cycle);
+        res
+    }
+}
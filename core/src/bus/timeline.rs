@@ -0,0 +1,264 @@
+//! Scripted input timelines.
+//!
+//! A timeline is a text file with one `<cycle> <event> [args...]` entry per
+//! line, which the bus applies at the given bus cycles. Lines starting with
+//! `#` are comments. The supported events are:
+//!
+//! - `gpio <mask> <0|1>`: drive some ARM GPIO input pins low or high
+//! - `irq <name>`: assert a Hollywood IRQ (e.g. `irq ArmIpc`)
+//! - `sd_eject [slot]`: remove the SD card from slot 0 (or some other slot)
+//! - `sd_insert <image> [slot]`: insert an SD card image into slot 0 (or
+//!   some other slot)
+//!
+//! For example:
+//!
+//! ```text
+//! 100000 sd_eject
+//! 200000 irq ArmIpc
+//! 300000 sd_insert wifi.img 1
+//! ```
+//!
+//! GPIO scripts are a simpler form for driving single pins, with one
+//! `<cycle>,<pin>,<value>` entry per line. Pins are numbered from zero, so
+//! this presses and releases the power button:
+//!
+//! ```text
+//! 50000000,0,1
+//! 50100000,0,0
+//! ```
+
+use bincode::{Decode, Encode};
+use anyhow::{anyhow, bail};
+use log::info;
+
+use crate::bus::Bus;
+use crate::bus::task::*;
+use crate::dev::hlwd::irq::HollywoodIrq;
+
+/// Some input event on a timeline.
+#[derive(Debug, Encode, Decode)]
+pub enum TimelineEvent {
+    /// Drive the ARM GPIO input pins in this mask low (false) or high (true)
+    GpioInput { mask: u32, level: bool },
+    /// Assert some Hollywood IRQ
+    Irq(HollywoodIrq),
+    /// Remove the SD card from some slot
+    SdEject { slot: usize },
+    /// Insert an SD card image into some slot
+    SdInsert { slot: usize, image: String },
+}
+
+fn parse_irq(name: &str) -> anyhow::Result<HollywoodIrq> {
+    use HollywoodIrq::*;
+    Ok(match name {
+        "Timer" => Timer, "Nand" => Nand, "Aes" => Aes, "Sha" => Sha,
+        "Ehci" => Ehci, "Ohci0" => Ohci0, "Ohci1" => Ohci1, "Sdhc" => Sdhc,
+        "Wifi" => Wifi, "PpcGpio" => PpcGpio, "ArmGpio" => ArmGpio,
+        "RstBtn" => RstBtn, "Di" => Di, "PpcIpc" => PpcIpc, "ArmIpc" => ArmIpc,
+        _ => bail!("Unknown IRQ \"{name}\""),
+    })
+}
+
+fn parse_u32(x: &str) -> anyhow::Result<u32> {
+    Ok(match x.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16)?,
+        None => x.parse()?,
+    })
+}
+
+/// Parse a timeline into a list of (bus cycle, event) entries.
+pub fn parse_timeline(text: &str) -> anyhow::Result<Vec<(usize, TimelineEvent)>> {
+    let mut res = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parse_line = || -> anyhow::Result<(usize, TimelineEvent)> {
+            let mut words = line.split_whitespace();
+            let cycle = words.next().unwrap().parse()?;
+            let event = words.next().ok_or_else(|| anyhow!("Missing event"))?;
+            let mut arg = || words.next().ok_or_else(|| anyhow!("Missing argument for {event}"));
+            let slot = |x: Option<&str>| -> anyhow::Result<usize> { Ok(x.map(str::parse).transpose()?.unwrap_or(0)) };
+            let event = match event {
+                "gpio" => TimelineEvent::GpioInput {
+                    mask: parse_u32(arg()?)?,
+                    level: parse_u32(arg()?)? != 0,
+                },
+                "irq" => TimelineEvent::Irq(parse_irq(arg()?)?),
+                "sd_eject" => TimelineEvent::SdEject { slot: slot(words.next())? },
+                "sd_insert" => TimelineEvent::SdInsert {
+                    image: arg()?.to_owned(),
+                    slot: slot(words.next())?,
+                },
+                _ => bail!("Unknown event \"{event}\""),
+            };
+            if let Some(extra) = words.next() {
+                bail!("Unexpected argument \"{extra}\"");
+            }
+            Ok((cycle, event))
+        };
+        res.push(parse_line().map_err(|e| anyhow!("Timeline line {}: {e}", lineno + 1))?);
+    }
+    Ok(res)
+}
+
+/// Parse a GPIO script into a list of (bus cycle, event) entries.
+pub fn parse_gpio_script(text: &str) -> anyhow::Result<Vec<(usize, TimelineEvent)>> {
+    let mut res = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parse_line = || -> anyhow::Result<(usize, TimelineEvent)> {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [cycle, pin, value] = fields[..] else {
+                bail!("Expected <cycle>,<pin>,<value>");
+            };
+            let pin = parse_u32(pin)?;
+            if pin >= 32 {
+                bail!("GPIO pin {pin} out of range");
+            }
+            let event = TimelineEvent::GpioInput { mask: 1 << pin, level: parse_u32(value)? != 0 };
+            Ok((cycle.parse()?, event))
+        };
+        res.push(parse_line().map_err(|e| anyhow!("GPIO script line {}: {e}", lineno + 1))?);
+    }
+    Ok(res)
+}
+
+impl Bus {
+    /// Load a timeline file, scheduling each of its events on the bus.
+    pub fn load_timeline(&mut self, path: &str) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read timeline {path}: {e}"))?;
+        for (cycle, event) in parse_timeline(&text)? {
+            self.tasks.push(Task { kind: BusTask::Timeline(event), target_cycle: cycle });
+        }
+        Ok(())
+    }
+
+    /// Load a GPIO script, scheduling each of its pin changes on the bus.
+    pub fn load_gpio_script(&mut self, path: &str) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read GPIO script {path}: {e}"))?;
+        for (cycle, event) in parse_gpio_script(&text)? {
+            self.tasks.push(Task { kind: BusTask::Timeline(event), target_cycle: cycle });
+        }
+        Ok(())
+    }
+
+    /// Apply some event from a timeline.
+    pub(crate) fn handle_task_timeline(&mut self, event: TimelineEvent) -> anyhow::Result<()> {
+        info!(target: "HLWD", "Timeline event at cycle {}: {event:?}", self.cycle);
+        match event {
+            TimelineEvent::GpioInput { mask, level } => {
+                if self.hlwd.gpio.arm.set_input(mask, level) {
+                    self.hlwd.irq.assert(HollywoodIrq::ArmGpio);
+                }
+            },
+            TimelineEvent::Irq(irq) => self.hlwd.irq.assert(irq),
+            TimelineEvent::SdEject { slot } => self.eject_sd(slot)?,
+            TimelineEvent::SdInsert { slot, image } => self.insert_sd(slot, &image)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpio_script_drives_input_pins() {
+        let path = std::env::temp_dir().join(format!("ironic-gpio-{}.txt", std::process::id()));
+        std::fs::write(&path, "# power button\n100,0,1\n200, 0, 0\n").unwrap();
+
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.load_gpio_script(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bus.hlwd.irq.arm_irq_enable.set(HollywoodIrq::ArmGpio);
+        bus.write32(0x0d80_00ec, 1).unwrap(); // intlvl: pin 0 active high
+        bus.write32(0x0d80_00f4, 1).unwrap(); // intmask
+
+        while bus.cycle < 100 {
+            bus.step(0).unwrap();
+        }
+        assert_eq!(bus.read32(0x0d80_00e8).unwrap() & 1, 0);
+        assert!(!bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::ArmGpio));
+
+        bus.step(0).unwrap();
+        assert_eq!(bus.read32(0x0d80_00e8).unwrap() & 1, 1);
+        assert_eq!(bus.read32(0x0d80_00f0).unwrap(), 1);
+        assert!(bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::ArmGpio));
+
+        // Flags are cleared by writing ones, and releasing the button
+        // doesn't match the level, so it doesn't raise them again
+        bus.write32(0x0d80_00f0, 1).unwrap();
+        while !bus.tasks.is_empty() {
+            bus.step(0).unwrap();
+        }
+        assert_eq!(bus.read32(0x0d80_00e8).unwrap() & 1, 0);
+        assert_eq!(bus.read32(0x0d80_00f0).unwrap(), 0);
+    }
+
+    #[test]
+    fn timeline_events_fire_at_their_cycles() {
+        use crate::dev::{SD0_BASE, SD1_BASE};
+        const PRESENT_STATE: u32 = 0x24;
+        const CARD_INSERTED: u32 = 1 << 16;
+        let image = std::env::temp_dir().join(format!("ironic-timeline-sd-{}.img", std::process::id()));
+        std::fs::write(&image, vec![0u8; 0x1000]).unwrap();
+        let path = std::env::temp_dir().join(format!("ironic-timeline-{}.txt", std::process::id()));
+        std::fs::write(&path, format!("100000 sd_eject\n150000 sd_insert {} 1\n200000 irq ArmIpc\n",
+            image.display())).unwrap();
+
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.insert_sd(0, image.to_str().unwrap()).unwrap();
+        bus.hlwd.irq.arm_irq_enable.set(HollywoodIrq::ArmIpc);
+        bus.load_timeline(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let inserted = |bus: &Bus, base: u32| bus.read32(base + PRESENT_STATE).unwrap() & CARD_INSERTED != 0;
+        let run_until = |bus: &mut Bus, cycle: usize| {
+            while bus.cycle < cycle {
+                bus.step(0).unwrap();
+            }
+        };
+
+        run_until(&mut bus, 100_000);
+        assert!(inserted(&bus, SD0_BASE));
+        bus.step(0).unwrap();
+        assert!(!inserted(&bus, SD0_BASE));
+
+        run_until(&mut bus, 150_000);
+        assert!(!inserted(&bus, SD1_BASE));
+        bus.step(0).unwrap();
+        assert!(inserted(&bus, SD1_BASE));
+        std::fs::remove_file(&image).unwrap();
+
+        run_until(&mut bus, 200_000);
+        assert!(!bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::ArmIpc));
+        bus.step(0).unwrap();
+        assert!(bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::ArmIpc));
+        assert!(bus.tasks.is_empty());
+    }
+
+    #[test]
+    fn timeline_rejects_bad_lines() {
+        assert!(matches!(parse_timeline("10 sd_eject 1").unwrap()[..], [(10, TimelineEvent::SdEject { slot: 1 })]));
+        assert!(parse_timeline("10 sd_eject x").is_err());
+        assert!(parse_timeline("10 sd_insert").is_err());
+        assert!(parse_timeline("10 irq ArmIpc 1").is_err());
+        let err = parse_timeline("\n10 bogus").unwrap_err();
+        assert!(err.to_string().starts_with("Timeline line 2"), "{err}");
+    }
+
+    #[test]
+    fn gpio_script_rejects_bad_lines() {
+        assert!(parse_gpio_script("100,0").is_err());
+        assert!(parse_gpio_script("100,32,1").is_err());
+        let err = parse_gpio_script("0x10 is not a cycle").unwrap_err();
+        assert!(err.to_string().starts_with("GPIO script line 1"));
+    }
+}
@@ -0,0 +1,161 @@
+//! Watchpoints on physical memory, for finding out who touches some address.
+//!
+//! Accesses from the CPU and from DMA are both checked. A hit is recorded on
+//! the bus, and it's up to the backend to take it (with [Bus::take_watch_hit])
+//! after each step and stop emulation.
+
+use std::fmt;
+
+use crate::bus::Bus;
+
+/// The kind of access which triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind { Read, Write, Access }
+impl WatchKind {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+/// A range of physical addresses being watched. Parsed from strings like
+/// `0x0d800000-0x0d80000f=w` (with `r`, `w` or `rw`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub start: u32,
+    pub end: u32,
+    pub kind: WatchKind,
+}
+impl Watchpoint {
+    /// Returns true if an access of `len` bytes at `addr` overlaps this range.
+    pub fn overlaps(&self, addr: u32, len: usize) -> bool {
+        let last = addr.saturating_add(len.saturating_sub(1) as u32);
+        addr <= self.end && last >= self.start
+    }
+}
+impl std::str::FromStr for Watchpoint {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        fn parse_addr(x: &str) -> anyhow::Result<u32> {
+            let x = x.trim();
+            let x = x.strip_prefix("0x").or_else(|| x.strip_prefix("0X")).unwrap_or(x);
+            Ok(u32::from_str_radix(x, 16)?)
+        }
+        let (range, kind) = s.split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected LO-HI=r|w|rw, got \"{s}\""))?;
+        let (start, end) = range.split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("Expected LO-HI=r|w|rw, got \"{s}\""))?;
+        let kind = match kind.trim() {
+            "r" => WatchKind::Read,
+            "w" => WatchKind::Write,
+            "rw" | "wr" => WatchKind::Access,
+            _ => anyhow::bail!("Unknown watchpoint kind \"{kind}\", expected r, w or rw"),
+        };
+        let res = Watchpoint { start: parse_addr(start)?, end: parse_addr(end)?, kind };
+        anyhow::ensure!(res.start <= res.end, "Watchpoint start {:08x} is after end {:08x}", res.start, res.end);
+        Ok(res)
+    }
+}
+
+/// An access which hit a watchpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchHit {
+    /// Physical address of the access.
+    pub addr: u32,
+    /// Size of the access in bytes.
+    pub len: usize,
+    pub write: bool,
+    /// True if this was a DMA access, rather than one from the CPU.
+    pub dma: bool,
+    /// Program counter of the last instruction started (from
+    /// [crate::bus::DebugInfo::last_pc]), if any.
+    pub pc: Option<u32>,
+}
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{} of {} byte(s) at {:08x}",
+            if self.dma { "DMA " } else { "" },
+            if self.write { "write" } else { "read" },
+            self.len, self.addr)?;
+        match self.pc {
+            Some(pc) => write!(f, " (pc={pc:08x})"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Bus {
+    /// Record a hit when any of the given kind of access touches some
+    /// inclusive range of physical addresses.
+    pub fn add_watchpoint(&mut self, range: std::ops::RangeInclusive<u32>, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { start: *range.start(), end: *range.end(), kind });
+    }
+
+    /// Remove all watchpoints.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Returns true if any watchpoints are set.
+    pub fn has_watchpoints(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    /// Take the first watchpoint hit since the last call, if any.
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.lock().unwrap().take()
+    }
+
+    /// Check an access against the watchpoints, and record it if it's the
+    /// first hit.
+    pub(crate) fn check_watchpoints(&self, addr: u32, len: usize, write: bool, dma: bool) {
+        let hit = self.watchpoints.iter()
+            .any(|wp| wp.kind.matches(write) && wp.overlaps(addr, len));
+        if !hit {
+            return;
+        }
+        let mut watch_hit = self.watch_hit.lock().unwrap();
+        if watch_hit.is_none() {
+            *watch_hit = Some(WatchHit { addr, len, write, dma, pc: self.debuginfo.last_pc });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchpoints_catch_cpu_and_dma_accesses() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.add_watchpoint(0x1000_0100..=0x1000_0103, WatchKind::Write);
+        bus.update_debug_location(Some(0x1234), None, None);
+
+        bus.write32(0x1000_0100, 1).unwrap();
+        assert_eq!(bus.take_watch_hit(), Some(WatchHit {
+            addr: 0x1000_0100, len: 4, write: true, dma: false, pc: Some(0x1234),
+        }));
+
+        // Reads, and writes elsewhere, don't count
+        bus.read32(0x1000_0100).unwrap();
+        bus.write32(0x1000_0104, 1).unwrap();
+        assert_eq!(bus.take_watch_hit(), None);
+
+        // A DMA write over the range
+        bus.dma_write(0x1000_00f0, &[0; 0x20]).unwrap();
+        let hit = bus.take_watch_hit().unwrap();
+        assert!(hit.dma && hit.write);
+        assert_eq!(hit.addr, 0x1000_00f0);
+    }
+
+    #[test]
+    fn parse_watchpoint() {
+        let wp: Watchpoint = "0x0d800000-0x0d80000f=rw".parse().unwrap();
+        assert_eq!(wp, Watchpoint { start: 0x0d80_0000, end: 0x0d80_000f, kind: WatchKind::Access });
+        assert!("0d800000-0d80000f=x".parse::<Watchpoint>().is_err());
+        assert!("0d80000f-0d800000=r".parse::<Watchpoint>().is_err());
+    }
+}
@@ -7,7 +7,9 @@ pub mod mmu;
 pub mod alu;
 
 use std::sync::Arc;
-use parking_lot::RwLock;
+use bincode::{Decode, Encode};
+use parking_lot::{RawRwLock, RwLock};
+use parking_lot::lock_api::ArcRwLockWriteGuard;
 
 use crate::bus::*;
 use crate::cpu::excep::*;
@@ -22,11 +24,18 @@ pub enum CpuRes {
     StepException(ExceptionType),
     /// We caught a Realview Semihosting command.
     Semihosting,
+    /// We stopped on a breakpoint instruction (with some immediate) without
+    /// executing it.
+    Breakpoint(u32),
+    /// We stopped after an instruction or DMA access hit a watchpoint.
+    Watchpoint(crate::bus::watch::WatchHit),
 }
 
 /// Container for ARMv5-compatible CPU state.
 pub struct Cpu {
     pub bus: Arc<RwLock<Bus>>,
+    /// The bus, while it's locked with [Cpu::hold_bus].
+    held_bus: Option<ArcRwLockWriteGuard<RawRwLock, Bus>>,
     /// The CPU's register file.
     pub reg: reg::RegisterFile,
     /// The system control co-processor.
@@ -39,17 +48,64 @@ pub struct Cpu {
 
     /// Whether or not an interrupt request is currently asserted.
     pub irq_input: bool,
+
+    /// The exclusive monitor: the address tagged by the last `ldrex`, until
+    /// a `strex` or `clrex` (or taking an exception) clears it. Not kept in
+    /// save states, which at worst makes one `strex` fail and be retried.
+    pub exclusive_addr: Option<u32>,
+    /// Execute `ldrex`/`strex`/`clrex`. These are ARMv6 instructions: the
+    /// Starlet is an ARM926EJ-S (ARMv5TEJ), so on hardware (and by default)
+    /// they're undefined instructions instead.
+    pub exclusives: bool,
 }
 impl Cpu {
     pub fn new(bus: Arc<RwLock<Bus>>) -> Self {
         Cpu {
             bus,
+            held_bus: None,
             reg: reg::RegisterFile::new(),
             p15: coproc::SystemControl::new(),
             scratch: 0,
             irq_input: false,
             current_exception: None,
             dbg_on: false,
+            exclusive_addr: None,
+            exclusives: false,
+        }
+    }
+}
+
+/// Access to the bus.
+impl Cpu {
+    /// Lock the bus until [Cpu::release_bus], so that accesses don't need to
+    /// take the lock each time. Nothing else can use the bus in the meantime
+    /// (and other threads trying to lock it will block).
+    pub fn hold_bus(&mut self) {
+        if self.held_bus.is_none() {
+            self.held_bus = Some(self.bus.write_arc());
+        }
+    }
+    /// Whether the bus is locked with [Cpu::hold_bus].
+    pub fn holds_bus(&self) -> bool {
+        self.held_bus.is_some()
+    }
+    /// Unlock the bus after [Cpu::hold_bus]. Returns true if it was held.
+    pub fn release_bus(&mut self) -> bool {
+        self.held_bus.take().is_some()
+    }
+
+    /// Run some closure with shared access to the bus.
+    pub fn with_bus<T>(&self, f: impl FnOnce(&Bus) -> T) -> T {
+        match self.held_bus.as_deref() {
+            Some(bus) => f(bus),
+            None => f(&self.bus.read()),
+        }
+    }
+    /// Run some closure with exclusive access to the bus.
+    pub fn with_bus_mut<T>(&mut self, f: impl FnOnce(&mut Bus) -> T) -> T {
+        match self.held_bus.as_deref_mut() {
+            Some(bus) => f(bus),
+            None => f(&mut self.bus.write()),
         }
     }
 }
@@ -77,3 +133,95 @@ impl Cpu {
         self.reg.pc = self.reg.pc.wrapping_add(pc_inc);
     }
 }
+
+/// Saving and restoring the register file.
+impl Cpu {
+    /// Write the register file (including all banked registers and saved
+    /// status registers) to some file.
+    pub fn save_regs(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let bytes = bincode::encode_to_vec(self.reg, bincode::config::standard())?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Replace the register file with one previously written by
+    /// [Cpu::save_regs]. No other CPU or bus state is touched.
+    pub fn load_regs(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let bytes = std::fs::read(path)?;
+        let (reg, _) = bincode::decode_from_slice(&bytes, bincode::config::standard())?;
+        self.reg = reg;
+        Ok(())
+    }
+}
+
+/// The parts of the CPU kept in a save state.
+#[derive(Encode, Decode)]
+pub struct CpuState {
+    pub reg: reg::RegisterFile,
+    pub p15: coproc::SystemControl,
+    pub current_exception: Option<ExceptionType>,
+    pub scratch: u32,
+    pub irq_input: bool,
+}
+
+/// Saving and restoring the whole CPU state.
+impl Cpu {
+    pub fn save_state(&self) -> CpuState {
+        CpuState {
+            reg: self.reg,
+            p15: self.p15.clone(),
+            current_exception: self.current_exception,
+            scratch: self.scratch,
+            irq_input: self.irq_input,
+        }
+    }
+
+    /// Replace the CPU state with one taken by [Cpu::save_state].
+    pub fn load_state(&mut self, state: CpuState) {
+        self.reg = state.reg;
+        self.p15 = state.p15;
+        self.current_exception = state.current_exception;
+        self.scratch = state.scratch;
+        self.irq_input = state.irq_input;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::psr::Psr;
+    use crate::cpu::reg::CpuMode;
+
+    #[test]
+    fn register_file_round_trip() {
+        let bus = Arc::new(RwLock::new(Bus::with_boot0(None).unwrap()));
+        let mut cpu = Cpu::new(bus);
+        for i in 0..15 { cpu.reg.r[i] = 0x1000 + i as u32; }
+        cpu.write_exec_pc(0xffff_1234);
+        cpu.reg.bank.irq = [0xdead_0001, 0xdead_0002];
+        cpu.reg.bank.fiq = [0xf1f1_0000; 8];
+        cpu.reg.spsr.svc = Psr(0x6000_001f);
+        let mut cpsr = cpu.reg.cpsr;
+        cpsr.set_mode(CpuMode::Irq);
+        cpsr.set_n(true);
+        cpsr.set_c(true);
+        cpu.reg.write_cpsr(cpsr);
+
+        let path = std::env::temp_dir().join(format!("ironic-regs-{}.bin", std::process::id()));
+        cpu.save_regs(&path).unwrap();
+        let saved = cpu.reg;
+        let saved_pc = cpu.read_fetch_pc();
+
+        for i in 0..15 { cpu.reg.r[i] = 0; }
+        cpu.increment_pc();
+        cpu.reg.bank.fiq = [0; 8];
+        cpu.reg.write_cpsr(Psr(0x0000_0013));
+
+        cpu.load_regs(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(cpu.reg == saved);
+        assert_eq!(cpu.read_fetch_pc(), saved_pc);
+        assert_eq!(cpu.reg.cpsr.mode(), CpuMode::Irq);
+        assert!(cpu.reg.cpsr.n() && cpu.reg.cpsr.c());
+    }
+}
@@ -51,7 +51,7 @@ pub fn lsl(rm: u32, simm: u8, c_in: bool) -> (u32, bool) {
         (rm, c_in) 
     } else if simm < 32 {
         let res = rm << simm;
-        let c_out = (1 << (32 - simm) & res) != 0;
+        let c_out = (rm >> (32 - simm)) & 1 != 0;
         (res, c_out)
     } else if simm == 32 {
         (0, (rm & 1) != 0)
@@ -66,7 +66,7 @@ pub fn lsr_imm(rm: u32, simm: u8, _c_in: bool) -> (u32, bool) {
         (0, (rm & 0x8000_0000) != 0)
     } else {
         let res = rm >> simm;
-        let c_out = (1 << (simm - 1) & res) != 0;
+        let c_out = (rm >> (simm - 1)) & 1 != 0;
         (res, c_out)
     }
 }
@@ -76,7 +76,7 @@ pub fn lsr_reg(rm: u32, simm: u8, c_in: bool) -> (u32, bool) {
         (rm, c_in)
     } else if simm < 32 {
         let res = rm >> simm;
-        let c_out = (1 << (simm - 1) & res) != 0;
+        let c_out = (rm >> (simm - 1)) & 1 != 0;
         (res, c_out)
     } else if simm == 32 {
         (0, (rm & 0x8000_0000) != 0)
@@ -95,7 +95,7 @@ pub fn asr_imm(rm: u32, simm: u8, _c_in: bool) -> (u32, bool) {
         }
     } else {
         let res = ((rm as i32) >> simm) as u32;
-        let c_out = (1 << (simm - 1) & res) != 0;
+        let c_out = (rm >> (simm - 1)) & 1 != 0;
         (res, c_out)
     }
 }
@@ -105,7 +105,7 @@ pub fn asr_reg(rm: u32, simm: u8, c_in: bool) -> (u32, bool) {
         (rm, c_in)
     } else if simm < 32 {
         let res = ((rm as i32) >> simm) as u32;
-        let c_out = (1 << (simm - 1) & res) != 0;
+        let c_out = (rm >> (simm - 1)) & 1 != 0;
         (res, c_out)
     } else if (rm & 0x8000_0000) == 0 {
         (0, false)
@@ -120,7 +120,7 @@ pub fn ror_imm(rm: u32, simm: u8, c_in: bool) -> (u32, bool) {
         (res, (rm & 1) != 0)
     } else {
         let res = rm.rotate_right(simm as u32);
-        let c_out = (1 << (simm - 1) & res) != 0;
+        let c_out = (res & 0x8000_0000) != 0;
         (res, c_out)
     }
 }
@@ -133,7 +133,7 @@ pub fn ror_reg(rm: u32, simm: u8, c_in: bool) -> (u32, bool) {
             (rm, (rm & 0x8000_0000) != 0)
         } else {
             let res = rm.rotate_right(imm);
-            let c_out = (1 << (imm - 1) & res) != 0;
+            let c_out = (res & 0x8000_0000) != 0;
             (res, c_out)
         }
     }
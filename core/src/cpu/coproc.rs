@@ -1,13 +1,14 @@
 //! Coprocessor register definitions and functionality.
 
-use std::{cell::RefCell, collections::HashMap, sync::Arc, hash::BuildHasherDefault};
-use parking_lot::RwLock;
+use bincode::{Decode, Encode};
+use std::{cell::RefCell, collections::HashMap, hash::BuildHasherDefault};
 
 use crate::bus::Bus;
 use fxhash::FxHasher32;
+use log::warn;
 
 /// The system control register (p15 register 1).
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Encode, Decode)]
 #[repr(transparent)]
 pub struct ControlRegister(pub u32);
 impl ControlRegister {
@@ -24,7 +25,7 @@ impl ControlRegister {
 }
 
 /// Domain access control register (DACR).
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Encode, Decode)]
 #[repr(transparent)]
 pub struct DACRegister(pub u32);
 impl DACRegister {
@@ -62,14 +63,18 @@ impl From<u32> for DomainMode {
 /// Registers in the System control coprocessor.
 #[derive(Debug)]
 pub enum SystemControlReg {
-    Control, PageControl, AccessControl,
+    IdCode, Control, PageControl, AccessControl,
     FaultStatus, FaultAddress,
     CacheControl, TlbControl, CacheLockdown, TlbLockdown,
+    ProcessId,
+    /// Registers the ARM926EJ-S doesn't have (or which we don't care about).
+    Other(u32),
 }
 impl From<u32> for SystemControlReg {
     fn from(x: u32) -> Self {
         use SystemControlReg::*;
         match x {
+            0 => IdCode,
             1 => Control,
             2 => PageControl,
             3 => AccessControl,
@@ -79,7 +84,8 @@ impl From<u32> for SystemControlReg {
             8 => TlbControl,
             9 => CacheLockdown,
             10 => TlbLockdown,
-            _ => panic!("Invalid p15 register number {x}"),
+            13 => ProcessId,
+            _ => Other(x),
         }
     }
 }
@@ -93,6 +99,7 @@ pub struct FlagRes {
 
 
 /// Container for the System Control coprocessor (p15).
+#[derive(Clone)]
 pub struct SystemControl {
     /// System control register
     pub c1_ctrl: ControlRegister,
@@ -116,6 +123,32 @@ impl Default for SystemControl {
     }
 }
 
+/// The TLB isn't encoded, so a decoded instance starts with an empty one.
+impl Encode for SystemControl {
+    fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> Result<(), bincode::error::EncodeError> {
+        self.c1_ctrl.encode(encoder)?;
+        self.c2_ttbr0.encode(encoder)?;
+        self.c3_dacr.encode(encoder)?;
+        self.c5_dfsr.encode(encoder)?;
+        self.c5_ifsr.encode(encoder)?;
+        self.c6_dfar.encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for SystemControl {
+    fn decode<D: bincode::de::Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+        Ok(SystemControl {
+            c1_ctrl: Decode::decode(decoder)?,
+            c2_ttbr0: Decode::decode(decoder)?,
+            c3_dacr: Decode::decode(decoder)?,
+            c5_dfsr: Decode::decode(decoder)?,
+            c5_ifsr: Decode::decode(decoder)?,
+            c6_dfar: Decode::decode(decoder)?,
+            ..Self::new()
+        })
+    }
+}
+bincode::impl_borrow_decode!(SystemControl);
+
 impl SystemControl {
     pub fn new() -> Self {
         SystemControl {
@@ -129,105 +162,77 @@ impl SystemControl {
         }
     }
 
+    /// Main ID register of the ARM926EJ-S in Starlet.
+    pub const MIDR: u32 = 0x4106_9265;
+    /// Cache type register: separate, write-back 16KiB instruction and data
+    /// caches with 32-byte lines.
+    pub const CACHE_TYPE: u32 = 0x1d15_2152;
+
     /// Returns a set of status flags; called by mrc when rt=15.
     pub fn read_alt(&self, reg: u32, crm: u32, opcd2: u32) -> FlagRes {
         use SystemControlReg::*;
-        match SystemControlReg::from(reg) {
-            CacheControl => match (crm, opcd2) {
-                (10, 3) => FlagRes { n: None, z: Some(true), c: None, v: None },
-                (14, 3) => FlagRes { n: None, z: Some(true), c: None, v: None }, //clean and invalidate entire DCache
-                _ => panic!(""),
+        match (SystemControlReg::from(reg), crm, opcd2) {
+            // Test and clean (and invalidate) the DCache. There's no cache,
+            // so it's always clean.
+            (CacheControl, 10, 3) |
+            (CacheControl, 14, 3) => FlagRes { n: None, z: Some(true), c: None, v: None },
+            (reg, ..) => {
+                warn!(target: "Other", "Unimpl p15 read_alt {reg:?} crm={crm} opcd2={opcd2}");
+                FlagRes { n: None, z: None, c: None, v: None }
             },
-            _ => panic!("Unimpl p15 read_alt {:?} crm={crm} opcd2={opcd2}", 
-                SystemControlReg::from(reg)),
         }
     }
 
     pub fn read(&self, reg: u32, crm: u32, opcd2: u32) -> u32 {
         use SystemControlReg::*;
-        match SystemControlReg::from(reg) {
-            Control => match (crm, opcd2) {
-                (0, 0) => self.c1_ctrl.0,
-                _ => panic!("Unimpl p15 read {:?} crm={crm} opcd2={opcd2}", 
-                    SystemControlReg::from(reg)),
-            },
-            AccessControl => match (crm, opcd2) {
-                (0, 0) => self.c3_dacr.0,
-                _ => panic!("Unimpl p15 read {:?} crm={crm} opcd2={opcd2}", 
-                    SystemControlReg::from(reg)),
-            },
-            PageControl => match (crm, opcd2) {
-                (0, 0) => self.read_ttbr(),
-                _ => panic!("Unimpl p15 read {:?} crm={crm} opcd2={opcd2}",
-                    SystemControlReg::from(reg)),
+        match (SystemControlReg::from(reg), crm, opcd2) {
+            (IdCode, 0, 0) => Self::MIDR,
+            (IdCode, 0, 1) => Self::CACHE_TYPE,
+            (IdCode, 0, 2) => 0, // No TCMs
+            (Control, 0, 0) => self.c1_ctrl.0,
+            (PageControl, 0, 0) => self.read_ttbr(),
+            (AccessControl, 0, 0) => self.c3_dacr.0,
+            (FaultStatus, 0, 0) => self.c5_dfsr,
+            (FaultStatus, 0, 1) => self.c5_ifsr,
+            (FaultAddress, 0, 0) => self.c6_dfar,
+            // Nothing is locked down, and there's no FCSE
+            (CacheLockdown, ..) | (TlbLockdown, ..) | (ProcessId, ..) => 0,
+            (reg, ..) => {
+                warn!(target: "Other", "Unimpl p15 read {reg:?} crm={crm} opcd2={opcd2}");
+                0
             },
-            _ => panic!("Unimpl p15 read {:?} crm={crm} opcd2={opcd2}", 
-                SystemControlReg::from(reg)),
         }
     }
 
     pub fn write(&mut self, val: u32, reg: u32, crm: u32, opcd2: u32) {
         use SystemControlReg::*;
-        match SystemControlReg::from(reg) {
-            Control => match (crm, opcd2) {
-                (0, 0) => {
-                    self.clear_tlb(); // They could be doing something interesting, like shutting off the MMU
-                    self.c1_ctrl.0 = val
-                },
-                _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}",
-                    val, SystemControlReg::from(reg), crm, opcd2),
+        match (SystemControlReg::from(reg), crm, opcd2) {
+            (Control, 0, 0) => {
+                self.clear_tlb(); // They could be doing something interesting, like shutting off the MMU
+                self.c1_ctrl.0 = val
             },
-
-            PageControl => match (crm, opcd2) {
-                (0, 0) => {
-                    self.clear_tlb();
-                    self.write_ttbr(val)
-                },
-                _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}",
-                    val, SystemControlReg::from(reg), crm, opcd2),
-            },
-
-            AccessControl => match (crm, opcd2) {
-                (0, 0) => {
-                    self.c3_dacr = DACRegister(val);
-                },
-                _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}",
-                    val, SystemControlReg::from(reg), crm, opcd2),
+            (PageControl, 0, 0) => {
+                self.clear_tlb();
+                self.write_ttbr(val)
             },
-
-            FaultStatus => match (crm, opcd2) {
-                (0, 0) => self.c5_dfsr = val,
-                (0, 1) => self.c5_ifsr = val,
-                _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}",
-                    val, SystemControlReg::from(reg), crm, opcd2),
-            },
-            FaultAddress => match (crm, opcd2) {
-                (0, 0) => self.c6_dfar = val,
-                _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}",
-                    val, SystemControlReg::from(reg), crm, opcd2),
-            },
-
-            CacheControl => match (crm, opcd2) {
-                (0, 4) => { // wait for interrupt
-                    // This isn't implemented currently. Since interrupts are serviced immediately, we should be able to no-op right?
-                },
-                (5, 0) => {}, // Invalidate entire icache
-                (6, 0) => {}, // Invalidate entire dcache
-                (6, 1) => {}, // Invalidate dcache line
-                (10, 1) => {}, // Clean dcache line
-                (10, 4) => {}, // Drain write buffer
-                _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}",
-                    val, SystemControlReg::from(reg), crm, opcd2),
+            (AccessControl, 0, 0) => {
+                self.c3_dacr = DACRegister(val);
             },
+            (FaultStatus, 0, 0) => self.c5_dfsr = val,
+            (FaultStatus, 0, 1) => self.c5_ifsr = val,
+            (FaultAddress, 0, 0) => self.c6_dfar = val,
 
-            TlbControl => match (crm, opcd2) {
-                (7, 0) => { self.clear_tlb(); }, // Invalidate entire TLB
-                _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}",
-                    val, SystemControlReg::from(reg), crm, opcd2),
+            (CacheControl, 0, 4) => { // wait for interrupt
+                // This isn't implemented currently. Since interrupts are serviced immediately, we should be able to no-op right?
             },
+            // There are no caches, so cache maintenance (invalidate/clean
+            // by line or entirely, drain write buffer, etc.) does nothing
+            (CacheControl, ..) => {},
+            // Invalidate the TLB (entirely, or by entry)
+            (TlbControl, ..) => self.clear_tlb(),
+            (CacheLockdown, ..) | (TlbLockdown, ..) => {},
 
-            _ => panic!("Unimpl P15 write {:08x} {:?} crm={} opcd2={}", 
-                val, SystemControlReg::from(reg), crm, opcd2),
+            (reg, ..) => warn!(target: "Other", "Unimpl P15 write {val:08x} {reg:?} crm={crm} opcd2={opcd2}"),
         }
     }
 }
@@ -244,12 +249,12 @@ impl SystemControl {
 
     /// Perform the actual L1 lookup
     /// Technically this is an MMU operation, but having it here is easier for now.
-    pub fn l1_fetch(&self, addr: u32, bus: &Arc<RwLock<Bus>>) -> anyhow::Result<u32> {
+    pub fn l1_fetch(&self, addr: u32, bus: &Bus) -> anyhow::Result<u32> {
         let mut tlb_inner = self.l1_tlb.borrow_mut();
         let val = match tlb_inner.get(&addr) {
             Some(val) => *val, // TLB hit
             None => { // miss
-                let val = bus.read().read32(addr)?;
+                let val = bus.read32(addr)?;
                 tlb_inner.insert(addr, val);
                 val
             },
@@ -1,5 +1,6 @@
 //! Implementation of exception behavior.
 
+use bincode::{Decode, Encode};
 use anyhow::bail;
 
 use crate::cpu::*;
@@ -7,7 +8,7 @@ use crate::dbg::ios;
 use crate::cpu::reg::*;
 
 /// Different types of exceptions.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
 pub enum ExceptionType {
     //Reset,
     Undef(u32),
@@ -86,6 +87,7 @@ impl Cpu {
         if let ExceptionType::Undef(opcd) = e {
             ios::log_syscall(self, opcd);
         }
+        self.exclusive_addr = None;
 
 
         // Build the new CPSR for the target mode and swap into it
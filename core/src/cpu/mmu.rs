@@ -11,31 +11,31 @@ use anyhow::{bail, Context};
 impl Cpu {
     pub fn read32(&self, addr: u32) -> anyhow::Result<u32> {
         let paddr = self.translate(TLBReq::new(addr, Access::Read))?;
-        let res = self.bus.read().read32(paddr)?;
+        let res = self.with_bus(|bus| bus.read32(paddr))?;
         Ok(res)
     }
     pub fn read16(&self, addr: u32) -> anyhow::Result<u16> {
         let paddr = self.translate(TLBReq::new(addr, Access::Read))?;
-        let res = self.bus.read().read16(paddr)?;
+        let res = self.with_bus(|bus| bus.read16(paddr))?;
         Ok(res)
     }
     pub fn read8(&self, addr: u32) -> anyhow::Result<u8> {
         let paddr = self.translate(TLBReq::new(addr, Access::Read))?;
-        let res = self.bus.read().read8(paddr)?;
+        let res = self.with_bus(|bus| bus.read8(paddr))?;
         Ok(res)
     }
 
     pub fn write32(&mut self, addr: u32, val: u32) -> anyhow::Result<()> {
         let paddr = self.translate(TLBReq::new(addr, Access::Write))?;
-        self.bus.write().write32(paddr, val)
+        self.with_bus_mut(|bus| bus.write32(paddr, val))
     }
     pub fn write16(&mut self, addr: u32, val: u32) -> anyhow::Result<()> {
         let paddr = self.translate(TLBReq::new(addr, Access::Write))?;
-        self.bus.write().write16(paddr, val as u16)
+        self.with_bus_mut(|bus| bus.write16(paddr, val as u16))
     }
     pub fn write8(&mut self, addr: u32, val: u32) -> anyhow::Result<()> {
         let paddr = self.translate(TLBReq::new(addr, Access::Write))?;
-        self.bus.write().write8(paddr, val as u8)
+        self.with_bus_mut(|bus| bus.write8(paddr, val as u8))
     }
 }
 
@@ -85,7 +85,7 @@ impl Cpu {
     /// Given some virtual address, return the first-level PTE.
     fn l1_fetch(&self, vaddr: VirtAddr) -> anyhow::Result<L1Descriptor> {
         let addr = (self.p15.read_ttbr() & 0xffff_c000) | vaddr.l1_idx() << 2;
-        let val = self.p15.l1_fetch(addr, &self.bus)?;
+        let val = self.with_bus(|bus| self.p15.l1_fetch(addr, bus))?;
 
         let res = L1Descriptor::from_u32(val);
         if let L1Descriptor::Fault(_) = res {
@@ -104,7 +104,7 @@ impl Cpu {
             },
             _ => bail!("l2_fetch requires an L1::Coarse descriptor"),
         };
-        let val = self.bus.read().read32(addr)?;
+        let val = self.with_bus(|bus| bus.read32(addr))?;
 
         L2Descriptor::from_u32_checked(val).with_context(|| format!("l2_fetch: VirtualAddr: 0x{:x} L1Descriptor: {d:?}", vaddr.0))
     }
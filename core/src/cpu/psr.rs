@@ -1,11 +1,12 @@
 //! Helpers for dealing with program status registers.
 
 use anyhow::bail;
+use bincode::{Decode, Encode};
 
 use crate::cpu::reg::CpuMode;
 
 /// Program status register.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Encode, Decode)]
 #[repr(transparent)]
 pub struct Psr(pub u32);
 impl Psr {
@@ -36,11 +37,31 @@ impl Psr {
     pub fn set_c(&mut self, val: bool) { self.set_bit(29, val); }
     pub fn set_z(&mut self, val: bool) { self.set_bit(30, val); }
     pub fn set_n(&mut self, val: bool) { self.set_bit(31, val); }
+
+    /// Describe every field in this PSR, e.g. to interpret a raw CPSR from
+    /// some log or register dump.
+    pub fn describe(&self) -> String {
+        let bit = |idx: u32| (self.0 >> idx) & 1;
+        let it = ((self.0 >> 25) & 0b11) | ((self.0 >> 8) & 0b1111_1100);
+        let ge = (self.0 >> 16) & 0b1111;
+        let mode_bits = self.0 & 0x1f;
+        let mode = match CpuMode::try_from(mode_bits) {
+            Ok(mode) => mode.name(),
+            Err(_) => "Invalid",
+        };
+        format!("PSR {:08x}\n\
+            N={} Z={} C={} V={} Q={}\n\
+            IT={it:#04x} GE={ge:04b}\n\
+            E={} A={} I={} F={} T={}\n\
+            Mode: {mode} ({mode_bits:#07b})",
+            self.0, bit(31), bit(30), bit(29), bit(28), bit(27),
+            bit(9), bit(8), bit(7), bit(6), bit(5))
+    }
 }
 
 
 /// Saved program status registers.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Encode, Decode)]
 pub struct SavedStatusBank {
     /// SVC mode saved program status register.
     pub svc: Psr,
@@ -99,4 +120,17 @@ impl SavedStatusBank {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn describe_svc_with_interrupts_masked() {
+        let desc = Psr(0x6000_00d3).describe();
+        assert_eq!(desc, "PSR 600000d3\n\
+            N=0 Z=1 C=1 V=0 Q=0\n\
+            IT=0x00 GE=0000\n\
+            E=0 A=0 I=1 F=1 T=0\n\
+            Mode: Supervisor (0b10011)");
+    }
+}
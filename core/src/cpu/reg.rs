@@ -1,6 +1,7 @@
 //! CPU register definitions.
 
 use anyhow::bail;
+use bincode::{Decode, Encode};
 
 use crate::cpu::psr::*;
 
@@ -20,6 +21,15 @@ pub enum CpuMode {
 }
 impl CpuMode {
     pub fn is_privileged(self) -> bool { self != CpuMode::Usr }
+
+    /// Full name of this mode, as used in the ARM ARM.
+    pub fn name(self) -> &'static str {
+        use CpuMode::*;
+        match self {
+            Usr => "User", Fiq => "FIQ", Irq => "IRQ", Svc => "Supervisor",
+            Abt => "Abort", Und => "Undefined", Sys => "System",
+        }
+    }
 }
 impl TryFrom<u32> for CpuMode {
     type Error = String;
@@ -66,7 +76,7 @@ impl TryFrom<u32> for Cond {
 }
 
 /// The set of banked registers for all operating modes.
-#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Encode, Decode)]
 pub struct RegisterBank {
     pub sys: [u32; 2],
     pub svc: [u32; 2],
@@ -77,7 +87,7 @@ pub struct RegisterBank {
 }
 
 /// Top-level container for register state.
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Encode, Decode)]
 #[repr(C)]
 pub struct RegisterFile {
     /// The currently-active set of general-purpose registers.
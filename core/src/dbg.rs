@@ -1 +1,2 @@
 pub mod ios;
+pub mod symbols;
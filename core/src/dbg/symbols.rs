@@ -0,0 +1,202 @@
+//! Symbol maps for annotating disassembly and crash dumps.
+//!
+//! Maps are plain text with one symbol per line, in the form that most
+//! tools can export (e.g. from IDA or Ghidra, with a little massaging):
+//!
+//! ```text
+//! # address   name            [size]
+//! 0xffff1234  ThreadCreate    0x40
+//! ffff1274,   ThreadJoin
+//! ```
+//!
+//! Fields are separated by whitespace and/or commas, addresses and sizes
+//! are hexadecimal (with or without `0x`), and blank lines or lines
+//! starting with `#` or `;` are ignored. Names may contain spaces (e.g.
+//! demangled C++), so everything after the address is the name, apart from
+//! a trailing field which parses as a size.
+
+use anyhow::{anyhow, bail};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Clone)]
+struct Symbol {
+    name: String,
+    size: Option<u32>,
+    /// True for symbols known to be functions (e.g. `STT_FUNC` in an ELF).
+    func: bool,
+}
+
+/// A set of symbols, keyed by address.
+#[derive(Clone, Default)]
+pub struct SymbolMap {
+    symbols: BTreeMap<u32, Symbol>,
+}
+impl SymbolMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a symbol map from a file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Couldn't read symbol map {}: {e}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    /// Parse the text of a symbol map.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        fn parse_hex(s: &str) -> Option<u32> {
+            let s = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+            u32::from_str_radix(s, 16).ok()
+        }
+
+        let mut map = Self::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let is_sep = |c: char| c == ',' || c.is_whitespace();
+            let (addr, rest) = line.split_once(is_sep).unwrap_or((line, ""));
+            let rest = rest.trim_start_matches(is_sep);
+            let (name, size) = match rest.rsplit_once(is_sep) {
+                Some((name, size)) if parse_hex(size).is_some() => (name.trim_end_matches(is_sep), Some(size)),
+                _ => (rest, None),
+            };
+            if name.is_empty() {
+                bail!("Symbol map line {}: expected `address name [size]`", idx + 1);
+            }
+            let Some(addr) = parse_hex(addr) else {
+                bail!("Symbol map line {}: invalid address {addr:?}", idx + 1);
+            };
+            let size = match size.map(|s| parse_hex(s).ok_or(s)).transpose() {
+                Ok(size) => size,
+                Err(s) => bail!("Symbol map line {}: invalid size {s:?}", idx + 1),
+            };
+            map.insert(addr, name, size);
+        }
+        Ok(map)
+    }
+
+    pub fn insert(&mut self, addr: u32, name: &str, size: Option<u32>) {
+        self.symbols.insert(addr, Symbol { name: name.to_owned(), size, func: false });
+    }
+
+    /// Add a symbol which is known to be a function.
+    pub fn insert_function(&mut self, addr: u32, name: &str, size: Option<u32>) {
+        self.symbols.insert(addr, Symbol { name: name.to_owned(), size, func: true });
+    }
+
+    /// Add the symbols from another map, keeping our own where both have a
+    /// symbol at the same address.
+    pub fn merge(&mut self, other: &SymbolMap) {
+        for (addr, sym) in other.symbols.iter() {
+            self.symbols.entry(*addr).or_insert_with(|| sym.clone());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Get the name of the symbol at exactly `addr`.
+    pub fn lookup(&self, addr: u32) -> Option<&str> {
+        self.symbols.get(&addr).map(|sym| sym.name.as_str())
+    }
+
+    /// Describe an address with a symbol, either as the symbol's name or as
+    /// an offset into a symbol with a known size (e.g. `memcpy+0x10`).
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        let (&base, sym) = self.symbols.range(..=addr).next_back()?;
+        if base == addr {
+            return Some(sym.name.clone());
+        }
+        match sym.size {
+            Some(size) if addr - base < size => Some(format!("{}+{:#x}", sym.name, addr - base)),
+            _ => None,
+        }
+    }
+
+    /// Describe a code address (e.g. PC or LR in a crash dump) as an offset
+    /// into the nearest preceding function. Symbol tables often leave out
+    /// the sizes of functions written in assembly, so unlike [Self::describe]
+    /// this doesn't need a size, but it won't run past the end of a function
+    /// with a known one.
+    pub fn describe_code(&self, addr: u32) -> Option<String> {
+        let (&base, sym) = self.symbols.range(..=addr).rev().find(|(_, sym)| sym.func)?;
+        match (addr - base, sym.size) {
+            (0, _) => Some(sym.name.clone()),
+            (off, Some(size)) if off >= size => None,
+            (off, _) => Some(format!("{}+{:#x}", sym.name, off)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "
+        # Exported symbols
+        0xffff1234  ThreadCreate    0x40
+        ffff1274,   ThreadJoin
+        ; Thumb code
+        00001000 thumb_func
+    ";
+
+    #[test]
+    fn parse_symbol_map() {
+        let map = SymbolMap::parse(MAP).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.lookup(0xffff_1234), Some("ThreadCreate"));
+        assert_eq!(map.lookup(0xffff_1274), Some("ThreadJoin"));
+        assert_eq!(map.describe(0xffff_1240).as_deref(), Some("ThreadCreate+0xc"));
+        assert_eq!(map.describe(0xffff_1278), None);
+        assert!(SymbolMap::parse("0xzz foo").is_err());
+        assert!(SymbolMap::parse("0x1000").is_err());
+    }
+
+    #[test]
+    fn names_may_contain_spaces() {
+        let map = SymbolMap::parse("
+            0x1000  operator new(unsigned int)  0x20
+            0x2000, IOS::Thread::Create(int, void*)
+        ").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.describe(0x1004).as_deref(), Some("operator new(unsigned int)+0x4"));
+        assert_eq!(map.describe(0x1020), None);
+        assert_eq!(map.lookup(0x2000), Some("IOS::Thread::Create(int, void*)"));
+    }
+
+    #[test]
+    fn merge_keeps_existing_symbols() {
+        let mut map = SymbolMap::parse(MAP).unwrap();
+        let mut other = SymbolMap::new();
+        other.insert(0xffff_1234, "sub_ffff1234", None);
+        other.insert(0x2000, "other_func", Some(0x10));
+        map.merge(&other);
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.lookup(0xffff_1234), Some("ThreadCreate"));
+        assert_eq!(map.describe(0x2004).as_deref(), Some("other_func+0x4"));
+    }
+
+    #[test]
+    fn code_is_described_by_the_preceding_function() {
+        let mut map = SymbolMap::new();
+        map.insert_function(0x1000, "no_size", None);
+        map.insert(0x1100, "some_label", None);
+        map.insert_function(0x2000, "sized", Some(0x10));
+        assert_eq!(map.describe_code(0x1000).as_deref(), Some("no_size"));
+        // Non-function symbols in between are skipped
+        assert_eq!(map.describe_code(0x1204).as_deref(), Some("no_size+0x204"));
+        assert_eq!(map.describe(0x1204), None);
+        assert_eq!(map.describe_code(0x200c).as_deref(), Some("sized+0xc"));
+        assert_eq!(map.describe_code(0x2010), None);
+        assert_eq!(map.describe_code(0xfff), None);
+    }
+}
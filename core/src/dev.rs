@@ -15,8 +15,11 @@ pub mod ehci;
 pub mod ohci;
 /// SD Host Controller interface.
 pub mod sdhc;
+/// Guest-visible cycle counter (not present on real hardware).
+pub mod cyclectr;
 
-// Sizes of physical memory devices.
+// Sizes of physical memory devices. MEM1 and MEM2 are only the defaults,
+// see [crate::bus::BusConfig].
 pub const MEM1_SIZE:    u32 = 0x0180_0000;
 pub const MEM2_SIZE:    u32 = 0x0400_0000;
 pub const MROM_SIZE:    u32 = 0x0000_2000;
@@ -28,6 +31,12 @@ pub const HLWDEV_SIZE:  u32 = 0x0000_0400;
 pub const MEMDEV_SIZE:  u32 = 0x0000_0200;
 pub const AHB_SIZE:     u32 = 0x0000_4000;
 
+// Largest MEM1/MEM2 which fit before the next region in the memory map.
+// MEM1 has to end below the legacy Flipper registers at 0x0c00_0000, which
+// aren't emulated but still belong to the register space.
+pub const MEM1_MAX_SIZE: u32 = 0x0c00_0000;
+pub const MEM2_MAX_SIZE: u32 = 0x1000_0000;
+
 // Base addresses for physical memory devices.
 pub const MEM1_BASE:    u32 = 0x0000_0000;
 pub const MEM1_MASK:    u32 = 0x017f_ffff;
@@ -44,12 +53,14 @@ pub const OH1_BASE:     u32 = 0x0d06_0000;
 pub const SD0_BASE:     u32 = 0x0d07_0000;
 pub const SD1_BASE:     u32 = 0x0d08_0000;
 pub const HLWD_BASE:    u32 = 0x0d80_0000;
+pub const DSP_BASE:     u32 = 0x0d80_5000;
 pub const DI_BASE:      u32 = 0x0d80_6000;
 pub const SI_BASE:      u32 = 0x0d80_6400;
 pub const EXI_BASE:     u32 = 0x0d80_6800;
 pub const AHB_BASE:     u32 = 0x0d8b_0000;
 pub const MEM_BASE:     u32 = 0x0d8b_4000;
 pub const DDR_BASE:     u32 = 0x0d8b_4200;
+pub const CYCLECTR_BASE:u32 = 0x0d8f_0000;
 pub const SRAM_BASE_A:  u32 = 0x0d40_0000;
 pub const SRAM_BASE_B:  u32 = 0x0d41_0000;
 pub const SRAM_BASE_C:  u32 = 0xfff0_0000;
@@ -72,6 +83,7 @@ pub const OH1_TAIL:     u32 = OH1_BASE + IODEV_SIZE - 1;
 pub const SD0_TAIL:     u32 = SD0_BASE + IODEV_SIZE - 1;
 pub const SD1_TAIL:     u32 = SD1_BASE + IODEV_SIZE - 1;
 pub const HLWD_TAIL:    u32 = HLWD_BASE + HLWDEV_SIZE - 1;
+pub const DSP_TAIL:     u32 = DSP_BASE + MEMDEV_SIZE - 1;
 pub const DI_TAIL:      u32 = DI_BASE + HLWDEV_SIZE - 1;
 pub const SI_TAIL:      u32 = SI_BASE + HLWDEV_SIZE - 1;
 pub const EXI_TAIL:     u32 = EXI_BASE + HLWDEV_SIZE - 1;
@@ -79,6 +91,57 @@ pub const AHB_TAIL:     u32 = AHB_BASE + AHB_SIZE - 1;
 pub const MEM_TAIL:     u32 = MEM_BASE + MEMDEV_SIZE - 1;
 pub const DDR_TAIL:     u32 = DDR_BASE + MEMDEV_SIZE - 1;
 pub const MROM_TAIL:    u32 = MROM_BASE + MROM_SIZE - 1;
+pub const CYCLECTR_TAIL:u32 = CYCLECTR_BASE + 0x7;
+
+/// Physical memory map as (name, base, tail) for each region, e.g. to print
+/// with `--dump-map`. SRAM and the mask ROM move around depending on the
+/// ROM/mirror configuration, so only their base addresses are listed.
+pub fn memory_map(mem1_size: u32, mem2_size: u32) -> Vec<(&'static str, u32, u32)> {
+    vec![
+        ("MEM1",            MEM1_BASE,      MEM1_BASE + mem1_size - 1),
+        ("NAND",            NAND_BASE,      NAND_TAIL),
+        ("AES",             AES_BASE,       AES_TAIL),
+        ("SHA",             SHA_BASE,       SHA_TAIL),
+        ("EHCI",            EHCI_BASE,      EHCI_TAIL),
+        ("OHCI0",           OH0_BASE,       OH0_TAIL),
+        ("OHCI1",           OH1_BASE,       OH1_TAIL),
+        ("SDHC0",           SD0_BASE,       SD0_TAIL),
+        ("SDHC1",           SD1_BASE,       SD1_TAIL),
+        ("SRAM",            SRAM_BASE_A,    SRAM_BASE_B + SRM1_SIZE - 1),
+        ("Hollywood",       HLWD_BASE,      HLWD_TAIL),
+        ("DSP",             DSP_BASE,       DSP_TAIL),
+        ("DI",              DI_BASE,        DI_TAIL),
+        ("EXI",             EXI_BASE,       EXI_TAIL),
+        ("AHB",             AHB_BASE,       AHB_TAIL),
+        ("MI",              MEM_BASE,       MEM_TAIL),
+        ("DDR",             DDR_BASE,       DDR_TAIL),
+        ("Cycle counter",   CYCLECTR_BASE,  CYCLECTR_TAIL),
+        ("MEM2",            MEM2_BASE,      MEM2_BASE + mem2_size - 1),
+        ("SRAM (high)",     SRAM_BASE_C,    SRAM_BASE_D + SRM1_SIZE - 1),
+        ("Mask ROM",        MROM_BASE,      MROM_TAIL),
+    ]
+}
+
+/// Check that every region in the [memory_map] for these MEM1/MEM2 sizes is
+/// well-formed and that no two regions overlap, returning a description of
+/// each problem.
+pub fn check_memory_map(mem1_size: u32, mem2_size: u32) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut regions = memory_map(mem1_size, mem2_size);
+    regions.sort_by_key(|(_, base, _)| *base);
+    for (name, base, tail) in regions.iter() {
+        if tail < base {
+            problems.push(format!("{name} ends ({tail:08x}) before it starts ({base:08x})"));
+        }
+    }
+    for pair in regions.windows(2) {
+        let ((a, _, a_tail), (b, b_base, _)) = (pair[0], pair[1]);
+        if b_base <= a_tail {
+            problems.push(format!("{a} (ends at {a_tail:08x}) overlaps {b} (starts at {b_base:08x})"));
+        }
+    }
+    problems
+}
 
 pub const EXI_REG_BASE: u32 = 0x0d00_6800;
 pub const EXI0_REG_BASE:u32 = EXI_REG_BASE;
@@ -106,3 +169,21 @@ pub const EXI2_DATA    :u32 = EXI2_REG_BASE + 0x10;
 pub const EXI_BOOT_BASE:u32 = EXI_REG_BASE  + 0x40;
 
 pub const EXI_REG_TAIL :u32 = EXI_BOOT_BASE;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_map_has_no_overlaps() {
+        assert_eq!(check_memory_map(MEM1_SIZE, MEM2_SIZE), Vec::<String>::new());
+        assert_eq!(check_memory_map(MEM1_MAX_SIZE, MEM2_MAX_SIZE), Vec::<String>::new());
+    }
+
+    #[test]
+    fn memory_map_uses_the_configured_sizes() {
+        let map = memory_map(2 * MEM1_SIZE, MEM2_MAX_SIZE);
+        assert!(map.contains(&("MEM1", MEM1_BASE, MEM1_BASE + 2 * MEM1_SIZE - 1)));
+        assert!(map.contains(&("MEM2", MEM2_BASE, MEM2_BASE + MEM2_MAX_SIZE - 1)));
+    }
+}
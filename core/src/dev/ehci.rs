@@ -1,4 +1,5 @@
 
+use bincode::{Decode, Encode};
 use anyhow::bail;
 use anyhow::ensure;
 
@@ -7,7 +8,7 @@ use crate::bus::mmio::*;
 use crate::bus::task::*;
 
 /// Representing the SHA interface.
-#[derive(Default)]
+#[derive(Default, Encode, Decode)]
 pub struct EhcInterface {
     pub unk_a4: u32,
     pub unk_b0: u32,
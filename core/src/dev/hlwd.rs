@@ -1,10 +1,11 @@
+use bincode::{Decode, Encode};
 use crate::bus::*;
 use crate::bus::prim::*;
 use crate::bus::mmio::*;
 use crate::bus::task::*;
 
 use anyhow::bail;
-use log::{error, warn, info};
+use log::{error, warn, info, debug, trace};
 
 /// One-time programmable [fused] memory.
 pub mod otp;
@@ -20,7 +21,7 @@ pub mod irq;
 pub mod ipc;
 
 /// The timer/alarm interface.
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Encode, Decode)]
 pub struct TimerInterface {
     pub timer: u32,
     pub alarm: u32,
@@ -32,12 +33,18 @@ impl TimerInterface {
     pub const CPU_CLK_DIV: usize = 128;
 
     pub fn step(&mut self, current_cpu_cycle: usize) -> bool {
-        // Fine as long as bus steps are interleaved with CPU steps I guess?
-        if current_cpu_cycle - self.cpu_cycle_prev >= Self::CPU_CLK_DIV {
-            self.timer += 1;
-            self.cpu_cycle_prev = current_cpu_cycle;
-            if self.timer == self.alarm {
-                info!(target: "HLWD", "alarm IRQ {:08x}", self.timer);
+        // Normally bus steps are interleaved with CPU steps, but the CPU
+        // may skip ahead (e.g. when fast-forwarding a delay loop), so we
+        // need to catch up on any ticks we missed.
+        let elapsed = current_cpu_cycle - self.cpu_cycle_prev;
+        if elapsed >= Self::CPU_CLK_DIV {
+            let ticks = (elapsed / Self::CPU_CLK_DIV) as u32;
+            let prev_timer = self.timer;
+            self.timer = self.timer.wrapping_add(ticks);
+            self.cpu_cycle_prev = current_cpu_cycle - (elapsed % Self::CPU_CLK_DIV);
+            // Did the timer pass over the alarm value?
+            if self.alarm.wrapping_sub(prev_timer).wrapping_sub(1) < ticks {
+                info!(target: "HLWD", "alarm IRQ {:08x}", self.alarm);
                 return true;
             } else {
                 return false;
@@ -48,7 +55,7 @@ impl TimerInterface {
 }
 
 /// Various clocking registers.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct ClockInterface {
     pub sys: u32,       // 0x1b0
     pub sys_ext: u32,   // 0x1b4
@@ -76,14 +83,14 @@ impl Default for ClockInterface {
 
 
 /// Various bus control registers (?)
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Encode, Decode)]
 pub struct BusCtrlInterface {
     pub srnprot: u32,
     pub ahbprot: u32,
     pub aipprot: u32,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Encode, Decode)]
 pub struct ArbCfgInterface {
     pub m0: u32,
     pub m1: u32,
@@ -145,7 +152,7 @@ impl ArbCfgInterface {
 
 
 /// Unknown interface (probably related to the AHB).
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Encode, Decode)]
 pub struct AhbInterface {
     pub unk_08: u32,
     pub unk_10: u32,
@@ -180,7 +187,77 @@ impl MmioDevice for AhbInterface {
 }
 
 
+/// Devices on the AHB which can be held in reset with the `reset_ahb`
+/// register. A device is held in reset while its bit is cleared.
+///
+/// NOTE: `reset_ahb` isn't documented. These bit assignments are guesses
+/// (following the order of the Hollywood IRQ lines), so they only take
+/// effect with [Hollywood::model_ahb_resets] set. Other bits are ignored.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum AhbResetBit {
+    Nand    = 0x0000_0002,
+    Aes     = 0x0000_0004,
+    Sha     = 0x0000_0008,
+}
+impl AhbResetBit {
+    /// All of the bits which are modeled.
+    pub const MASK: u32 = Self::Nand as u32 | Self::Aes as u32 | Self::Sha as u32;
+}
+
+/// Devices on the AHB which the PPC may access when their bit is set in the
+/// `ahbprot` register. Homebrew sets every bit ("full AHBPROT") to get
+/// direct access to the hardware.
+///
+/// NOTE: These bit assignments haven't been confirmed on hardware.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum AhbProtBit {
+    Nand    = 0x0000_0002,
+    Aes     = 0x0000_0004,
+    Sha     = 0x0000_0008,
+    Ehci    = 0x0000_0010,
+    Ohci0   = 0x0000_0020,
+    Ohci1   = 0x0000_0040,
+    Sdhc0   = 0x0000_0080,
+    Sdhc1   = 0x0000_0100,
+}
+
+/// When set in `aipprot`, the PPC may access the Starlet-only mapping of the
+/// Hollywood registers (at 0x0d80_0000) instead of just the 0x0d00_0000 one.
+pub const AIPPROT_ENABLE_PPC: u32 = 0x0000_0001;
+
+/// Log a write to one of the pad drive-strength registers. These have no
+/// effect on emulation, they're only decoded here to show what the guest
+/// configured.
+///
+/// NOTE: The exact layout isn't documented. We assume each register holds
+/// eight 4-bit strength fields, and only print the ones that changed.
+fn log_io_str_ctrl(name: &str, old: u32, new: u32) {
+    if !log::log_enabled!(target: "HLWD", log::Level::Trace) {
+        return;
+    }
+    let mut fields = String::new();
+    for idx in (0..8).rev() {
+        let shift = idx * 4;
+        let (o, n) = ((old >> shift) & 0xf, (new >> shift) & 0xf);
+        if o != n {
+            fields += &format!(" [{}:{}] {o}->{n}", shift + 3, shift);
+        }
+    }
+    if fields.is_empty() {
+        fields.push_str(" (no change)");
+    }
+    trace!(target: "HLWD", "{name}={new:08x}{fields}");
+}
+
+/// Append a `name = 0xvalue` line to a register dump.
+pub(crate) fn dump_reg(out: &mut String, name: &str, val: u32) {
+    out.push_str(&format!("{name} = 0x{val:08x}\n"));
+}
+
 /// Hollywood memory-mapped registers
+#[derive(Encode, Decode)]
 pub struct Hollywood {
     pub task: Option<HlwdTask>,
 
@@ -194,12 +271,17 @@ pub struct Hollywood {
 
     pub exi: compat::exi::EXInterface,
     pub di: compat::di::DriveInterface,
+    pub dsp: compat::dsp::DspInterface,
     pub mi: compat::mem::MemInterface,
     pub ahb: AhbInterface,
     pub ddr: ddr::DdrInterface,
 
     pub arb: ArbCfgInterface,
     pub reset_ahb: u32,
+    /// Hold devices in reset according to `reset_ahb`. The register's
+    /// layout is guessed (see [AhbResetBit]), so by default writes to it
+    /// are only logged.
+    pub model_ahb_resets: bool,
     pub clocks: u32,
     pub resets: u32,
     pub compat: u32,
@@ -227,6 +309,7 @@ impl Hollywood {
 
             ahb: AhbInterface::default(),
             di: compat::di::DriveInterface::default(),
+            dsp: compat::dsp::DspInterface::new(),
             exi: compat::exi::EXInterface::new(),
             mi: compat::mem::MemInterface::new(),
             ddr: ddr::DdrInterface::new(),
@@ -234,6 +317,7 @@ impl Hollywood {
             usb_frc_rst: 0,
             arb: ArbCfgInterface::default(),
             reset_ahb: 0x0000_ffff,
+            model_ahb_resets: false,
             resets: 0x0000_0008,
             clocks: 0,
             compat: 0,
@@ -247,6 +331,107 @@ impl Hollywood {
 }
 
 
+impl Hollywood {
+    /// Render the state of all Hollywood registers as text, with one
+    /// `name = 0xvalue` per line. The ordering is stable, so two dumps can
+    /// be compared with a plain text diff.
+    pub fn dump_regs(&self) -> String {
+        let mut out = String::new();
+        for (name, val) in [
+            ("reset_ahb", self.reset_ahb),
+            ("clocks", self.clocks),
+            ("resets", self.resets),
+            ("compat", self.compat),
+            ("spare0", self.spare0),
+            ("spare1", self.spare1),
+            ("io_str_ctrl0", self.io_str_ctrl0),
+            ("io_str_ctrl1", self.io_str_ctrl1),
+            ("usb_frc_rst", self.usb_frc_rst),
+            ("ppc_on", self.ppc_on as u32),
+
+            ("ipc.ppc_msg", self.ipc.ppc_msg),
+            ("ipc.arm_msg", self.ipc.arm_msg),
+            ("ipc.ppc_ctrl", self.ipc.state.ppc_ctrl_read()),
+            ("ipc.arm_ctrl", self.ipc.state.arm_ctrl_read()),
+
+            ("timer.timer", self.timer.timer),
+            ("timer.alarm", self.timer.alarm),
+
+            ("busctrl.srnprot", self.busctrl.srnprot),
+            ("busctrl.ahbprot", self.busctrl.ahbprot),
+            ("busctrl.aipprot", self.busctrl.aipprot),
+
+            ("pll.sys", self.pll.sys),
+            ("pll.sys_ext", self.pll.sys_ext),
+            ("pll.ddr", self.pll.ddr),
+            ("pll.ddr_ext", self.pll.ddr_ext),
+            ("pll.vi_ext", self.pll.vi_ext),
+            ("pll.ai", self.pll.ai),
+            ("pll.ai_ext", self.pll.ai_ext),
+            ("pll.usb_ext", self.pll.usb_ext),
+
+            ("otp.cmd", self.otp.cmd),
+            ("otp.out", self.otp.out),
+
+            ("irq.ppc_status", self.irq.ppc_irq_status.0),
+            ("irq.ppc_enable", self.irq.ppc_irq_enable.0),
+            ("irq.arm_status", self.irq.arm_irq_status.0),
+            ("irq.arm_enable", self.irq.arm_irq_enable.0),
+            ("irq.arm_fiq_enable", self.irq.arm_fiq_enable.0),
+            ("irq.arm_output", self.irq.arm_irq_output as u32),
+            ("irq.ppc_output", self.irq.ppc_irq_output as u32),
+
+            ("ahb.unk_08", self.ahb.unk_08),
+            ("ahb.unk_10", self.ahb.unk_10),
+        ] {
+            dump_reg(&mut out, name, val);
+        }
+        self.gpio.arm.dump_regs("gpio.arm", &mut out);
+        self.gpio.ppc.dump_regs("gpio.ppc", &mut out);
+        self.di.dump_regs("di", &mut out);
+        self.dsp.dump_regs("dsp", &mut out);
+        for (idx, chan) in [&self.exi.chan0, &self.exi.chan1, &self.exi.chan2].iter().enumerate() {
+            dump_reg(&mut out, &format!("exi.chan{idx}.csr"), chan.csr);
+            dump_reg(&mut out, &format!("exi.chan{idx}.mar"), chan.mar);
+            dump_reg(&mut out, &format!("exi.chan{idx}.len"), chan.len);
+            dump_reg(&mut out, &format!("exi.chan{idx}.ctrl"), chan.ctrl);
+            dump_reg(&mut out, &format!("exi.chan{idx}.data"), chan.data);
+        }
+        for (idx, val) in self.mi.reg.iter().enumerate() {
+            dump_reg(&mut out, &format!("mi.reg[{:02x}]", idx * 2), *val as u32);
+        }
+        dump_reg(&mut out, "ddr.seq_addr", self.ddr.seq_addr as u32);
+        dump_reg(&mut out, "ddr.seq_data", self.ddr.seq_data as u32);
+        dump_reg(&mut out, "ddr.ahmflush", self.ddr.ahmflush as u32);
+        dump_reg(&mut out, "ddr.ahmflush_ack", self.ddr.ahmflush_ack as u32);
+        for (idx, val) in self.ddr.ddr_reg.iter().enumerate() {
+            dump_reg(&mut out, &format!("ddr.ddr_reg[{idx:02x}]"), *val as u32);
+        }
+        for (idx, val) in self.ddr.seq_reg.iter().enumerate() {
+            dump_reg(&mut out, &format!("ddr.seq_reg[{idx:02x}]"), *val as u32);
+        }
+        for (name, val) in [
+            ("arb.m0", self.arb.m0), ("arb.m1", self.arb.m1),
+            ("arb.m2", self.arb.m2), ("arb.m3", self.arb.m3),
+            ("arb.m4", self.arb.m4), ("arb.m5", self.arb.m5),
+            ("arb.m6", self.arb.m6), ("arb.m7", self.arb.m7),
+            ("arb.m8", self.arb.m8), ("arb.m9", self.arb.m9),
+            ("arb.ma", self.arb.ma), ("arb.mb", self.arb.mb),
+            ("arb.mc", self.arb.mc), ("arb.md", self.arb.md),
+            ("arb.me", self.arb.me), ("arb.mf", self.arb.mf),
+            ("arb.cpu", self.arb.cpu), ("arb.dma", self.arb.dma),
+        ] {
+            dump_reg(&mut out, name, val);
+        }
+        out
+    }
+
+    /// Returns true if the given AHB device is currently held in reset.
+    pub fn ahb_in_reset(&self, dev: AhbResetBit) -> bool {
+        self.model_ahb_resets && (self.reset_ahb & dev as u32) == 0
+    }
+}
+
 impl MmioDevice for Hollywood {
     type Width = u32;
     fn read(&self, off: usize) -> anyhow::Result<BusPacket> {
@@ -258,6 +443,7 @@ impl MmioDevice for Hollywood {
             0x060           => self.busctrl.srnprot,
             0x064           => self.busctrl.ahbprot,
             0x070           => self.busctrl.aipprot,
+            0x088           => self.usb_frc_rst,
             0x0c0..=0x0d8   => self.gpio.ppc.read_handler(off - 0xc0)?,
             0x0dc..=0x0fc   => self.gpio.arm.read_handler(off - 0xdc)?,
             0x100..=0x13c   => self.arb.read_handler(off - 0x100)?,
@@ -306,7 +492,15 @@ impl MmioDevice for Hollywood {
             }
             0x064 => self.busctrl.ahbprot = val,
             0x070 => self.busctrl.aipprot = val,
-            0x088 => self.usb_frc_rst = val,
+            0x088 => {
+                info!(target: "HLWD", "usb_frc_rst={val:08x}");
+                let prev = self.usb_frc_rst;
+                self.usb_frc_rst = val;
+                // Only edges on the reset line are interesting
+                if (prev != 0) != (val != 0) {
+                    return Ok(Some(BusTask::SetUsbReset(val != 0)));
+                }
+            },
             0x0c0..=0x0d8 => self.gpio.ppc.write_handler(off - 0xc0, val)?,
             0x0dc..=0x0fc => {
                 self.task = self.gpio.arm.write_handler(off - 0xdc, val)?;
@@ -315,7 +509,17 @@ impl MmioDevice for Hollywood {
             0x180 => self.compat = val,
             0x184 => {
                 info!(target: "HLWD", "reset_ahb={val:08x}");
+                let modeled = if self.model_ahb_resets { AhbResetBit::MASK } else { 0 };
+                let ignored = (self.reset_ahb ^ val) & !modeled;
+                if ignored != 0 {
+                    debug!(target: "HLWD", "reset_ahb: no effect for changed bits {ignored:08x}");
+                }
+                // Bits going from set to cleared put a device into reset
+                let entering_reset = self.reset_ahb & !val & modeled;
                 self.reset_ahb = val;
+                if entering_reset != 0 {
+                    return Ok(Some(BusTask::AhbReset(entering_reset)));
+                }
             },
             0x188 => {
                 self.spare0 = val;
@@ -410,9 +614,16 @@ impl MmioDevice for Hollywood {
             0x1cc => self.pll.ai = val,
             0x1d0 => self.pll.ai_ext = val,
             0x1d8 => self.pll.usb_ext = val,
-            0x1e0 => self.io_str_ctrl0 = val,
-            0x1e4 => self.io_str_ctrl1 = val,
-            0x1ec => self.otp.write_handler(val),
+            0x1e0 => {
+                log_io_str_ctrl("io_str_ctrl0", self.io_str_ctrl0, val);
+                self.io_str_ctrl0 = val;
+            },
+            0x1e4 => {
+                log_io_str_ctrl("io_str_ctrl1", self.io_str_ctrl1, val);
+                self.io_str_ctrl1 = val;
+            },
+            0x1ec => self.otp.write_handler(val)?,
+            0x1f0 => self.otp.write_data(val),
             _ => { bail!("Unimplemented Hollywood write at {off:x}"); },
         }
         Ok(None)
@@ -420,7 +631,118 @@ impl MmioDevice for Hollywood {
 
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+impl Bus {
+    /// Reset the state of AHB devices which were just put into reset.
+    pub fn handle_task_ahb_reset(&mut self, mask: u32) {
+        if mask & AhbResetBit::Nand as u32 != 0 {
+            info!(target: "HLWD", "NAND interface held in reset");
+            self.nand.reset();
+        }
+        if mask & AhbResetBit::Aes as u32 != 0 {
+            info!(target: "HLWD", "AES interface held in reset");
+            self.aes = crate::dev::aes::AesInterface::new();
+        }
+        if mask & AhbResetBit::Sha as u32 != 0 {
+            info!(target: "HLWD", "SHA interface held in reset");
+            self.sha = crate::dev::sha::ShaInterface::new();
+        }
+    }
+
+    /// Returns the AHBPROT bit gating PPC access to some I/O device, if any.
+    pub fn ahbprot_bit(dev: IoDevice) -> Option<AhbProtBit> {
+        match dev {
+            IoDevice::Nand => Some(AhbProtBit::Nand),
+            IoDevice::Aes => Some(AhbProtBit::Aes),
+            IoDevice::Sha => Some(AhbProtBit::Sha),
+            IoDevice::Ehci => Some(AhbProtBit::Ehci),
+            IoDevice::Ohci0 => Some(AhbProtBit::Ohci0),
+            IoDevice::Ohci1 => Some(AhbProtBit::Ohci1),
+            IoDevice::Sdhc0 => Some(AhbProtBit::Sdhc0),
+            IoDevice::Sdhc1 => Some(AhbProtBit::Sdhc1),
+            _ => None,
+        }
+    }
+
+    /// Check that the PPC may access every device touched by `len` bytes at
+    /// some physical address, according to the `ahbprot` and `aipprot`
+    /// registers. Memory is always accessible.
+    pub fn check_ppc_access(&self, addr: u32, len: usize) -> anyhow::Result<()> {
+        let end = addr as u64 + len.max(1) as u64;
+        let mut cur = addr as u64;
+        while cur < end {
+            let addr = cur as u32;
+            let Some(handle) = self.decode_phys_addr(addr) else {
+                bail!("Unresolved physical address {addr:08x}");
+            };
+            // Devices are aligned to their size, and the memory map doesn't
+            // change more often than every 64KiB
+            let chunk_mask = handle.mask & 0x0000_ffff;
+            cur = (addr | chunk_mask) as u64 + 1;
+            let Device::Io(dev) = handle.dev else {
+                continue;
+            };
+            if let Some(bit) = Bus::ahbprot_bit(dev) && self.hlwd.busctrl.ahbprot & bit as u32 == 0 {
+                warn!(target: "HLWD", "Denied PPC access to {dev:?} at {addr:08x} (ahbprot={:08x})", self.hlwd.busctrl.ahbprot);
+                bail!("PPC access to {dev:?} at {addr:08x} denied by AHBPROT");
+            }
+            let starlet_only = addr & 0xfff0_0000 == 0x0d80_0000;
+            if starlet_only && self.hlwd.busctrl.aipprot & AIPPROT_ENABLE_PPC == 0 {
+                warn!(target: "HLWD", "Denied PPC access to {dev:?} at {addr:08x} (aipprot={:08x})", self.hlwd.busctrl.aipprot);
+                bail!("PPC access to {dev:?} at {addr:08x} denied by AIPPROT");
+            }
+        }
+        Ok(())
+    }
+
+    /// Perform a read on behalf of the PPC. Single word reads may target I/O
+    /// devices, everything else is a DMA read.
+    pub fn ppc_read(&self, addr: u32, buf: &mut [u8]) -> anyhow::Result<()> {
+        self.check_ppc_access(addr, buf.len())?;
+        if buf.len() == 4 && matches!(self.decode_phys_addr(addr), Some(DeviceHandle { dev: Device::Io(_), .. })) {
+            buf.copy_from_slice(&self.read32(addr)?.to_be_bytes());
+            return Ok(());
+        }
+        self.dma_read(addr, buf)
+    }
+
+    /// Perform a write on behalf of the PPC. See [Bus::ppc_read].
+    pub fn ppc_write(&mut self, addr: u32, buf: &[u8]) -> anyhow::Result<()> {
+        self.check_ppc_access(addr, buf.len())?;
+        if let Ok(word) = <[u8; 4]>::try_from(buf)
+            && matches!(self.decode_phys_addr(addr), Some(DeviceHandle { dev: Device::Io(_), .. })) {
+            return self.write32(addr, u32::from_be_bytes(word));
+        }
+        self.dma_write(addr, buf)
+    }
+
+    /// Returns the AHB reset bit gating some I/O device, if any.
+    pub fn ahb_reset_bit(dev: IoDevice) -> Option<AhbResetBit> {
+        match dev {
+            IoDevice::Nand => Some(AhbResetBit::Nand),
+            IoDevice::Aes => Some(AhbResetBit::Aes),
+            IoDevice::Sha => Some(AhbResetBit::Sha),
+            _ => None,
+        }
+    }
+
+    /// Handle a change on the USB force-reset line.
+    ///
+    /// While reset is asserted, the host controllers are held at their
+    /// power-on defaults. When it's deasserted, they come back ready to be
+    /// initialized by software.
+    pub fn handle_task_usb_reset(&mut self, asserted: bool) {
+        if asserted {
+            info!(target: "xHCI", "USB controllers held in reset");
+            self.ehci = crate::dev::ehci::EhcInterface::new();
+            self.ohci0 = crate::dev::ohci::OhcInterface { idx: 0, ..Default::default() };
+            self.ohci1 = crate::dev::ohci::OhcInterface { idx: 1, ..Default::default() };
+        } else {
+            info!(target: "xHCI", "USB controllers released from reset");
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Encode, Decode)]
 pub enum HlwdTask { 
     GpioOutput(u32) 
 }
@@ -433,8 +755,13 @@ impl Bus {
         if timer_irq {
             self.hlwd.irq.assert(irq::HollywoodIrq::Timer);
         }
+        self.hlwd.exi.rtc.step(cpu_cycle);
         if self.hlwd.ipc.assert_ppc_irq() {
+            let was_raised = self.hlwd.irq.ppc_irq_output;
             self.hlwd.irq.assert(irq::HollywoodIrq::PpcIpc);
+            if !was_raised && self.hlwd.irq.ppc_irq_output {
+                self.ppc_irq_signal.notify();
+            }
         }
         if self.hlwd.ipc.assert_arm_irq() {
             self.hlwd.irq.assert(irq::HollywoodIrq::ArmIpc);
@@ -450,4 +777,97 @@ impl Bus {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dev::HLWD_BASE;
+
+    #[test]
+    fn ahbprot_gates_ppc_access() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        let mut buf = [0u8; 4];
+        // SDHC0 present state register
+        bus.hlwd.busctrl.ahbprot = 0;
+        assert!(bus.ppc_read(0x0d07_0024, &mut buf).is_err());
+        assert!(bus.ppc_write(0x0d07_0024, &buf).is_err());
+
+        // Full AHBPROT
+        bus.hlwd.busctrl.ahbprot = 0xffff_ffff;
+        assert!(bus.ppc_read(0x0d07_0024, &mut buf).is_ok());
+
+        // Memory is never protected
+        bus.hlwd.busctrl.ahbprot = 0;
+        assert!(bus.ppc_write(0x0000_1000, &[1, 2, 3, 4]).is_ok());
+        assert!(bus.ppc_read(0x0000_1000, &mut buf).is_ok());
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn ahb_reset_holds_aes_in_reset() {
+        use crate::dev::AES_BASE;
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.hlwd.model_ahb_resets = true;
+        let src = [0x5a; 16];
+        bus.dma_write(0x1000, &src).unwrap();
+        let run_aes = |bus: &mut Bus| {
+            // Key and IV, all zeroes
+            for _ in 0..4 {
+                bus.write32(AES_BASE + 0x0c, 0).unwrap();
+                bus.write32(AES_BASE + 0x10, 0).unwrap();
+            }
+            bus.write32(AES_BASE + 0x04, 0x1000).unwrap();
+            bus.write32(AES_BASE + 0x08, 0x2000).unwrap();
+            bus.write32(AES_BASE + 0x00, 0x9000_0000).unwrap();
+            bus.step(0).unwrap();
+            let mut out = [0u8; 16];
+            bus.dma_read(0x2000, &mut out).unwrap();
+            out
+        };
+
+        // The command is dropped while the AES engine is held in reset
+        bus.write32(HLWD_BASE + 0x184, 0x0000_ffff & !(AhbResetBit::Aes as u32)).unwrap();
+        bus.step(0).unwrap();
+        assert!(bus.hlwd.ahb_in_reset(AhbResetBit::Aes));
+        assert_eq!(run_aes(&mut bus), [0; 16]);
+
+        // Released, it goes through
+        bus.write32(HLWD_BASE + 0x184, 0x0000_ffff).unwrap();
+        assert!(!bus.hlwd.ahb_in_reset(AhbResetBit::Aes));
+        assert_ne!(run_aes(&mut bus), [0; 16]);
+    }
+
+    #[test]
+    fn ahb_resets_are_ignored_unless_modeled() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.write32(HLWD_BASE + 0x184, 0).unwrap();
+        bus.step(0).unwrap();
+        assert_eq!(bus.hlwd.reset_ahb, 0);
+        assert!(!bus.hlwd.ahb_in_reset(AhbResetBit::Aes));
+    }
 
+    #[test]
+    fn ahbprot_checks_every_device_in_range() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.hlwd.busctrl.ahbprot = AhbProtBit::Sha as u32;
+        // Starts on SHA (allowed) and runs into SDHC0 (denied)
+        assert!(bus.check_ppc_access(0x0d03_0000, 4).is_ok());
+        assert!(bus.check_ppc_access(0x0d03_0000, 0x4_0010).is_err());
+        // Crossing from AES into SHA needs both
+        bus.hlwd.busctrl.ahbprot = AhbProtBit::Aes as u32;
+        assert!(bus.check_ppc_access(0x0d02_fffc, 4).is_ok());
+        assert!(bus.check_ppc_access(0x0d02_fffc, 8).is_err());
+        bus.hlwd.busctrl.ahbprot = AhbProtBit::Aes as u32 | AhbProtBit::Sha as u32;
+        assert!(bus.check_ppc_access(0x0d02_fffc, 8).is_ok());
+    }
+
+    #[test]
+    fn aipprot_gates_starlet_registers() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        let mut buf = [0u8; 4];
+        bus.hlwd.busctrl.aipprot = 0;
+        // The timer, through the Starlet mapping
+        assert!(bus.ppc_read(0x0d80_0010, &mut buf).is_err());
+        bus.hlwd.busctrl.aipprot = AIPPROT_ENABLE_PPC;
+        assert!(bus.ppc_read(0x0d80_0010, &mut buf).is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use bincode::{Decode, Encode};
 use anyhow::bail;
 
 use crate::bus::mmio::*;
@@ -5,7 +6,7 @@ use crate::bus::prim::*;
 use crate::bus::task::*;
 
 /// Legacy disc drive interface.
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Encode, Decode)]
 #[allow(dead_code)]
 pub struct DriveInterface {
     disr: u32,
@@ -17,6 +18,20 @@ pub struct DriveInterface {
     diimmbuf: u32,
     dicfg: u32,
 }
+impl DriveInterface {
+    /// Append the state of these registers to a register dump.
+    pub fn dump_regs(&self, prefix: &str, out: &mut String) {
+        use crate::dev::hlwd::dump_reg;
+        for (name, val) in [
+            ("disr", self.disr), ("dicvr", self.dicvr),
+            ("dicmdbuf0", self.dicmdbuf[0]), ("dicmdbuf1", self.dicmdbuf[1]), ("dicmdbuf2", self.dicmdbuf[2]),
+            ("dimar", self.dimar), ("dilength", self.dilength), ("dicr", self.dicr),
+            ("diimmbuf", self.diimmbuf), ("dicfg", self.dicfg),
+        ] {
+            dump_reg(out, &format!("{prefix}.{name}"), val);
+        }
+    }
+}
 impl MmioDevice for DriveInterface {
     type Width = u32;
     fn read(&self, off: usize) -> anyhow::Result<BusPacket> {
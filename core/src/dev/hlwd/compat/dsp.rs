@@ -0,0 +1,317 @@
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+use bincode::{Decode, Encode};
+use anyhow::bail;
+use log::{debug, info, warn};
+
+use crate::bus::Bus;
+use crate::bus::mmio::*;
+use crate::bus::prim::*;
+use crate::bus::task::*;
+
+/// Size of the emulated auxiliary RAM (16MiB, as on the GameCube).
+pub const ARAM_SIZE: usize = 0x0100_0000;
+
+/// DSPCR: DSP interrupt status, raised when the DSP posts mail (write 1 to clear).
+pub const DSPCR_DSPINT: u16 = 1 << 7;
+/// DSPCR: DSP interrupt mask.
+pub const DSPCR_DSPINTMSK: u16 = 1 << 8;
+/// DSPCR: ARAM DMA completion interrupt status (write 1 to clear).
+pub const DSPCR_ARINT: u16 = 1 << 5;
+/// DSPCR: ARAM DMA completion interrupt mask.
+pub const DSPCR_ARINTMSK: u16 = 1 << 6;
+/// DSPCR: an ARAM DMA transfer is in progress.
+pub const DSPCR_DSPDMA: u16 = 1 << 9;
+
+/// DSPCR: all of the interrupt status bits.
+const DSPCR_INT_STATUS: u16 = DSPCR_ARINT | DSPCR_DSPINT;
+
+/// Mailbox high halves: the mailbox holds a message which hasn't been read.
+pub const MAILBOX_FULL: u16 = 1 << 15;
+
+/// AR_DMA_CNT_H: transfer from ARAM to main memory (instead of the reverse).
+const AR_DMA_CNT_READ: u16 = 1 << 15;
+
+/// Legacy DSP interface.
+///
+/// The DSP core itself isn't emulated: mail sent to it is consumed (and
+/// logged) right away, and mail from it only comes from [DspInterface::post_mail].
+///
+/// Nothing delivers the DSP interrupts: the PPC isn't emulated, and there's
+/// no DSP line on the ARM side. The guest has to poll the status bits in
+/// DSPCR instead.
+#[derive(Encode, Decode)]
+pub struct DspInterface {
+    /// Mailbox from the CPU to the DSP
+    pub cpu_mbox_h: u16,
+    pub cpu_mbox_l: u16,
+    /// Mailbox from the DSP to the CPU, without the full bit.
+    pub dsp_mbox_h: u16,
+    pub dsp_mbox_l: u16,
+    /// The full bit for the mailbox from the DSP. Reading the low half
+    /// clears it, but [MmioDevice::read] only gets `&self`, so this one bit
+    /// is atomic instead of the whole register.
+    pub dsp_mbox_full: AtomicBool,
+    pub dspcr: u16,
+    pub ar_size: u16,
+    pub ar_mode: u16,
+    pub ar_refresh: u16,
+    pub ar_dma_mmaddr_h: u16,
+    pub ar_dma_mmaddr_l: u16,
+    pub ar_dma_araddr_h: u16,
+    pub ar_dma_araddr_l: u16,
+    pub ar_dma_size_h: u16,
+    pub ar_dma_size_l: u16,
+    /// Backing memory for the auxiliary RAM.
+    pub aram: Vec<u8>,
+}
+impl Default for DspInterface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl DspInterface {
+    pub fn new() -> Self {
+        DspInterface {
+            cpu_mbox_h: 0,
+            cpu_mbox_l: 0,
+            dsp_mbox_h: 0,
+            dsp_mbox_l: 0,
+            dsp_mbox_full: AtomicBool::new(false),
+            dspcr: 0,
+            ar_size: 0,
+            ar_mode: 0,
+            ar_refresh: 0,
+            ar_dma_mmaddr_h: 0,
+            ar_dma_mmaddr_l: 0,
+            ar_dma_araddr_h: 0,
+            ar_dma_araddr_l: 0,
+            ar_dma_size_h: 0,
+            ar_dma_size_l: 0,
+            aram: vec![0; ARAM_SIZE],
+        }
+    }
+
+    /// Main memory address for the ARAM DMA (32-byte aligned).
+    fn dma_mmaddr(&self) -> u32 {
+        ((self.ar_dma_mmaddr_h as u32) << 16 | self.ar_dma_mmaddr_l as u32) & 0x03ff_ffe0
+    }
+    /// ARAM address for the ARAM DMA (32-byte aligned).
+    fn dma_araddr(&self) -> usize {
+        (((self.ar_dma_araddr_h as u32) << 16 | self.ar_dma_araddr_l as u32) & 0x03ff_ffe0) as usize
+    }
+    /// Length of the ARAM DMA in bytes (a multiple of 32).
+    fn dma_len(&self) -> usize {
+        (((self.ar_dma_size_h as u32 & 0x7fff) << 16 | self.ar_dma_size_l as u32) & !0x1f) as usize
+    }
+
+    /// Post a message in the mailbox from the DSP to the CPU, setting the
+    /// DSP interrupt status bit.
+    pub fn post_mail(&mut self, mail: u32) {
+        if self.dsp_mbox_full.load(Relaxed) {
+            warn!(target: "DSP", "Mail {mail:08x} overwrote unread DSP mail");
+        }
+        self.dsp_mbox_h = (mail >> 16) as u16 & !MAILBOX_FULL;
+        self.dsp_mbox_l = mail as u16;
+        self.dsp_mbox_full.store(true, Relaxed);
+        self.dspcr |= DSPCR_DSPINT;
+    }
+
+    /// The high half of the mailbox from the DSP, as the CPU reads it.
+    fn dsp_mbox_h(&self) -> u16 {
+        if self.dsp_mbox_full.load(Relaxed) { self.dsp_mbox_h | MAILBOX_FULL } else { self.dsp_mbox_h }
+    }
+
+    /// Append the state of these registers to a register dump.
+    pub fn dump_regs(&self, prefix: &str, out: &mut String) {
+        use crate::dev::hlwd::dump_reg;
+        for (name, val) in [
+            ("cpu_mbox_h", self.cpu_mbox_h), ("cpu_mbox_l", self.cpu_mbox_l),
+            ("dsp_mbox_h", self.dsp_mbox_h()), ("dsp_mbox_l", self.dsp_mbox_l),
+            ("dspcr", self.dspcr), ("ar_size", self.ar_size), ("ar_mode", self.ar_mode),
+            ("ar_refresh", self.ar_refresh),
+            ("ar_dma_mmaddr_h", self.ar_dma_mmaddr_h), ("ar_dma_mmaddr_l", self.ar_dma_mmaddr_l),
+            ("ar_dma_araddr_h", self.ar_dma_araddr_h), ("ar_dma_araddr_l", self.ar_dma_araddr_l),
+            ("ar_dma_size_h", self.ar_dma_size_h), ("ar_dma_size_l", self.ar_dma_size_l),
+        ] {
+            dump_reg(out, &format!("{prefix}.{name}"), val as u32);
+        }
+    }
+}
+impl MmioDevice for DspInterface {
+    type Width = u16;
+    fn read(&self, off: usize) -> anyhow::Result<BusPacket> {
+        let val = match off {
+            0x00 => self.cpu_mbox_h,
+            0x02 => self.cpu_mbox_l,
+            0x04 => self.dsp_mbox_h(),
+            // Reading the low half of the DSP mailbox empties it
+            0x06 => {
+                self.dsp_mbox_full.store(false, Relaxed);
+                self.dsp_mbox_l
+            },
+            0x0a => self.dspcr,
+            0x12 => self.ar_size,
+            0x16 => self.ar_mode,
+            0x1a => self.ar_refresh,
+            0x20 => self.ar_dma_mmaddr_h,
+            0x22 => self.ar_dma_mmaddr_l,
+            0x24 => self.ar_dma_araddr_h,
+            0x26 => self.ar_dma_araddr_l,
+            0x28 => self.ar_dma_size_h,
+            0x2a => self.ar_dma_size_l,
+            _ => { bail!("DSP read to undefined offset {off:x}"); },
+        };
+        Ok(BusPacket::Half(val))
+    }
+    fn write(&mut self, off: usize, val: u16) -> anyhow::Result<Option<BusTask>> {
+        match off {
+            0x00 => self.cpu_mbox_h = val & !MAILBOX_FULL,
+            // Writing the low half of the CPU mailbox sends the mail
+            0x02 => {
+                self.cpu_mbox_l = val;
+                self.cpu_mbox_h |= MAILBOX_FULL;
+                return Ok(Some(BusTask::DspMail));
+            },
+            0x0a => {
+                // Interrupt status bits are cleared by writing 1, and the
+                // DMA status bit is read-only
+                let status = self.dspcr & (DSPCR_INT_STATUS | DSPCR_DSPDMA);
+                let ack = val & DSPCR_INT_STATUS;
+                self.dspcr = (val & !(DSPCR_INT_STATUS | DSPCR_DSPDMA)) | (status & !ack);
+            },
+            0x12 => self.ar_size = val,
+            0x16 => self.ar_mode = val,
+            0x1a => self.ar_refresh = val,
+            0x20 => self.ar_dma_mmaddr_h = val,
+            0x22 => self.ar_dma_mmaddr_l = val,
+            0x24 => self.ar_dma_araddr_h = val,
+            0x26 => self.ar_dma_araddr_l = val,
+            0x28 => self.ar_dma_size_h = val,
+            // Writing the low half of the size starts the transfer
+            0x2a => {
+                self.ar_dma_size_l = val;
+                self.dspcr |= DSPCR_DSPDMA;
+                return Ok(Some(BusTask::AramDma));
+            },
+            _ => { bail!("DSP write {val:04x?} to undefined offset {off:x}"); },
+        }
+        Ok(None)
+    }
+}
+
+impl Bus {
+    /// Deliver mail from the CPU to the DSP.
+    pub fn handle_task_dsp_mail(&mut self) -> anyhow::Result<()> {
+        let dsp = &mut self.hlwd.dsp;
+        let mail = ((dsp.cpu_mbox_h & !MAILBOX_FULL) as u32) << 16 | dsp.cpu_mbox_l as u32;
+        // There's no DSP core to run, so just take the mail out of the box
+        info!(target: "DSP", "CPU sent mail {mail:08x}");
+        dsp.cpu_mbox_h &= !MAILBOX_FULL;
+        Ok(())
+    }
+
+    /// Perform an ARAM DMA transfer between main memory and ARAM.
+    pub fn handle_task_aram_dma(&mut self) -> anyhow::Result<()> {
+        let dsp = &self.hlwd.dsp;
+        let mmaddr = dsp.dma_mmaddr();
+        let araddr = dsp.dma_araddr();
+        let len = dsp.dma_len();
+        let to_mm = dsp.ar_dma_size_h & AR_DMA_CNT_READ != 0;
+        if araddr + len > ARAM_SIZE {
+            bail!("ARAM DMA at {araddr:08x} (len={len:x}) is out of bounds");
+        }
+        debug!(target: "DSP", "ARAM DMA {} mm={mmaddr:08x} ar={araddr:08x} len={len:x}",
+            if to_mm { "ARAM->MM" } else { "MM->ARAM" });
+
+        if to_mm {
+            let data = self.hlwd.dsp.aram[araddr..araddr + len].to_vec();
+            self.dma_write(mmaddr, &data)?;
+        } else {
+            let mut data = vec![0; len];
+            self.dma_read(mmaddr, &mut data)?;
+            self.hlwd.dsp.aram[araddr..araddr + len].copy_from_slice(&data);
+        }
+
+        // The count reads back the number of bytes left, which is none now.
+        // The guest polls DSPCR for completion (see [DspInterface]).
+        let dsp = &mut self.hlwd.dsp;
+        dsp.ar_dma_size_h &= AR_DMA_CNT_READ;
+        dsp.ar_dma_size_l = 0;
+        dsp.dspcr = (dsp.dspcr & !DSPCR_DSPDMA) | DSPCR_ARINT;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dev::DSP_BASE;
+
+    /// Program and run an ARAM DMA transfer.
+    fn aram_dma(bus: &mut Bus, mmaddr: u32, araddr: u32, len: u32, to_mm: bool) {
+        bus.write16(DSP_BASE + 0x20, (mmaddr >> 16) as u16).unwrap();
+        bus.write16(DSP_BASE + 0x22, mmaddr as u16).unwrap();
+        bus.write16(DSP_BASE + 0x24, (araddr >> 16) as u16).unwrap();
+        bus.write16(DSP_BASE + 0x26, araddr as u16).unwrap();
+        let dir = if to_mm { AR_DMA_CNT_READ } else { 0 };
+        bus.write16(DSP_BASE + 0x28, (len >> 16) as u16 | dir).unwrap();
+        bus.write16(DSP_BASE + 0x2a, len as u16).unwrap();
+        assert_ne!(bus.read16(DSP_BASE + 0x0a).unwrap() & DSPCR_DSPDMA, 0);
+        bus.step(0).unwrap();
+        let dspcr = bus.read16(DSP_BASE + 0x0a).unwrap();
+        assert_eq!(dspcr & (DSPCR_DSPDMA | DSPCR_ARINT), DSPCR_ARINT);
+        assert_eq!(bus.read16(DSP_BASE + 0x28).unwrap() & !AR_DMA_CNT_READ, 0);
+        assert_eq!(bus.read16(DSP_BASE + 0x2a).unwrap(), 0);
+        // Acknowledge the completion
+        bus.write16(DSP_BASE + 0x0a, DSPCR_ARINT).unwrap();
+        assert_eq!(bus.read16(DSP_BASE + 0x0a).unwrap() & DSPCR_ARINT, 0);
+    }
+
+    #[test]
+    fn aram_dma_round_trip() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        let data: Vec<u8> = (0..0x40).collect();
+        bus.dma_write(0x0000_1000, &data).unwrap();
+
+        aram_dma(&mut bus, 0x0000_1000, 0x0001_0000, 0x40, false);
+        assert_eq!(&bus.hlwd.dsp.aram[0x1_0000..0x1_0040], &data[..]);
+
+        aram_dma(&mut bus, 0x0000_2000, 0x0001_0000, 0x40, true);
+        let mut out = vec![0; 0x40];
+        bus.dma_read(0x0000_2000, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn aram_dma_completion_can_be_polled() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.write16(DSP_BASE + 0x0a, DSPCR_ARINTMSK).unwrap();
+        bus.write16(DSP_BASE + 0x2a, 0x20).unwrap();
+        assert_eq!(bus.read16(DSP_BASE + 0x0a).unwrap(), DSPCR_ARINTMSK | DSPCR_DSPDMA);
+        bus.step(0).unwrap();
+        assert_eq!(bus.read16(DSP_BASE + 0x0a).unwrap(), DSPCR_ARINTMSK | DSPCR_ARINT);
+        bus.write16(DSP_BASE + 0x0a, DSPCR_ARINTMSK | DSPCR_ARINT).unwrap();
+        assert_eq!(bus.read16(DSP_BASE + 0x0a).unwrap(), DSPCR_ARINTMSK);
+    }
+
+    #[test]
+    fn mailboxes_track_unread_mail() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+
+        // Mail to the DSP is full until the DSP takes it
+        bus.write16(DSP_BASE + 0x00, 0x1234).unwrap();
+        bus.write16(DSP_BASE + 0x02, 0x5678).unwrap();
+        assert_eq!(bus.read16(DSP_BASE + 0x00).unwrap(), MAILBOX_FULL | 0x1234);
+        bus.step(0).unwrap();
+        assert_eq!(bus.read16(DSP_BASE + 0x00).unwrap(), 0x1234);
+
+        // Mail from the DSP is full until the low half is read
+        bus.write16(DSP_BASE + 0x0a, DSPCR_DSPINTMSK).unwrap();
+        bus.hlwd.dsp.post_mail(0x1122_3344);
+        assert_ne!(bus.read16(DSP_BASE + 0x0a).unwrap() & DSPCR_DSPINT, 0);
+        assert_eq!(bus.read16(DSP_BASE + 0x04).unwrap(), MAILBOX_FULL | 0x1122);
+        assert_eq!(bus.read16(DSP_BASE + 0x06).unwrap(), 0x3344);
+        assert_eq!(bus.read16(DSP_BASE + 0x04).unwrap(), 0x1122);
+    }
+}
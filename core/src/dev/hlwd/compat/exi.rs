@@ -1,4 +1,6 @@
 pub mod device;
+pub mod rtc;
+use bincode::{Decode, Encode};
 use anyhow::bail;
 use device::*;
 
@@ -7,7 +9,7 @@ use crate::bus::prim::*;
 use crate::bus::task::*;
 
 /// Representing user-configurable EXI clock freqencies.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Encode, Decode)]
 pub enum EXIFreq {
     Clk1Mhz, Clk2Mhz, Clk4Mhz, Clk8Mhz, Clk16Mhz, Clk32Mhz, Undef
 }
@@ -27,7 +29,7 @@ impl From<u32> for EXIFreq {
 }
 
 /// Representing an EXI transfer type.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Encode, Decode)]
 pub enum EXITransfer {
     Read, Write, ReadWrite, Undef,
 }
@@ -35,8 +37,8 @@ impl From<u32> for EXITransfer {
     fn from(x: u32) -> Self {
         match x {
             0b00 => Self::Read,
-            0b10 => Self::Write,
-            0b01 => Self::ReadWrite,
+            0b01 => Self::Write,
+            0b10 => Self::ReadWrite,
             0b11 => Self::Undef,
             _ => unreachable!(),
         }
@@ -45,7 +47,7 @@ impl From<u32> for EXITransfer {
 
 /// Container for the state associated with an EXI channel, determined by the 
 /// current value of the channel's status and control registers.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Encode, Decode)]
 pub struct ChannelState {
     /// Device connected flag
     pub ext: bool,
@@ -110,7 +112,7 @@ impl ChannelState {
 }
 
 /// Representing a single channel on the external interface.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct EXIChannel {
     /// Channel index
     idx: usize,
@@ -172,18 +174,44 @@ impl EXIChannel {
 
     pub fn update_state(&mut self) {
         self.state = ChannelState::from_chn(self.idx, self.csr, self.ctrl);
+    }
 
-        if self.state.transfer {
-            // FIXME: implement EXI transfers to something (literally anything)
-            self.ctrl &= !1;
-            log::error!(target: "EXI", "Transfer swallowed!");
+    /// Run a pending transfer with the selected device (if any).
+    pub fn transfer(&mut self, dev: Option<&mut dyn ExiDevice>) {
+        if !self.state.transfer {
+            return;
+        }
+        self.ctrl &= !1;
+        self.update_state();
+        let dev = match dev {
+            Some(dev) if !self.state.dma => dev,
+            _ => {
+                // FIXME: implement DMA, and transfers to other devices
+                log::error!(target: "EXI", "chn{} transfer to {:?} swallowed!", self.idx, self.state.dev);
+                return;
+            },
+        };
+        let len = self.state.imm_len as usize + 1;
+        let out = match self.state.transfer_type {
+            EXITransfer::Read => [0; 4],
+            _ => self.data.to_be_bytes(),
+        };
+        let mut res = [0u8; 4];
+        for (idx, byte) in res.iter_mut().enumerate().take(len) {
+            let read = dev.transfer(out[idx]);
+            if !matches!(self.state.transfer_type, EXITransfer::Write) {
+                *byte = read;
+            }
+        }
+        if !matches!(self.state.transfer_type, EXITransfer::Write) {
+            self.data = u32::from_be_bytes(res);
         }
     }
 }
 
 
 /// Legacy external interface (EXI).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct EXInterface {
     /// EXI Channel 0 state
     pub chan0: Box<EXIChannel>,
@@ -193,6 +221,8 @@ pub struct EXInterface {
     pub chan2: Box<EXIChannel>,
     /// Buffer for Broadway bootstrap instructions
     pub ppc_bootstrap: Box<[u32; 0x10]>,
+    /// The RTC/SRAM, on channel 0 device 1
+    pub rtc: rtc::ExiRtc,
 }
 
 impl Default for EXInterface {
@@ -208,6 +238,7 @@ impl EXInterface {
             chan1: Box::new(EXIChannel::new(1)),
             chan2: Box::new(EXIChannel::new(2)),
             ppc_bootstrap: Box::new([0; 0x10]),
+            rtc: rtc::ExiRtc::default(),
         }
     }
 }
@@ -228,9 +259,23 @@ impl MmioDevice for EXInterface {
     }
     fn write(&mut self, off: usize, val: u32) -> anyhow::Result<Option<BusTask>> {
         match off { 
-            0x00..=0x10 => self.chan0.write(off, val)?,
-            0x14..=0x24 => self.chan1.write(off - 0x14, val)?,
-            0x28..=0x38 => self.chan2.write(off - 0x28, val)?,
+            0x00..=0x10 => {
+                let prev_csr = self.chan0.csr;
+                self.chan0.write(off, val)?;
+                let rtc_selected = matches!(self.chan0.state.dev, Some(EXIDeviceKind::Rtc));
+                if rtc_selected && (prev_csr ^ self.chan0.csr) & 0x380 != 0 {
+                    self.rtc.select();
+                }
+                self.chan0.transfer(if rtc_selected { Some(&mut self.rtc) } else { None });
+            },
+            0x14..=0x24 => {
+                self.chan1.write(off - 0x14, val)?;
+                self.chan1.transfer(None);
+            },
+            0x28..=0x38 => {
+                self.chan2.write(off - 0x28, val)?;
+                self.chan2.transfer(None);
+            },
 
 
             0x40..=0x7c => self.ppc_bootstrap[(off - 0x40)/4] = val,
@@ -240,4 +285,51 @@ impl MmioDevice for EXInterface {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Select the RTC and shift a command word out, then either write 4
+    /// bytes of data or read 4 bytes back.
+    fn rtc_command(exi: &mut EXInterface, cmd: u32, data: Option<u32>) -> u32 {
+        exi.write(0x00, 0x0000_0100).unwrap();
+        exi.write(0x10, cmd).unwrap();
+        exi.write(0x0c, 0x0000_0035).unwrap(); // 4 bytes, write, immediate
+        if let Some(data) = data {
+            exi.write(0x10, data).unwrap();
+            exi.write(0x0c, 0x0000_0035).unwrap();
+        } else {
+            exi.write(0x0c, 0x0000_0031).unwrap(); // 4 bytes, read, immediate
+        }
+        let res = exi.chan0.data;
+        exi.write(0x00, 0).unwrap();
+        res
+    }
+
+    #[test]
+    fn rtc_counts_cpu_cycles() {
+        let mut exi = EXInterface::new();
+        assert_eq!(exi.rtc.clock, rtc::RtcClock::Cycles);
+        exi.rtc.step(rtc::ExiRtc::CPU_CLOCK_HZ * 10);
+        let first = rtc_command(&mut exi, 0x2000_0000, None);
+        assert_eq!(first, 10);
+        exi.rtc.step(rtc::ExiRtc::CPU_CLOCK_HZ * 12);
+        assert!(rtc_command(&mut exi, 0x2000_0000, None) > first);
+        assert_eq!(exi.chan0.ctrl & 1, 0);
 
+        // Setting the clock moves it without stopping it
+        rtc_command(&mut exi, 0xa000_0000, Some(0x1000));
+        assert_eq!(exi.rtc.seconds(), 0x1000);
+        exi.rtc.step(rtc::ExiRtc::CPU_CLOCK_HZ * 13);
+        assert_eq!(rtc_command(&mut exi, 0x2000_0000, None), 0x1001);
+    }
+
+    #[test]
+    fn rtc_sram_holds_the_counter_bias() {
+        let mut exi = EXInterface::new();
+        exi.rtc.sram[rtc::ExiRtc::SRAM_BIAS..][..4].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+        // The bias is the fourth word of the SRAM
+        assert_eq!(rtc_command(&mut exi, 0x2000_0100 + (0x0c << 6), None), 0x1234_5678);
+        assert_eq!(exi.rtc.counter_bias(), 0x1234_5678);
+    }
+}
@@ -1,10 +1,14 @@
 
+use bincode::{Decode, Encode};
+
 /// Representing a particular EXI device.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Encode, Decode)]
 pub enum EXIDeviceKind {
     CardSlotA,
     CardSlotB,
     UsbGecko,
+    /// The RTC and SRAM (see [super::rtc]).
+    Rtc,
 }
 impl EXIDeviceKind {
     pub fn resolve(idx: usize, cs: u32) -> Option<Self> {
@@ -12,12 +16,18 @@ impl EXIDeviceKind {
             (0, 0) => Some(Self::CardSlotA),
             (1, 0) => Some(Self::CardSlotB),
             (1, 1) => Some(Self::UsbGecko),
+            // Chip selects are one-hot, so device 1 is bit 1
+            (0, 0b010) => Some(Self::Rtc),
             (_, _) => None,
         }
     }
 }
 
 
-//pub trait ExiDevice {
-//    fn imm_read(&mut self, gt
-//}
+/// A device which can be selected on an EXI channel.
+pub trait ExiDevice {
+    /// Called when the device is selected, before any transfers.
+    fn select(&mut self);
+    /// Shift one byte out to the device, returning the byte shifted in.
+    fn transfer(&mut self, out: u8) -> u8;
+}
@@ -0,0 +1,163 @@
+//! The real time clock and SRAM, on EXI channel 0 device 1.
+//!
+//! After the device is selected, the first four bytes shifted in are a
+//! command word. Bit 31 is set for writes, and the rest (shifted right by 6)
+//! is the address of the first byte transferred, which is incremented for
+//! each byte after that:
+//!
+//! ```text
+//! 0x20000000  read the RTC (4 bytes, big-endian seconds since 2000-01-01)
+//! 0x20000100  read the SRAM (64 bytes, with the counter bias at 0x0c)
+//! 0xa0000000  write the RTC
+//! 0xa0000100  write the SRAM
+//! ```
+
+use bincode::{Decode, Encode};
+use log::{debug, warn};
+
+use super::device::ExiDevice;
+
+/// Where the seconds counted by the RTC come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum RtcClock {
+    /// Count emulated CPU cycles, starting from zero. Runs are reproducible,
+    /// but the guest always boots at the start of 2000. This is the default.
+    Cycles,
+    /// Follow the host's wall clock.
+    Host,
+}
+impl std::str::FromStr for RtcClock {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "cycles" => Ok(RtcClock::Cycles),
+            "host" => Ok(RtcClock::Host),
+            _ => anyhow::bail!("Unknown RTC clock \"{s}\", expected cycles or host"),
+        }
+    }
+}
+
+/// State of the RTC and SRAM.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct ExiRtc {
+    pub clock: RtcClock,
+    /// Seconds added to the clock source, changed by writes to the RTC.
+    pub offset: u32,
+    pub sram: [u8; Self::SRAM_SIZE],
+    /// The last CPU cycle seen by [ExiRtc::step].
+    cpu_cycle: usize,
+
+    /// Command word shifted in so far
+    cmd: u32,
+    /// Number of command bytes shifted in so far
+    cmd_len: usize,
+    /// Number of data bytes transferred since the command word
+    cursor: u32,
+    /// The RTC value, latched when the command word is complete
+    latch: u32,
+}
+impl Default for ExiRtc {
+    fn default() -> Self {
+        Self::new(RtcClock::Cycles)
+    }
+}
+impl ExiRtc {
+    /// Rate of the cycle counter used with [RtcClock::Cycles].
+    pub const CPU_CLOCK_HZ: usize = 243_000_000;
+    pub const SRAM_SIZE: usize = 0x40;
+    /// Offset of the counter bias in the SRAM.
+    pub const SRAM_BIAS: usize = 0x0c;
+
+    /// Seconds between the Unix epoch and the RTC epoch (2000-01-01).
+    const EPOCH_OFFSET: u64 = 946_684_800;
+    const RTC_ADDR: u32 = 0x80_0000;
+    const SRAM_ADDR: u32 = 0x80_0004;
+
+    pub fn new(clock: RtcClock) -> Self {
+        ExiRtc {
+            clock, offset: 0, sram: [0; Self::SRAM_SIZE], cpu_cycle: 0,
+            cmd: 0, cmd_len: 0, cursor: 0, latch: 0,
+        }
+    }
+
+    /// Keep track of the current CPU cycle, for [RtcClock::Cycles].
+    pub fn step(&mut self, cpu_cycle: usize) {
+        self.cpu_cycle = cpu_cycle;
+    }
+
+    /// The current value of the RTC, in seconds since 2000-01-01.
+    pub fn seconds(&self) -> u32 {
+        let source = match self.clock {
+            RtcClock::Cycles => (self.cpu_cycle / Self::CPU_CLOCK_HZ) as u32,
+            RtcClock::Host => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs().saturating_sub(Self::EPOCH_OFFSET) as u32),
+        };
+        source.wrapping_add(self.offset)
+    }
+
+    /// The counter bias stored in the SRAM.
+    pub fn counter_bias(&self) -> u32 {
+        let bias = &self.sram[Self::SRAM_BIAS..Self::SRAM_BIAS + 4];
+        u32::from_be_bytes(bias.try_into().unwrap())
+    }
+
+    fn read_byte(&self, addr: u32) -> u8 {
+        match addr {
+            Self::RTC_ADDR..Self::SRAM_ADDR => {
+                self.latch.to_be_bytes()[(addr - Self::RTC_ADDR) as usize]
+            },
+            Self::SRAM_ADDR.. if addr - Self::SRAM_ADDR < Self::SRAM_SIZE as u32 => {
+                self.sram[(addr - Self::SRAM_ADDR) as usize]
+            },
+            _ => {
+                warn!(target: "EXI", "RTC read from unimplemented address {addr:06x}");
+                0
+            },
+        }
+    }
+
+    fn write_byte(&mut self, addr: u32, val: u8) {
+        match addr {
+            Self::RTC_ADDR..Self::SRAM_ADDR => {
+                let mut bytes = self.latch.to_be_bytes();
+                bytes[(addr - Self::RTC_ADDR) as usize] = val;
+                let new = u32::from_be_bytes(bytes);
+                self.offset = self.offset.wrapping_add(new.wrapping_sub(self.latch));
+                self.latch = new;
+            },
+            Self::SRAM_ADDR.. if addr - Self::SRAM_ADDR < Self::SRAM_SIZE as u32 => {
+                self.sram[(addr - Self::SRAM_ADDR) as usize] = val;
+            },
+            _ => warn!(target: "EXI", "RTC write {val:02x} to unimplemented address {addr:06x}"),
+        }
+    }
+}
+
+impl ExiDevice for ExiRtc {
+    fn select(&mut self) {
+        self.cmd = 0;
+        self.cmd_len = 0;
+        self.cursor = 0;
+    }
+
+    fn transfer(&mut self, out: u8) -> u8 {
+        if self.cmd_len < 4 {
+            self.cmd = (self.cmd << 8) | out as u32;
+            self.cmd_len += 1;
+            if self.cmd_len == 4 {
+                self.latch = self.seconds();
+                debug!(target: "EXI", "RTC command {:08x}", self.cmd);
+            }
+            return 0;
+        }
+        let addr = ((self.cmd & 0x7fff_ffff) >> 6).wrapping_add(self.cursor);
+        self.cursor += 1;
+        if self.cmd & 0x8000_0000 != 0 {
+            self.write_byte(addr, out);
+            0
+        } else {
+            self.read_byte(addr)
+        }
+    }
+}
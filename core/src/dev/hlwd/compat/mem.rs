@@ -1,10 +1,11 @@
+use bincode::{Decode, Encode};
 use crate::bus::prim::*;
 use crate::bus::mmio::*;
 use crate::bus::task::*;
 use crate::bus::Bus;
 
 /// Legacy memory interface.
-#[derive(Clone)]
+#[derive(Clone, Encode, Decode)]
 pub struct MemInterface {
     pub reg: [u16; 0x40],
     pub ddr_data: u16,
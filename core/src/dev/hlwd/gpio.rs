@@ -1,5 +1,6 @@
 
 pub mod seeprom;
+use bincode::{Decode, Encode};
 use anyhow::bail;
 use log::{info, error};
 
@@ -28,6 +29,7 @@ pub enum GpioPin {
 
 
 /// Top-level container for GPIO pin state.
+#[derive(Encode, Decode)]
 pub struct GpioInterface {
     pub arm: ArmGpio,
     pub ppc: PpcGpio,
@@ -66,7 +68,7 @@ impl GpioInterface {
 
 
 /// ARM-facing GPIO pin state.
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Encode, Decode)]
 #[allow(dead_code)]
 pub struct ArmGpio {
     en: u32,
@@ -80,6 +82,33 @@ pub struct ArmGpio {
     owner: u32,
 }
 impl ArmGpio {
+    /// Drive the input pins in `mask` low (false) or high (true).
+    ///
+    /// Pins which change to the level selected in `intlvl` latch their bit
+    /// in `intflag`. Returns true when an unmasked flag is pending, meaning
+    /// the GPIO IRQ should be asserted.
+    pub fn set_input(&mut self, mask: u32, level: bool) -> bool {
+        let old = self.input;
+        if level {
+            self.input |= mask;
+        } else {
+            self.input &= !mask;
+        }
+        let changed = old ^ self.input;
+        self.intflag |= changed & !(self.input ^ self.intlvl);
+        self.intflag & self.intmask != 0
+    }
+
+    /// Append the state of these registers to a register dump.
+    pub fn dump_regs(&self, prefix: &str, out: &mut String) {
+        for (name, val) in [
+            ("en", self.en), ("output", self.output), ("dir", self.dir),
+            ("input", self.input), ("intlvl", self.intlvl), ("intflag", self.intflag),
+            ("intmask", self.intmask), ("straps", self.straps), ("owner", self.owner),
+        ] {
+            dump_reg(out, &format!("{prefix}.{name}"), val);
+        }
+    }
     pub fn write_handler(&mut self, off: usize, data: u32) -> anyhow::Result<Option<HlwdTask>> {
         match off {
             0x00 => self.en = data,
@@ -94,7 +123,7 @@ impl ArmGpio {
             0x08 => self.dir = data,
             0x0c => { bail!("CPU wrote to GPIO inputs!?".to_string()); },
             0x10 => self.intlvl = data,
-            0x14 => self.intflag = data,
+            0x14 => self.intflag &= !data,
             0x18 => self.intmask = data,
             0x1c => self.straps = data,
             0x20 => self.owner = data,
@@ -109,7 +138,7 @@ impl ArmGpio {
             0x08 => self.dir,
             0x0c => self.input,
             0x10 => self.intlvl,
-            0x14 => 0x0000_0000, //self.intflag,
+            0x14 => self.intflag,
             0x18 => self.intmask,
             0x1c => self.straps,
             0x20 => self.owner,
@@ -119,7 +148,7 @@ impl ArmGpio {
 }
 
 /// PowerPC-facing GPIO pin state.
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Encode, Decode)]
 #[allow(dead_code)]
 pub struct PpcGpio {
     output: u32,
@@ -131,6 +160,16 @@ pub struct PpcGpio {
     straps: u32,
 }
 impl PpcGpio {
+    /// Append the state of these registers to a register dump.
+    pub fn dump_regs(&self, prefix: &str, out: &mut String) {
+        for (name, val) in [
+            ("output", self.output), ("dir", self.dir), ("input", self.input),
+            ("intlvl", self.intlvl), ("intflag", self.intflag),
+            ("intmask", self.intmask), ("straps", self.straps),
+        ] {
+            dump_reg(out, &format!("{prefix}.{name}"), val);
+        }
+    }
     pub fn write_handler(&mut self, off: usize, data: u32) -> anyhow::Result<()> {
         match off {
             0x00 => self.output = data,
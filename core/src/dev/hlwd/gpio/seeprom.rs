@@ -1,11 +1,13 @@
 #![allow(clippy::unusual_byte_groupings)]
+use bincode::{Decode, Encode};
 use crate::dev::hlwd::gpio::*;
 use crate::mem::*;
 
-use log::{debug, info};
+use anyhow::Context;
+use log::{debug, info, warn};
 
 /// Set of commands to/states of the SEEPROM state machine.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
 pub enum SeepromOp { 
     Ewds, Wral, Eral, Ewen, Ext, Write, Read, Erase, Init
 }
@@ -35,7 +37,7 @@ impl SeepromOp {
 }
 
 /// Container for the state of the emulated SEEPROM device.
-#[derive(Debug)]
+#[derive(Debug, Encode, Decode)]
 pub struct SeepromState {
     /// Data on the SEEPROM device.
     data: BigEndianMemory,
@@ -55,13 +57,34 @@ pub struct SeepromState {
     pub write_buffer: Option<u16>,
 }
 impl SeepromState {
+    /// Size of the SEEPROM (128 16-bit words).
+    pub const SIZE: usize = 0x100;
+
     pub fn new() -> anyhow::Result<Self> {
+        const FILENAME: &str = "seeprom.bin";
+        if std::path::Path::new(FILENAME).exists() {
+            Self::with_file(Some(FILENAME))
+        } else {
+            warn!(target: "SEEPROM", "{FILENAME} not found, using zeroed SEEPROM: console-specific data (counters, certs) is unavailable");
+            Self::with_file(None)
+        }
+    }
+
+    /// Create a SEEPROM with its contents read from some file, or zeroed.
+    pub fn with_file(filename: Option<&str>) -> anyhow::Result<Self> {
+        if let Some(filename) = filename {
+            let len = std::fs::metadata(filename)
+                .with_context(|| format!("Couldn't open SEEPROM image {filename}"))?.len();
+            if len < Self::SIZE as u64 {
+                bail!("SEEPROM image {filename} is {len:#x} bytes, expected {:#x}", Self::SIZE);
+            }
+        }
         Ok(SeepromState {
             in_buf: 0,
             num_bits: 0,
             out_buf: None,
             opcd: SeepromOp::Init,
-            data: BigEndianMemory::new(0x100, Some("seeprom.bin"), false)?,
+            data: BigEndianMemory::new(Self::SIZE, filename, None)?,
             wren: false,
             addr: None,
             write_buffer: None,
@@ -199,3 +222,60 @@ impl GpioInterface {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock one bit into the SEEPROM, returning the state of MISO after
+    /// the rising edge.
+    fn clock_bit(gpio: &mut GpioInterface, bit: bool) -> bool {
+        let cs = GpioPin::SeepromCs as u32;
+        let mosi = if bit { GpioPin::SeepromMosi as u32 } else { 0 };
+        gpio.handle_output(cs | mosi).unwrap();
+        gpio.handle_output(cs | mosi | GpioPin::SeepromClk as u32).unwrap();
+        (gpio.arm.input & GpioPin::SeepromMiso as u32) != 0
+    }
+
+    #[test]
+    fn read_word_over_gpio() {
+        let path = std::env::temp_dir().join(format!("ironic-seeprom-{}.bin", std::process::id()));
+        let mut image = vec![0u8; SeepromState::SIZE];
+        image[0x4a..0x4c].copy_from_slice(&0xbeefu16.to_be_bytes());
+        std::fs::write(&path, &image).unwrap();
+        let seeprom = SeepromState::with_file(path.to_str());
+        std::fs::remove_file(&path).unwrap();
+
+        let mut gpio = GpioInterface {
+            arm: ArmGpio::default(),
+            ppc: PpcGpio::default(),
+            seeprom: seeprom.unwrap(),
+        };
+
+        // Start bit, READ opcode (0b10), then the address of word 0x25
+        for bit in [1, 1, 0] {
+            clock_bit(&mut gpio, bit != 0);
+        }
+        for i in (0..8).rev() {
+            clock_bit(&mut gpio, (0x25 >> i) & 1 != 0);
+        }
+        let mut word = 0u16;
+        for _ in 0..16 {
+            word = (word << 1) | clock_bit(&mut gpio, false) as u16;
+        }
+        assert_eq!(word, 0xbeef);
+
+        // Deasserting CS ends the command
+        gpio.handle_output(0).unwrap();
+        assert_eq!(gpio.seeprom.opcd, SeepromOp::Init);
+    }
+
+    #[test]
+    fn short_image_is_rejected() {
+        let path = std::env::temp_dir().join(format!("ironic-seeprom-short-{}.bin", std::process::id()));
+        std::fs::write(&path, [0u8; 0x10]).unwrap();
+        let res = SeepromState::with_file(path.to_str());
+        std::fs::remove_file(&path).unwrap();
+        assert!(res.is_err());
+    }
+}
@@ -1,9 +1,12 @@
 //use crate::bus::task::*;
 //use crate::dev::hlwd::irq::*;
+use bincode::{Decode, Encode};
 use anyhow::bail;
 use log::debug;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, Encode, Decode)]
 pub struct MailboxState {
     pub ppc_req: bool,
     pub ppc_ack: bool,
@@ -67,7 +70,7 @@ impl MailboxState {
 }
 
 /// The inter-processor communication interface.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Encode, Decode)]
 pub struct IpcInterface {
     pub ppc_msg: u32,
     pub arm_msg: u32,
@@ -129,3 +132,55 @@ impl IpcInterface {
     }
 }
 
+
+/// Wakes up threads waiting for the PPC IRQ line to be raised (e.g. the PPC
+/// HLE backend waiting on a reply from ARM-world), instead of having them
+/// poll the bus.
+#[derive(Debug, Default)]
+pub struct PpcIrqSignal {
+    /// Number of times the line has been raised.
+    count: Mutex<u64>,
+    cond: Condvar,
+}
+impl PpcIrqSignal {
+    /// The number of times the line has been raised so far.
+    pub fn count(&self) -> u64 {
+        *self.count.lock().unwrap()
+    }
+
+    /// Note that the line was raised, waking any waiting threads.
+    pub fn notify(&self) {
+        *self.count.lock().unwrap() += 1;
+        self.cond.notify_all();
+    }
+
+    /// Wait until the line has been raised since [PpcIrqSignal::count]
+    /// returned `seen`, or until the timeout. Returns the new count.
+    pub fn wait(&self, seen: u64, timeout: Duration) -> u64 {
+        let count = self.count.lock().unwrap();
+        let (count, _) = self.cond.wait_timeout_while(count, timeout, |count| *count == seen).unwrap();
+        *count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn irq_signal_wakes_waiter() {
+        let signal = Arc::new(PpcIrqSignal::default());
+        let seen = signal.count();
+        let notifier = signal.clone();
+        let thread = std::thread::spawn(move || notifier.notify());
+        let start = Instant::now();
+        assert_eq!(signal.wait(seen, Duration::from_secs(10)), seen + 1);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread.join().unwrap();
+
+        // Nothing new, so this times out
+        assert_eq!(signal.wait(seen + 1, Duration::from_millis(1)), seen + 1);
+    }
+}
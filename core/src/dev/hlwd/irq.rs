@@ -1,8 +1,9 @@
+use bincode::{Decode, Encode};
 use anyhow::bail;
 use log::{debug, error, info};
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Encode, Decode)]
 #[repr(u32)]
 pub enum HollywoodIrq {
     Timer   = 0x0000_0001,
@@ -27,7 +28,7 @@ pub enum HollywoodIrq {
     ArmIpc  = 0x8000_0000,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Encode, Decode)]
 #[repr(transparent)]
 pub struct IrqBits(pub u32);
 impl IrqBits {
@@ -63,7 +64,7 @@ impl IrqBits {
     pub fn armipc(&self) -> bool    { (self.0 & 0x8000_0000) != 0 }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Encode, Decode)]
 pub struct IrqInterface {
     /// Output IRQ line to the ARM side; set true when any IRQ is asserted
     pub arm_irq_output: bool,
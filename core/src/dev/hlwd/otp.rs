@@ -1,32 +1,74 @@
 
-use std::io::Read;
-use std::fs::File;
+use anyhow::Context;
+use bincode::{Decode, Encode};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::{File, OpenOptions};
 use crate::bus::prim::AccessWidth;
 
-use log::{debug, trace, log_enabled};
+use log::{debug, info, trace, warn, log_enabled};
+
+/// Command bit: read a word into the output register.
+const OTP_CMD_READ: u32 = 0x8000_0000;
+/// Command bit: program the bits set in the output register into a word.
+const OTP_CMD_PROGRAM: u32 = 0x4000_0000;
 
 /// One-time programmable memory device/interface.
+#[derive(Encode, Decode)]
 pub struct OtpInterface {
     /// Bits fused to the device.
-    data: Box<[u8; 0x80]>,
+    data: Box<[u8; OtpInterface::SIZE]>,
     /// Command register.
     pub cmd: u32,
-    /// Command output register.
+    /// Command output register, which also holds the data for programming.
     pub out: u32,
+    /// Allow the program command to fuse new bits (real OTP is one-time, so
+    /// this is off by default).
+    pub writable: bool,
+    /// File that programmed words are written back to, if any.
+    pub persist_path: Option<String>,
 }
 impl OtpInterface {
+    /// Size of the OTP memory in bytes.
+    pub const SIZE: usize = 0x80;
+
     pub fn new() -> Result<Self, std::io::Error> {
-        let mut f = File::open("otp.bin")?;
-        let mut otp = OtpInterface { data: Box::new([0; 0x80]), cmd: 0, out: 0 };
-        f.read_exact(otp.data.as_mut_slice())?;
+        let mut otp = OtpInterface {
+            data: Box::new([0; Self::SIZE]), cmd: 0, out: 0, writable: false, persist_path: None,
+        };
+        match File::open("otp.bin") {
+            Ok(mut f) => f.read_exact(otp.data.as_mut_slice())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!(target: "OTP", "otp.bin not found, using zeroed OTP: boot1 version detection and OTP-derived keys are unavailable");
+            },
+            Err(e) => return Err(e),
+        }
+        otp.trace_data();
+        Ok(otp)
+    }
+
+    /// Create an OTP with its contents read from some file. When the OTP is
+    /// writable, programmed words are written back to the same file.
+    pub fn with_file(filename: &str, writable: bool) -> anyhow::Result<Self> {
+        let mut otp = OtpInterface {
+            data: Box::new([0; Self::SIZE]), cmd: 0, out: 0, writable,
+            persist_path: Some(filename.to_owned()),
+        };
+        File::open(filename)
+            .and_then(|mut f| f.read_exact(otp.data.as_mut_slice()))
+            .with_context(|| format!("Couldn't read OTP image {filename}"))?;
+        otp.trace_data();
+        Ok(otp)
+    }
+
+    /// Dump the initial contents to the trace log.
+    fn trace_data(&self) {
         if log_enabled!(target: "OTP", log::Level::Trace) {
-            trace!(target: "OTP", "Initial data: {} bytes", otp.data.len());
-            for (word_idx, chunk )in otp.data.chunks(4).enumerate() {
+            trace!(target: "OTP", "Initial data: {} bytes", self.data.len());
+            for (word_idx, chunk )in self.data.chunks(4).enumerate() {
                 trace!(target: "OTP", "Word {word_idx:02}: {:02x}{:02x}{:02x}{:02x}", chunk[0], chunk[1], chunk[2], chunk[3]);
             }
             trace!(target: "OTP", "End Initial data");
         }
-        Ok(otp)
     }
 }
 
@@ -43,13 +85,81 @@ impl OtpInterface {
         AccessWidth::from_be_bytes(&self.data[off..off+4])
     }
 
+    /// Program some bits into a word of OTP memory. Fused bits can't be
+    /// cleared, so the new bits are ORed into the old value.
+    pub fn program(&mut self, word_idx: usize, bits: u32) -> anyhow::Result<()> {
+        let off = word_idx * 4;
+        let old = self.read(word_idx);
+        let new = old | bits;
+        self.data[off..off+4].copy_from_slice(&new.to_be_bytes());
+        info!(target: "OTP", "programmed {bits:08x} @ idx={word_idx:x} ({old:08x} -> {new:08x})");
+
+        if let Some(ref path) = self.persist_path {
+            let mut f = OpenOptions::new().write(true).open(path)
+                .with_context(|| format!("Couldn't open OTP image {path} for writing"))?;
+            f.seek(SeekFrom::Start(off as u64))?;
+            f.write_all(&new.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
     /// Handle a command request.
-    pub fn write_handler(&mut self, cmd: u32) {
-        if cmd & 0x8000_0000 != 0 {
-            let addr = (cmd & 0x0000_001f) as usize;
+    pub fn write_handler(&mut self, cmd: u32) -> anyhow::Result<()> {
+        let addr = (cmd & 0x0000_001f) as usize;
+        if cmd & OTP_CMD_READ != 0 {
             let out = self.read(addr);
             self.cmd = cmd;
             self.out = out;
+        } else if cmd & OTP_CMD_PROGRAM != 0 {
+            self.cmd = cmd;
+            if !self.writable {
+                warn!(target: "OTP", "Ignored program of {:08x} @ idx={addr:x}, the OTP isn't writable", self.out);
+                return Ok(());
+            }
+            self.program(addr, self.out)?;
         }
+        Ok(())
+    }
+
+    /// Handle a write to the output register, setting the data for the
+    /// next program command.
+    pub fn write_data(&mut self, val: u32) {
+        self.out = val;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn programmed_words_read_back() {
+        let path = std::env::temp_dir().join(format!("ironic-otp-{}.bin", std::process::id()));
+        std::fs::write(&path, [0u8; 0x80]).unwrap();
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.hlwd.otp = OtpInterface::with_file(path.to_str().unwrap(), true).unwrap();
+
+        bus.write32(0x0d80_01f0, 0x1234_5678).unwrap();
+        bus.write32(0x0d80_01ec, OTP_CMD_PROGRAM).unwrap();
+        // Bits can be fused, but not cleared
+        bus.write32(0x0d80_01f0, 0x8000_0000).unwrap();
+        bus.write32(0x0d80_01ec, OTP_CMD_PROGRAM).unwrap();
+        bus.write32(0x0d80_01ec, OTP_CMD_READ).unwrap();
+        assert_eq!(bus.read32(0x0d80_01f0).unwrap(), 0x9234_5678);
+
+        let image = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image[..4], [0x92, 0x34, 0x56, 0x78]);
+    }
+
+    #[test]
+    fn read_only_otp_ignores_programming() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.hlwd.otp.writable = false;
+        let old = bus.hlwd.otp.read(0);
+        bus.write32(0x0d80_01f0, !old).unwrap();
+        bus.write32(0x0d80_01ec, OTP_CMD_PROGRAM).unwrap();
+        assert_eq!(bus.hlwd.otp.read(0), old);
     }
 }
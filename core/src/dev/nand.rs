@@ -1,6 +1,7 @@
 pub mod util;
+use bincode::{Decode, Encode};
 use anyhow::bail;
-use log::info;
+use log::{info, warn};
 
 use crate::dev::nand::util::*;
 
@@ -21,7 +22,7 @@ const NAND_BLOCK_LEN: usize = NAND_PAGE_LEN * 64;
 const NUM_NAND_PAGES: usize = 0x0004_0000;
 
 /// The total length of the NAND flash, in bytes.
-const NAND_SIZE: usize = NAND_PAGE_LEN * NUM_NAND_PAGES;
+pub const NAND_SIZE: usize = NAND_PAGE_LEN * NUM_NAND_PAGES;
 
 /// NAND device ID.
 const NAND_ID: [u8; 4] = [ 0xad, 0xdc, 0x80, 0x95 ]; // HY27UF084G2M
@@ -105,7 +106,7 @@ pub enum NandState {
 }
 
 /// Set of registers exposed by the NAND interface.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default, Encode, Decode)]
 pub struct NandRegisters {
     pub ctrl: u32,
     pub cfg: u32,
@@ -130,25 +131,27 @@ pub struct NandInterface {
     pub reg: NandRegisters,
 }
 impl NandInterface {
-    /// Create a new instance of the NAND interface.
-    pub fn new(filename: &str) -> anyhow::Result<Self> {
-        let reg = NandRegisters {
-            ctrl: 0,
-            cfg: 0,
-            addr1: 0,
-            addr2: 0,
-            databuf: 0,
-            eccbuf: 0,
-            unk: 0,
-            _cycle: 0,
-            current_page: 0,
-            current_poff: 0,
+    /// Create a new instance of the NAND interface, with writes to the
+    /// flash saved under `saved_writes`.
+    pub fn new(filename: &str, saved_writes: &std::path::Path) -> anyhow::Result<Self> {
+        let reg = NandRegisters::default();
+        let data = if std::path::Path::new(filename).exists() {
+            BigEndianMemory::new(NAND_SIZE, Some(filename), Some(saved_writes))?
+        } else {
+            // Without a dump there is nothing to persist writes against,
+            // so write tracking is left disabled.
+            warn!(target: "NAND", "{filename} not found, using blank NAND: nothing past boot1 will be loadable and NAND writes will not be saved");
+            BigEndianMemory::new(NAND_SIZE, None, None)?
         };
         Ok(NandInterface {
-            data: Box::new(BigEndianMemory::new(NAND_SIZE, Some(filename), true)?),
+            data: Box::new(data),
             reg,
         })
     }
+    /// Reset the interface registers (leaving the contents of the flash alone).
+    pub fn reset(&mut self) {
+        self.reg = NandRegisters::default();
+    }
     /// Read data from the specified offset in the NAND flash into some buffer
     pub fn read_data(&self, off: usize, dst: &mut [u8]) -> anyhow::Result<()> {
         self.data.read_buf(off, dst)
@@ -381,3 +384,43 @@ impl Bus {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_page_from_dump() {
+        const NAND_BASE: u32 = 0x0d01_0000;
+        let path = std::env::temp_dir().join(format!("ironic-nand-{}.bin", std::process::id()));
+        let saved_writes = std::env::temp_dir().join(format!("ironic-nand-writes-{}", std::process::id()));
+        let page: Vec<u8> = (0..NAND_PAGE_LEN).map(|i| (i * 7) as u8).collect();
+        std::fs::write(&path, &page).unwrap();
+
+        let mut bus = Bus::new(&BusConfig {
+            boot0: None,
+            nand: path.to_str().unwrap().to_owned(),
+            saved_writes: saved_writes.clone(),
+            ..Default::default()
+        }).unwrap();
+        bus.hlwd.irq.arm_irq_enable.set(HollywoodIrq::Nand);
+        bus.write32(NAND_BASE + 0x0c, 0).unwrap(); // page 0
+        bus.write32(NAND_BASE + 0x10, 0x0000_1000).unwrap(); // data buffer
+        bus.write32(NAND_BASE + 0x14, 0x0000_2000).unwrap(); // ECC buffer
+        // Read setup, then read the page along with its spare area
+        for cmd in [0x9f00_0000, 0x8030_2840] {
+            bus.write32(NAND_BASE, cmd).unwrap();
+            while !bus.tasks.is_empty() {
+                bus.step(0).unwrap();
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(&saved_writes).unwrap();
+
+        let mut data = vec![0; 0x840];
+        bus.dma_read(0x1000, &mut data[..0x800]).unwrap();
+        bus.dma_read(0x2000, &mut data[0x800..]).unwrap();
+        assert!(data == page, "page 0 doesn't match the dump");
+        assert!(bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::Nand));
+    }
+}
@@ -1,4 +1,5 @@
 
+use bincode::{Decode, Encode};
 use anyhow::bail;
 use log::debug;
 
@@ -6,7 +7,7 @@ use crate::bus::prim::*;
 use crate::bus::mmio::*;
 use crate::bus::task::*;
 
-#[derive(Default)]
+#[derive(Default, Encode, Decode)]
 pub struct OhcInterface {
     pub idx: usize,
 
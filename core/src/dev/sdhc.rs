@@ -2,9 +2,13 @@
 pub(crate) mod card;
 
 use anyhow::anyhow;
-use anyhow::bail;
+use bincode::{Decode, Encode};
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
 use log::debug;
 use log::error;
+use log::info;
 use log::log_enabled;
 use log::trace;
 
@@ -12,12 +16,38 @@ use crate::bus::prim::*;
 use crate::bus::mmio::*;
 use crate::bus::task::*;
 use crate::bus::Bus;
+use crate::dev::hlwd::irq::HollywoodIrq;
 use card::*;
 
 /// Changing this to false will disable DMA support
 const SDHC_ENABLE_DMA: bool = true;
 
-#[derive(Debug)]
+/// Changing this to false will silently drop writes to read-only registers
+const SDHC_LOG_READONLY_WRITES: bool = true;
+
+/// TxMode register fields
+const TX_MODE_DMA_ENABLE: u32 = 1 << 0;
+const TX_MODE_BLOCK_COUNT_ENABLE: u32 = 1 << 1;
+const TX_MODE_AUTO_CMD12: u32 = 1 << 2;
+const TX_MODE_READ: u32 = 1 << 4;
+
+/// ErrorIntStatus fields
+const ERROR_INT_DATA_TIMEOUT: u32 = 1 << 4;
+
+/// PresentState card detection fields
+const PRESENT_CARD_INSERTED: u32 = 1 << 16;
+const PRESENT_CARD_STABLE: u32 = 1 << 17;
+const PRESENT_CARD_DETECT_PIN: u32 = 1 << 18;
+const PRESENT_WRITE_ENABLE_PIN: u32 = 1 << 19;
+
+/// HostControl: the data bus is 4 bits wide (instead of 1).
+const HOST_CONTROL_4BIT: u32 = 1 << 1;
+
+/// Polls without the guest finishing a block before a transfer is treated
+/// as a runaway and aborted.
+const MAX_IDLE_POLLS: u32 = 10_000;
+
+#[derive(Debug, Encode, Decode)]
 pub enum SDHCTask {
     RaiseInt,
     SendBufReadReady,
@@ -186,7 +216,24 @@ impl SDRegisters {
             SDRegisters::SystemAddress
         )
     }
+    // These registers are HWInit or otherwise read-only from the perspective of the host driver
+    fn is_read_only(&self) -> bool {
+        matches!(self,
+            SDRegisters::Response |
+            SDRegisters::PresentState |
+            SDRegisters::Capabilities |
+            SDRegisters::MaxCurrentCapabilities |
+            SDRegisters::SlotIntStatus |
+            SDRegisters::HostControllerVersion
+        )
+    }
     fn run_write_handler(&self, iface: &mut SDInterface, old: u32, new: u32) -> Option<SDHCTask> {
+        if self.is_read_only() {
+            if SDHC_LOG_READONLY_WRITES {
+                error!(target: "SDHC", "Ignoring write {new:08x} to read-only register {self:?}");
+            }
+            return None;
+        }
         let shift: usize;
         let mask: u32;
         if self.bytecount_of_reg() >= 4 {
@@ -208,7 +255,8 @@ impl SDRegisters {
                     dbg!(&x);
                 }
                 // let cmd = x.index;
-                if let Some(response) = iface.card.issue(x, iface.raw_read(SDRegisters::Argument.base_offset())){
+                let argument = iface.raw_read(SDRegisters::Argument.base_offset());
+                if let Some(response) = iface.card.issue(x, argument, iface.write_protected) {
                     self.apply_response(iface, response);
                 }
                 if iface.cmd_complete() {
@@ -225,38 +273,34 @@ impl SDRegisters {
                 // If there is a pending transfer that's supposed to be associated with that command
                 // This is the time to kick it off.
                 match iface.card.tx_status {
-                    CardTXStatus::MultiReadPending => { // Multi Block Read
+                    CardTXStatus::MultiReadPending | CardTXStatus::MultiWritePending => {
                         if new & 1 == 1 {
-                            let use_dma = iface.raw_read(SDRegisters::TxMode.base_offset()) & 0x1 == 1;
-                            if use_dma {
-                                if !SDHC_ENABLE_DMA {
-                                    error!(target:"SDHC", "Software Attempted to use DMA, which is disabled.");
-                                    return None;
-                                }
-                                iface.card.tx_status = CardTXStatus::DMAReadInProgress;
-                                return Some(SDHCTask::DoDMARead);
+                            let tx_mode = iface.tx_mode();
+                            // The direction comes from TxMode, not the command
+                            let is_read = tx_mode & TX_MODE_READ != 0;
+                            if is_read != matches!(iface.card.tx_status, CardTXStatus::MultiReadPending) {
+                                error!(target: "SDHC", "TxMode direction ({}) disagrees with the pending transfer {:?}",
+                                    if is_read { "read" } else { "write" }, iface.card.tx_status);
                             }
-                            else {
-                                iface.card.tx_status = CardTXStatus::MultiReadInProgress;
-                                return Some(SDHCTask::SendBufReadReady);
+                            if tx_mode & TX_MODE_AUTO_CMD12 != 0 {
+                                // The card is already returned to the transfer state when
+                                // the transfer completes, so there's nothing else to do.
+                                debug!(target: "SDHC", "Auto CMD12 requested");
                             }
-                        }
-                    },
-                    CardTXStatus::MultiWritePending => {
-                        if new & 1 == 1 {
-                            let use_dma = iface.raw_read(SDRegisters::TxMode.base_offset()) & 0x1 == 1;
-                            if use_dma {
-                                if !SDHC_ENABLE_DMA {
-                                    error!(target:"SDHC", "Software Attempted to use DMA, which is disabled.");
-                                    return None;
-                                }
-                                iface.card.tx_status = CardTXStatus::DMAWriteInProgress;
-                                return Some(SDHCTask::DoDMAWrite);
-                            }
-                            else {
-                                iface.card.tx_status = CardTXStatus::MultiWriteInProgress;
-                                return Some(SDHCTask::SendBufWriteReady);
+                            iface.latch_block_count();
+                            let use_dma = tx_mode & TX_MODE_DMA_ENABLE != 0;
+                            if use_dma && !SDHC_ENABLE_DMA {
+                                error!(target:"SDHC", "Software Attempted to use DMA, which is disabled.");
+                                return None;
                             }
+                            let (status, task) = match (is_read, use_dma) {
+                                (true, true) => (CardTXStatus::DMAReadInProgress, SDHCTask::DoDMARead),
+                                (true, false) => (CardTXStatus::MultiReadInProgress, SDHCTask::SendBufReadReady),
+                                (false, true) => (CardTXStatus::DMAWriteInProgress, SDHCTask::DoDMAWrite),
+                                (false, false) => (CardTXStatus::MultiWriteInProgress, SDHCTask::SendBufWriteReady),
+                            };
+                            iface.card.tx_status = status;
+                            return Some(task);
                         }
                     },
                     CardTXStatus::None | CardTXStatus::MultiReadInProgress | CardTXStatus::MultiWriteInProgress | CardTXStatus::DMAReadInProgress | CardTXStatus::DMAWriteInProgress => { // No action taken here
@@ -284,6 +328,14 @@ impl SDRegisters {
                     return Some(SDHCTask::RaiseInt);
                 }
             },
+            SDRegisters::ErrorIntSignalEnable |
+            SDRegisters::ErrorIntStatusEnable => {
+                debug!(target: "SDHC", "{self:?} {new:b}");
+                iface.setreg(*self, new);
+                if iface.do_pending_ints() {
+                    return Some(SDHCTask::RaiseInt);
+                }
+            },
             SDRegisters::ClockControl => {
                 // set internal clock stable (bit 1) based on internal clock enable (bit 0)
                 match new & 0b1 {
@@ -301,7 +353,10 @@ impl SDRegisters {
                 if new & 1 == 1 {
                     iface.reset();
                 }
-                else { unimplemented!("DAT and CMD line resets"); }
+                else {
+                    // Not emulated yet, but there's no reason to bring down the emulator
+                    error!(target: "SDHC", "Unimplemented DAT/CMD line reset {new:#x}, ignoring");
+                }
             },
             SDRegisters::BufferDataPort => {
                 match iface.card.tx_status {
@@ -342,8 +397,6 @@ impl SDRegisters {
             SDRegisters::BlockCount |
             SDRegisters::BlockSize |
             SDRegisters::Argument |
-            SDRegisters::ErrorIntStatusEnable |
-            SDRegisters::ErrorIntSignalEnable |
             SDRegisters::TimeoutControl |
             SDRegisters::PowerControl => {
                 // No special handling needed for these registers
@@ -373,16 +426,38 @@ impl SDRegisters {
 
 #[repr(C, align(64))]
 pub struct SDInterface {
+    /// Which slot (host controller) this is.
+    slot: usize,
     register_file: [u8; 256],
     pending_interrupt_flags: u32,
+    pending_error_flags: u32,
     insert_raised: bool,
     first_ack: bool,
     card: Card,
     card_available: bool,
+    /// State of the write-protect switch on the card.
+    write_protected: bool,
     tx_status: CardTXStatus,
+    /// Consecutive polls during a transfer without the current block being
+    /// finished by the guest.
+    idle_polls: u32,
+    /// Blocks left in a transfer started with block count disabled in
+    /// TxMode. The guest-visible BlockCount register isn't used for these.
+    single_block_left: Option<u32>,
 }
 
 impl SDInterface {
+    /// Returns a copy of the register file.
+    pub fn dump_regs(&self) -> [u8; 256] {
+        self.register_file
+    }
+    /// Restore the register file from a previous dump.
+    pub fn load_regs(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(buf.len() == self.register_file.len(),
+            "SDHC register dump should be {} bytes, got {}", self.register_file.len(), buf.len());
+        self.register_file.copy_from_slice(buf);
+        Ok(())
+    }
     fn raw_read(&self, off: usize) -> u32 {
         let p = (&self.register_file) as *const [u8;256] as *const u32;
         assert!(off & 0xffff_fffc == off); // alignment
@@ -415,14 +490,43 @@ impl SDInterface {
         let new = old | ((val << val_shift) & mask);
         self.raw_write(reg.base_offset() & 0xffff_fffc, new);
     }
+    fn tx_mode(&self) -> u32 {
+        self.raw_read(SDRegisters::TxMode.base_offset()) & 0xffff
+    }
+    /// When block count is disabled in TxMode, the BlockCount register is
+    /// ignored and only a single block is transferred. The count for these
+    /// is kept internally, so the guest never reads back a value it didn't
+    /// write.
+    fn latch_block_count(&mut self) {
+        self.single_block_left = if self.tx_mode() & TX_MODE_BLOCK_COUNT_ENABLE == 0 {
+            debug!(target: "SDHC", "Block count disabled, transferring a single block");
+            Some(1)
+        } else {
+            None
+        };
+    }
+    /// Blocks left in the current transfer.
+    fn blocks_remaining(&self) -> u32 {
+        match self.single_block_left {
+            Some(n) => n,
+            None => self.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16,
+        }
+    }
+    fn set_blocks_remaining(&mut self, n: u32) {
+        match self.single_block_left.as_mut() {
+            Some(left) => *left = n,
+            None => self.setreg(SDRegisters::BlockCount, n),
+        }
+    }
     fn ck_int_enabled(&self, int: u32) -> bool {
         let signal = self.raw_read(SDRegisters::NormalIntSignalEnable.base_offset());
         let status = self.raw_read(SDRegisters::NormalIntStatusEnable.base_offset());
         signal & int != 0 && status & int != 0
     }
     fn do_pending_ints(&mut self) -> bool {
+        let found_error = self.do_pending_error_ints();
         if self.pending_interrupt_flags == 0 {
-            return false;
+            return found_error;
         }
         let mut nisr = self.raw_read(SDRegisters::NormalIntStatus.base_offset());
         let mut found = false;
@@ -439,7 +543,21 @@ impl SDInterface {
             self.setreg(SDRegisters::NormalIntStatus, nisr);
             self.setreg(SDRegisters::SlotIntStatus, sisr | 0x1); // slot 1
         }
-        return found;
+        return found || found_error;
+    }
+    fn do_pending_error_ints(&mut self) -> bool {
+        let mut found = 0;
+        for i in 0..16 {
+            let err = self.pending_error_flags & (1 << i);
+            if err != 0 && self.ck_error_int_enabled(err) {
+                self.pending_error_flags &= !err;
+                found |= err;
+            }
+        }
+        if found != 0 {
+            self.set_error_status(found);
+        }
+        found != 0
     }
     // returns true if the interrupt should be raised now, false if it's masked and will be raised later
     fn raise_int(&mut self, int: u32) -> bool {
@@ -457,22 +575,127 @@ impl SDInterface {
     }
     fn reset(&mut self) {
         debug!(target: "SDHC", "SD interface software reset");
-        let mut new = Self::default();
-        let card_detection_circuit_status = self.raw_read(SDRegisters::PresentState.base_offset()) & 0x70000;
-        new.raw_write(SDRegisters::PresentState.base_offset(), card_detection_circuit_status);
-        new.insert_raised = self.insert_raised;
+        // Resetting the host controller doesn't remove the card
+        let mut new = Self {
+            slot: self.slot,
+            card_available: self.card_available,
+            write_protected: self.write_protected,
+            insert_raised: self.insert_raised,
+            ..Self::default()
+        };
+        std::mem::swap(&mut new.card, &mut self.card);
+        new.update_card_detect();
         *self = new;
     }
+    /// Recompute the card detection bits in PresentState (card inserted,
+    /// card state stable, card detect and write protect pin levels) from
+    /// the current card state.
+    fn update_card_detect(&mut self) {
+        const CARD_DETECT_MASK: u32 = PRESENT_CARD_INSERTED | PRESENT_CARD_STABLE
+            | PRESENT_CARD_DETECT_PIN | PRESENT_WRITE_ENABLE_PIN;
+        let mut bits = PRESENT_CARD_STABLE;
+        if self.card_available {
+            bits |= PRESENT_CARD_INSERTED | PRESENT_CARD_DETECT_PIN;
+        }
+        // The pin level is high when writes are allowed
+        if !self.write_protected {
+            bits |= PRESENT_WRITE_ENABLE_PIN;
+        }
+        let ps = self.raw_read(SDRegisters::PresentState.base_offset());
+        self.setreg(SDRegisters::PresentState, (ps & !CARD_DETECT_MASK) | bits);
+    }
+    /// Bus cycles taken to move one block at the configured block size, SD
+    /// clock and bus width. `base` is the time for a 512-byte block on a
+    /// 4-bit bus at the undivided base clock.
+    fn block_latency(&self, base: usize) -> usize {
+        let block_size = (self.raw_read(SDRegisters::BlockSize.base_offset()) & 0xfff) as usize;
+        // The base clock is divided by 2N, or not at all when N is zero
+        let freq_select = ((self.raw_read(SDRegisters::ClockControl.base_offset()) >> 8) & 0xff) as usize;
+        let divisor = if freq_select == 0 { 1 } else { 2 * freq_select };
+        let host_control = self.raw_read(SDRegisters::HostControl.base_offset());
+        let bus_width = if host_control & HOST_CONTROL_4BIT != 0 { 4 } else { 1 };
+        (base * divisor * block_size * 4 / (512 * bus_width)).max(1)
+    }
+    /// Set the state of the write-protect switch on the card.
+    pub fn set_write_protect(&mut self, write_protected: bool) {
+        self.write_protected = write_protected;
+        self.update_card_detect();
+    }
     fn insert_card(&mut self) -> bool {
         if self.insert_raised || !self.card_available {
             return false;
         }
-        let current_state = self.raw_read(SDRegisters::PresentState.base_offset());
-        self.setreg(SDRegisters::PresentState, current_state | (1<<16) | (1<<17) | (1 << 18)); // card inserted
+        self.update_card_detect();
         self.insert_raised = true;
         const INSERT_INT_MASK: u32 = 1 << 6;
         return self.raise_int(INSERT_INT_MASK);
     }
+    /// Remove the card from the slot. Returns true if the card removal
+    /// interrupt should be raised now.
+    pub fn eject(&mut self) -> bool {
+        if !self.card_available {
+            return false;
+        }
+        info!(target: "SDHC", "Ejecting SD card");
+        self.card = Card::empty();
+        self.card_available = false;
+        self.insert_raised = false;
+        self.tx_status = CardTXStatus::None;
+        self.update_card_detect();
+        const REMOVAL_INT_MASK: u32 = 1 << 7;
+        self.raise_int(REMOVAL_INT_MASK)
+    }
+    /// Insert a card backed by some image file. Returns true if the card
+    /// insertion interrupt should be raised now.
+    pub fn insert(&mut self, image: &str) -> anyhow::Result<bool> {
+        let (card, card_available) = Card::try_open(image);
+        anyhow::ensure!(card_available, "Failed to open SD card image {image}");
+        info!(target: "SDHC", "Inserting SD card {image}");
+        self.card = card;
+        self.card_available = true;
+        self.insert_raised = false;
+        self.update_card_detect();
+        Ok(self.insert_card())
+    }
+    fn ck_error_int_enabled(&self, err: u32) -> bool {
+        let signal = self.raw_read(SDRegisters::NormalIntSignalEnable.base_offset()) >> 16;
+        let status = self.raw_read(SDRegisters::NormalIntStatusEnable.base_offset()) >> 16;
+        signal & err != 0 && status & err != 0
+    }
+    /// Latch some error interrupt(s) in ErrorIntStatus, along with Error
+    /// Interrupt (15) in NormalIntStatus.
+    fn set_error_status(&mut self, err: u32) {
+        const ERROR_INT_MASK: u32 = 1 << 15;
+        let status = self.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        let sisr = self.raw_read(SDRegisters::SlotIntStatus.base_offset()) & 0xffff;
+        self.setreg(SDRegisters::NormalIntStatus, (status & 0xffff) | ERROR_INT_MASK);
+        self.setreg(SDRegisters::ErrorIntStatus, (status >> 16) | err);
+        self.setreg(SDRegisters::SlotIntStatus, sisr | 0x1); // slot 1
+    }
+    // returns true if the interrupt should be raised now, false if it's masked and will be raised later
+    fn raise_error_int(&mut self, err: u32) -> bool {
+        if self.ck_error_int_enabled(err) {
+            self.set_error_status(err);
+            true
+        }
+        else {
+            self.pending_error_flags |= err;
+            false
+        }
+    }
+    /// Abort the current transfer after the guest asked for more data than
+    /// it set up: a buffer ready with no blocks remaining.
+    /// Returns true if the error interrupt should be raised now.
+    fn abort_transfer(&mut self) -> bool {
+        error!(target: "SDHC", "No blocks remaining for {:?}, aborting the transfer", self.card.tx_status);
+        // clear PS Buffer read/write enable & Read/Write Tx Active & CMD Inhibit (DAT)
+        let ps = self.raw_read(SDRegisters::PresentState.base_offset());
+        const KILL_MASK: u32 = !(1 << 11 | 1 << 10 | 1 << 9 | 1 << 8 | 1 << 1);
+        self.setreg(SDRegisters::PresentState, ps & KILL_MASK);
+        self.card.tx_status = CardTXStatus::None;
+        self.card.state = CardState::Trans;
+        return self.raise_error_int(ERROR_INT_DATA_TIMEOUT);
+    }
     fn first_ack(&mut self) -> bool {
         if self.first_ack {
             return false;
@@ -488,10 +711,10 @@ impl SDInterface {
         return self.raise_int(CMD_COMPLETE_MASK);
     }
     fn buffer_ready_read(&mut self) -> bool {
-        let blocks_remaining = self.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16;
+        let blocks_remaining = self.blocks_remaining();
         if blocks_remaining > 0 {
             self.card.rw_stop = self.card.rw_index.load(std::sync::atomic::Ordering::Relaxed) + 512;
-            self.setreg(SDRegisters::BlockCount, blocks_remaining.saturating_sub(1));
+            self.set_blocks_remaining(blocks_remaining.saturating_sub(1));
         }
         else {
             return false;
@@ -505,11 +728,11 @@ impl SDInterface {
         return self.raise_int(BUFFER_READ_READY_MASK);
     }
     fn buffer_ready_write(&mut self) -> bool {
-        let blocks_remaining = self.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16; // p83
+        let blocks_remaining = self.blocks_remaining(); // p83
         if blocks_remaining > 0 {
             // tell card it's rw_stop
             self.card.rw_stop = self.card.rw_index.load(std::sync::atomic::Ordering::Relaxed) + 512;
-            self.setreg(SDRegisters::BlockCount, blocks_remaining.saturating_sub(1));
+            self.set_blocks_remaining(blocks_remaining.saturating_sub(1));
         }
         else {
             return false;
@@ -533,7 +756,7 @@ impl SDInterface {
             },
             CardTXStatus::MultiWriteInProgress => {
                 // Clear Block Count Register
-                self.setreg(SDRegisters::BlockCount, 0);
+                self.set_blocks_remaining(0);
                 // clear PS Buffer write enable & Write Tx Active & CMD Inhibit (DAT)
                 let ps = self.raw_read(SDRegisters::PresentState.base_offset());
                 const KILL_MASK: u32 = !(1 << 10 | 1 << 8 | 1 << 1);
@@ -545,7 +768,7 @@ impl SDInterface {
             },
             CardTXStatus::MultiReadInProgress => {
                 // Clear Block Count Register
-                self.setreg(SDRegisters::BlockCount, 0);
+                self.set_blocks_remaining(0);
                 // clear PS Buffer read enable & Read Tx Active & CMD Inhibit (DAT)
                 let ps = self.raw_read(SDRegisters::PresentState.base_offset());
                 const KILL_MASK: u32 = !(1 << 11 | 1 << 9 | 1 << 1);
@@ -557,7 +780,7 @@ impl SDInterface {
             },
             CardTXStatus::DMAReadInProgress => {
                 // Clear Block Count Register
-                self.setreg(SDRegisters::BlockCount, 0);
+                self.set_blocks_remaining(0);
                 // clear PS Read Tx Active & CMD Inhibit (DAT)
                 let ps = self.raw_read(SDRegisters::PresentState.base_offset());
                 const KILL_MASK: u32 = !(1 << 9 | 1 << 1);
@@ -569,7 +792,7 @@ impl SDInterface {
             },
             CardTXStatus::DMAWriteInProgress => {
                 // Clear Block Count Register
-                self.setreg(SDRegisters::BlockCount, 0);
+                self.set_blocks_remaining(0);
                 // clear PS Buffer  Write Tx Active & CMD Inhibit (DAT)
                 let ps = self.raw_read(SDRegisters::PresentState.base_offset());
                 const KILL_MASK: u32 = !(1 << 8 | 1 << 1);
@@ -583,7 +806,7 @@ impl SDInterface {
     }
     fn dma_int(&mut self) -> bool {
         const DMA_INT: u32 = 1 << 3;
-        match self.tx_status {
+        match self.card.tx_status {
             CardTXStatus::None |
             CardTXStatus::MultiReadPending |
             CardTXStatus::MultiReadInProgress |
@@ -602,7 +825,18 @@ impl SDInterface {
 impl Default for SDInterface {
     fn default() -> Self {
         let (card, card_available) = Card::try_new();
-        let mut new = Self { register_file: [0;256], pending_interrupt_flags: 0, insert_raised: false, first_ack: false, card, card_available, tx_status: CardTXStatus::None };
+        Self::with_card(0, card, card_available)
+    }
+}
+
+impl SDInterface {
+    /// Create the host controller for some slot, with no card inserted.
+    pub fn empty(slot: usize) -> Self {
+        Self::with_card(slot, Card::empty(), false)
+    }
+
+    fn with_card(slot: usize, card: Card, card_available: bool) -> Self {
+        let mut new = Self { slot, register_file: [0;256], pending_interrupt_flags: 0, pending_error_flags: 0, insert_raised: false, first_ack: false, card, card_available, write_protected: false, tx_status: CardTXStatus::None, idle_polls: 0, single_block_left: None };
         // Fill HWInit registers
         // Capabilities Register
         const VOLTAGE_SUPPORT_3_3V: u32 = 1 << 24;
@@ -613,11 +847,52 @@ impl Default for SDInterface {
         const CURRENT_CAP_3_3V_MAX: u32 = 0xff;
         new.raw_write(SDRegisters::MaxCurrentCapabilities.base_offset(), CURRENT_CAP_3_3V_MAX);
         // End HWInit Registers
+        new.update_card_detect();
         debug!(target: "SDHC", "init sdhc");
         new
     }
 }
 
+/// The card image isn't encoded, so the same image needs to be inserted
+/// when the state is restored.
+impl Encode for SDInterface {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.register_file.encode(encoder)?;
+        self.pending_interrupt_flags.encode(encoder)?;
+        self.pending_error_flags.encode(encoder)?;
+        self.insert_raised.encode(encoder)?;
+        self.first_ack.encode(encoder)?;
+        self.card_available.encode(encoder)?;
+        self.write_protected.encode(encoder)?;
+        self.tx_status.encode(encoder)?;
+        self.idle_polls.encode(encoder)?;
+        self.single_block_left.encode(encoder)?;
+        self.card.encode(encoder)
+    }
+}
+
+impl SDInterface {
+    /// Restore state written by the [Encode] implementation, keeping the
+    /// card image that's currently inserted.
+    pub(crate) fn decode_state<D: Decoder>(&mut self, decoder: &mut D) -> Result<(), DecodeError> {
+        self.register_file = Decode::decode(decoder)?;
+        self.pending_interrupt_flags = Decode::decode(decoder)?;
+        self.pending_error_flags = Decode::decode(decoder)?;
+        self.insert_raised = Decode::decode(decoder)?;
+        self.first_ack = Decode::decode(decoder)?;
+        let card_available: bool = Decode::decode(decoder)?;
+        if card_available != self.card_available {
+            return Err(DecodeError::OtherString(format!("SD slot {} was {} when the state was saved",
+                self.slot, if card_available { "in use" } else { "empty" })));
+        }
+        self.write_protected = Decode::decode(decoder)?;
+        self.tx_status = Decode::decode(decoder)?;
+        self.idle_polls = Decode::decode(decoder)?;
+        self.single_block_left = Decode::decode(decoder)?;
+        self.card.decode_state(decoder)
+    }
+}
+
 impl MmioDevice for SDInterface {
     type Width = u32;
 
@@ -665,86 +940,112 @@ impl MmioDevice for SDInterface {
             Ok(None)
         }
         else {
-            Ok(Some(BusTask::SDHC(tasks.pop().unwrap())))
+            Ok(Some(BusTask::SDHC(self.slot, tasks.pop().unwrap())))
         }
     }
 }
 
-#[derive(Default)]
-pub struct WLANInterface {
-    pub unk_24: u32,
-    pub unk_40: u32,
-    pub unk_fc: u32,
-}
-
-impl MmioDevice for WLANInterface {
-    type Width = u32;
-    fn read(&self, off: usize) -> anyhow::Result<BusPacket> {
-        let val = match off {
-            0x24 => self.unk_24,
-            //0x24 => 0x0001_0000, //self.unk_24,
-            //0x40 => 0x0040_0000, //self.unk_24,
-            //0xfc => self.unk_fc,
-            _ => { bail!("SDHC1 read at {off:x} unimpl"); },
-        };
-        Ok(BusPacket::Word(val))
+impl Bus {
+    /// Get the SD host controller for some slot.
+    pub fn sd(&mut self, slot: usize) -> anyhow::Result<&mut SDInterface> {
+        match slot {
+            0 => Ok(&mut self.sd0),
+            1 => Ok(&mut self.sd1),
+            _ => Err(anyhow!("No SD slot {slot}")),
+        }
     }
-    fn write(&mut self, off: usize, val: u32) -> anyhow::Result<Option<BusTask>> {
-        bail!("SDHC1 write {val:08x} at {off:x} unimpl")
+    /// The interrupt raised by the SD host controller for some slot.
+    fn sd_irq(slot: usize) -> HollywoodIrq {
+        // The second controller is normally wired to the SDIO WLAN module
+        if slot == 0 { HollywoodIrq::Sdhc } else { HollywoodIrq::Wifi }
+    }
+    /// Remove the SD card from some slot (e.g. to test hot-plug handling).
+    pub fn eject_sd(&mut self, slot: usize) -> anyhow::Result<()> {
+        if self.sd(slot)?.eject() {
+            self.hlwd.irq.assert(Self::sd_irq(slot));
+        }
+        Ok(())
+    }
+    /// Insert an SD card image into some slot.
+    pub fn insert_sd(&mut self, slot: usize, image: &str) -> anyhow::Result<()> {
+        if self.sd(slot)?.insert(image)? {
+            self.hlwd.irq.assert(Self::sd_irq(slot));
+        }
+        Ok(())
     }
-}
 
+    /// Poll again later while the guest hasn't finished with the current
+    /// block, aborting the transfer if it never does.
+    fn sd_idle_poll(&mut self, slot: usize, latency: usize) -> anyhow::Result<()> {
+        let sd = self.sd(slot)?;
+        sd.idle_polls += 1;
+        if sd.idle_polls < MAX_IDLE_POLLS {
+            self.tasks.push(Task { kind: BusTask::SDHC(slot, SDHCTask::IOPoll), target_cycle: self.cycle + latency });
+            return Ok(());
+        }
+        error!(target: "SDHC", "SDHC{slot}: block not finished after {MAX_IDLE_POLLS} polls, aborting the transfer");
+        sd.idle_polls = 0;
+        if sd.abort_transfer() {
+            self.hlwd.irq.assert(Self::sd_irq(slot));
+        }
+        Ok(())
+    }
 
-impl Bus {
-    pub(crate) fn handle_task_sdhc(&mut self, task: SDHCTask) {
-        use super::hlwd::irq::HollywoodIrq;
+    pub(crate) fn handle_task_sdhc(&mut self, slot: usize, task: SDHCTask) -> anyhow::Result<()> {
+        let irq = Self::sd_irq(slot);
+        let base = self.sd_block_latency;
+        let latency = self.sd(slot)?.block_latency(base);
         match task {
             SDHCTask::RaiseInt => {
-                debug!(target: "SDHC", "Raising SDHC interrupt.");
-                self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                debug!(target: "SDHC", "Raising SDHC{slot} interrupt.");
+                self.hlwd.irq.assert(irq);
             },
             SDHCTask::SendBufReadReady => {
-                match self.sd0.buffer_ready_read() {
+                match self.sd(slot)?.buffer_ready_read() {
                     true => {
                         self.tasks.push(
-                            Task { kind: BusTask::SDHC(SDHCTask::IOPoll), target_cycle: self.cycle+10000 }
+                            Task { kind: BusTask::SDHC(slot, SDHCTask::IOPoll), target_cycle: self.cycle + latency }
                         );
-                        self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                        self.hlwd.irq.assert(irq);
                     },
                     false => {
-                        unimplemented!();
+                        if self.sd(slot)?.abort_transfer() {
+                            self.hlwd.irq.assert(irq);
+                        }
                     },
                 }
             },
             SDHCTask::SendBufWriteReady => {
-                match self.sd0.buffer_ready_write() {
+                match self.sd(slot)?.buffer_ready_write() {
                     true => {
                         self.tasks.push(
-                            Task { kind: BusTask::SDHC(SDHCTask::IOPoll), target_cycle: self.cycle+10000 }
+                            Task { kind: BusTask::SDHC(slot, SDHCTask::IOPoll), target_cycle: self.cycle + latency }
                         );
-                        self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                        self.hlwd.irq.assert(irq);
                     },
                     false => {
-                        unimplemented!();
+                        if self.sd(slot)?.abort_transfer() {
+                            self.hlwd.irq.assert(irq);
+                        }
                     },
                 }
             },
             SDHCTask::DoDMARead => {
-                let sysaddr = self.sd0.raw_read(SDRegisters::SystemAddress.base_offset());
-                let buff_boundry = 0x1000u32 << ((self.sd0.raw_read(SDRegisters::BlockSize.base_offset()) & 0x7000) >> 12);
+                let sysaddr = self.sd(slot)?.raw_read(SDRegisters::SystemAddress.base_offset());
+                let buff_boundry = 0x1000u32 << ((self.sd(slot)?.raw_read(SDRegisters::BlockSize.base_offset()) & 0x7000) >> 12);
                 let stop_addr = match sysaddr.checked_add(buff_boundry) { // mini always sets 512k boundry size, even if that would overrun the address space
                     Some(x) => (x + 1) & !(buff_boundry - 1),
                     None => u32::MAX,
                 };
-                let mut block_count = self.sd0.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16;
+                let mut block_count = self.sd(slot)?.blocks_remaining();
                 let mut current_addr = sysaddr;
                 debug!(target: "SDHC", "Starting DMA Read Tx to sysaddr: {sysaddr:x}");
                 let mut local_buf = vec![0;512];
-                while current_addr+512 < stop_addr && block_count > 0 {
-                    let offset = self.sd0.card.rw_index.load(std::sync::atomic::Ordering::Relaxed);
-                    self.sd0.card.backing_mem.lock().read_buf(offset, &mut local_buf).unwrap();
+                while current_addr + 512 <= stop_addr && block_count > 0 {
+                    let offset = self.sd(slot)?.card.rw_index.load(std::sync::atomic::Ordering::Relaxed);
+                    self.sd(slot)?.card.backing_mem.lock().read_buf(offset, &mut local_buf).unwrap();
                     self.dma_write(current_addr, &local_buf).unwrap();
-                    self.sd0.card.rw_index.store(offset + 512, std::sync::atomic::Ordering::Relaxed);
+                    self.sd(slot)?.card.rw_index.store(offset + 512, std::sync::atomic::Ordering::Relaxed);
                     local_buf.fill(0);
                     block_count -= 1;
                     current_addr += 512;
@@ -752,38 +1053,42 @@ impl Bus {
                 let send_dma_int = current_addr >= stop_addr;
                 let send_tx_complete = block_count == 0;
                 debug!(target: "SDHC", "DMA Transfer completed after {} blocks. Reached DMA Boundry: {send_dma_int}. Reached Block Count: {send_tx_complete}", (current_addr-sysaddr) / 512);
-                self.sd0.setreg(SDRegisters::BlockCount, block_count);
-                self.sd0.setreg(SDRegisters::SystemAddress, current_addr);
+                self.sd(slot)?.set_blocks_remaining(block_count);
+                self.sd(slot)?.setreg(SDRegisters::SystemAddress, current_addr);
                 if send_tx_complete { // TX Complete has higher priority than DMA complete. Never send both!
-                    if self.sd0.tx_complete() {
-                        self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                    if self.sd(slot)?.tx_complete() {
+                        self.hlwd.irq.assert(irq);
                     }
                 }
                 else if send_dma_int {
-                    if self.sd0.dma_int() {
-                        self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                    if self.sd(slot)?.dma_int() {
+                        self.hlwd.irq.assert(irq);
                     }
                 }
                 else {
-                    unreachable!("SDHC DMA Logic Error");
+                    // SYSADDR isn't block-aligned, so the next block would cross the boundary
+                    error!(target: "SDHC", "DMA stopped at {current_addr:x} before the boundary at {stop_addr:x}");
+                    if self.sd(slot)?.abort_transfer() {
+                        self.hlwd.irq.assert(irq);
+                    }
                 }
             },
             SDHCTask::DoDMAWrite => {
-                let sysaddr: u32 = self.sd0.raw_read(SDRegisters::SystemAddress.base_offset());
-                let buff_boundry = 0x1000u32 << ((self.sd0.raw_read(SDRegisters::BlockSize.base_offset()) & 0x7000) >> 12);
+                let sysaddr: u32 = self.sd(slot)?.raw_read(SDRegisters::SystemAddress.base_offset());
+                let buff_boundry = 0x1000u32 << ((self.sd(slot)?.raw_read(SDRegisters::BlockSize.base_offset()) & 0x7000) >> 12);
                 let stop_addr = match sysaddr.checked_add(buff_boundry) { // mini always sets 512k boundry size, even if that would overrun the address space
                     Some(x) => (x + 1) & !(buff_boundry - 1),
                     None => u32::MAX,
                 };
-                let mut block_count = self.sd0.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16;
+                let mut block_count = self.sd(slot)?.blocks_remaining();
                 let mut current_addr = sysaddr;
                 debug!(target: "SDHC", "Starting DMA Write Tx from sysaddr: {sysaddr:x}");
                 let mut local_buf = vec![0;512];
-                while current_addr+512 < stop_addr && block_count > 0 {
+                while current_addr + 512 <= stop_addr && block_count > 0 {
                     self.dma_read(current_addr, &mut local_buf).unwrap();
-                    let offset = self.sd0.card.rw_index.load(std::sync::atomic::Ordering::Relaxed);
-                    self.sd0.card.backing_mem.lock().write_buf(offset, &local_buf).unwrap();
-                    self.sd0.card.rw_index.store(offset + 512, std::sync::atomic::Ordering::Relaxed);
+                    let offset = self.sd(slot)?.card.rw_index.load(std::sync::atomic::Ordering::Relaxed);
+                    self.sd(slot)?.card.backing_mem.lock().write_buf(offset, &local_buf).unwrap();
+                    self.sd(slot)?.card.rw_index.store(offset + 512, std::sync::atomic::Ordering::Relaxed);
                     local_buf.fill(0);
                     block_count -= 1;
                     current_addr += 512;
@@ -791,26 +1096,30 @@ impl Bus {
                 let send_dma_int = current_addr >= stop_addr;
                 let send_tx_complete = block_count == 0;
                 debug!(target: "SDHC", "DMA Transfer completed after {} blocks. Reached DMA Boundry: {send_dma_int}. Reached Block Count: {send_tx_complete}", (current_addr-sysaddr) / 512);
-                self.sd0.setreg(SDRegisters::BlockCount, block_count);
-                self.sd0.setreg(SDRegisters::SystemAddress, current_addr);
+                self.sd(slot)?.set_blocks_remaining(block_count);
+                self.sd(slot)?.setreg(SDRegisters::SystemAddress, current_addr);
                 if send_tx_complete { // TX Complete has higher priority than DMA complete. Never send both!
-                    if self.sd0.tx_complete() {
-                        self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                    if self.sd(slot)?.tx_complete() {
+                        self.hlwd.irq.assert(irq);
                     }
                 }
                 else if send_dma_int {
-                    if self.sd0.dma_int() {
-                        self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                    if self.sd(slot)?.dma_int() {
+                        self.hlwd.irq.assert(irq);
                     }
                 }
                 else {
-                    unreachable!("SDHC DMA Logic Error");
+                    // SYSADDR isn't block-aligned, so the next block would cross the boundary
+                    error!(target: "SDHC", "DMA stopped at {current_addr:x} before the boundary at {stop_addr:x}");
+                    if self.sd(slot)?.abort_transfer() {
+                        self.hlwd.irq.assert(irq);
+                    }
                 }
             }
             SDHCTask::IOPoll => {
-                let rw_index = self.sd0.card.rw_index.load(std::sync::atomic::Ordering::Relaxed);
-                trace!(target: "SDHC", "SDHC IOPOLL {} {}", rw_index, self.sd0.card.rw_stop);
-                match self.sd0.card.tx_status {
+                let rw_index = self.sd(slot)?.card.rw_index.load(std::sync::atomic::Ordering::Relaxed);
+                trace!(target: "SDHC", "SDHC IOPOLL {} {}", rw_index, self.sd(slot)?.card.rw_stop);
+                match self.sd(slot)?.card.tx_status {
                     CardTXStatus::None |
                     CardTXStatus::MultiReadPending |
                     CardTXStatus::MultiWritePending => {},
@@ -818,43 +1127,401 @@ impl Bus {
                         error!(target: "SDHC", "Improper state for SDHC IOPOLLing.");
                     }
                     CardTXStatus::MultiReadInProgress => {
-                        if rw_index >= self.sd0.card.rw_stop {
-                            let blocks_remain = self.sd0.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16;
+                        if rw_index >= self.sd(slot)?.card.rw_stop {
+                            self.sd(slot)?.idle_polls = 0;
+                            let blocks_remain = self.sd(slot)?.blocks_remaining();
                             if blocks_remain > 0 {
                                 self.tasks.push(
-                                    Task { kind: BusTask::SDHC(SDHCTask::SendBufReadReady), target_cycle: self.cycle + 10000 }
+                                    Task { kind: BusTask::SDHC(slot, SDHCTask::SendBufReadReady), target_cycle: self.cycle + latency }
                                 );
                             }
-                            else if self.sd0.tx_complete() {
-                               self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                            else if self.sd(slot)?.tx_complete() {
+                               self.hlwd.irq.assert(irq);
                             }
                         }
                         else {
-                            self.tasks.push(
-                                Task { kind: BusTask::SDHC(SDHCTask::IOPoll), target_cycle: self.cycle+10000 }
-                            );
+                            self.sd_idle_poll(slot, latency)?;
                         }
                     },
                     CardTXStatus::MultiWriteInProgress => {
-                        if rw_index >= self.sd0.card.rw_stop {
-                            let blocks_remain = self.sd0.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16;
+                        if rw_index >= self.sd(slot)?.card.rw_stop {
+                            self.sd(slot)?.idle_polls = 0;
+                            let blocks_remain = self.sd(slot)?.blocks_remaining();
                             if blocks_remain > 0 {
                                 self.tasks.push(
-                                    Task { kind: BusTask::SDHC(SDHCTask::SendBufWriteReady), target_cycle: self.cycle + 10000 }
+                                    Task { kind: BusTask::SDHC(slot, SDHCTask::SendBufWriteReady), target_cycle: self.cycle + latency }
                                 );
                             }
-                            else if self.sd0.tx_complete() {
-                                self.hlwd.irq.assert(HollywoodIrq::Sdhc);
+                            else if self.sd(slot)?.tx_complete() {
+                                self.hlwd.irq.assert(irq);
                             }
                         }
                         else {
-                            self.tasks.push(
-                                Task { kind: BusTask::SDHC(SDHCTask::IOPoll), target_cycle: self.cycle+10000 }
-                            );
+                            self.sd_idle_poll(slot, latency)?;
                         }
                     }
                 }
             },
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::timeline::TimelineEvent;
+
+    #[test]
+    fn buffer_ready_without_blocks_raises_error() {
+        let mut sd = SDInterface::default();
+        // Enable (and signal) Data Timeout errors
+        sd.setreg(SDRegisters::ErrorIntStatusEnable, ERROR_INT_DATA_TIMEOUT);
+        sd.setreg(SDRegisters::ErrorIntSignalEnable, ERROR_INT_DATA_TIMEOUT);
+        sd.setreg(SDRegisters::BlockCount, 0);
+        sd.card.tx_status = CardTXStatus::MultiReadInProgress;
+
+        // This is the path taken by SDHCTask::SendBufReadReady
+        assert!(!sd.buffer_ready_read());
+        assert!(sd.abort_transfer());
+
+        let status = sd.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status >> 16, ERROR_INT_DATA_TIMEOUT);
+        assert_ne!(status & (1 << 15), 0);
+        assert!(matches!(sd.card.tx_status, CardTXStatus::None));
+
+        // Same for writes, but with the error masked
+        sd.setreg(SDRegisters::ErrorIntSignalEnable, 0);
+        sd.card.tx_status = CardTXStatus::MultiWriteInProgress;
+        assert!(!sd.buffer_ready_write());
+        assert!(!sd.abort_transfer());
+    }
+
+    #[test]
+    fn masked_error_int_is_pending_until_enabled() {
+        let mut sd = SDInterface::default();
+        sd.setreg(SDRegisters::ErrorIntStatusEnable, ERROR_INT_DATA_TIMEOUT);
+        assert!(!sd.raise_error_int(ERROR_INT_DATA_TIMEOUT));
+        let status = sd.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status, 0);
+
+        // Enabling the signal raises the pending error
+        let old = sd.raw_read(0x38);
+        let task = SDRegisters::ErrorIntSignalEnable.run_write_handler(&mut sd, old, ERROR_INT_DATA_TIMEOUT << 16);
+        assert!(matches!(task, Some(SDHCTask::RaiseInt)));
+        let status = sd.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status >> 16, ERROR_INT_DATA_TIMEOUT);
+        assert_ne!(status & (1 << 15), 0);
+
+        // ... only once
+        assert!(!sd.do_pending_ints());
+    }
+
+    #[test]
+    fn masked_int_is_pending_until_signal_enabled() {
+        const CMD_COMPLETE: u32 = 1 << 0;
+        const XFER_COMPLETE: u32 = 1 << 1;
+        // Don't let the initial command complete ack muddy the status
+        let mut sd = SDInterface { first_ack: true, ..Default::default() };
+        sd.setreg(SDRegisters::NormalIntStatusEnable, CMD_COMPLETE | XFER_COMPLETE);
+        sd.setreg(SDRegisters::NormalIntSignalEnable, CMD_COMPLETE);
+
+        // With the signal disabled, the interrupt is only pending
+        assert!(!sd.raise_int(XFER_COMPLETE));
+        assert_eq!(sd.pending_interrupt_flags, XFER_COMPLETE);
+        assert_eq!(sd.raw_read(SDRegisters::NormalIntStatus.base_offset()), 0);
+        assert_eq!(sd.raw_read(SDRegisters::SlotIntStatus.base_offset()) & 1, 0);
+
+        // Enabling the signal asserts it and clears the pending flag
+        let old = sd.raw_read(SDRegisters::NormalIntSignalEnable.base_offset());
+        let task = SDRegisters::NormalIntSignalEnable.run_write_handler(&mut sd, old, CMD_COMPLETE | XFER_COMPLETE);
+        assert!(matches!(task, Some(SDHCTask::RaiseInt)));
+        assert_eq!(sd.pending_interrupt_flags, 0);
+        assert_eq!(sd.raw_read(SDRegisters::NormalIntStatus.base_offset()), XFER_COMPLETE);
+        assert_ne!(sd.raw_read(SDRegisters::SlotIntStatus.base_offset()) & 1, 0);
+        assert!(!sd.do_pending_ints());
+
+        // Writing 1 clears only the written status bits
+        assert!(sd.raise_int(CMD_COMPLETE));
+        let old = sd.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(old, CMD_COMPLETE | XFER_COMPLETE);
+        assert!(SDRegisters::NormalIntStatus.run_write_handler(&mut sd, old, XFER_COMPLETE).is_none());
+        assert_eq!(sd.raw_read(SDRegisters::NormalIntStatus.base_offset()), CMD_COMPLETE);
+        let old = sd.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        SDRegisters::NormalIntStatus.run_write_handler(&mut sd, old, CMD_COMPLETE);
+        assert_eq!(sd.raw_read(SDRegisters::NormalIntStatus.base_offset()), 0);
+        assert_eq!(sd.pending_interrupt_flags, 0);
+    }
+
+    fn present_state(sd: &SDInterface) -> u32 {
+        sd.raw_read(SDRegisters::PresentState.base_offset())
+    }
+
+    #[test]
+    fn present_state_tracks_card_detection() {
+        const DETECTED: u32 = PRESENT_CARD_INSERTED | PRESENT_CARD_DETECT_PIN;
+        let path = std::env::temp_dir().join(format!("ironic-sd-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0u8; 0x1000]).unwrap();
+
+        let mut sd = SDInterface::default();
+        sd.eject();
+        assert_eq!(present_state(&sd) & DETECTED, 0);
+        assert_ne!(present_state(&sd) & PRESENT_CARD_STABLE, 0);
+
+        sd.insert(path.to_str().unwrap()).unwrap();
+        assert_eq!(present_state(&sd) & DETECTED, DETECTED);
+        assert_ne!(present_state(&sd) & PRESENT_WRITE_ENABLE_PIN, 0);
+
+        sd.set_write_protect(true);
+        assert_eq!(present_state(&sd) & PRESENT_WRITE_ENABLE_PIN, 0);
+        assert_eq!(present_state(&sd) & DETECTED, DETECTED);
+
+        // A software reset keeps the card detection state
+        sd.reset();
+        assert_eq!(present_state(&sd) & (DETECTED | PRESENT_WRITE_ENABLE_PIN), DETECTED);
+
+        sd.eject();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(present_state(&sd) & DETECTED, 0);
+        assert_ne!(present_state(&sd) & PRESENT_CARD_STABLE, 0);
+    }
+
+    #[test]
+    fn slot1_insert_raises_its_own_irq() {
+        const INSERT_INT: u32 = 1 << 6;
+        let path = std::env::temp_dir().join(format!("ironic-sd1-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0u8; 0x1000]).unwrap();
+
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.hlwd.irq.arm_irq_enable.set(HollywoodIrq::Sdhc);
+        bus.hlwd.irq.arm_irq_enable.set(HollywoodIrq::Wifi);
+        assert_eq!(present_state(&bus.sd1) & PRESENT_CARD_INSERTED, 0);
+        bus.sd1.setreg(SDRegisters::NormalIntStatusEnable, INSERT_INT);
+        bus.sd1.setreg(SDRegisters::NormalIntSignalEnable, INSERT_INT);
+        bus.insert_sd(1, path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_ne!(present_state(&bus.sd1) & PRESENT_CARD_INSERTED, 0);
+        let status = bus.sd1.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_ne!(status & INSERT_INT, 0);
+        assert!(bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::Wifi));
+        assert!(!bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::Sdhc));
+    }
+
+    #[test]
+    fn multi_block_read_tasks_are_listed_until_done() {
+        const BUFFER_READ_READY: u32 = 1 << 5;
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.sd0.setreg(SDRegisters::NormalIntStatusEnable, BUFFER_READ_READY);
+        bus.sd0.setreg(SDRegisters::NormalIntSignalEnable, BUFFER_READ_READY);
+        bus.sd0.setreg(SDRegisters::BlockSize, 512);
+        bus.sd0.setreg(SDRegisters::BlockCount, 2);
+        bus.sd0.setreg(SDRegisters::HostControl, HOST_CONTROL_4BIT);
+        bus.sd0.card.tx_status = CardTXStatus::MultiReadInProgress;
+        bus.tasks.push(Task { kind: BusTask::SDHC(0, SDHCTask::SendBufReadReady), target_cycle: 0 });
+        assert_eq!(bus.pending_tasks(), vec![(0, "SDHC0 SendBufReadReady".to_owned())]);
+        let run_until = |bus: &mut Bus, cycle: usize| {
+            while bus.cycle <= cycle {
+                bus.step(0).unwrap();
+            }
+        };
+        // With a 4-bit bus at full clock, each block takes the base latency
+        let t = bus.sd_block_latency as u64;
+
+        // The first block is ready, then we poll until the guest has read it
+        run_until(&mut bus, 0);
+        assert_eq!(bus.pending_tasks(), vec![(t, "SDHC0 IOPoll".to_owned())]);
+        bus.sd0.card.rw_index.store(512, std::sync::atomic::Ordering::Relaxed);
+        run_until(&mut bus, t as usize);
+        assert_eq!(bus.pending_tasks(), vec![(2 * t, "SDHC0 SendBufReadReady".to_owned())]);
+
+        // After the last block, nothing is left
+        run_until(&mut bus, 2 * t as usize);
+        assert_eq!(bus.pending_tasks(), vec![(3 * t, "SDHC0 IOPoll".to_owned())]);
+        bus.sd0.card.rw_index.store(1024, std::sync::atomic::Ordering::Relaxed);
+        run_until(&mut bus, 3 * t as usize);
+        assert!(bus.pending_tasks().is_empty());
+    }
+
+    #[test]
+    fn block_count_disabled_transfers_a_single_block() {
+        const XFER_COMPLETE: u32 = 1 << 1;
+        const BUFFER_READ_READY: u32 = 1 << 5;
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.sd0.setreg(SDRegisters::NormalIntStatusEnable, XFER_COMPLETE | BUFFER_READ_READY);
+        bus.sd0.setreg(SDRegisters::NormalIntSignalEnable, XFER_COMPLETE | BUFFER_READ_READY);
+        bus.sd0.setreg(SDRegisters::BlockSize, 512);
+        bus.sd0.setreg(SDRegisters::BlockCount, 5);
+        bus.sd0.setreg(SDRegisters::TxMode, TX_MODE_READ);
+        bus.sd0.card.tx_status = CardTXStatus::MultiReadPending;
+
+        // Acknowledging the command starts the transfer
+        let old = bus.sd0.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        let task = SDRegisters::NormalIntStatus.run_write_handler(&mut bus.sd0, old, 1).unwrap();
+        assert!(matches!(task, SDHCTask::SendBufReadReady));
+        bus.tasks.push(Task { kind: BusTask::SDHC(0, task), target_cycle: 0 });
+        bus.step(0).unwrap();
+        bus.sd0.card.rw_index.store(512, std::sync::atomic::Ordering::Relaxed);
+        while !bus.tasks.is_empty() {
+            bus.step(0).unwrap();
+        }
+
+        // Only one block was transferred, and BlockCount is left alone
+        assert!(matches!(bus.sd0.card.tx_status, CardTXStatus::None));
+        let status = bus.sd0.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status & (XFER_COMPLETE | BUFFER_READ_READY), XFER_COMPLETE | BUFFER_READ_READY);
+        assert_eq!(bus.sd0.raw_read(SDRegisters::BlockCount.base_offset() & 0xffff_fffc) >> 16, 5);
+    }
+
+    #[test]
+    fn block_latency_follows_clock_width_and_block_size() {
+        let mut sd = SDInterface::default();
+        sd.setreg(SDRegisters::BlockSize, 512);
+        sd.setreg(SDRegisters::HostControl, HOST_CONTROL_4BIT);
+        assert_eq!(sd.block_latency(1000), 1000);
+        // Base clock / 4
+        sd.setreg(SDRegisters::ClockControl, 2 << 8 | 0b101);
+        assert_eq!(sd.block_latency(1000), 4000);
+        sd.setreg(SDRegisters::BlockSize, 64);
+        assert_eq!(sd.block_latency(1000), 500);
+        sd.setreg(SDRegisters::HostControl, 0);
+        assert_eq!(sd.block_latency(1000), 2000);
+    }
+
+    #[test]
+    fn runaway_transfer_is_aborted() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.sd_block_latency = 1;
+        bus.sd0.setreg(SDRegisters::ErrorIntStatusEnable, ERROR_INT_DATA_TIMEOUT);
+        bus.sd0.setreg(SDRegisters::ErrorIntSignalEnable, ERROR_INT_DATA_TIMEOUT);
+        bus.sd0.setreg(SDRegisters::BlockSize, 512);
+        bus.sd0.setreg(SDRegisters::BlockCount, 1);
+        bus.sd0.setreg(SDRegisters::HostControl, HOST_CONTROL_4BIT);
+        bus.sd0.card.tx_status = CardTXStatus::MultiReadInProgress;
+        bus.sd0.card.rw_stop = 512;
+        bus.tasks.push(Task { kind: BusTask::SDHC(0, SDHCTask::IOPoll), target_cycle: 0 });
+
+        // The guest never reads the buffer
+        while !bus.tasks.is_empty() {
+            bus.step(0).unwrap();
+        }
+        assert!(bus.cycle >= MAX_IDLE_POLLS as usize);
+        assert!(matches!(bus.sd0.card.tx_status, CardTXStatus::None));
+        let status = bus.sd0.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status >> 16, ERROR_INT_DATA_TIMEOUT);
+    }
+
+    #[test]
+    fn dma_stops_at_the_buffer_boundary() {
+        const DMA_INT: u32 = 1 << 3;
+        const SYSADDR: u32 = 0x0001_0000;
+        let path = std::env::temp_dir().join(format!("ironic-sd-dma-{}.img", std::process::id()));
+        let image: Vec<u8> = (0..0x2000u32).map(|idx| (idx / 512) as u8).collect();
+        std::fs::write(&path, &image).unwrap();
+
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.insert_sd(0, path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bus.sd0.setreg(SDRegisters::NormalIntStatusEnable, DMA_INT);
+        bus.sd0.setreg(SDRegisters::NormalIntSignalEnable, DMA_INT);
+        bus.sd0.setreg(SDRegisters::ErrorIntStatusEnable, ERROR_INT_DATA_TIMEOUT);
+        bus.sd0.setreg(SDRegisters::ErrorIntSignalEnable, ERROR_INT_DATA_TIMEOUT);
+        bus.sd0.setreg(SDRegisters::TxMode, TX_MODE_READ | TX_MODE_BLOCK_COUNT_ENABLE);
+        // A 4 KiB boundary, with more blocks than fit before it
+        bus.sd0.setreg(SDRegisters::BlockSize, 512);
+        bus.sd0.setreg(SDRegisters::BlockCount, 10);
+        bus.sd0.setreg(SDRegisters::SystemAddress, SYSADDR);
+        bus.sd0.card.tx_status = CardTXStatus::DMAReadInProgress;
+        bus.tasks.push(Task { kind: BusTask::SDHC(0, SDHCTask::DoDMARead), target_cycle: 0 });
+        bus.step(0).unwrap();
+
+        // All 8 blocks up to the boundary were moved
+        let status = bus.sd0.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status & DMA_INT, DMA_INT);
+        assert_eq!(status >> 16, 0);
+        assert_eq!(bus.sd0.raw_read(SDRegisters::SystemAddress.base_offset()), SYSADDR + 0x1000);
+        assert_eq!(bus.sd0.blocks_remaining(), 2);
+        let mut buf = [0u8; 0x1000];
+        bus.dma_read(SYSADDR, &mut buf).unwrap();
+        assert_eq!(buf[..], image[..0x1000]);
+    }
+
+    #[test]
+    fn scheduled_removal_and_insertion_raise_interrupts() {
+        const INSERT_INT: u32 = 1 << 6;
+        const REMOVAL_INT: u32 = 1 << 7;
+        let path = std::env::temp_dir().join(format!("ironic-sd-hotplug-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0u8; 0x1000]).unwrap();
+
+        let mut bus = Bus::with_boot0(None).unwrap();
+        bus.insert_sd(0, path.to_str().unwrap()).unwrap();
+        bus.hlwd.irq.arm_irq_enable.set(HollywoodIrq::Sdhc);
+        bus.sd0.setreg(SDRegisters::NormalIntStatusEnable, INSERT_INT | REMOVAL_INT);
+        bus.sd0.setreg(SDRegisters::NormalIntSignalEnable, INSERT_INT | REMOVAL_INT);
+        bus.tasks.push(Task { kind: BusTask::Timeline(TimelineEvent::SdEject { slot: 0 }), target_cycle: 10 });
+        let image = path.to_str().unwrap().to_owned();
+        bus.tasks.push(Task { kind: BusTask::Timeline(TimelineEvent::SdInsert { slot: 0, image }), target_cycle: 20 });
+        let run_until = |bus: &mut Bus, cycle: usize| {
+            while bus.cycle <= cycle {
+                bus.step(0).unwrap();
+            }
+        };
+
+        run_until(&mut bus, 10);
+        assert_eq!(present_state(&bus.sd0) & PRESENT_CARD_INSERTED, 0);
+        let status = bus.sd0.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status & (INSERT_INT | REMOVAL_INT), REMOVAL_INT);
+        assert!(bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::Sdhc));
+
+        // Acknowledge everything before the card comes back
+        bus.sd0.setreg(SDRegisters::NormalIntStatus, 0);
+        bus.hlwd.irq.arm_irq_status.unset(HollywoodIrq::Sdhc);
+        run_until(&mut bus, 20);
+        std::fs::remove_file(&path).unwrap();
+        assert_ne!(present_state(&bus.sd0) & PRESENT_CARD_INSERTED, 0);
+        let status = bus.sd0.raw_read(SDRegisters::NormalIntStatus.base_offset());
+        assert_eq!(status & (INSERT_INT | REMOVAL_INT), INSERT_INT);
+        assert!(bus.hlwd.irq.arm_irq_status.is_set(HollywoodIrq::Sdhc));
+    }
+
+    #[test]
+    fn tasks_for_missing_slots_are_errors() {
+        let mut bus = Bus::with_boot0(None).unwrap();
+        assert!(bus.sd(1).is_ok());
+        assert_eq!(bus.sd(2).err().unwrap().to_string(), "No SD slot 2");
+        assert!(bus.eject_sd(2).is_err());
+        bus.tasks.push(Task { kind: BusTask::SDHC(2, SDHCTask::RaiseInt), target_cycle: 0 });
+        assert!(bus.step(0).is_err());
+
+        // Nothing is inserted in an empty slot
+        let sd = SDInterface::empty(1);
+        assert!(!sd.card_available);
+        assert_eq!(present_state(&sd) & PRESENT_CARD_INSERTED, 0);
+    }
+
+    #[test]
+    fn write_protected_card_refuses_writes() {
+        const WP_VIOLATION: u32 = 1 << 26;
+        let mut sd = SDInterface::default();
+        sd.set_write_protect(true);
+        let write = card::Command::from(25 << 8);
+        assert_eq!(sd.card.issue(write, 0, sd.write_protected), Some(card::Response::Regular(WP_VIOLATION)));
+        assert!(matches!(sd.card.tx_status, CardTXStatus::None));
+
+        sd.set_write_protect(false);
+        let write = card::Command::from(25 << 8);
+        assert!(matches!(sd.card.issue(write, 0, sd.write_protected), Some(card::Response::Regular(r)) if r & WP_VIOLATION == 0));
+        assert!(matches!(sd.card.tx_status, CardTXStatus::MultiWritePending));
+    }
+
+    #[test]
+    fn capabilities_are_read_only() {
+        let mut sd = SDInterface::default();
+        let off = SDRegisters::Capabilities.base_offset();
+        let caps = sd.raw_read(off);
+        assert_ne!(caps, 0);
+
+        assert!(sd.write(off, 0xdead_beef).unwrap().is_none());
+        assert!(matches!(sd.read(off).unwrap(), BusPacket::Word(v) if v == caps));
     }
 }
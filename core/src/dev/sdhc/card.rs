@@ -1,9 +1,13 @@
-use std::{num::NonZeroU16, sync::atomic::AtomicUsize};
-use log::{debug, error};
+use bincode::{Decode, Encode};
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use std::{num::NonZeroU16, sync::atomic::{AtomicUsize, Ordering::Relaxed}};
+use log::{debug, error, warn};
 
 use crate::mem::BigEndianMemory;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
 /// The Transaction State of the emulated SD card.
 /// The SD Interface and Bus Tasks will check and update this as I/O is performed on the card
 pub(super) enum CardTXStatus {
@@ -93,25 +97,66 @@ pub(super) struct Card {
     pub tx_status: CardTXStatus,
 }
 
+/// Everything but the backing memory is encoded, since the card image isn't
+/// part of a save state.
+impl Encode for Card {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.state.encode(encoder)?;
+        self.acmd.encode(encoder)?;
+        self.ocr.encode(encoder)?;
+        self.cid.encode(encoder)?;
+        self.rca.encode(encoder)?;
+        self.csd.encode(encoder)?;
+        self.selected.encode(encoder)?;
+        self.rw_index.load(Relaxed).encode(encoder)?;
+        self.rw_stop.encode(encoder)?;
+        self.tx_status.encode(encoder)
+    }
+}
+
 impl Card {
-    pub(super) fn try_new() -> (Self, bool) {
-        const FILENAME: &str = "sd.img";
-        let mut len = 0usize;
-        let backing_mem: BigEndianMemory;
-        let mut card_inserted = true;
-        if let Ok(f) = std::fs::File::open(FILENAME)
-        && let Ok(metadata) = f.metadata() {
-            len = metadata.len() as usize;
-            backing_mem = BigEndianMemory::new(len, Some(FILENAME), false).unwrap_or_else(|_|{
-                card_inserted = false;
-                BigEndianMemory::new(len, None, false).unwrap()
-            });
+    /// Restore state written by the [Encode] implementation, keeping the
+    /// current backing memory.
+    pub(super) fn decode_state<D: Decoder>(&mut self, decoder: &mut D) -> Result<(), DecodeError> {
+        self.state = Decode::decode(decoder)?;
+        self.acmd = Decode::decode(decoder)?;
+        self.ocr = Decode::decode(decoder)?;
+        self.cid = Decode::decode(decoder)?;
+        self.rca = Decode::decode(decoder)?;
+        let csd: CsdReg = Decode::decode(decoder)?;
+        if csd != self.csd {
+            return Err(DecodeError::Other("The inserted SD card image isn't the same size as the saved one"));
         }
-        else {
-            card_inserted = false;
-            backing_mem = BigEndianMemory::new(len, None, false).unwrap();
+        self.selected = Decode::decode(decoder)?;
+        self.rw_index.store(Decode::decode(decoder)?, Relaxed);
+        self.rw_stop = Decode::decode(decoder)?;
+        self.tx_status = Decode::decode(decoder)?;
+        Ok(())
+    }
+
+    pub(super) fn try_new() -> (Self, bool) {
+        Self::try_open("sd.img")
+    }
+
+    /// Create an empty card, for a slot with nothing inserted.
+    pub(super) fn empty() -> Self {
+        Self::with_memory(BigEndianMemory::new(0, None, None).unwrap(), 0)
+    }
+
+    /// Create a card backed by some image file. Returns false if the image
+    /// couldn't be opened (in which case the card is empty).
+    pub(super) fn try_open(filename: &str) -> (Self, bool) {
+        let Ok(len) = std::fs::metadata(filename).map(|m| m.len() as usize) else {
+            return (Self::empty(), false);
+        };
+        match BigEndianMemory::new(len, Some(filename), None) {
+            Ok(backing_mem) => (Self::with_memory(backing_mem, len), true),
+            Err(_) => (Self::empty(), false),
         }
-        (Self {
+    }
+
+    fn with_memory(backing_mem: BigEndianMemory, len: usize) -> Self {
+        Self {
             state: Default::default(),
             backing_mem: Mutex::new(backing_mem),
             acmd: Default::default(),
@@ -123,15 +168,17 @@ impl Card {
             rw_index: Default::default(),
             rw_stop: Default::default(),
             tx_status: Default::default()
-        }, card_inserted)
+        }
     }
 }
 
 impl Card {
     /// Issue a command to the emulated SD card. Unimplemented commands will terminate the emulator.
-    pub(super) fn issue(&mut self, cmd: Command, argument: u32) -> Option<Response> {
+    /// Write commands are refused while the write-protect switch is set.
+    pub(super) fn issue(&mut self, cmd: Command, argument: u32, write_protected: bool) -> Option<Response> {
         let acmd = std::mem::replace(&mut self.acmd, false);
         match (acmd, cmd.index) {
+            (false, 24 | 25) if write_protected => { return Some(self.wp_violation(cmd.index)); },
             (false, 0) => { return Some(self.cmd0(argument)); },
             (false, 8) => {
                 return Some(self.cmd8(argument));
@@ -233,6 +280,11 @@ impl Card {
         self.tx_status = CardTXStatus::MultiWritePending;
         Response::Regular(response)
     }
+    fn wp_violation(&self, index: u8) -> Response {
+        const WP_VIOLATION: u32 = 1 << 26;
+        warn!(target: "SDHC", "Refused CMD{index}, the card is write-protected");
+        Response::Regular(WP_VIOLATION | (self.state.bits_for_card_status() as u32) << 9)
+    }
     fn acmd6(&mut self, _argument: u32) -> Response {
         // Set bus width command, we aren't emulating individual SD bus cycles, so this is just a stub
         Response::Regular((self.state.bits_for_card_status() as u32) << 9)
@@ -251,7 +303,7 @@ pub(super) enum Response {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
 #[repr(u8)]
 /// Card States as defined in Part 1
 pub(super) enum CardState {
@@ -291,7 +343,7 @@ impl CardState {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
 struct OcrReg(u32);
 
 impl Default for OcrReg {
@@ -301,7 +353,7 @@ impl Default for OcrReg {
 }
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
 /// Operation Condition Register of the emulated SD card.
 /// Mostly does not matter.
 struct CidReg(u128);
@@ -316,7 +368,7 @@ impl Default for CidReg {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
 /// Card Specific Data Register of the emulated SD card.
 /// Defines to the Host Driver what kind of card we are and what we support.
 struct CsdReg(u128);
@@ -12,6 +12,7 @@
 //! messages which aren't a multiple of 64-bytes long, or it always performs 
 //! DMA reads in 64-byte chunks).
 
+use bincode::{Decode, Encode};
 use std::{sync::atomic::{AtomicU8, Ordering::{Acquire, Release, AcqRel}}};
 
 const K: [u32; 4] = [ 0x5a82_7999, 0x6ed9_eba1, 0x8f1b_bcdc, 0xca62_c1d6, ];
@@ -25,6 +26,7 @@ const STATE_STEADY: u8 = 2;
 static FN_PTR_STATE: AtomicU8 = AtomicU8::new(STATE_UNTOUCHED);
 static mut PROCESS_MSG_FN: unsafe fn(&mut Sha1State) = Sha1State::process_message_scalar;
 
+#[derive(Encode, Decode)]
 pub struct Sha1State {
     pub digest: [u32; 5],
     pub buf: [u8; 64],
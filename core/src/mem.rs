@@ -1,5 +1,5 @@
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fmt;
 use std::fs::File;
 use std::io::Read;
@@ -60,9 +60,29 @@ pub struct BigEndianMemory {
     /// write_index
     pub write_index: u8,
     already_wrote: AtomicBool,
+    /// Directory with the patch files for each tracked memory, by hash
+    saved_writes: Option<PathBuf>,
 }
 impl BigEndianMemory {
-    pub fn new(len: usize, init_fn: Option<&str>, track_writes: bool) -> anyhow::Result<Self> {
+    /// Create a zeroed memory backed by an anonymous mapping, so that pages
+    /// are only allocated once they're touched.
+    pub fn new_mapped(len: usize) -> anyhow::Result<Self> {
+        let map = MmapMut::map_anon(len)
+            .with_context(|| format!("Couldn't map {len:#x} bytes of memory"))?;
+        Ok(BigEndianMemory {
+            data: BackingMem::Mapped(map),
+            hash: 0xDEADC0DE,
+            writes: None,
+            write_index: 0,
+            already_wrote: AtomicBool::new(true),
+            saved_writes: None,
+        })
+    }
+
+    /// Create a memory, initialized from a file if there is one. With
+    /// `saved_writes`, writes are tracked and saved as patches under that
+    /// directory, and any patches saved by earlier runs are applied.
+    pub fn new(len: usize, init_fn: Option<&str>, saved_writes: Option<&Path>) -> anyhow::Result<Self> {
         let hash: u32;
         let data = if let Some(filename) = init_fn { unsafe {
             let mut f = File::open(filename)?;
@@ -81,16 +101,17 @@ impl BigEndianMemory {
             hash = 0xDEADC0DE;
             BackingMem::Local(vec![0u8; len])
         };
-        let writes: Option<IntervalMap<usize, Vec<u8>>> = if track_writes {
+        let writes: Option<IntervalMap<usize, Vec<u8>>> = if saved_writes.is_some() {
             debug!(target: "MEMSAVE", "BEMemory: Writes Enabled, hash: {hash}");
             Some(IntervalMap::new())
         }
         else {
             None
         };
-        let mut res = BigEndianMemory { data, hash, writes, write_index: 0, already_wrote: AtomicBool::new(true)};
-        if track_writes {
-            if let Ok((write_index, mpfs)) = BigEndianMemory::get_patchfiles(hash) {
+        let saved_writes = saved_writes.map(Path::to_path_buf);
+        let mut res = BigEndianMemory { data, hash, writes, write_index: 0, already_wrote: AtomicBool::new(true), saved_writes };
+        if let Some(dir) = res.saved_writes.clone() {
+            if let Ok((write_index, mpfs)) = BigEndianMemory::get_patchfiles(&dir, hash) {
                 res.write_index = write_index.checked_add(1).unwrap();
                 for mpf in mpfs {
                     res.patch(mpf)?;
@@ -102,17 +123,18 @@ impl BigEndianMemory {
 
     /// Get the patches to apply persistent writes
     /// Returns the highest numbered patch file, so this time around we can write to n+1
-    fn get_patchfiles(hash: u32) -> anyhow::Result<(u8, Vec<MemoryPatchFile>)> {
-        let dir = match std::fs::read_dir(format!("./saved-writes/{hash}/")) {
+    fn get_patchfiles(saved_writes: &Path, hash: u32) -> anyhow::Result<(u8, Vec<MemoryPatchFile>)> {
+        let path = saved_writes.join(hash.to_string());
+        let dir = match std::fs::read_dir(&path) {
             Ok(dir) => dir,
             Err(err) => {
                 // handle no directory by creating it and trying again
                 match err.raw_os_error() {
                     Some(2) => {
-                        std::fs::create_dir_all(format!("./saved-writes/{hash}/"))?;
-                        std::fs::read_dir(format!("./saved-writes/{hash}/"))?
+                        std::fs::create_dir_all(&path)?;
+                        std::fs::read_dir(&path)?
                     },
-                    Some(_) | None => { return Err(err).context(format!("Failed to open directory ./saved-writes/{hash}/ for get_patchfiles")) }
+                    Some(_) | None => { return Err(err).context(format!("Failed to open directory {} for get_patchfiles", path.display())) }
                 }
             },
         };
@@ -122,19 +144,18 @@ impl BigEndianMemory {
             if let Ok(entry) = maybe_entry {
                 debug!(target: "MEMSAVE", "Candidate entry: {:?}", entry.path());
                 if let Ok(num) = entry.file_name().to_string_lossy().into_owned().parse::<u8>() {
-                    let mpf = MemoryPatchFile::from_file(entry.path());
                     debug!(target: "MEMSAVE", "Found MemoryPatchFile: {num} {:?}", entry.path());
-                    Some((num, mpf))
+                    Some(MemoryPatchFile::from_file(entry.path()).map(|mpf| (num, mpf)))
                 }
                 else {
                     None
                 }
             }
             else {
-                error!(target: "MEMSAVE", "Unable to read ./saved-writes/{hash}/");
+                error!(target: "MEMSAVE", "Unable to read {}", path.display());
                 None
             }
-        }).collect();
+        }).collect::<anyhow::Result<_>>()?;
         // Sort by filename
         pfs.sort_by(|a, b| {
             a.0.cmp(&b.0)
@@ -143,6 +164,23 @@ impl BigEndianMemory {
         Ok((max, pfs.iter().map(|x|x.1.clone()).collect()))
     }
 
+    /// Compare two dumps (e.g. written by [BigEndianMemory::dump]) byte by
+    /// byte, returning each run of differing bytes with its contents in `b`.
+    /// Only the length of the shorter dump is compared.
+    pub fn diff_dumps(a: &[u8], b: &[u8]) -> Vec<MemoryPatch> {
+        let len = a.len().min(b.len());
+        let (a, b) = (&a[..len], &b[..len]);
+        let mut ranges = Vec::new();
+        let mut offset = 0;
+        while let Some(start) = a[offset..].iter().zip(&b[offset..]).position(|(x, y)| x != y) {
+            let start = offset + start;
+            let end = a[start..].iter().zip(&b[start..]).position(|(x, y)| x == y).map_or(len, |n| start + n);
+            ranges.push(MemoryPatch { offset: start, data: b[start..end].to_vec() });
+            offset = end;
+        }
+        ranges
+    }
+
     pub fn dump(&self, filename: &impl AsRef<Path>) -> anyhow::Result<()> {
         let filename = filename.as_ref();
         let mut f = File::create(filename).context(format!("BigEndianMemory: Couldn't create dump file: {}", filename.to_string_lossy()))?;
@@ -165,15 +203,15 @@ impl BigEndianMemory {
     }
 
     pub fn dump_writes(&self) -> anyhow::Result<()> {
-        if self.writes.is_none() {
+        let (Some(writes), Some(saved_writes)) = (self.writes.as_ref(), self.saved_writes.as_ref()) else {
             bail!("dump_writes but writes not enabled!");
-        }
+        };
         if self.already_wrote.load(Relaxed) {
             debug!(target: "MEMSAVE", "dump_writes but already wrote the latest changes!");
             return Ok(());
         }
         self.already_wrote.store(true, Relaxed);
-        let patches: Vec<MemoryPatch> = self.writes.as_ref().unwrap().iter(..).map(|x|{
+        let patches: Vec<MemoryPatch> = writes.iter(..).map(|x|{
             MemoryPatch { offset: x.0.start, data: x.1.clone() }
         }).collect();
         let mut mpf = MemoryPatchFile {
@@ -181,7 +219,7 @@ impl BigEndianMemory {
             ranges: patches,
         };
         mpf.merge_adjacent_ranges();
-        mpf.to_file(format!("./saved-writes/{}/{}", self.hash, self.write_index).into())?;
+        mpf.to_file(saved_writes.join(self.hash.to_string()).join(self.write_index.to_string()))?;
         Ok(())
     }
 }
@@ -192,8 +230,34 @@ impl fmt::Debug for BigEndianMemory {
     }
 }
 
+/// Only the contents are encoded. A decoded memory is always a local copy,
+/// without write tracking.
+impl Encode for BigEndianMemory {
+    fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> Result<(), bincode::error::EncodeError> {
+        self.data.as_slice().encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for BigEndianMemory {
+    fn decode<D: bincode::de::Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+        let data: Vec<u8> = Decode::decode(decoder)?;
+        Ok(BigEndianMemory {
+            data: BackingMem::Local(data),
+            hash: 0xDEADC0DE,
+            writes: None,
+            write_index: 0,
+            already_wrote: AtomicBool::new(true),
+            saved_writes: None,
+        })
+    }
+}
+bincode::impl_borrow_decode!(BigEndianMemory);
+
 /// Generic reads and writes.
 impl BigEndianMemory {
+    /// Size of the memory in bytes.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
     pub fn read<T: AccessWidth>(&self, off: usize) -> anyhow::Result<T> {
         let src_len = mem::size_of::<T>();
         if off + src_len > self.data.len() {
@@ -301,13 +365,30 @@ pub struct MemoryPatchFile {
 }
 
 impl MemoryPatchFile {
-    pub fn from_file(path: std::path::PathBuf) -> Self {
+    pub fn from_file(path: std::path::PathBuf) -> anyhow::Result<Self> {
         use lz4_flex::frame::*;
         let mut bytes: Vec<u8> = Vec::new();
-        FrameDecoder::new(std::fs::File::open(path).unwrap()).read_to_end(&mut bytes).unwrap();
-        let res: MemoryPatchFile = bincode::decode_from_slice(&bytes, config::standard()).unwrap().0;
+        let file = std::fs::File::open(&path).context(format!("Couldn't open patch file {}", path.display()))?;
+        FrameDecoder::new(file).read_to_end(&mut bytes)
+            .context(format!("Couldn't decompress patch file {}", path.display()))?;
+        let res: MemoryPatchFile = bincode::decode_from_slice(&bytes, config::standard())
+            .context(format!("Couldn't decode patch file {}", path.display()))?.0;
         debug!(target: "MEMSAVE", "decoded MemoryPatchFile: hash: {} # of ranges: {}", res.hash, res.ranges.len());
-        res
+        Ok(res)
+    }
+
+    /// Describe the patch file for a person: the hash of the memory it
+    /// applies to, then the offset and length of each range along with a
+    /// CRC32 of its data.
+    pub fn to_report(&self) -> String {
+        let total: usize = self.ranges.iter().map(|r| r.data.len()).sum();
+        let mut report = format!("hash: {} ({:#010x}), {} range(s), {total:#x} bytes\n",
+            self.hash, self.hash, self.ranges.len());
+        for range in &self.ranges {
+            report.push_str(&format!("{:08x} +{:#x} crc32 {:08x}\n",
+                range.offset, range.data.len(), crc32fast::hash(&range.data)));
+        }
+        report
     }
 
     pub fn to_file(&self, path: std::path::PathBuf) -> anyhow::Result<()> {
@@ -333,7 +414,7 @@ impl MemoryPatchFile {
         let mut done = false;
         while !done {
             let mut found = false;
-            for i in 0..(self.ranges.len()-1) {
+            for i in 0..self.ranges.len().saturating_sub(1) {
                 if adjacent(&self.ranges[i], &self.ranges[i+1]) {
                     found = true;
                     let extend = std::mem::take(&mut self.ranges[i+1].data);
@@ -346,3 +427,40 @@ impl MemoryPatchFile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_dumps_reports_exact_byte_ranges() {
+        let a = vec![0u8; 0x40];
+        let mut b = a.clone();
+        b[0x05] = 1;
+        b[0x07..0x10].fill(2);
+        b[0x3f] = 3;
+        let diff = BigEndianMemory::diff_dumps(&a, &b);
+        let ranges: Vec<_> = diff.iter().map(|p| (p.offset, p.data.len())).collect();
+        assert_eq!(ranges, [(0x05, 1), (0x07, 9), (0x3f, 1)]);
+        assert_eq!(diff[1].data, [2; 9]);
+        assert!(BigEndianMemory::diff_dumps(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn patch_file_report_lists_each_range() {
+        let mpf = MemoryPatchFile {
+            hash: 0x1234,
+            ranges: vec![
+                MemoryPatch { offset: 0x10, data: b"1234".to_vec() },
+                MemoryPatch { offset: 0x200, data: vec![0; 0x20] },
+            ],
+        };
+        let path = std::env::temp_dir().join(format!("ironic-patch-{}.bin", std::process::id()));
+        mpf.to_file(path.clone()).unwrap();
+        let report = MemoryPatchFile::from_file(path.clone()).unwrap().to_report();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(report, "hash: 4660 (0x00001234), 2 range(s), 0x24 bytes\n\
+            00000010 +0x4 crc32 9be3e0a3\n\
+            00000200 +0x20 crc32 190a55ad\n");
+    }
+}
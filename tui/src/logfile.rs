@@ -0,0 +1,123 @@
+//! Log file output with size-based rotation.
+//!
+//! [RotatingFile] appends to a log file. When a limit is set and a write
+//! would take the file past it, the file is renamed to `<path>.1` (moving
+//! `<path>.1` to `<path>.2` and so on, dropping the oldest) and a new file is
+//! started. Records are never split between files: writes are buffered until
+//! the next flush (which the logger does after every record).
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// A log file which is rotated once it grows past some size.
+pub struct RotatingFile {
+    path: PathBuf,
+    /// Rotate before a write would take the file past this many bytes.
+    max_size: Option<u64>,
+    /// Number of rotated files to keep.
+    keep: usize,
+    file: File,
+    size: u64,
+    /// The record being written.
+    pending: Vec<u8>,
+}
+impl RotatingFile {
+    /// Create (or truncate) a log file, rotating it at `max_size` bytes if
+    /// set and keeping up to `keep` old files.
+    pub fn create(path: &Path, max_size: Option<u64>, keep: usize) -> anyhow::Result<Self> {
+        let file = Self::open(path)?;
+        Ok(Self { path: path.to_owned(), max_size, keep, file, size: 0, pending: Vec::new() })
+    }
+
+    fn open(path: &Path) -> anyhow::Result<File> {
+        OpenOptions::new().write(true).create(true).truncate(true).open(path)
+            .with_context(|| format!("Couldn't create log file {}", path.display()))
+    }
+
+    /// The name of some rotated file (where 1 is the newest).
+    fn rotated_path(&self, idx: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{idx}"));
+        PathBuf::from(name)
+    }
+
+    /// Move each file along by one, and start a new one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for idx in (1..self.keep).rev() {
+                let from = self.rotated_path(idx);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(idx + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = File::create(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        let len = self.pending.len() as u64;
+        // Don't rotate an empty file, or a single record bigger than the
+        // limit would never be written
+        if let Some(max_size) = self.max_size
+            && self.size > 0 && self.size + len > max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(&self.pending)?;
+        self.pending.clear();
+        self.size += len;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_past_the_size_limit() {
+        let dir = std::env::temp_dir().join(format!("ironic-logfile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ironic.log");
+        let mut log = RotatingFile::create(&path, Some(16), 2).unwrap();
+        for line in ["aaaaaaaaa\n", "bbbbbbbbb\n", "ccccccccc\n", "ddddddddd\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+            log.flush().unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("ironic.log"), "ddddddddd\n");
+        assert_eq!(read("ironic.log.1"), "ccccccccc\n");
+        assert_eq!(read("ironic.log.2"), "bbbbbbbbb\n");
+        // Only two old files are kept
+        assert!(!dir.join("ironic.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn without_a_limit_everything_goes_to_one_file() {
+        let path = std::env::temp_dir().join(format!("ironic-logfile-{}.log", std::process::id()));
+        let mut log = RotatingFile::create(&path, None, 2).unwrap();
+        for _ in 0..100 {
+            log.write_all(b"0123456789\n").unwrap();
+            log.flush().unwrap();
+        }
+        log.flush().unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(len, 1100);
+    }
+}
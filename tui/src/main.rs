@@ -1,19 +1,34 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
+mod logfile;
+mod ratelimit;
+
 use addr2line::Context;
 use gimli::BigEndian;
 use gimli::EndianSlice;
 use ironic_core::bus::*;
+use ironic_core::bus::prim::AccessLatency;
+use ironic_core::bus::watch::Watchpoint;
+use ironic_core::dev::hlwd::compat::exi::rtc::RtcClock;
+use ironic_core::dev::hlwd::gpio::seeprom::SeepromState;
+use ironic_core::dev::hlwd::otp::OtpInterface;
+use ironic_core::dev::nand::NAND_SIZE;
+use ironic_core::mem::{BigEndianMemory, MemoryPatchFile};
 use ironic_backend::interp::*;
 use ironic_backend::back::*;
 use ironic_backend::ppc::*;
+use ironic_backend::perf::PerfReporter;
+use ironic_backend::symbols::SymbolMap;
+use ironic_backend::trace::{json_string, TraceExporter, TraceFormat};
 use log::info;
 use log::{debug, error};
 use strum::VariantNames;
 use parking_lot::RwLock;
 
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::Builder;
 use std::time::Duration;
 
@@ -30,86 +45,457 @@ struct Args {
     /// Path to a custom kernel ELF
     #[clap(short, long)]
     custom_kernel: Option<String>,
+    /// Validation rules for the custom kernel: ios (entry at the reset
+    /// vector), boot2 (EXEC or DYN, any loaded entry point) or raw (any ARM ELF)
+    #[clap(long, default_value = "ios")]
+    kernel_profile: KernelProfile,
+    /// Start from a decrypted boot1 image, loaded into SRAM, instead of
+    /// running boot0
+    #[clap(long, conflicts_with_all = ["custom_kernel", "boot2"])]
+    boot1: Option<String>,
+    /// Start from a decrypted boot2 image, loaded into MEM2, instead of
+    /// running boot0 and boot1
+    #[clap(long, conflicts_with = "custom_kernel")]
+    boot2: Option<String>,
+
+    /// NAND flash dump to boot from. The dump itself is never modified;
+    /// writes are saved as patches under ./saved-writes instead.
+    #[clap(long, default_value = "./nand.bin")]
+    nand: String,
+
+    /// Size of MEM1 in bytes, in hex (e.g. `--mem1-size 0x1800000`)
+    #[clap(long, value_parser = parse_hex_u32, default_value = "0x1800000")]
+    mem1_size: u32,
+    /// Size of MEM2 in bytes, in hex (e.g. `--mem2-size 0x8000000` for the
+    /// 128MiB of a development console)
+    #[clap(long, value_parser = parse_hex_u32, default_value = "0x4000000")]
+    mem2_size: u32,
+    /// Back MEM1 and MEM2 with anonymous mappings, so that memory which the
+    /// guest never touches isn't allocated
+    #[clap(long)]
+    mmap_mem: bool,
+    /// Bus cycles for the SD host controller to move a 512-byte block on a
+    /// 4-bit bus at full SD clock; the guest's clock and block size scale this
+    #[clap(long, default_value_t = 1000)]
+    sd_block_latency: usize,
+
+    /// SEEPROM image to use instead of ./seeprom.bin (which is optional;
+    /// without either, the SEEPROM reads as zeroes)
+    #[clap(long)]
+    seeprom: Option<String>,
+    /// OTP image to use instead of ./otp.bin (which is optional; without
+    /// either, the OTP reads as zeroes)
+    #[clap(long)]
+    otp: Option<String>,
+    /// Let the guest program OTP bits. With --otp, the programmed words are
+    /// also written back to that image.
+    #[clap(long)]
+    otp_writable: bool,
+    /// Hold NAND, AES and SHA in reset according to the (undocumented)
+    /// reset_ahb register. The bit layout is guessed, so this is off by
+    /// default and writes to the register are only logged
+    #[clap(long)]
+    model_ahb_resets: bool,
+    /// Set the write-protect switch on the SD card in the first slot
+    /// (./sd.img), so that the card refuses writes. Cards inserted into the
+    /// second slot are never write-protected.
+    #[clap(long)]
+    sd_readonly: bool,
+    /// Clock source for the RTC: cycles (counting from 2000-01-01 with
+    /// emulated time, so runs are reproducible), or host (the wall clock)
+    #[clap(long, default_value = "cycles")]
+    rtc_clock: RtcClock,
+
     /// Enable the PPC HLE server (default = False)
     #[clap(short, long)]
     ppc_hle: bool,
+    /// How the PPC-side client connects to the PPC HLE server: unix (a
+    /// socket named ironic-ppc.sock in the temporary directory) or
+    /// tcp:<addr:port>. Clients aren't authenticated and can read and write
+    /// emulated memory, so bind TCP to a loopback address
+    #[clap(long, default_value = "unix")]
+    ppc_transport: PpcTransport,
     /// Define log levels for the program
     #[clap(long, default_value="info")]
     logging: String,
+    /// Only log errors (shorthand for `--logging error`)
+    #[clap(short, long, conflicts_with_all = ["logging", "verbose"])]
+    quiet: bool,
+    /// Increase log verbosity from the default of info: -v (debug), -vv (trace)
+    #[clap(short, long, action = clap::ArgAction::Count, conflicts_with = "logging")]
+    verbose: u8,
+    /// Log output format: human (colored text), or json (one object per line
+    /// with a sequence number, target, level and message)
+    #[clap(long, value_enum, default_value_t = LogFormat::Human)]
+    log_format: LogFormat,
+    /// Also write the log to this file (without colors)
+    #[clap(long)]
+    log_file: Option<PathBuf>,
+    /// Rotate the log file once it would grow past this many bytes,
+    /// renaming it to <file>.1 (and <file>.1 to <file>.2, and so on)
+    #[clap(long, requires = "log_file")]
+    log_rotate_size: Option<u64>,
+    /// Number of rotated log files to keep
+    #[clap(long, default_value_t = 2, requires = "log_rotate_size")]
+    log_rotate_keep: usize,
+    /// Don't log to stdout, only to the --log-file
+    #[clap(long, requires = "log_file")]
+    no_stdout_log: bool,
+    /// Limit a log target to some number of messages per second, summarizing
+    /// the rest (e.g. `--log-rate sdhc:1000`). May be repeated.
+    #[clap(long, value_parser = parse_log_rate)]
+    log_rate: Vec<(LogTarget, u32)>,
+    /// Add artificial latency to accesses on a physical address range
+    /// (e.g. `--access-latency 0x0d070000-0x0d0701ff=8`). May be repeated.
+    #[clap(long)]
+    access_latency: Vec<AccessLatency>,
+    /// Stop emulation when a physical address range is read (r), written
+    /// (w) or either (rw), by the CPU or by DMA, and report the PC (e.g.
+    /// `--watch 0x0d800000-0x0d80000f=w`). May be repeated.
+    #[clap(long)]
+    watch: Vec<Watchpoint>,
+    /// Also write a machine-readable crash.json when the emulator crashes
+    #[clap(long)]
+    crash_json: bool,
+    /// Dump device registers to the `regs` directory on crash or exit
+    #[clap(long)]
+    dump_regs: bool,
+    /// Write memory dumps, crash.json and `regs` here instead of the current
+    /// directory. Their names include the PID, so that several instances can
+    /// share a directory.
+    #[clap(long)]
+    dump_dir: Option<PathBuf>,
+
+    /// Write a textual dump of the Hollywood registers to hlwd.txt on exit
+    #[clap(long)]
+    dump_hlwd: bool,
+
+    /// Dump the Hollywood registers to hlwd_<pc>_<n>.txt each time the
+    /// instruction at this PC retires (e.g. `--dump-hlwd-at 0xffff0100`).
+    /// May be repeated.
+    #[clap(long, value_parser = parse_hex_u32)]
+    dump_hlwd_at: Vec<u32>,
+
+    /// Replay a timeline of input events (GPIO inputs, IRQs, SD card
+    /// insertion/removal) at fixed bus cycles. See `bus::timeline`.
+    #[clap(long)]
+    timeline: Option<String>,
+
+    /// Drive ARM GPIO input pins from a script of `<cycle>,<pin>,<value>`
+    /// lines (e.g. pin 0 to press the power button). See `bus::timeline`.
+    #[clap(long)]
+    gpio_script: Option<String>,
+
+    /// Skip over simple countdown delay loops instead of interpreting them
+    #[clap(long)]
+    fast_forward_loops: bool,
+
+    /// Stop emulation (and dump memory as usual) after this many CPU cycles
+    #[clap(long)]
+    max_cycles: Option<usize>,
+    /// Stop emulation (and dump memory as usual) on entering a boot stage:
+    /// boot1, boot2-stub, boot2, ios-kernel, user-kernel-stub or user-kernel
+    #[clap(long)]
+    stop_at_stage: Option<BootStatus>,
+
+    /// Stop emulation when an instruction fails to decode, instead of taking
+    /// the undefined instruction exception
+    #[clap(long)]
+    halt_on_undef: bool,
+
+    /// Execute the ARMv6 ldrex/strex/clrex instructions, which are undefined
+    /// on the real (ARMv5) Starlet
+    #[clap(long)]
+    armv6_exclusives: bool,
+
+    /// Log the emulation speed (instructions and bus cycles per second) once
+    /// a second
+    #[clap(long)]
+    report_perf: bool,
+
+    /// Resume from a save state written by --save-state-on-exit. The same
+    /// NAND and SD card images need to be used.
+    #[clap(long)]
+    load_state: Option<PathBuf>,
+    /// Write a save state to this file when emulation stops
+    #[clap(long)]
+    save_state_on_exit: Option<PathBuf>,
+
+    /// Wait for GDB to connect on this port before starting, and let it
+    /// control the ARM core (`target remote :<port>`). `monitor tasks` lists
+    /// the tasks pending on the bus.
+    #[clap(long)]
+    gdb_stub: Option<u16>,
+
+    /// Write the guest's semihosting output to this file (without any log
+    /// formatting) instead of logging it
+    #[clap(long)]
+    svc_output: Option<String>,
+
+    /// Annotate disassembled branch targets and literal loads with symbols
+    /// from this file (one `address name [size]` per line). Symbols from a
+    /// custom kernel's ELF symbol table are used as well.
+    #[clap(long)]
+    symbol_map: Option<PathBuf>,
+
+    /// Write one line per retired instruction to this file, with the PC,
+    /// opcode, disassembly, and register file
+    #[clap(long, conflicts_with = "trace_json")]
+    trace: Option<String>,
+    /// Export an execution trace to this file as newline-delimited JSON
+    /// (one record per instruction, with the registers it changed)
+    #[clap(long)]
+    trace_json: Option<String>,
+
+    /// Print the valid logging subsystems for --logging and exit
+    #[clap(long)]
+    list_log_targets: bool,
+    /// Print the physical memory map and exit
+    #[clap(long)]
+    dump_map: bool,
+    /// Construct the bus, load all images and the custom kernel, report any
+    /// problems, then exit without running the CPU
+    #[clap(long)]
+    dry_run: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Write a tiny test image which prints "hello" over semihosting and
+    /// exits. Run it with `--custom-kernel <path> --kernel-profile raw`.
+    GenTestRom {
+        path: String,
+    },
+    /// Decode a raw CPSR/SPSR value (e.g. from a register dump) and exit
+    Psr {
+        #[clap(value_parser = parse_hex_u32)]
+        value: u32,
+    },
+    /// Disassemble a flat big-endian binary (e.g. a chunk of an IOS module)
+    /// and exit. Branch targets are annotated with --symbol-map, if given.
+    Disasm {
+        /// Instruction set of the code
+        #[clap(long, value_enum, default_value_t = DisasmArch::Arm)]
+        arch: DisasmArch,
+        /// Address of the first byte of the file, in hex
+        #[clap(long, value_parser = parse_hex_u32, default_value = "0")]
+        base: u32,
+        path: PathBuf,
+    },
+    /// Compare two memory dumps (e.g. mem1.bin from two runs) and list the
+    /// ranges of bytes which differ, then exit
+    Diff {
+        /// Address of the first byte of the dumps, in hex
+        #[clap(long, value_parser = parse_hex_u32, default_value = "0")]
+        base: u32,
+        a: PathBuf,
+        b: PathBuf,
+    },
+    /// List the ranges in a persistent-writes patch file (from
+    /// ./saved-writes/<hash>/), then exit
+    InspectWrites {
+        path: PathBuf,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    Human,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum DisasmArch {
+    Arm,
+    Thumb,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    handle_logging_argument(args.logging)?;
+    if args.list_log_targets {
+        for target in LogTarget::VARIANTS {
+            println!("{target}");
+        }
+        return Ok(());
+    }
+    if let Some(Command::GenTestRom { ref path }) = args.command {
+        std::fs::write(path, ironic_backend::testrom::build_test_rom())?;
+        println!("Wrote test image to {path}");
+        return Ok(());
+    }
+    if let Some(Command::Psr { value }) = args.command {
+        println!("{}", ironic_core::cpu::psr::Psr(value).describe());
+        return Ok(());
+    }
+    if let Some(Command::Disasm { arch, base, ref path }) = args.command {
+        let data = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Couldn't read {}: {e}", path.display()))?;
+        let symbols = args.symbol_map.as_deref().map(SymbolMap::load).transpose()?;
+        let lines = ironic_backend::bits::disassembly::disassemble_listing(
+            &data, base, arch == DisasmArch::Thumb, symbols.as_ref());
+        for line in lines {
+            println!("{line}");
+        }
+        return Ok(());
+    }
+    if let Some(Command::Diff { base, ref a, ref b }) = args.command {
+        let read = |path: &Path| std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Couldn't read {}: {e}", path.display()));
+        let (a_data, b_data) = (read(a)?, read(b)?);
+        if a_data.len() != b_data.len() {
+            println!("Sizes differ ({:#x} and {:#x} bytes), only comparing the first {:#x}",
+                a_data.len(), b_data.len(), a_data.len().min(b_data.len()));
+        }
+        let diff = BigEndianMemory::diff_dumps(&a_data, &b_data);
+        for range in &diff {
+            // Show the first few bytes of each side
+            let shown = range.data.len().min(8);
+            let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ");
+            let more = if range.data.len() > shown { " ..." } else { "" };
+            println!("{:08x} +{:#x}: {}{more} -> {}{more}",
+                base as usize + range.offset, range.data.len(),
+                hex(&a_data[range.offset..range.offset + shown]), hex(&range.data[..shown]));
+        }
+        println!("{} differing range(s), {:#x} bytes", diff.len(), diff.iter().map(|r| r.data.len()).sum::<usize>());
+        return Ok(());
+    }
+    if let Some(Command::InspectWrites { ref path }) = args.command {
+        print!("{}", MemoryPatchFile::from_file(path.clone())?.to_report());
+        return Ok(());
+    }
+    if args.dump_map {
+        bus_config(&args).validate()?;
+        for (name, base, tail) in ironic_core::dev::memory_map(args.mem1_size, args.mem2_size) {
+            println!("{base:08x}-{tail:08x} {name}");
+        }
+        return Ok(());
+    }
+    let logging = log_level(&args);
+    let log_file = args.log_file.as_deref()
+        .map(|path| logfile::RotatingFile::create(path, args.log_rotate_size, args.log_rotate_keep))
+        .transpose()?;
+    let output = LogOutput { format: args.log_format, stdout: !args.no_stdout_log, file: log_file };
+    handle_logging_argument(logging, output, &args.log_rate)?;
+    if args.dry_run {
+        let problems = dry_run(&args);
+        if problems.is_empty() {
+            println!("OK");
+            return Ok(());
+        }
+        for problem in problems {
+            eprintln!("{problem}");
+        }
+        process::exit(1);
+    }
     let custom_kernel = args.custom_kernel.clone();
     let enable_ppc_hle = args.ppc_hle;
+    let ppc_transport = args.ppc_transport.clone();
+    let fast_forward_loops = args.fast_forward_loops;
+    let max_cycles = args.max_cycles;
+    let stop_at_stage = args.stop_at_stage;
+    let halt_on_undef = args.halt_on_undef;
+    let armv6_exclusives = args.armv6_exclusives;
+    let report_perf = args.report_perf;
+    let load_state = args.load_state.clone();
+    let save_state_on_exit = args.save_state_on_exit.clone();
+    let kernel_profile = args.kernel_profile;
+    let boot1 = args.boot1.clone();
+    let boot2 = args.boot2.clone();
+    let dump_hlwd_at = args.dump_hlwd_at.clone();
+    let gdb_stub = args.gdb_stub;
+    let svc_sink = match args.svc_output.as_deref().map(svc_file_sink).transpose() {
+        Ok(sink) => sink,
+        Err(reason) => {
+            println!("Failed to open semihosting output file: {reason}");
+            process::exit(-1);
+        }
+    };
+
+    let trace = match (&args.trace, &args.trace_json) {
+        (Some(path), _) => Some((path, TraceFormat::Text)),
+        (_, Some(path)) => Some((path, TraceFormat::Json)),
+        (None, None) => None,
+    };
+    let trace = match trace.map(|(path, format)| TraceExporter::create(path, format)).transpose() {
+        Ok(trace) => trace,
+        Err(reason) => {
+            println!("Failed to create execution trace file: {reason}");
+            process::exit(-1);
+        }
+    };
+
+    let symbols = match args.symbol_map.as_deref().map(SymbolMap::load).transpose() {
+        Ok(symbols) => symbols,
+        Err(reason) => {
+            println!("Failed to load symbol map: {reason}");
+            process::exit(-1);
+        }
+    };
 
     // The bus is shared between any threads we spin up
-    let bus = match Bus::new() {
+    let mut bus = match build_bus(&args) {
         Ok(val) => val,
         Err(reason) => {
             println!("Failed to construct emulator Bus: {reason}");
             process::exit(-1);
         }
     };
+    // Only crash.json reports the register file
+    bus.debuginfo.track_regs = args.crash_json;
 
     let bus = Arc::new(RwLock::new(bus));
 
+    // Without PPC HLE or a debugger, the emulator thread is the only one
+    // using the bus, and it can keep the bus locked while it runs
+    let single_threaded = !enable_ppc_hle && gdb_stub.is_none();
+
     // Setup panic hook
-    // We try to avoid panics inside the emulator, but it can happen so try to dump guest memory.
-    let panic_bus = bus.clone();
+    // We try to avoid panics inside the emulator, but it can happen, so the
+    // emulator thread tries to dump guest memory once it has unwound (see
+    // crash_dump). The hook just keeps the message for the report.
+    let crash = CrashDump {
+        bus: bus.clone(),
+        crash_json: args.crash_json,
+        dump_regs: args.dump_regs,
+        dump_dir: args.dump_dir.clone(),
+        message: Arc::new(parking_lot::Mutex::new(None)),
+    };
+    let dump_regs = args.dump_regs;
+    let panic_message = crash.message.clone();
     let orig_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info|{
-        'attempt_fancy_crashdump: {
-            // We only care if the emulator thread crashes, so check the thread name and see whodunnit
-            let thread = std::thread::current();
-            if thread.name() == Some("EmuThread") {
-                let bus = match panic_bus.try_read_for(Duration::new(3, 0)) {
-                    Some(b) => b,
-                    None => {
-                        println!("Failed to get the Bus lock in time, it's stuck!");
-                        println!("Unable to procede with a crash dump");
-                        break 'attempt_fancy_crashdump;
-                    },
-                };
-                // Dump emulator memory.
-                println!("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@");
-                match bus.dump_memory("crash.bin") {
-                    Ok(p) => println!("Emulator crashed! Dumped RAM to {}/*.crash.bin", p.to_string_lossy()),
-                    Err(e) => println!("Emulator crashed! Failed to dump RAM: {e}"),
-                }
-                println!("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@");
-                match bus.nand.data.dump_writes() {
-                    Ok(_) => println!("NAND WRITES DUMPED TO {}", bus.nand.data.write_index),
-                    Err(e) => println!("FAILED TO DUMP NAND WRITE DATA: {e}"),
-                }
-                // Attempt a debuginfo enhanced crashdump.
-                if bus.debuginfo.debuginfo.is_none() {
-                    println!("Debug location never saved to bus, can not continue crashdump");
-                    break 'attempt_fancy_crashdump;
-                }
-                let pc = bus.debuginfo.last_pc.unwrap();
-                let lr = bus.debuginfo.last_lr.unwrap();
-                let _sp = bus.debuginfo.last_sp.unwrap();
-                if let Some(ref debuginfo) = bus.debuginfo.debuginfo {
-                    let debuginfo_b = debuginfo.borrow(|section|{
-                        EndianSlice::new(section, BigEndian)
-                    });
-                    match addr2line::Context::from_dwarf(debuginfo_b) {
-                        Ok(addr2line_ctx) => {
-                            let _ = enhanced_crashdump(addr2line_ctx, pc, lr);
-                        },
-                        Err(err) => println!("Failed to initialize addr2line, cannot procede with crashdump! {err}"),
-                    }
-                }
-            }
+        // We only care if the emulator thread crashes, so check the thread name and see whodunnit
+        if std::thread::current().name() == Some("EmuThread") {
+            *panic_message.lock() = Some(panic_info.to_string());
         }
         orig_hook(panic_info);
     }));
 
     // Setup Ctrl-C handler
+    // The first Ctrl-C asks the emulator thread to stop, so that we get the
+    // usual dumps on the way out. If it's stuck, a second one bails out.
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    let emu_done = Arc::new(AtomicBool::new(false));
+    let ctrl_c_stop = stop_requested.clone();
+    let ctrl_c_emu_done = emu_done.clone();
     let ctrl_c_bus = bus.clone();
     ctrlc::set_handler(move ||{
+        if !ctrl_c_stop.swap(true, Ordering::Relaxed) {
+            println!("Stopping emulation, press Ctrl-C again if it's stuck");
+            return;
+        }
+        // The emulator thread may still be flushing the trace or writing the
+        // save state, so give it a chance to finish before bailing out
+        for _ in 0..500 {
+            if ctrl_c_emu_done.load(Ordering::Relaxed) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        println!("Emulator thread didn't stop, quitting immediately");
         debug!(target: "MEMSAVE", "BEMemory Ctrl-C handler. Good luck!");
         let bus = match ctrl_c_bus.try_read_for(Duration::new(5, 0)) {
             Some(b) => b,
@@ -128,33 +514,98 @@ fn main() -> anyhow::Result<()> {
         std::process::exit(0);
     }).unwrap();
 
+    // Fork off the GDB stub thread
+    let debug_port = gdb_stub.map(|port| {
+        let (debug_port, link) = ironic_backend::gdb::debug_channel();
+        let _ = Builder::new().name("GdbThread".to_owned()).spawn(move || {
+            if let Err(reason) = ironic_backend::gdb::GdbStub::new(link).serve(port) {
+                println!("GDB stub returned an Err: {reason}");
+            }
+        }).unwrap();
+        debug_port
+    });
+
     // Fork off the backend thread
     let emu_bus = bus.clone();
+    let emu_stop_requested = stop_requested.clone();
     let ppc_early_on = custom_kernel.is_some() && enable_ppc_hle;
     let emu_thread = Builder::new().name("EmuThread".to_owned()).spawn(move || {
-        let mut back = InterpBackend::new(emu_bus, custom_kernel, ppc_early_on);
-        if let Err(reason) = back.run() {
-            println!("InterpBackend returned an Err: {reason}");
-        };
+        // Unwinding drops the backend, which also unlocks the bus if it was
+        // held, so the crash dump can get at it afterwards
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let mut back = InterpBackend::new(emu_bus, custom_kernel, ppc_early_on);
+            back.fast_forward_loops = fast_forward_loops;
+            back.max_cycles = max_cycles;
+            back.stop_at_stage = stop_at_stage;
+            back.halt_on_undef = halt_on_undef;
+            back.cpu.exclusives = armv6_exclusives;
+            if report_perf {
+                back.perf = Some(PerfReporter::new(Duration::from_secs(1)));
+            }
+            back.load_state_from = load_state;
+            back.save_state_on_exit = save_state_on_exit;
+            back.single_threaded = single_threaded;
+            back.kernel_profile = kernel_profile;
+            back.boot1 = boot1;
+            back.boot2 = boot2;
+            back.debug_port = debug_port;
+            back.svc_sink = svc_sink;
+            back.trace = trace;
+            back.symbols = symbols;
+            back.stop_requested = emu_stop_requested;
+            for pc in dump_hlwd_at {
+                let mut count = 0;
+                back.add_pc_hook(pc, Box::new(move |cpu| {
+                    count += 1;
+                    let path = format!("hlwd_{pc:08x}_{count}.txt");
+                    match std::fs::write(&path, cpu.with_bus(|bus| bus.hlwd.dump_regs())) {
+                        Ok(_) => info!(target: "Other", "Dumped Hollywood registers to {path}"),
+                        Err(e) => error!(target: "Other", "Failed to write {path}: {e}"),
+                    }
+                }));
+            }
+            if let Err(reason) = back.run() {
+                println!("InterpBackend returned an Err: {reason}");
+            };
+            back.exit_code.unwrap_or(0)
+        }));
+        let res = res.unwrap_or_else(|payload| {
+            crash.dump();
+            emu_done.store(true, Ordering::Relaxed);
+            std::panic::resume_unwind(payload)
+        });
+        emu_done.store(true, Ordering::Relaxed);
+        res
     }).unwrap();
 
     // Fork off the PPC HLE thread
-    if enable_ppc_hle {
+    let ppc_stop_requested = Arc::new(AtomicBool::new(false));
+    let ppc_thread = enable_ppc_hle.then(|| {
         let ppc_bus = bus.clone();
-        let _ = Some(Builder::new().name("IpcThread".to_owned()).spawn(move || {
+        let ppc_stop_requested = ppc_stop_requested.clone();
+        Builder::new().name("IpcThread".to_owned()).spawn(move || {
             let mut back = PpcBackend::new(ppc_bus);
+            back.stop_requested = ppc_stop_requested;
+            back.transport = ppc_transport;
             if let Err(reason) = back.run(){
                 println!("PPC Backend returned an Err: {reason}");
             };
-        }).unwrap());
-    }
+        }).unwrap()
+    });
+
+    let exit_code = emu_thread.join().unwrap_or(0);
 
-    let _ = emu_thread.join();
+    // Nothing is left to answer the PPC side, so stop serving the socket
+    if let Some(ppc_thread) = ppc_thread {
+        ppc_stop_requested.store(true, Ordering::Relaxed);
+        let _ = ppc_thread.join();
+    }
 
     let bus_ref = bus.read();
-    match bus_ref.dump_memory("bin") {
+    let (dump_dir, suffix) = dump_location(args.dump_dir.as_deref(), "bin");
+    match bus_ref.dump_memory_to(&dump_dir, &suffix) {
         Ok(path) => {
-            debug!(target: "Other", "Dumped ram to {}/*.bin", path.to_string_lossy())
+            debug!(target: "Other", "Dumped ram to {}/*.{suffix}", path.to_string_lossy())
         }
         Err(e) => {
             error!(target: "Other", "Failed to dump ram: {e:?}");
@@ -164,9 +615,237 @@ fn main() -> anyhow::Result<()> {
         Ok(_) => info!(target: "MEMSAVE", "NAND writes saved sucessfully"),
         Err(e) => error!(target: "MEMSAVE", "NAND writes failed to save {e}"),
     }
+    if args.dump_hlwd {
+        match std::fs::write("hlwd.txt", bus_ref.hlwd.dump_regs()) {
+            Ok(_) => debug!(target: "Other", "Dumped Hollywood registers to hlwd.txt"),
+            Err(e) => error!(target: "Other", "Failed to write hlwd.txt: {e:?}"),
+        }
+    }
+    if dump_regs {
+        let regs_dir = dump_path(args.dump_dir.as_deref(), "regs");
+        match bus_ref.dump_device_regs(&regs_dir) {
+            Ok(_) => debug!(target: "Other", "Dumped device registers to {}", regs_dir.display()),
+            Err(e) => error!(target: "Other", "Failed to dump device registers: {e:?}"),
+        }
+    }
     println!("Bus cycles elapsed: {}", bus_ref.cycle);
-    process::exit(0);
+    if stop_requested.load(Ordering::Relaxed) {
+        let last_pc = bus_ref.debuginfo.last_pc.map_or("unknown".to_owned(), |pc| format!("{pc:08x}"));
+        println!("Interrupted by Ctrl-C, last pc={last_pc}");
+    }
+    // Report anything the log rate limits are still holding back
+    log::logger().flush();
+    process::exit(exit_code);
+
+}
+
+/// What to dump when the emulator thread panics.
+struct CrashDump {
+    bus: Arc<RwLock<Bus>>,
+    crash_json: bool,
+    dump_regs: bool,
+    dump_dir: Option<PathBuf>,
+    /// The panic message, kept by the panic hook.
+    message: Arc<parking_lot::Mutex<Option<String>>>,
+}
+impl CrashDump {
+    /// Dump guest memory and whatever else was asked for after the emulator
+    /// thread panicked. This runs once the thread has unwound, so that the
+    /// backend (and any bus lock it held) has been dropped.
+    fn dump(&self) {
+        let message = self.message.lock().take().unwrap_or_default();
+        let message = message.as_str();
+        let bus = match self.bus.try_read_for(Duration::new(3, 0)) {
+            Some(b) => b,
+            None => {
+                println!("Failed to get the Bus lock in time, it's stuck!");
+                println!("Unable to procede with a crash dump");
+                return;
+            },
+        };
+        // Dump emulator memory.
+        println!("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@");
+        let (dir, suffix) = dump_location(self.dump_dir.as_deref(), "crash.bin");
+        let ram_dump = match bus.dump_memory_to(&dir, &suffix) {
+            Ok(p) => {
+                println!("Emulator crashed! Dumped RAM to {}/*.{suffix}", p.to_string_lossy());
+                Some(p)
+            },
+            Err(e) => {
+                println!("Emulator crashed! Failed to dump RAM: {e}");
+                None
+            },
+        };
+        println!("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@");
+        match bus.nand.data.dump_writes() {
+            Ok(_) => println!("NAND WRITES DUMPED TO {}", bus.nand.data.write_index),
+            Err(e) => println!("FAILED TO DUMP NAND WRITE DATA: {e}"),
+        }
+        if self.dump_regs {
+            let regs_dir = dump_path(self.dump_dir.as_deref(), "regs");
+            match bus.dump_device_regs(&regs_dir) {
+                Ok(_) => println!("Dumped device registers to {}", regs_dir.display()),
+                Err(e) => println!("Failed to dump device registers: {e}"),
+            }
+        }
+        // Attempt a debuginfo enhanced crashdump, falling back to
+        // the symbol table for anything DWARF doesn't cover.
+        let mut locations = None;
+        if let (Some(pc), Some(lr)) = (bus.debuginfo.last_pc, bus.debuginfo.last_lr) {
+            if let Some(ref debuginfo) = bus.debuginfo.debuginfo {
+                let debuginfo_b = debuginfo.borrow(|section|{
+                    EndianSlice::new(section, BigEndian)
+                });
+                match addr2line::Context::from_dwarf(debuginfo_b) {
+                    Ok(addr2line_ctx) => {
+                        locations = enhanced_crashdump(addr2line_ctx, pc, lr).ok();
+                    },
+                    Err(err) => println!("Failed to initialize addr2line, cannot procede with crashdump! {err}"),
+                }
+            }
+            if let Some(ref symbols) = bus.debuginfo.symbols {
+                let (pc_sym, lr_sym) = symbol_crashdump(symbols, pc, lr);
+                let (pc_loc, lr_loc) = locations.get_or_insert_with(|| ("??:0".to_owned(), "??:0".to_owned()));
+                if pc_loc.starts_with("??:") && let Some(sym) = pc_sym {
+                    *pc_loc = sym;
+                }
+                if lr_loc.starts_with("??:") && let Some(sym) = lr_sym {
+                    *lr_loc = sym;
+                }
+            }
+        }
+        else {
+            println!("Debug location never saved to bus, can not continue crashdump");
+        }
+        if self.crash_json {
+            let path = dump_path(self.dump_dir.as_deref(), "crash.json");
+            match write_crash_json(&path, &bus.debuginfo, ram_dump, locations, message) {
+                Ok(_) => println!("Wrote crash report to {}", path.display()),
+                Err(e) => println!("Failed to write crash.json: {e}"),
+            }
+        }
+    }
+}
+
+/// The [BusConfig] asked for on the command line.
+/// Pick the `--logging` string to use, taking -q and -v into account.
+fn log_level(args: &Args) -> String {
+    if args.quiet {
+        return "error".to_owned();
+    }
+    match args.verbose {
+        0 => args.logging.clone(),
+        1 => "debug".to_owned(),
+        _ => "trace".to_owned(),
+    }
+}
 
+fn bus_config(args: &Args) -> BusConfig {
+    // A custom kernel or boot1/boot2 image doesn't need to run boot0, and a
+    // save state brings its own copy of the mask ROM, so don't require a dump
+    let skips_boot0 = args.custom_kernel.is_some() || args.boot1.is_some() || args.boot2.is_some();
+    let no_boot0 = (skips_boot0 || args.load_state.is_some())
+        && !std::path::Path::new("./boot0.bin").exists();
+    BusConfig {
+        boot0: if no_boot0 { None } else { Some("./boot0.bin".to_owned()) },
+        nand: args.nand.clone(),
+        mem1_size: args.mem1_size,
+        mem2_size: args.mem2_size,
+        mmap_mem: args.mmap_mem,
+        sd_block_latency: args.sd_block_latency,
+        ..Default::default()
+    }
+}
+
+/// Construct the bus and apply the bus-level options.
+fn build_bus(args: &Args) -> anyhow::Result<Bus> {
+    let mut bus = Bus::new(&bus_config(args))?;
+    if let Some(ref seeprom) = args.seeprom {
+        bus.hlwd.gpio.seeprom = SeepromState::with_file(Some(seeprom))?;
+    }
+    if let Some(ref otp) = args.otp {
+        bus.hlwd.otp = OtpInterface::with_file(otp, args.otp_writable)?;
+    } else {
+        bus.hlwd.otp.writable = args.otp_writable;
+    }
+    bus.hlwd.model_ahb_resets = args.model_ahb_resets;
+    bus.sd0.set_write_protect(args.sd_readonly);
+    bus.hlwd.exi.rtc.clock = args.rtc_clock;
+    bus.access_latency = args.access_latency.clone();
+    for wp in &args.watch {
+        bus.add_watchpoint(wp.start..=wp.end, wp.kind);
+    }
+    if let Some(ref timeline) = args.timeline {
+        bus.load_timeline(timeline)?;
+    }
+    if let Some(ref script) = args.gpio_script {
+        bus.load_gpio_script(script)?;
+    }
+    Ok(bus)
+}
+
+/// Check that the image at `path` is at least `len` bytes long. Images which
+/// aren't `required` may also be missing.
+fn check_image(problems: &mut Vec<String>, what: &str, path: &str, len: usize, required: bool) {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.len() < len as u64 => {
+            problems.push(format!("{what} image {path} is {:#x} bytes, expected {len:#x}", meta.len()));
+        },
+        Ok(_) => {},
+        Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+            problems.push(format!("Can't read {what} image {path}: {e}"));
+        },
+        Err(_) => {},
+    }
+}
+
+/// Validate all of the inputs without running the CPU, returning a
+/// description of each problem.
+fn dry_run(args: &Args) -> Vec<String> {
+    if let Err(reason) = bus_config(args).validate() {
+        return vec![reason.to_string()];
+    }
+    let mut problems = ironic_core::dev::check_memory_map(args.mem1_size, args.mem2_size);
+    let skips_boot0 = args.custom_kernel.is_some() || args.boot1.is_some() || args.boot2.is_some();
+    if !skips_boot0 && !std::path::Path::new("./boot0.bin").exists() {
+        problems.push("boot0.bin not found (required without --custom-kernel, --boot1 or --boot2)".to_owned());
+    }
+    // Everything but a custom kernel goes on to load IOS from the NAND
+    check_image(&mut problems, "NAND", &args.nand, NAND_SIZE, args.custom_kernel.is_none());
+    check_image(&mut problems, "OTP", args.otp.as_deref().unwrap_or("./otp.bin"),
+        OtpInterface::SIZE, args.otp.is_some());
+    check_image(&mut problems, "SEEPROM", args.seeprom.as_deref().unwrap_or("./seeprom.bin"),
+        SeepromState::SIZE, args.seeprom.is_some());
+    if !problems.is_empty() {
+        return problems;
+    }
+    let bus = match build_bus(args) {
+        Ok(bus) => bus,
+        Err(reason) => {
+            problems.push(format!("Failed to construct emulator Bus: {reason}"));
+            return problems;
+        },
+    };
+    if let Some(ref kernel) = args.custom_kernel {
+        let kernel_problems = check_custom_kernel(kernel, args.kernel_profile);
+        if !kernel_problems.is_empty() {
+            problems.extend(kernel_problems);
+            return problems;
+        }
+        let mut back = InterpBackend::new(Arc::new(RwLock::new(bus)), Some(kernel.clone()), false);
+        back.kernel_profile = args.kernel_profile;
+        if let Err(reason) = back.load_custom_kernel() {
+            problems.push(format!("Failed to load custom kernel {kernel}: {reason}"));
+        }
+    } else if args.boot1.is_some() || args.boot2.is_some() {
+        let mut back = InterpBackend::new(Arc::new(RwLock::new(bus)), None, false);
+        back.boot1 = args.boot1.clone();
+        back.boot2 = args.boot2.clone();
+        if let Err(reason) = back.load_boot_images() {
+            problems.push(format!("Failed to load boot images: {reason}"));
+        }
+    }
+    problems
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, strum::AsRefStr, strum::Display, strum::EnumVariantNames, strum::EnumString)]
@@ -175,7 +854,9 @@ fn main() -> anyhow::Result<()> {
 enum LogTarget {
     AES,
     DEBUG_PORT,
+    DSP,
     EXI,
+    GDB,
     HLWD,
     IPC,
     IRQ,
@@ -192,34 +873,94 @@ enum LogTarget {
     Other,
 }
 
-fn setup_logger(base_level: log::LevelFilter, target_level_overrides: &[(LogTarget, log::LevelFilter)]) -> anyhow::Result<()> {
+/// Parse a u32 from a hex string, with or without a leading `0x`.
+fn parse_hex_u32(s: &str) -> anyhow::Result<u32> {
+    let x = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    Ok(u32::from_str_radix(x, 16)?)
+}
+
+/// Parse a `target:count` pair for --log-rate.
+fn parse_log_rate(s: &str) -> anyhow::Result<(LogTarget, u32)> {
+    let Some((target, count)) = s.split_once(':') else {
+        anyhow::bail!("Expected `target:messages_per_second`, got \"{s}\"");
+    };
+    Ok((target.parse::<LogTarget>()?, count.parse::<u32>()?))
+}
+
+/// Where log records go, and how they're formatted.
+struct LogOutput {
+    format: LogFormat,
+    /// Log to stdout (as well as to the file, if any)
+    stdout: bool,
+    file: Option<logfile::RotatingFile>,
+}
+
+fn setup_logger(base_level: log::LevelFilter, target_level_overrides: &[(LogTarget, log::LevelFilter)],
+    output: LogOutput, rate_limits: &[(LogTarget, u32)]) -> anyhow::Result<()>
+{
     use fern::colors::{Color, ColoredLevelConfig};
+    use std::sync::atomic::{AtomicU64, Ordering};
     let colors = ColoredLevelConfig::default().debug(Color::Cyan).trace(Color::BrightCyan);
+    let seq = AtomicU64::new(0);
+    let format = output.format;
     let mut config = fern::Dispatch::new().level(base_level);
     for specific_override in target_level_overrides {
         config = config.level_for(specific_override.0.to_string(), specific_override.1);
     }
-    config = config.format(move |out, message, record| {
-        if record.target() == "SVC" {
-            out.finish(format_args!("[SVC] {}", message));
-        }
-        else {
+    // JSON records are formatted once for every sink, so that they all
+    // agree on the sequence numbers
+    if format == LogFormat::Json {
+        config = config.format(move |out, message, record| {
+            // Guest output from semihosting is flagged, since it isn't
+            // really a log message
             out.finish(format_args!(
-                "[{}][{}] {}",
-                record.target(),
-                colors.color(record.level()),
-                message
+                "{{\"seq\":{},\"target\":{},\"level\":\"{}\",\"svc\":{},\"message\":{}}}",
+                seq.fetch_add(1, Ordering::Relaxed),
+                json_string(record.target()),
+                record.level(),
+                record.target() == "SVC",
+                json_string(&message.to_string())
             ))
-        }
-    }).chain(std::io::stdout());
-    Ok(config.apply()?)
+        });
+    }
+    // Human-readable records are only colored on stdout
+    let human = move |colored: bool| {
+        fern::Dispatch::new().format(move |out, message, record| {
+            if format == LogFormat::Json {
+                out.finish(*message)
+            }
+            else if record.target() == "SVC" {
+                out.finish(format_args!("[SVC] {}", message));
+            }
+            else if colored {
+                out.finish(format_args!("[{}][{}] {}", record.target(), colors.color(record.level()), message))
+            }
+            else {
+                out.finish(format_args!("[{}][{}] {}", record.target(), record.level(), message))
+            }
+        })
+    };
+    if output.stdout {
+        config = config.chain(human(true).chain(std::io::stdout()));
+    }
+    if let Some(file) = output.file {
+        config = config.chain(human(false).chain(Box::new(file) as Box<dyn std::io::Write + Send>));
+    }
+    if rate_limits.is_empty() {
+        return Ok(config.apply()?);
+    }
+    let (max_level, logger) = config.into_log();
+    let limiter = ratelimit::RateLimiter::new(rate_limits);
+    log::set_boxed_logger(Box::new(ratelimit::RateLimitedLog::new(logger, limiter)))?;
+    log::set_max_level(max_level);
+    Ok(())
 }
 
 // I'm sorry for this monster
-fn handle_logging_argument(log_string: String) -> anyhow::Result<()> {
+fn handle_logging_argument(log_string: String, output: LogOutput, rate_limits: &[(LogTarget, u32)]) -> anyhow::Result<()> {
     if !log_string.contains(',') {
         if let Ok(base_only) = log_string.parse::<log::LevelFilter>() {
-            return setup_logger(base_only, &[]);
+            return setup_logger(base_only, &[], output, rate_limits);
         }
         anyhow::bail!(
             "Failed to parse --logging argument: Base-level must be `off`, `error`, `warn`, `info`, `debug`, or `trace`. You supplied \"{log_string}\"{LOGGING_EXAMPLE_TXT}"
@@ -254,7 +995,7 @@ fn handle_logging_argument(log_string: String) -> anyhow::Result<()> {
                 );
             }
         }
-        return setup_logger(base_level, target_level_overrides.as_slice());
+        return setup_logger(base_level, target_level_overrides.as_slice(), output, rate_limits);
     }
     else {
         // Failed to parse base level
@@ -264,13 +1005,76 @@ fn handle_logging_argument(log_string: String) -> anyhow::Result<()> {
     }
 }
 
-fn enhanced_crashdump(addr2line_ctx: Context<EndianSlice<BigEndian>>, pc: u32, lr: u32) -> anyhow::Result<()> {
+/// Print the addr2line locations of PC and LR, returning them as strings.
+fn enhanced_crashdump(addr2line_ctx: Context<EndianSlice<BigEndian>>, pc: u32, lr: u32) -> anyhow::Result<(String, String)> {
     // addr2line of PC and LR
-    {
-        let pc_line = addr2line_ctx.find_location(pc as u64).unwrap_or_default();
-        let lr_line = addr2line_ctx.find_location(lr as u64).unwrap_or_default();
-        println!("addr2line\nPC:{pc:08x} Loc:{}\nLR:{lr:08x} Loc:{}", fmt_location(pc_line), fmt_location(lr_line));
+    let pc_line = fmt_location(addr2line_ctx.find_location(pc as u64).unwrap_or_default());
+    let lr_line = fmt_location(addr2line_ctx.find_location(lr as u64).unwrap_or_default());
+    println!("addr2line\nPC:{pc:08x} Loc:{pc_line}\nLR:{lr:08x} Loc:{lr_line}");
+    Ok((pc_line, lr_line))
+}
+
+/// Print the functions containing PC and LR (as `name+offset`), returning
+/// their descriptions.
+fn symbol_crashdump(symbols: &SymbolMap, pc: u32, lr: u32) -> (Option<String>, Option<String>) {
+    let describe = |addr| symbols.describe_code(addr).or_else(|| symbols.describe(addr));
+    let (pc_sym, lr_sym) = (describe(pc), describe(lr));
+    println!("Symbols\nPC:{pc:08x} <{}>\nLR:{lr:08x} <{}>",
+        pc_sym.as_deref().unwrap_or("?"), lr_sym.as_deref().unwrap_or("?"));
+    (pc_sym, lr_sym)
+}
+
+/// Where to write memory dumps with some suffix. Without `--dump-dir`,
+/// they go to the current directory as usual. Otherwise, the suffix is
+/// tagged with the PID so that instances sharing the directory don't
+/// clobber each other.
+fn dump_location(dump_dir: Option<&Path>, suffix: &str) -> (PathBuf, String) {
+    match dump_dir {
+        Some(dir) => (dir.to_path_buf(), format!("{}.{suffix}", process::id())),
+        None => (std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")), suffix.to_owned()),
+    }
+}
+
+/// Where to write some other file (or directory) next to the memory dumps,
+/// with the PID added to its name under `--dump-dir` in the same way.
+fn dump_path(dump_dir: Option<&Path>, name: &str) -> PathBuf {
+    let Some(dir) = dump_dir else {
+        return dump_location(None, name).0.join(name);
+    };
+    let pid = process::id();
+    dir.join(match name.split_once('.') {
+        Some((stem, ext)) => format!("{stem}.{pid}.{ext}"),
+        None => format!("{name}.{pid}"),
+    })
+}
+
+/// Write a machine-readable crash report for automated triage.
+fn write_crash_json(path: &Path, debuginfo: &DebugInfo, ram_dump: Option<std::path::PathBuf>,
+    locations: Option<(String, String)>, reason: &str) -> anyhow::Result<()> {
+    fn opt_reg(x: Option<u32>) -> String {
+        x.map_or("null".to_owned(), |x| format!("\"0x{x:08x}\""))
     }
+    let (pc_loc, lr_loc) = match locations {
+        Some((pc, lr)) => (json_string(&pc), json_string(&lr)),
+        None => ("null".to_owned(), "null".to_owned()),
+    };
+    let regs = match debuginfo.last_regs {
+        Some((r, cpsr)) => {
+            let mut s = String::from("{");
+            for (idx, val) in r.iter().enumerate() {
+                s.push_str(&format!("\"r{idx}\": \"0x{val:08x}\", "));
+            }
+            s.push_str(&format!("\"cpsr\": \"0x{cpsr:08x}\"}}"));
+            s
+        },
+        None => "null".to_owned(),
+    };
+    let ram_dump = ram_dump.map_or("null".to_owned(), |p| json_string(&p.to_string_lossy()));
+    let json = format!(
+        "{{\n  \"reason\": {},\n  \"pc\": {},\n  \"lr\": {},\n  \"sp\": {},\n  \"pc_location\": {pc_loc},\n  \"lr_location\": {lr_loc},\n  \"registers\": {regs},\n  \"ram_dump_dir\": {ram_dump}\n}}\n",
+        json_string(reason), opt_reg(debuginfo.last_pc), opt_reg(debuginfo.last_lr), opt_reg(debuginfo.last_sp),
+    );
+    std::fs::write(path, json)?;
     Ok(())
 }
 
@@ -281,4 +1085,79 @@ fn fmt_location(loc: Option<addr2line::Location>) -> String {
     else {
         "??:0".to_owned()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_checks_each_image() {
+        let rom = std::env::temp_dir().join(format!("ironic-dry-run-rom-{}.elf", process::id()));
+        let otp = std::env::temp_dir().join(format!("ironic-dry-run-otp-{}.bin", process::id()));
+        std::fs::write(&rom, ironic_backend::testrom::build_test_rom()).unwrap();
+        std::fs::write(&otp, [0; 0x40]).unwrap();
+        let rom = rom.to_str().unwrap();
+        let run = |extra: &[&str]| {
+            let args = [&["ironic-tui", "--dry-run", "-c", rom, "--kernel-profile", "raw"], extra].concat();
+            dry_run(&Args::try_parse_from(args).unwrap())
+        };
+
+        assert_eq!(run(&[]), Vec::<String>::new());
+        let problems = run(&["--otp", otp.to_str().unwrap(), "--seeprom", "/nonexistent/seeprom.bin"]);
+        std::fs::remove_file(rom).unwrap();
+        std::fs::remove_file(&otp).unwrap();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("OTP image") && problems[0].ends_with("is 0x40 bytes, expected 0x80"), "{}", problems[0]);
+        assert!(problems[1].starts_with("Can't read SEEPROM image /nonexistent/seeprom.bin"), "{}", problems[1]);
+    }
+
+    #[test]
+    fn log_options_need_a_log_file() {
+        assert!(Args::try_parse_from(["ironic-tui", "--no-stdout-log"]).is_err());
+        assert!(Args::try_parse_from(["ironic-tui", "--no-stdout-log", "--log-file", "log.txt"]).is_ok());
+        assert!(Args::try_parse_from(["ironic-tui", "--log-file", "log.txt", "--log-rotate-keep", "3"]).is_err());
+        assert!(Args::try_parse_from(["ironic-tui", "--log-file", "log.txt", "--log-rotate-size", "4096",
+            "--log-rotate-keep", "3"]).is_ok());
+    }
+
+    #[test]
+    fn verbose_flags_raise_the_log_level() {
+        let level = |args: &[&str]| log_level(&Args::try_parse_from(
+            std::iter::once("ironic-tui").chain(args.iter().copied())).unwrap());
+        assert_eq!(level(&[]), "info");
+        assert_eq!(level(&["-v"]), "debug");
+        assert_eq!(level(&["-vv"]), "trace");
+        assert_eq!(level(&["-vvv"]), "trace");
+        assert_eq!(level(&["-q"]), "error");
+    }
+
+    #[test]
+    fn conflicting_boot_images_are_rejected() {
+        assert!(Args::try_parse_from(["ironic-tui", "--boot1", "boot1.bin"]).is_ok());
+        assert!(Args::try_parse_from(["ironic-tui", "--boot1", "boot1.bin", "--boot2", "boot2.bin"]).is_err());
+        assert!(Args::try_parse_from(["ironic-tui", "--boot2", "boot2.bin", "-c", "kernel.elf"]).is_err());
+    }
+
+    #[test]
+    fn dump_dir_is_created_and_names_include_the_pid() {
+        let dir = std::env::temp_dir().join(format!("ironic-dump-dir-{}", process::id())).join("nested");
+        let pid = process::id();
+        let bus = Bus::with_boot0(None).unwrap();
+
+        let (mem_dir, suffix) = dump_location(Some(&dir), "crash.bin");
+        bus.dump_memory_to(&mem_dir, &suffix).unwrap();
+        bus.dump_device_regs(&dump_path(Some(&dir), "regs")).unwrap();
+        write_crash_json(&dump_path(Some(&dir), "crash.json"), &bus.debuginfo, Some(mem_dir), None, "test").unwrap();
+        for name in [format!("mem1.{pid}.crash.bin"), format!("sram0.{pid}.crash.bin"),
+            format!("regs.{pid}/hlwd.txt"), format!("crash.{pid}.json")] {
+            assert!(dir.join(&name).exists(), "{name} is missing");
+        }
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+
+        // Without --dump-dir, everything goes to the current directory as before
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(dump_location(None, "bin"), (cwd.clone(), "bin".to_owned()));
+        assert_eq!(dump_path(None, "crash.json"), cwd.join("crash.json"));
+        assert_eq!(dump_path(None, "regs"), cwd.join("regs"));
+    }
+}
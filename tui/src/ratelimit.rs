@@ -0,0 +1,223 @@
+//! Per-target rate limiting for log output.
+//!
+//! Some targets (e.g. SDHC or HLWD at debug/trace) can produce enough output
+//! during boot to drown out everything else. [RateLimitedLog] wraps the
+//! logger built by fern and drops messages from a target once it exceeds
+//! its budget for the current one-second window. The number of dropped
+//! messages is reported with the first record logged (from any target) after
+//! that window ends, or when the logger is flushed.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// Length of the window that each limit applies to.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Result of checking a message against the limiter.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RateDecision {
+    /// Log the message.
+    Allow,
+    /// Drop the message.
+    Suppress,
+}
+
+/// Accounting for a single rate-limited target.
+struct TargetWindow {
+    limit: u32,
+    start: Option<Instant>,
+    count: u32,
+    suppressed: u64,
+}
+
+/// Tracks how many messages each limited target has logged recently.
+pub struct RateLimiter {
+    targets: Mutex<HashMap<String, TargetWindow>>,
+}
+impl RateLimiter {
+    /// Create a limiter from a list of (target, messages per second) pairs.
+    pub fn new<S: ToString>(limits: &[(S, u32)]) -> Self {
+        let targets = limits.iter().map(|(target, limit)| {
+            (target.to_string(), TargetWindow { limit: *limit, start: None, count: 0, suppressed: 0 })
+        }).collect();
+        Self { targets: Mutex::new(targets) }
+    }
+
+    /// Decide whether a message from some target may be logged at `now`.
+    pub fn check(&self, target: &str, now: Instant) -> RateDecision {
+        let mut targets = self.targets.lock();
+        let Some(win) = targets.get_mut(target) else {
+            return RateDecision::Allow;
+        };
+        if win.start.is_none_or(|start| now.duration_since(start) >= WINDOW) {
+            win.start = Some(now);
+            win.count = 0;
+        }
+        if win.count >= win.limit {
+            win.suppressed += 1;
+            return RateDecision::Suppress;
+        }
+        win.count += 1;
+        RateDecision::Allow
+    }
+
+    /// Take the number of messages dropped from each target whose window
+    /// has ended by `now`, or from every target if `now` is None.
+    pub fn take_suppressed(&self, now: Option<Instant>) -> Vec<(String, u64)> {
+        let mut targets = self.targets.lock();
+        let mut res: Vec<_> = targets.iter_mut().filter(|(_, win)| {
+            win.suppressed != 0 && now.is_none_or(|now| win.start.is_none_or(|start| now.duration_since(start) >= WINDOW))
+        }).map(|(target, win)| (target.clone(), std::mem::take(&mut win.suppressed))).collect();
+        res.sort();
+        res
+    }
+}
+
+/// A logger which applies a [RateLimiter] before passing records through.
+pub struct RateLimitedLog {
+    inner: Box<dyn log::Log>,
+    limiter: RateLimiter,
+    clock: Box<dyn Fn() -> Instant + Send + Sync>,
+}
+impl RateLimitedLog {
+    pub fn new(inner: Box<dyn log::Log>, limiter: RateLimiter) -> Self {
+        Self::with_clock(inner, limiter, Box::new(Instant::now))
+    }
+
+    /// Like [RateLimitedLog::new], but with the time of each record taken
+    /// from `clock`.
+    pub fn with_clock(inner: Box<dyn log::Log>, limiter: RateLimiter,
+        clock: Box<dyn Fn() -> Instant + Send + Sync>) -> Self {
+        Self { inner, limiter, clock }
+    }
+
+    /// Log how many messages were dropped from some target.
+    fn summarize(&self, target: &str, suppressed: u64) {
+        self.inner.log(&log::Record::builder()
+            .target(target)
+            .level(log::Level::Warn)
+            .args(format_args!("{suppressed} messages suppressed"))
+            .build());
+    }
+}
+impl log::Log for RateLimitedLog {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let now = (self.clock)();
+        for (target, suppressed) in self.limiter.take_suppressed(Some(now)) {
+            self.summarize(&target, suppressed);
+        }
+        if self.limiter.check(record.target(), now) == RateDecision::Allow {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        for (target, suppressed) in self.limiter.take_suppressed(None) {
+            self.summarize(&target, suppressed);
+        }
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Logger which records every message it receives.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(String, String)>>>);
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool { true }
+        fn log(&self, record: &log::Record) {
+            self.0.lock().push((record.target().to_owned(), record.args().to_string()));
+        }
+        fn flush(&self) {}
+    }
+
+    fn log_to(logger: &dyn log::Log, target: &str, msg: &str) {
+        logger.log(&log::Record::builder()
+            .target(target)
+            .level(log::Level::Debug)
+            .args(format_args!("{msg}"))
+            .build());
+    }
+
+    #[test]
+    fn limit_resets_each_window() {
+        let limiter = RateLimiter::new(&[("SDHC", 2)]);
+        let t0 = Instant::now();
+        assert_eq!(limiter.check("SDHC", t0), RateDecision::Allow);
+        assert_eq!(limiter.check("SDHC", t0), RateDecision::Allow);
+        assert_eq!(limiter.check("SDHC", t0), RateDecision::Suppress);
+        assert_eq!(limiter.check("SDHC", t0), RateDecision::Suppress);
+        assert_eq!(limiter.take_suppressed(Some(t0)), []);
+        let t1 = t0 + WINDOW;
+        assert_eq!(limiter.take_suppressed(Some(t1)), [("SDHC".to_owned(), 2)]);
+        assert_eq!(limiter.check("SDHC", t1), RateDecision::Allow);
+        assert_eq!(limiter.check("SDHC", t1), RateDecision::Allow);
+    }
+
+    /// A limited logger whose clock only moves when the test advances it.
+    fn limited_logger(limit: u32) -> (Capture, Arc<Mutex<Instant>>, RateLimitedLog) {
+        let capture = Capture::default();
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        let logger = RateLimitedLog::with_clock(Box::new(capture.clone()),
+            RateLimiter::new(&[("SDHC", limit)]), Box::new(move || *clock.lock()));
+        (capture, now, logger)
+    }
+
+    #[test]
+    fn flooded_target_is_capped_and_summarized() {
+        let (capture, now, logger) = limited_logger(10);
+        for _ in 0..1000 {
+            log_to(&logger, "SDHC", "chatter");
+            log_to(&logger, "HLWD", "unlimited");
+        }
+        {
+            let msgs = capture.0.lock();
+            assert_eq!(msgs.iter().filter(|(t, _)| t == "SDHC").count(), 10);
+            assert_eq!(msgs.iter().filter(|(t, _)| t == "HLWD").count(), 1000);
+        }
+
+        // The next window reports how many messages were dropped.
+        *now.lock() += WINDOW;
+        log_to(&logger, "SDHC", "chatter");
+        let msgs = capture.0.lock();
+        let n = msgs.len();
+        assert_eq!(msgs[n - 2], ("SDHC".to_owned(), "990 messages suppressed".to_owned()));
+        assert_eq!(msgs[n - 1], ("SDHC".to_owned(), "chatter".to_owned()));
+    }
+
+    #[test]
+    fn burst_is_summarized_without_more_from_its_target() {
+        let (capture, now, logger) = limited_logger(1);
+        for _ in 0..5 {
+            log_to(&logger, "SDHC", "chatter");
+        }
+
+        // Other targets logging after the window ends carry the summary
+        *now.lock() += WINDOW;
+        log_to(&logger, "HLWD", "unlimited");
+        assert_eq!(capture.0.lock()[1..], [
+            ("SDHC".to_owned(), "4 messages suppressed".to_owned()),
+            ("HLWD".to_owned(), "unlimited".to_owned()),
+        ]);
+
+        // Flushing reports drops from a window which hasn't ended yet
+        log_to(&logger, "SDHC", "chatter");
+        log_to(&logger, "SDHC", "chatter");
+        log::Log::flush(&logger);
+        assert_eq!(capture.0.lock().last().unwrap(), &("SDHC".to_owned(), "1 messages suppressed".to_owned()));
+    }
+}